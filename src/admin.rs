@@ -0,0 +1,294 @@
+use crate::config::TxKind;
+use crate::deny_list_import::DenyListImport;
+use crate::group::PERMANENT_EXPIRATION;
+use crate::recording::Recorder;
+use crate::state::State;
+use crossbeam::channel::{bounded, Sender};
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+const RESPONSE_TIMEOUT_MS : u64 = 5 * 1000; // 5 seconds
+
+// Rule on behalf of which addresses are added via the admin socket
+const ADMIN_RULE : &str = "admin";
+
+const USAGE : &str = "Commands: show groups | show peer <ip> | add <ip> <group> <ttl seconds> | remove <ip> <group> | \
+                      reload | reload config | record [on|off]";
+
+// A command received on the admin socket
+pub enum AdminCommand
+{
+    ShowGroups,
+
+    ShowPeer(IpAddr),
+
+    // Adds an address to a group for a number of seconds
+    Add(IpAddr, String, u64),
+
+    Remove(IpAddr, String),
+
+    // Re-imports the deny list import file, whether or not it has changed
+    Reload,
+
+    // Re-reads the config file, swapping in its classification definitions
+    ReloadConfig,
+
+    // Turns event recording on or off, or with None, describes it
+    Record(Option<bool>)
+}
+
+// A command to be handled on the event processing thread (which owns State) and responded to via response_sender
+pub struct AdminRequest
+{
+    pub command : AdminCommand,
+
+    response_sender : Sender<String>
+}
+
+impl AdminRequest
+{
+    pub fn respond(
+        self,
+        response : String
+    )
+    {
+        // The connection thread may have timed out and gone away; nothing to do in that case
+        self.response_sender.send(response).ok();
+    }
+}
+
+// Parses a command line, returning an error describing the valid commands if it is not one
+fn parse(line : &str) -> Result<AdminCommand, String>
+{
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+
+    let ip_addr = |word : &str| word.parse::<IpAddr>().map_err(|_| format!("Invalid ip address: {word}"));
+
+    match words.as_slice() {
+        ["show", "groups"] => Ok(AdminCommand::ShowGroups),
+        ["show", "peer", ip] => Ok(AdminCommand::ShowPeer(ip_addr(ip)?)),
+        ["add", ip, group_name, ttl] => Ok(AdminCommand::Add(
+            ip_addr(ip)?,
+            group_name.to_string(),
+            ttl.parse::<u64>().map_err(|_| format!("Invalid ttl: {ttl}"))?
+        )),
+        ["remove", ip, group_name] => Ok(AdminCommand::Remove(ip_addr(ip)?, group_name.to_string())),
+        ["reload"] => Ok(AdminCommand::Reload),
+        ["reload", "config"] => Ok(AdminCommand::ReloadConfig),
+        ["record"] => Ok(AdminCommand::Record(None)),
+        ["record", "on"] => Ok(AdminCommand::Record(Some(true))),
+        ["record", "off"] => Ok(AdminCommand::Record(Some(false))),
+        _ => Err(USAGE.to_string())
+    }
+}
+
+// Handles a command, returning its response as lines of text
+pub fn handle(
+    state : &mut State,
+    deny_list_import : Option<&mut DenyListImport>,
+    recorder : Option<&mut Recorder>,
+    config_path : &str,
+    command : &AdminCommand,
+    now : u64
+) -> String
+{
+    match command {
+        AdminCommand::ShowGroups => {
+            let mut group_member_counts = state.group_member_counts();
+            group_member_counts.sort();
+            group_member_counts
+                .into_iter()
+                .map(|(group_name, member_count)| format!("{group_name}: {member_count} members\n"))
+                .collect()
+        },
+        AdminCommand::ShowPeer(ip_addr) => show_peer(state, ip_addr),
+        AdminCommand::Add(ip_addr, group_name, ttl_seconds) => {
+            let ttl_ms = ttl_seconds.saturating_mul(1000).min(PERMANENT_EXPIRATION - now);
+            if state.add_group_member(group_name, *ip_addr, ttl_ms, ADMIN_RULE, now) {
+                format!("Added {ip_addr} to {group_name}\n")
+            }
+            else {
+                format!("{ip_addr} was already a member of {group_name}\n")
+            }
+        },
+        AdminCommand::Remove(ip_addr, group_name) => {
+            if state.remove_group_member(group_name, ip_addr) {
+                format!("Removed {ip_addr} from {group_name}\n")
+            }
+            else {
+                format!("{ip_addr} was not a member of {group_name}\n")
+            }
+        },
+        AdminCommand::Reload => match deny_list_import {
+            Some(deny_list_import) => match deny_list_import.reload(state, now) {
+                Ok(()) => "Reloaded deny list\n".to_string(),
+                Err(e) => format!("ERROR: {e}\n")
+            },
+            None => "ERROR: Nothing to reload; no deny_list_import is configured\n".to_string()
+        },
+        AdminCommand::ReloadConfig => crate::reload::reload(state, config_path).unwrap_or_else(|e| format!("{e}\n")),
+        AdminCommand::Record(enabled) => match recorder {
+            Some(recorder) => {
+                if let Some(enabled) = enabled {
+                    recorder.set_enabled(*enabled);
+                }
+                recorder.status()
+            },
+            None => "ERROR: No recording is configured\n".to_string()
+        }
+    }
+}
+
+// Describes a peer and each of its group memberships, with the rule that added it, so as to answer why an address is
+// in a group
+fn show_peer(
+    state : &State,
+    ip_addr : &IpAddr
+) -> String
+{
+    let mut lines = match state.peers.get(ip_addr) {
+        Some(peer) => format!(
+            "{ip_addr}: stake {}, pubkey {}, {} tx submitted ({} votes, {} user), {} connections, {} fee lamports, {} \
+             failed, {} duplicates, score {:.2}\n",
+            state.stakes.get(ip_addr).unwrap_or(&0),
+            peer.pubkey.map(|pubkey| pubkey.to_string()).unwrap_or_else(|| "unknown".to_string()),
+            peer.tx_submitted(TxKind::All),
+            peer.vote_tx_submitted,
+            peer.user_tx_submitted,
+            peer.connection_count,
+            peer.fee_lamports,
+            peer.failed_count,
+            peer.duplicate_count,
+            peer.score
+        ),
+        None => format!("{ip_addr}: not a tracked peer\n")
+    };
+
+    for group_name in state.groups_of(ip_addr) {
+        let Some(membership) = state.groups.get(&group_name).and_then(|group| group.members().get(ip_addr))
+        else {
+            continue;
+        };
+        let expiration = if membership.expiration == PERMANENT_EXPIRATION {
+            "never expires".to_string()
+        }
+        else {
+            format!("expires at {}", membership.expiration)
+        };
+        lines.push_str(&format!("  {group_name}: added by {}, {expiration}", membership.added_by));
+        if let Some(extended_by) = &membership.extended_by {
+            lines.push_str(&format!(", extended by {extended_by}"));
+        }
+        lines.push('\n');
+    }
+
+    lines
+}
+
+// Spawns the admin socket thread, which accepts connections on a Unix domain socket at path, reads a command from each
+// line, and forwards the commands to request_sender, writing back each response.  Any file left at path by a prior run
+// is replaced.
+pub fn spawn_server(
+    path : &str,
+    request_sender : Sender<AdminRequest>
+) -> Result<(), String>
+{
+    if std::fs::symlink_metadata(path).is_ok() {
+        std::fs::remove_file(path).map_err(|e| format!("Failed to remove stale admin socket {path}: {e}"))?;
+    }
+
+    let unix_listener = UnixListener::bind(path).map_err(|e| format!("Failed to bind admin socket {path}: {e}"))?;
+
+    std::thread::spawn(move || {
+        for unix_stream in unix_listener.incoming() {
+            match unix_stream {
+                Ok(unix_stream) => {
+                    let request_sender = request_sender.clone();
+                    std::thread::spawn(move || handle_connection(unix_stream, request_sender));
+                },
+                Err(e) => eprintln!("Admin socket failed accept because {e}")
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(
+    unix_stream : UnixStream,
+    request_sender : Sender<AdminRequest>
+)
+{
+    let Ok(mut writer) = unix_stream.try_clone()
+    else {
+        return;
+    };
+
+    for line in BufReader::new(unix_stream).lines() {
+        let Ok(line) = line
+        else {
+            break;
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match parse(&line) {
+            Ok(command) => {
+                let (response_sender, response_receiver) = bounded(1);
+                if request_sender.send(AdminRequest { command, response_sender }).is_err() {
+                    "ERROR: Event processing has stopped\n".to_string()
+                }
+                else {
+                    response_receiver
+                        .recv_timeout(std::time::Duration::from_millis(RESPONSE_TIMEOUT_MS))
+                        .unwrap_or_else(|_| "ERROR: Timed out waiting for response\n".to_string())
+                }
+            },
+            Err(e) => format!("ERROR: {e}\n")
+        };
+
+        if writer.write_all(response.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const PEER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    const OTHER_PEER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1));
+
+    #[test]
+    fn admin_commands_parse_with_their_arguments()
+    {
+        assert!(matches!(parse("show groups"), Ok(AdminCommand::ShowGroups)));
+        assert!(matches!(parse(" show  peer 10.0.0.1 "), Ok(AdminCommand::ShowPeer(ip_addr)) if ip_addr == PEER));
+        assert!(matches!(
+            parse("add 10.2.0.1 manual 600"),
+            Ok(AdminCommand::Add(ip_addr, group_name, 600)) if (ip_addr == OTHER_PEER) && (group_name == "manual")
+        ));
+        assert!(matches!(
+            parse("remove 10.2.0.1 manual"),
+            Ok(AdminCommand::Remove(ip_addr, group_name)) if (ip_addr == OTHER_PEER) && (group_name == "manual")
+        ));
+        assert!(matches!(parse("reload"), Ok(AdminCommand::Reload)));
+        assert!(matches!(parse("reload config"), Ok(AdminCommand::ReloadConfig)));
+        assert!(matches!(parse("record"), Ok(AdminCommand::Record(None))));
+        assert!(matches!(parse("record on"), Ok(AdminCommand::Record(Some(true)))));
+        assert!(matches!(parse("record off"), Ok(AdminCommand::Record(Some(false)))));
+
+        // Malformed arguments are reported as such, and unknown commands with the list of commands
+        assert_eq!(parse("show peer 10.0.0").err(), Some("Invalid ip address: 10.0.0".to_string()));
+        assert_eq!(parse("add 10.2.0.1 manual forever").err(), Some("Invalid ttl: forever".to_string()));
+        assert!(parse("remove 10.2.0.1").is_err_and(|e| e.starts_with("Commands: ")));
+        assert!(parse("").is_err_and(|e| e.starts_with("Commands: ")));
+    }
+}
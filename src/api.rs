@@ -0,0 +1,1206 @@
+use crate::churn::ChurnBucket;
+use crate::config::GroupMetadata;
+use crate::histogram::Histogram;
+use crate::log::{self, LogLevel};
+use crate::state::{PeerAggregate, PeerSummary, State, SubmittedTx};
+use crossbeam::channel::{bounded, Sender};
+use serde::Serialize;
+use solana_sdk::signature::Signature;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+
+const DEFAULT_PAGE_LIMIT : usize = 100;
+const MAX_PAGE_LIMIT : usize = 10_000;
+const RESPONSE_TIMEOUT_MS : u64 = 5 * 1000; // 5 seconds
+const REQUEST_TIMEOUT_MS : u64 = 5 * 1000; // 5 seconds
+
+// A request received by the API server that changes state, or queries state too large to copy into every Snapshot
+// (i.e. tx), to be handled on the event processing thread (which owns State) and responded to via response_sender.
+// Other queries are instead answered from the most recent Snapshot.
+pub struct ApiRequest
+{
+    method : Method,
+
+    pub path : String,
+
+    pub query : HashMap<String, String>,
+
+    response_sender : Sender<ApiResponse>
+}
+
+#[derive(Clone, Copy)]
+enum Method
+{
+    // Queries state
+    Get,
+
+    // Changes state
+    Post
+}
+
+pub struct ApiResponse
+{
+    pub status : u16,
+
+    pub body : String
+}
+
+// A consistent, read-only copy of everything queried via the API, built by the event processing thread from State
+// at a single point in time.  Queries are answered from the most recently published Snapshot on the API server's own
+// threads, so that they neither block nor are blocked by event processing.
+pub struct Snapshot
+{
+    status : StatusSummary,
+
+    mode : ModeSummary,
+
+    log : LogSummary,
+
+    aggregates : AggregatesSummary,
+
+    classifications : Vec<ClassificationSummary>,
+
+    leader : LeaderSummary,
+
+    connection_durations : Vec<ConnectionDurationsSummary>,
+
+    // Ordered by ip address
+    peers : Vec<PeerSummary>,
+
+    // Map from group name to members, ordered by ip address
+    groups : HashMap<String, Vec<GroupMember>>,
+
+    // Map from group name to the group's metadata, from config
+    group_metadata : HashMap<String, Arc<GroupMetadata>>,
+
+    // Map from group name to the group's churn history
+    churn : HashMap<String, ChurnSummary>
+}
+
+// Holds the most recently published Snapshot, and capability report.  Publishing replaces the snapshot as a whole, so
+// readers holding the previous snapshot continue to see it unchanged.
+pub struct SnapshotCell
+{
+    snapshot : RwLock<Option<Arc<Snapshot>>>,
+
+    // Published along with each snapshot, since a config reload may have changed it
+    capabilities : RwLock<Arc<serde_json::Value>>
+}
+
+#[derive(Serialize)]
+struct PeersPage<'a>
+{
+    total : usize,
+
+    offset : usize,
+
+    limit : usize,
+
+    peers : Vec<&'a PeerSummary>
+}
+
+#[derive(Serialize)]
+struct ClassificationSummary
+{
+    name : String,
+
+    tracked_ip_count : usize,
+
+    add_count : u64,
+
+    alert_count : u64,
+
+    merged_value_count : u64,
+
+    last_evaluation_us : u64,
+
+    max_evaluation_us : u64,
+
+    last_scanned_ip_count : usize,
+
+    deferred_count : u64,
+
+    evaluation_interval_ms : Option<u64>
+}
+
+#[derive(Serialize)]
+struct StatusSummary
+{
+    // Timestamp of the State from which this response was built
+    snapshot_timestamp : u64,
+
+    peer_count : usize,
+
+    tx_count : usize,
+
+    dropped_sink_event_count : u64,
+
+    ignored_event_count : u64,
+
+    // Number of Deprecated messages received, by ingest source; any entry here identifies a sender needing upgrade
+    deprecated_counts : HashMap<String, u64>,
+
+    // Number of fees reported for tx never seen submitted, and their total; landed value invisible to peer scoring
+    unseen_fee_count : u64,
+
+    unseen_fee_lamports : u64,
+
+    // Number of fees credited to the first submitter of a tx after the tx was flushed
+    late_fee_count : u64,
+
+    // Number of fee classification values left out for their submitter's stake being outside of the fee stake bounds
+    fee_stake_excluded_count : u64,
+
+    // Number of jumps of the system clock detected, and the milliseconds subtracted from every timestamp as a result
+    clock_jump_count : u64,
+
+    clock_offset_ms : i64,
+
+    // Most recent readings of the internal clock, on which everything is scheduled, and of the system clock; these
+    // differ by clock_offset_ms plus any drift or jump not yet detected
+    internal_clock_timestamp : u64,
+
+    system_clock_timestamp : u64,
+
+    // Number of tx reported to have landed in another leader's block, and how many of those we forwarded
+    landed_elsewhere_count : u64,
+
+    forwarded_landed_elsewhere_count : u64,
+
+    // Number of ingest events dropped and connections closed due to the ingest rate limit
+    rate_limited_event_count : u64,
+
+    rate_limited_disconnect_count : u64,
+
+    // Number of ingest connections closed on accept because their sender is not allowed
+    rejected_connection_count : u64,
+
+    // Health of ingest: connections currently open, connections closed as hung, connection threads that panicked,
+    // and accept threads restarted after dying
+    open_connection_count : u64,
+
+    idle_disconnect_count : u64,
+
+    connection_panic_count : u64,
+
+    listener_restart_count : u64
+}
+
+#[derive(Serialize)]
+struct AggregatesSummary
+{
+    staked : AggregateSummary,
+
+    unstaked : AggregateSummary
+}
+
+// Aggregate activity of a set of peers over the most recent reporting interval
+#[derive(Serialize)]
+struct AggregateSummary
+{
+    duration_ms : u64,
+
+    peer_count : u64,
+
+    active_peer_count : u64,
+
+    // Votes + user tx
+    tx_submitted_per_second : f64,
+
+    vote_tx_submitted_per_second : f64,
+
+    user_tx_submitted_per_second : f64,
+
+    connections_per_second : f64,
+
+    failed_per_second : f64,
+
+    duplicates_per_second : f64,
+
+    // Average fee lamports credited per tx submitted; None if no tx were submitted
+    average_fee_lamports : Option<f64>,
+
+    // Fraction of connections that failed or were refused; None if there were no connections
+    failure_rate : Option<f64>
+}
+
+impl From<&PeerAggregate> for AggregateSummary
+{
+    fn from(aggregate : &PeerAggregate) -> Self
+    {
+        Self {
+            duration_ms : aggregate.duration_ms,
+            peer_count : aggregate.peer_count,
+            active_peer_count : aggregate.active_peer_count,
+            tx_submitted_per_second : aggregate.rate(aggregate.counters.tx_submitted()),
+            vote_tx_submitted_per_second : aggregate.rate(aggregate.counters.vote_tx_submitted),
+            user_tx_submitted_per_second : aggregate.rate(aggregate.counters.user_tx_submitted),
+            connections_per_second : aggregate.rate(aggregate.counters.connection_count),
+            failed_per_second : aggregate.rate(aggregate.counters.failed_count),
+            duplicates_per_second : aggregate.rate(aggregate.counters.duplicate_count),
+            average_fee_lamports : aggregate.average_fee_lamports(),
+            failure_rate : aggregate.failure_rate()
+        }
+    }
+}
+
+// Histogram of the durations in milliseconds of connections from peers with at least min_stake lamports of stake, and
+// less than the min_stake of the next tier
+#[derive(Serialize)]
+struct ConnectionDurationsSummary
+{
+    min_stake : u64,
+
+    histogram : Histogram
+}
+
+#[derive(Serialize)]
+struct RemovedMember
+{
+    group_name : String,
+
+    ip_addr : IpAddr,
+
+    // False if ip_addr was not a member of the group
+    removed : bool
+}
+
+#[derive(Serialize)]
+struct ModeSummary
+{
+    // "enforce" or "monitor"
+    mode : &'static str
+}
+
+#[derive(Serialize)]
+struct LogSummary
+{
+    level : LogLevel,
+
+    // Ordered by ip address
+    debug_addresses : Vec<IpAddr>,
+
+    // Ordered by ip address
+    traced_addresses : Vec<IpAddr>
+}
+
+#[derive(Serialize)]
+struct LeaderSummary
+{
+    // True if currently in our leader slots
+    in_leader_slots : bool,
+
+    // Number of slots until our next leader slots, as most recently reported
+    slots_until_leader : Option<u8>,
+
+    // Timestamp at which slots_until_leader was reported
+    slots_until_leader_timestamp : Option<u64>,
+
+    // slots_until_leader extrapolated to the current time
+    estimated_slots_until_leader : Option<u64>,
+
+    // Leader classification status used to apply outside leader slots classifications
+    leader_classification : Option<bool>,
+
+    // Leader classification status of each outside leader slots window
+    windows : Vec<LeaderWindowSummary>,
+
+    // Fill of the blocks of the most recently completed leader rotation
+    last_slot_fill : Option<SlotFillSummary>
+}
+
+#[derive(Serialize)]
+struct SlotFillSummary
+{
+    start_timestamp : u64,
+
+    end_timestamp : u64,
+
+    slot_count : u64,
+
+    tracked_cu : u64,
+
+    unseen_cu : u64,
+
+    // Fraction of the capacity of the rotation's blocks filled by tx seen submitted by tracked peers
+    tracked_fill : f64,
+
+    // Fraction of the capacity of the rotation's blocks filled by tx never seen submitted
+    unseen_fill : f64
+}
+
+#[derive(Serialize)]
+struct LeaderWindowSummary
+{
+    group_name : String,
+
+    leader_slots : u64,
+
+    leader_classification : Option<bool>
+}
+
+#[derive(Serialize)]
+struct GroupsPage<'a>
+{
+    total : usize,
+
+    offset : usize,
+
+    limit : usize,
+
+    groups : Vec<GroupSummary<'a>>
+}
+
+#[derive(Serialize)]
+struct GroupSummary<'a>
+{
+    name : String,
+
+    member_count : usize,
+
+    // Members added and removed per minute over the group's churn history; None until a churn bucket has completed
+    churn_per_minute : Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata : Option<&'a GroupMetadata>
+}
+
+#[derive(Serialize)]
+struct ChurnSummary
+{
+    churn_per_minute : Option<f64>,
+
+    // Oldest first
+    buckets : Vec<ChurnBucket>
+}
+
+#[derive(Serialize)]
+struct GroupChurn<'a>
+{
+    name : &'a str,
+
+    #[serde(flatten)]
+    churn : &'a ChurnSummary
+}
+
+#[derive(Serialize)]
+struct GroupMembersPage<'a>
+{
+    name : String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata : Option<&'a GroupMetadata>,
+
+    total : usize,
+
+    offset : usize,
+
+    limit : usize,
+
+    members : &'a [GroupMember]
+}
+
+// A peer and its group memberships, answering why an address is in a group
+#[derive(Serialize)]
+struct PeerDetail<'a>
+{
+    ip_addr : IpAddr,
+
+    // None if the address is not tracked as a peer, as for members added via the API that have not connected
+    peer : Option<&'a PeerSummary>,
+
+    // Ordered by group name
+    memberships : Vec<PeerMembership<'a>>
+}
+
+#[derive(Serialize)]
+struct PeerMembership<'a>
+{
+    group_name : &'a str,
+
+    #[serde(flatten)]
+    member : &'a GroupMember
+}
+
+#[derive(Serialize)]
+struct TxSummary
+{
+    signature : String,
+
+    // False if the tx has been flushed, in which case only its first submission is retained, awaiting a late fee
+    current : bool,
+
+    // In order of first submission by each submitter
+    submissions : Vec<SubmissionSummary>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee : Option<FeeSummary>,
+
+    forwarded : bool,
+
+    // The slot in which the tx landed and whether it was one of our own blocks, if reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    landed_slot : Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    landed_in_our_block : Option<bool>
+}
+
+#[derive(Serialize)]
+struct LandedTx
+{
+    signature : String,
+
+    // False if the tx is not current, in which case only a flushed tx awaiting its fee is affected
+    current : bool
+}
+
+#[derive(Serialize)]
+struct SubmissionSummary
+{
+    timestamp : u64,
+
+    submitter : IpAddr,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    submitter_pubkey : Option<String>,
+
+    source : String
+}
+
+#[derive(Serialize)]
+struct FeeSummary
+{
+    total : u64,
+
+    cu_limit : u64,
+
+    cu_used : u64
+}
+
+impl From<&SubmittedTx> for SubmissionSummary
+{
+    fn from(submission : &SubmittedTx) -> Self
+    {
+        Self {
+            timestamp : submission.timestamp,
+            submitter : submission.submitter,
+            submitter_pubkey : submission.submitter_pubkey.map(|pubkey| pubkey.to_string()),
+            source : submission.source.to_string()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct GroupMember
+{
+    ip_addr : IpAddr,
+
+    expiration : u64,
+
+    // The rule that added the member, and the rule that most recently changed its expiration, if any
+    added_by : String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extended_by : Option<String>,
+
+    // The pubkey by which the member was added, for members added by known pubkey
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey : Option<String>
+}
+
+impl ApiRequest
+{
+    pub fn respond(
+        self,
+        response : ApiResponse
+    )
+    {
+        // The connection thread may have timed out and gone away; nothing to do in that case
+        self.response_sender.send(response).ok();
+    }
+}
+
+impl ApiResponse
+{
+    fn ok<T : Serialize>(value : &T) -> Self
+    {
+        match serde_json::to_string(value) {
+            Ok(body) => Self { status : 200, body },
+            Err(e) => Self::error(500, format!("Failed to serialize response: {e}"))
+        }
+    }
+
+    fn error(
+        status : u16,
+        message : String
+    ) -> Self
+    {
+        Self { status, body : serde_json::json!({ "error" : message }).to_string() }
+    }
+}
+
+impl SnapshotCell
+{
+    pub fn new(capabilities : serde_json::Value) -> Self
+    {
+        Self { snapshot : Default::default(), capabilities : RwLock::new(Arc::new(capabilities)) }
+    }
+
+    pub fn publish(
+        &self,
+        snapshot : Snapshot,
+        capabilities : serde_json::Value
+    )
+    {
+        *self.snapshot.write().unwrap() = Some(Arc::new(snapshot));
+        *self.capabilities.write().unwrap() = Arc::new(capabilities);
+    }
+
+    pub fn capabilities(&self) -> Arc<serde_json::Value>
+    {
+        self.capabilities.read().unwrap().clone()
+    }
+
+    pub fn load(&self) -> Option<Arc<Snapshot>>
+    {
+        self.snapshot.read().unwrap().clone()
+    }
+}
+
+// Spawns the API server thread, which accepts HTTP connections, answers queries from the snapshot and capability
+// report held by snapshot_cell, and forwards requests that change state to request_sender
+pub fn spawn_server(
+    listen_address : SocketAddr,
+    snapshot_cell : Arc<SnapshotCell>,
+    request_sender : Sender<ApiRequest>
+) -> Result<(), String>
+{
+    let tcp_listener =
+        TcpListener::bind(listen_address).map_err(|e| format!("Failed to bind API address {listen_address}: {e}"))?;
+
+    std::thread::spawn(move || loop {
+        match tcp_listener.accept() {
+            Ok((tcp_stream, _)) => {
+                let snapshot_cell = snapshot_cell.clone();
+                let request_sender = request_sender.clone();
+                std::thread::spawn(move || handle_connection(tcp_stream, &snapshot_cell, request_sender));
+            },
+            Err(e) => eprintln!("API failed accept because {e}")
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(
+    mut tcp_stream : TcpStream,
+    snapshot_cell : &SnapshotCell,
+    request_sender : Sender<ApiRequest>
+)
+{
+    let response = match read_request(&tcp_stream) {
+        // The capability report is produced at startup, so it is available before any snapshot is
+        Ok((Method::Get, path, _)) if path == "/capabilities" => ApiResponse::ok(&*snapshot_cell.capabilities()),
+        // Tx are not included in snapshots, so are queried on the event processing thread
+        Ok((Method::Get, path, query)) if path.starts_with("/txs/") => {
+            forward(&request_sender, Method::Get, path, query)
+        },
+        Ok((Method::Get, path, query)) => match snapshot_cell.load() {
+            Some(snapshot) => answer_query(&snapshot, &path, &query),
+            None => ApiResponse::error(503, "No snapshot is available yet".to_string())
+        },
+        Ok((Method::Post, path, query)) => forward(&request_sender, Method::Post, path, query),
+        Err(response) => response
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Error"
+    };
+
+    write!(
+        tcp_stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )
+    .ok();
+}
+
+// Forwards a request to the event processing thread and waits for its response
+fn forward(
+    request_sender : &Sender<ApiRequest>,
+    method : Method,
+    path : String,
+    query : HashMap<String, String>
+) -> ApiResponse
+{
+    let (response_sender, response_receiver) = bounded(1);
+
+    if request_sender.send(ApiRequest { method, path, query, response_sender }).is_err() {
+        return ApiResponse::error(503, "Event processing has stopped".to_string());
+    }
+
+    response_receiver
+        .recv_timeout(std::time::Duration::from_millis(RESPONSE_TIMEOUT_MS))
+        .unwrap_or_else(|_| ApiResponse::error(503, "Timed out waiting for response".to_string()))
+}
+
+// Reads an HTTP request, returning its method, path, and query parameters
+fn read_request(tcp_stream : &TcpStream) -> Result<(Method, String, HashMap<String, String>), ApiResponse>
+{
+    // A client that never finishes sending its request must not hold its connection's thread forever
+    tcp_stream.set_read_timeout(Some(std::time::Duration::from_millis(REQUEST_TIMEOUT_MS))).ok();
+
+    let mut reader = BufReader::new(tcp_stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).map_err(|e| ApiResponse::error(400, format!("Failed to read request: {e}")))?;
+
+    // Skip headers; requests have no meaningful body
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header) {
+            Ok(0) => break,
+            Ok(_) if header.trim().is_empty() => break,
+            Ok(_) => (),
+            Err(e) => return Err(ApiResponse::error(400, format!("Failed to read request: {e}")))
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+
+    let method = match parts.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        _ => return Err(ApiResponse::error(405, "Only GET and POST are supported".to_string()))
+    };
+
+    let target = parts.next().ok_or_else(|| ApiResponse::error(400, "Missing request target".to_string()))?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+
+    Ok((method, path.to_string(), query))
+}
+
+// Builds a Snapshot from the current state
+pub fn snapshot(state : &State) -> Snapshot
+{
+    let mut peers =
+        state.peers.iter().map(|(ip_addr, peer)| state.peer_summary(ip_addr, peer)).collect::<Vec<PeerSummary>>();
+
+    peers.sort_by_key(|peer| peer.ip_addr);
+
+    let groups = state
+        .group_member_counts()
+        .into_iter()
+        .filter_map(|(name, _)| {
+            let mut members = state
+                .group_members(&name)?
+                .into_iter()
+                .map(|(ip_addr, membership)| GroupMember {
+                    ip_addr,
+                    expiration : membership.expiration,
+                    added_by : membership.added_by.to_string(),
+                    extended_by : membership.extended_by.map(|rule| rule.to_string()),
+                    pubkey : membership.pubkey.map(|pubkey| pubkey.to_string())
+                })
+                .collect::<Vec<GroupMember>>();
+            members.sort_by_key(|member| member.ip_addr);
+            Some((name, members))
+        })
+        .collect();
+
+    Snapshot {
+        status : StatusSummary {
+            snapshot_timestamp : state.most_recent_timestamp,
+            peer_count : state.peers.len(),
+            tx_count : state.current_tx.len(),
+            dropped_sink_event_count : state.sink.dropped(),
+            ignored_event_count : state.ignored_event_count,
+            deprecated_counts : state
+                .deprecated_counts
+                .iter()
+                .map(|(source, count)| (source.to_string(), *count))
+                .collect(),
+            unseen_fee_count : state.unseen_fee_count,
+            unseen_fee_lamports : state.unseen_fee_lamports,
+            late_fee_count : state.late_fee_count,
+            fee_stake_excluded_count : state.fee_stake_excluded_count,
+            clock_jump_count : state.clock_jump_count,
+            clock_offset_ms : state.clock_offset_ms,
+            internal_clock_timestamp : state.internal_clock_timestamp,
+            system_clock_timestamp : state.system_clock_timestamp,
+            landed_elsewhere_count : state.landed_elsewhere_count,
+            forwarded_landed_elsewhere_count : state.forwarded_landed_elsewhere_count,
+            rate_limited_event_count : state.ingest_counters.rate_limited_event_count.load(Ordering::Relaxed),
+            rate_limited_disconnect_count : state.ingest_counters.rate_limited_disconnect_count.load(Ordering::Relaxed),
+            rejected_connection_count : state.ingest_counters.rejected_connection_count.load(Ordering::Relaxed),
+            open_connection_count : state.ingest_counters.open_connection_count.load(Ordering::Relaxed),
+            idle_disconnect_count : state.ingest_counters.idle_disconnect_count.load(Ordering::Relaxed),
+            connection_panic_count : state.ingest_counters.connection_panic_count.load(Ordering::Relaxed),
+            listener_restart_count : state.ingest_counters.listener_restart_count.load(Ordering::Relaxed)
+        },
+        mode : mode_summary(state),
+        log : log_summary(state),
+        aggregates : AggregatesSummary {
+            staked : (&state.staked_aggregate).into(),
+            unstaked : (&state.unstaked_aggregate).into()
+        },
+        classifications : state
+            .config
+            .classifications()
+            .into_iter()
+            .map(|classification| ClassificationSummary {
+                name : classification.name.clone(),
+                tracked_ip_count : classification.tracked_ip_count(),
+                add_count : classification.add_count(),
+                alert_count : classification.alert_count,
+                merged_value_count : classification.merged_value_count,
+                last_evaluation_us : classification.last_evaluation_us,
+                max_evaluation_us : classification.max_evaluation_us,
+                last_scanned_ip_count : classification.last_scanned_ip_count,
+                deferred_count : classification.deferred_count,
+                evaluation_interval_ms : classification.evaluation_interval_ms
+            })
+            .collect(),
+        leader : LeaderSummary {
+            in_leader_slots : state.in_leader_slots,
+            slots_until_leader : state.slots_until_leader.map(|(slots, _)| slots),
+            slots_until_leader_timestamp : state.slots_until_leader.map(|(_, timestamp)| timestamp),
+            estimated_slots_until_leader : state.estimated_slots_until_leader(state.most_recent_timestamp),
+            leader_classification : state.leader_status,
+            windows : state
+                .config
+                .outside_leader_slots
+                .iter()
+                .flat_map(|outside_leader_slots| outside_leader_slots.windows.iter().flatten())
+                .map(|window| LeaderWindowSummary {
+                    group_name : window.group_name.clone(),
+                    leader_slots : window.leader_slots,
+                    leader_classification : window.leader_status
+                })
+                .collect(),
+            last_slot_fill : state.last_slot_fill.as_ref().map(|slot_fill| SlotFillSummary {
+                start_timestamp : slot_fill.start_timestamp,
+                end_timestamp : slot_fill.end_timestamp,
+                slot_count : slot_fill.slot_count,
+                tracked_cu : slot_fill.tracked_cu,
+                unseen_cu : slot_fill.unseen_cu,
+                tracked_fill : slot_fill.fill(slot_fill.tracked_cu, state.block_cu_limit()),
+                unseen_fill : slot_fill.fill(slot_fill.unseen_cu, state.block_cu_limit())
+            })
+        },
+        connection_durations : state
+            .stake_tiers
+            .iter()
+            .zip(&state.connection_durations)
+            .map(|(min_stake, histogram)| ConnectionDurationsSummary {
+                min_stake : *min_stake,
+                histogram : histogram.clone()
+            })
+            .collect(),
+        peers,
+        groups,
+        group_metadata : state.group_metadata.clone(),
+        churn : state
+            .group_churn
+            .iter()
+            .map(|(name, churn)| {
+                (name.clone(), ChurnSummary {
+                    churn_per_minute : churn.churn_per_minute(),
+                    buckets : churn.buckets().iter().cloned().collect()
+                })
+            })
+            .collect()
+    }
+}
+
+// Handles a request that changes the current state, or queries tx
+pub fn handle(
+    state : &mut State,
+    request : &ApiRequest
+) -> ApiResponse
+{
+    let path = request.path.trim_end_matches('/').split('/').skip(1).collect::<Vec<&str>>();
+
+    let result = match (request.method, path.as_slice()) {
+        (Method::Get, ["txs", signature]) => tx(state, signature),
+        (Method::Get, _) => Err(ApiResponse::error(404, format!("No such resource: {}", request.path))),
+        (Method::Post, ["mode"]) => set_mode(state, &request.query),
+        (Method::Post, ["log"]) => set_log(state, &request.query),
+        (Method::Post, ["trace"]) => set_trace(state, &request.query),
+        (Method::Post, ["groups", name, "remove"]) => remove_group_member(state, name, &request.query),
+        (Method::Post, ["txs", signature, "landed"]) => set_landed(state, signature, &request.query),
+        (Method::Post, _) => Err(ApiResponse::error(405, format!("Resource does not support POST: {}", request.path)))
+    };
+
+    result.unwrap_or_else(|response| response)
+}
+
+// Answers a query from a snapshot
+fn answer_query(
+    snapshot : &Snapshot,
+    path : &str,
+    query : &HashMap<String, String>
+) -> ApiResponse
+{
+    let result = match path.trim_end_matches('/').split('/').skip(1).collect::<Vec<&str>>().as_slice() {
+        ["status"] => Ok(ApiResponse::ok(&snapshot.status)),
+        ["mode"] => Ok(ApiResponse::ok(&snapshot.mode)),
+        ["log"] => Ok(ApiResponse::ok(&snapshot.log)),
+        ["aggregates"] => Ok(ApiResponse::ok(&snapshot.aggregates)),
+        ["classifications"] => Ok(ApiResponse::ok(&snapshot.classifications)),
+        ["leader"] => Ok(ApiResponse::ok(&snapshot.leader)),
+        ["connection_durations"] => Ok(ApiResponse::ok(&snapshot.connection_durations)),
+        ["peers"] => peers(snapshot, query),
+        ["peers", ip_addr] => peer(snapshot, ip_addr),
+        ["groups"] => groups(snapshot, query),
+        ["groups", name] => group_members(snapshot, name, query),
+        ["groups", name, "churn"] => group_churn(snapshot, name),
+        _ => Err(ApiResponse::error(404, format!("No such resource: {path}")))
+    };
+
+    result.unwrap_or_else(|response| response)
+}
+
+fn query_value<T : std::str::FromStr>(
+    query : &HashMap<String, String>,
+    key : &str
+) -> Result<Option<T>, ApiResponse>
+{
+    query
+        .get(key)
+        .map(|value| value.parse::<T>().map_err(|_| ApiResponse::error(400, format!("Invalid {key}: {value}"))))
+        .transpose()
+}
+
+// Returns (offset, limit) as given by query parameters
+fn page(query : &HashMap<String, String>) -> Result<(usize, usize), ApiResponse>
+{
+    let offset = query_value::<usize>(query, "offset")?.unwrap_or(0);
+
+    let limit = query_value::<usize>(query, "limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+    if (limit == 0) || (limit > MAX_PAGE_LIMIT) {
+        return Err(ApiResponse::error(400, format!("Invalid limit {limit}; must be between 1 and {MAX_PAGE_LIMIT}")));
+    }
+
+    Ok((offset, limit))
+}
+
+fn mode_summary(state : &State) -> ModeSummary
+{
+    ModeSummary { mode : if state.enforcing { "enforce" } else { "monitor" } }
+}
+
+// Switches between enforce and monitor mode according to the mode query parameter ("enforce" or "monitor")
+fn set_mode(
+    state : &mut State,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let enforcing = match query.get("mode").map(|mode| mode.as_str()) {
+        Some("enforce") => true,
+        Some("monitor") => false,
+        _ => return Err(ApiResponse::error(400, "mode must be enforce or monitor".to_string()))
+    };
+
+    state.set_enforcing(enforcing, state.most_recent_timestamp);
+
+    Ok(ApiResponse::ok(&mode_summary(state)))
+}
+
+fn log_summary(state : &State) -> LogSummary
+{
+    let sorted = |addresses : &HashSet<IpAddr>| {
+        let mut addresses = addresses.iter().cloned().collect::<Vec<IpAddr>>();
+        addresses.sort();
+        addresses
+    };
+
+    LogSummary {
+        level : log::level(),
+        debug_addresses : sorted(&state.debug_addresses),
+        traced_addresses : sorted(&state.traced_addresses)
+    }
+}
+
+// Changes the log level to the level query parameter ("quiet", "normal", or "debug") and replaces the debug addresses
+// with the comma separated debug_addresses query parameter (which may be empty), each if present
+fn set_log(
+    state : &mut State,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let level = query_value::<LogLevel>(query, "level")?;
+
+    let debug_addresses = query
+        .get("debug_addresses")
+        .map(|debug_addresses| {
+            debug_addresses
+                .split(',')
+                .filter(|address| !address.is_empty())
+                .map(|address| {
+                    address
+                        .parse::<IpAddr>()
+                        .map_err(|_| ApiResponse::error(400, format!("Invalid debug address: {address}")))
+                })
+                .collect::<Result<HashSet<IpAddr>, ApiResponse>>()
+        })
+        .transpose()?;
+
+    if let Some(level) = level {
+        log::set_level(level);
+        println!("Log level changed to {} via the API", level.as_str());
+    }
+
+    if let Some(debug_addresses) = debug_addresses {
+        state.debug_addresses = debug_addresses;
+    }
+
+    Ok(ApiResponse::ok(&log_summary(state)))
+}
+
+// Starts tracing the address given by the ip_addr query parameter, or stops if the enabled query parameter is false
+fn set_trace(
+    state : &mut State,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let ip_addr = query_value::<IpAddr>(query, "ip_addr")?
+        .ok_or_else(|| ApiResponse::error(400, "ip_addr is required".to_string()))?;
+
+    let enabled = query_value::<bool>(query, "enabled")?.unwrap_or(true);
+
+    state.set_traced(ip_addr, enabled);
+
+    Ok(ApiResponse::ok(&log_summary(state)))
+}
+
+// Describes a tx by its signature: every submission of it and its fee, if known
+fn tx(
+    state : &State,
+    signature : &str
+) -> Result<ApiResponse, ApiResponse>
+{
+    let signature = Signature::from_str(signature)
+        .map_err(|_| ApiResponse::error(400, format!("Invalid signature: {signature}")))?;
+
+    let (tx, current) = match (state.current_tx.get(&signature), state.flushed_tx.get(&signature)) {
+        (Some(tx), _) => (tx, true),
+        (None, Some(tx)) => (tx, false),
+        (None, None) => return Err(ApiResponse::error(404, format!("No such tx: {signature}")))
+    };
+
+    let summary = TxSummary {
+        signature : signature.to_string(),
+        current,
+        submissions : tx.submissions.iter().map(SubmissionSummary::from).collect(),
+        fee : tx.fee.as_ref().map(|fee| FeeSummary {
+            total : fee.total,
+            cu_limit : fee.cu_limit,
+            cu_used : fee.cu_used
+        }),
+        forwarded : tx.forwarded,
+        landed_slot : tx.landed.map(|landed| landed.slot),
+        landed_in_our_block : tx.landed.map(|landed| landed.our_block)
+    };
+
+    Ok(ApiResponse::ok(&summary))
+}
+
+// Records that a tx landed in the block of the slot query parameter, which is one of our own if the our_block query
+// parameter is true, as for landing reported from RPC
+fn set_landed(
+    state : &mut State,
+    signature : &str,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let parsed = Signature::from_str(signature)
+        .map_err(|_| ApiResponse::error(400, format!("Invalid signature: {signature}")))?;
+
+    let slot =
+        query_value::<u64>(query, "slot")?.ok_or_else(|| ApiResponse::error(400, "slot is required".to_string()))?;
+
+    let our_block = query_value::<bool>(query, "our_block")?
+        .ok_or_else(|| ApiResponse::error(400, "our_block is required".to_string()))?;
+
+    let current = state.landed(&parsed, slot, our_block);
+
+    Ok(ApiResponse::ok(&LandedTx { signature : signature.to_string(), current }))
+}
+
+// Removes the member given by the ip_addr query parameter from a classification group
+fn remove_group_member(
+    state : &mut State,
+    group_name : &str,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let ip_addr = query_value::<IpAddr>(query, "ip_addr")?
+        .ok_or_else(|| ApiResponse::error(400, "ip_addr is required".to_string()))?;
+
+    let removed = state.remove_group_member(group_name, &ip_addr);
+
+    Ok(ApiResponse::ok(&RemovedMember { group_name : group_name.to_string(), ip_addr, removed }))
+}
+
+// Lists peers, optionally filtered by group membership (group), stake range (min_stake, max_stake), most recent
+// event timestamp (seen_since), and first event timestamp (first_seen_since), paginated by offset and limit.  Peers
+// are ordered by ip address so that pages are stable, or by descending score if order is "score".
+fn peers(
+    snapshot : &Snapshot,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let group = query.get("group");
+    let min_stake = query_value::<u64>(query, "min_stake")?;
+    let max_stake = query_value::<u64>(query, "max_stake")?;
+    let seen_since = query_value::<u64>(query, "seen_since")?;
+    let first_seen_since = query_value::<u64>(query, "first_seen_since")?;
+    let order_by_score = match query.get("order").map(|order| order.as_str()) {
+        None | Some("ip") => false,
+        Some("score") => true,
+        Some(order) => return Err(ApiResponse::error(400, format!("Invalid order {order}; must be ip or score")))
+    };
+    let (offset, limit) = page(query)?;
+
+    let mut peers = snapshot
+        .peers
+        .iter()
+        .filter(|peer| {
+            group.map(|group| peer.groups.contains(group)).unwrap_or(true) &&
+                min_stake.map(|min_stake| peer.stake >= min_stake).unwrap_or(true) &&
+                max_stake.map(|max_stake| peer.stake <= max_stake).unwrap_or(true) &&
+                seen_since.map(|seen_since| peer.most_recent_timestamp >= seen_since).unwrap_or(true) &&
+                first_seen_since.map(|first_seen_since| peer.first_timestamp >= first_seen_since).unwrap_or(true)
+        })
+        .collect::<Vec<&PeerSummary>>();
+
+    if order_by_score {
+        // Stable sort, so peers with equal scores remain ordered by ip address
+        peers.sort_by(|a, b| b.score.total_cmp(&a.score));
+    }
+
+    let total = peers.len();
+
+    let peers = peers.into_iter().skip(offset).take(limit).collect();
+
+    Ok(ApiResponse::ok(&PeersPage { total, offset, limit, peers }))
+}
+
+// Lists groups with their member counts, ordered by name and paginated by offset and limit
+fn groups(
+    snapshot : &Snapshot,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let (offset, limit) = page(query)?;
+
+    let mut groups = snapshot
+        .groups
+        .iter()
+        .map(|(name, members)| GroupSummary {
+            name : name.clone(),
+            member_count : members.len(),
+            churn_per_minute : snapshot.churn.get(name).and_then(|churn| churn.churn_per_minute),
+            metadata : snapshot.group_metadata.get(name).map(|metadata| metadata.as_ref())
+        })
+        .collect::<Vec<GroupSummary>>();
+
+    groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let total = groups.len();
+
+    let groups = groups.into_iter().skip(offset).take(limit).collect();
+
+    Ok(ApiResponse::ok(&GroupsPage { total, offset, limit, groups }))
+}
+
+// Describes a single peer and the groups it is a member of, with the rule that added each membership
+fn peer(
+    snapshot : &Snapshot,
+    ip_addr : &str
+) -> Result<ApiResponse, ApiResponse>
+{
+    let ip_addr =
+        ip_addr.parse::<IpAddr>().map_err(|_| ApiResponse::error(400, format!("Invalid ip address: {ip_addr}")))?;
+
+    let peer =
+        snapshot.peers.binary_search_by_key(&ip_addr, |peer| peer.ip_addr).ok().map(|index| &snapshot.peers[index]);
+
+    let mut memberships = snapshot
+        .groups
+        .iter()
+        .filter_map(|(group_name, members)| {
+            let index = members.binary_search_by_key(&ip_addr, |member| member.ip_addr).ok()?;
+            Some(PeerMembership { group_name, member : &members[index] })
+        })
+        .collect::<Vec<PeerMembership>>();
+
+    if peer.is_none() && memberships.is_empty() {
+        return Err(ApiResponse::error(404, format!("No such peer: {ip_addr}")));
+    }
+
+    memberships.sort_by_key(|membership| membership.group_name);
+
+    Ok(ApiResponse::ok(&PeerDetail { ip_addr, peer, memberships }))
+}
+
+// Returns the churn history of a group: the numbers of its members added, removed, and expired in each bucket
+fn group_churn(
+    snapshot : &Snapshot,
+    name : &str
+) -> Result<ApiResponse, ApiResponse>
+{
+    let churn = snapshot.churn.get(name).ok_or_else(|| ApiResponse::error(404, format!("No such group: {name}")))?;
+
+    Ok(ApiResponse::ok(&GroupChurn { name, churn }))
+}
+
+// Lists the members of a group, ordered by ip address and paginated by offset and limit
+fn group_members(
+    snapshot : &Snapshot,
+    name : &str,
+    query : &HashMap<String, String>
+) -> Result<ApiResponse, ApiResponse>
+{
+    let (offset, limit) = page(query)?;
+
+    let members = snapshot.groups.get(name).ok_or_else(|| ApiResponse::error(404, format!("No such group: {name}")))?;
+
+    let total = members.len();
+
+    let start = offset.min(total);
+
+    let members = &members[start..(start + limit).min(total)];
+
+    Ok(ApiResponse::ok(&GroupMembersPage {
+        name : name.to_string(),
+        metadata : snapshot.group_metadata.get(name).map(|metadata| metadata.as_ref()),
+        total,
+        offset,
+        limit,
+        members
+    }))
+}
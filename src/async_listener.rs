@@ -0,0 +1,167 @@
+// An ingest listener that multiplexes all connections on a small async runtime, rather than handling each connection
+// in a thread of its own, for deployments aggregating many senders.  Messages are handled exactly as by the threaded
+// listener.
+
+use crate::legacy::LegacyTxIngestMsg;
+use crate::listener::{self, ConnectionHandler, IngestSources, MessageLayout};
+use bincode::Options;
+use crossbeam::channel::Sender;
+use solana_sdk::txingest::TxIngestMsg;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+
+const READ_BUFFER_SIZE : usize = 64 * 1024;
+
+// Spawns a thread running an async runtime of worker_threads threads, which accepts connections on tcp_listener and
+// handles each in a task of its own.  As with the threaded listener, the accepting thread is watched and restarted if
+// it dies; connections open at the time are closed, and are expected to be re-established by their senders.
+pub fn spawn_listener(
+    tcp_listener : std::net::TcpListener,
+    layout : MessageLayout,
+    ingest_sources : Arc<IngestSources>,
+    sender : Sender<(Arc<str>, TxIngestMsg)>,
+    worker_threads : usize
+)
+{
+    listener::supervise(tcp_listener, ingest_sources.counters.clone(), move |accept_listener| {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(worker_threads)
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("Failed to build ingest runtime");
+        runtime.block_on(accept(accept_listener, layout, ingest_sources.clone(), sender.clone()));
+    });
+}
+
+async fn accept(
+    tcp_listener : std::net::TcpListener,
+    layout : MessageLayout,
+    ingest_sources : Arc<IngestSources>,
+    sender : Sender<(Arc<str>, TxIngestMsg)>
+)
+{
+    let tcp_listener = match tcp_listener.set_nonblocking(true).and_then(|()| TcpListener::from_std(tcp_listener)) {
+        Ok(tcp_listener) => tcp_listener,
+        Err(e) => {
+            eprintln!("Failed to register ingest listener with async runtime because {e}");
+            return;
+        }
+    };
+
+    loop {
+        let (tcp_stream, sender_address) = match tcp_listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Failed accept because {e}");
+                continue;
+            }
+        };
+
+        // If the sender is not allowed, the stream is dropped, closing the connection
+        let Some(source) = listener::connection_source(&ingest_sources, &sender_address)
+        else {
+            continue;
+        };
+
+        let handler = ConnectionHandler::new(sender_address, source, ingest_sources.clone(), sender.clone());
+        let counters = ingest_sources.counters.clone();
+
+        counters.open_connection_count.fetch_add(1, Ordering::Relaxed);
+
+        let connection = tokio::spawn(handle_connection(tcp_stream, layout, ingest_sources.idle_timeout, handler));
+
+        // A panic ends only its own task, and so only its own connection
+        tokio::spawn(async move {
+            let result = connection.await;
+            counters.open_connection_count.fetch_sub(1, Ordering::Relaxed);
+            if result.is_err() {
+                counters.connection_panic_count.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Ingest connection task for {sender_address} panicked; connection closed");
+            }
+        });
+    }
+}
+
+// Reads from tcp_stream, handling every complete message read, until the connection closes or is to be closed
+async fn handle_connection(
+    mut tcp_stream : TcpStream,
+    layout : MessageLayout,
+    idle_timeout : Option<Duration>,
+    mut handler : ConnectionHandler
+)
+{
+    let options = bincode::DefaultOptions::new();
+
+    // Bytes read but not yet decoded, which begin with a partial message
+    let mut pending = Vec::<u8>::with_capacity(READ_BUFFER_SIZE);
+
+    let mut read_buffer = vec![0_u8; READ_BUFFER_SIZE];
+
+    loop {
+        let read = tcp_stream.read(&mut read_buffer);
+
+        let result = match idle_timeout {
+            Some(idle_timeout) => match tokio::time::timeout(idle_timeout, read).await {
+                Ok(result) => result,
+                Err(_) => {
+                    handler.idle();
+                    return;
+                }
+            },
+            None => read.await
+        };
+
+        match result {
+            // The sender closed the connection
+            Ok(0) => return,
+            Ok(read_count) => pending.extend_from_slice(&read_buffer[..read_count]),
+            Err(e) => {
+                eprintln!("Failed read because {e}; closing connection");
+                return;
+            }
+        }
+
+        let mut decoded_count = 0;
+
+        loop {
+            let mut remaining = &pending[decoded_count..];
+
+            let result = match layout {
+                MessageLayout::Current => options.deserialize_from::<_, TxIngestMsg>(&mut remaining),
+                MessageLayout::Legacy => {
+                    options.deserialize_from::<_, LegacyTxIngestMsg>(&mut remaining).map(Into::into)
+                },
+            };
+
+            match result {
+                Ok(tx_ingest_msg) => {
+                    decoded_count = pending.len() - remaining.len();
+                    if !handler.receive(tx_ingest_msg) {
+                        return;
+                    }
+                },
+                // The rest of the message has not been read yet
+                Err(e) if is_partial(&e) => break,
+                Err(e) => {
+                    eprintln!("Failed deserialize because {e}; closing connection");
+                    return;
+                }
+            }
+        }
+
+        pending.drain(..decoded_count);
+    }
+}
+
+// Returns true if a deserialization error is due to a message being incomplete
+fn is_partial(e : &bincode::Error) -> bool
+{
+    match e.as_ref() {
+        bincode::ErrorKind::Io(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+        _ => false
+    }
+}
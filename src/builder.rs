@@ -0,0 +1,441 @@
+// Typed builders for constructing configs programmatically, as an alternative to writing config JSON.  Required
+// fields are arguments of the constructors, so that an incomplete policy does not compile; everything else is
+// optional, and absent fields take the same defaults as absent JSON fields.  ConfigBuilder::build validates the
+// result exactly as a config file is validated.
+
+use crate::classification::{Classification, FeeCreditPolicy, ZeroFeeAttribution};
+use crate::config::{Config, ExtensionEvents, GroupExpirationLimits, GroupTtlPolicy, PubkeyClassification};
+use crate::threshold::{AutoTune, Threshold, ThresholdType, ValueOperation};
+use solana_sdk::pubkey::Pubkey;
+use std::net::IpAddr;
+
+pub struct ConfigBuilder
+{
+    config : Config
+}
+
+impl Default for ConfigBuilder
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl ConfigBuilder
+{
+    // Starts from an empty config, in which no classification is done
+    pub fn new() -> Self
+    {
+        Self { config : Config::default() }
+    }
+
+    pub fn failed_exceeded_quic_connections(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.failed_exceeded_quic_connections = Some(classification.build());
+        self
+    }
+
+    pub fn useless_quic_connections(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.useless_quic_connections = Some(classification.build());
+        self
+    }
+
+    pub fn fee_lamports_submitted(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.fee_lamports_submitted = Some(classification.build());
+        self
+    }
+
+    pub fn fee_microlamports_per_cu_limit(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.fee_microlamports_per_cu_limit = Some(classification.build());
+        self
+    }
+
+    pub fn fee_microlamports_per_cu_used(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.fee_microlamports_per_cu_used = Some(classification.build());
+        self
+    }
+
+    pub fn zero_fee_share(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.zero_fee_share = Some(classification.build());
+        self
+    }
+
+    // Leaves peers with stake outside of min_stake..=max_stake out of every fee classification; None leaves a bound
+    // open
+    pub fn fee_stake_range(
+        mut self,
+        min_stake : Option<u64>,
+        max_stake : Option<u64>
+    ) -> Self
+    {
+        self.config.fee_min_stake = min_stake;
+        self.config.fee_max_stake = max_stake;
+        self
+    }
+
+    pub fn pubkey_changes(
+        mut self,
+        classification : ClassificationBuilder
+    ) -> Self
+    {
+        self.config.pubkey_changes = Some(classification.build());
+        self
+    }
+
+    // Adds pubkey to group_name (or "known_pubkeys" if None) whenever it connects
+    pub fn known_pubkey(
+        mut self,
+        pubkey : Pubkey,
+        group_name : Option<&str>,
+        group_expiration_seconds : Option<u64>
+    ) -> Self
+    {
+        self.config.known_pubkeys.get_or_insert_with(Vec::new).push(PubkeyClassification {
+            group_name : group_name.map(str::to_string),
+            group_expiration_seconds,
+            pubkey : pubkey.to_string()
+        });
+        self
+    }
+
+    pub fn ignored_address(
+        mut self,
+        ip_addr : IpAddr
+    ) -> Self
+    {
+        self.config.ignored_addresses.get_or_insert_with(Vec::new).push(ip_addr);
+        self
+    }
+
+    pub fn group_ttl_policy(
+        mut self,
+        group_name : &str,
+        policy : GroupTtlPolicy
+    ) -> Self
+    {
+        self.config.group_ttl_policies.get_or_insert_with(Default::default).insert(group_name.to_string(), policy);
+        self
+    }
+
+    pub fn group_expiration_limits(
+        mut self,
+        group_name : &str,
+        limits : GroupExpirationLimits
+    ) -> Self
+    {
+        self.config.group_expiration_limits.get_or_insert_with(Default::default).insert(group_name.to_string(), limits);
+        self
+    }
+
+    pub fn group_extension_events(
+        mut self,
+        group_name : &str,
+        extension_events : ExtensionEvents
+    ) -> Self
+    {
+        self.config
+            .group_extension_events
+            .get_or_insert_with(Default::default)
+            .insert(group_name.to_string(), extension_events);
+        self
+    }
+
+    pub fn periodic_interval_ms(
+        mut self,
+        periodic_interval_ms : u64
+    ) -> Self
+    {
+        self.config.periodic_interval_ms = Some(periodic_interval_ms);
+        self
+    }
+
+    pub fn classification_interval_ms(
+        mut self,
+        classification_interval_ms : u64
+    ) -> Self
+    {
+        self.config.classification_interval_ms = Some(classification_interval_ms);
+        self
+    }
+
+    pub fn monitor_mode(
+        mut self,
+        monitor_mode : bool
+    ) -> Self
+    {
+        self.config.monitor_mode = Some(monitor_mode);
+        self
+    }
+
+    // Sets any other config field directly; every field of Config is public
+    pub fn configure(
+        mut self,
+        configure : impl FnOnce(&mut Config)
+    ) -> Self
+    {
+        configure(&mut self.config);
+        self
+    }
+
+    // Validates and returns the config
+    pub fn build(self) -> Result<Config, String>
+    {
+        let mut config = self.config;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+pub struct ClassificationBuilder
+{
+    classification : Classification
+}
+
+impl Default for ClassificationBuilder
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl ClassificationBuilder
+{
+    // Starts a classification with no thresholds; at least one must be added for the config to validate
+    pub fn new() -> Self
+    {
+        Self { classification : Classification::default() }
+    }
+
+    pub fn group_name(
+        mut self,
+        group_name : &str
+    ) -> Self
+    {
+        self.classification.group_name = Some(group_name.to_string());
+        self
+    }
+
+    pub fn group_expiration_seconds(
+        mut self,
+        group_expiration_seconds : u64
+    ) -> Self
+    {
+        self.classification.group_expiration_seconds = Some(group_expiration_seconds);
+        self
+    }
+
+    pub fn threshold(
+        mut self,
+        threshold : ThresholdBuilder
+    ) -> Self
+    {
+        self.classification.thresholds.push(threshold.build());
+        self
+    }
+
+    pub fn overload_threshold(
+        mut self,
+        threshold : ThresholdBuilder
+    ) -> Self
+    {
+        self.classification.overload_thresholds.get_or_insert_with(Vec::new).push(threshold.build());
+        self
+    }
+
+    // Accumulates only values from the named ingest source; may be called for each of several sources
+    pub fn source(
+        mut self,
+        source : &str
+    ) -> Self
+    {
+        self.classification.sources.get_or_insert_with(Vec::new).push(source.to_string());
+        self
+    }
+
+    pub fn attribution_window_ms(
+        mut self,
+        attribution_window_ms : u64
+    ) -> Self
+    {
+        self.classification.attribution_window_ms = Some(attribution_window_ms);
+        self
+    }
+
+    pub fn fee_credit_policy(
+        mut self,
+        fee_credit_policy : FeeCreditPolicy
+    ) -> Self
+    {
+        self.classification.fee_credit_policy = Some(fee_credit_policy);
+        self
+    }
+
+    pub fn zero_fee_attribution(
+        mut self,
+        zero_fee_attribution : ZeroFeeAttribution
+    ) -> Self
+    {
+        self.classification.zero_fee_attribution = Some(zero_fee_attribution);
+        self
+    }
+
+    pub fn decay_half_life_ms(
+        mut self,
+        decay_half_life_ms : u64
+    ) -> Self
+    {
+        self.classification.decay_half_life_ms = Some(decay_half_life_ms);
+        self
+    }
+
+    pub fn max_values_per_ip(
+        mut self,
+        max_values_per_ip : usize
+    ) -> Self
+    {
+        self.classification.max_values_per_ip = Some(max_values_per_ip);
+        self
+    }
+
+    pub fn attribute_by_pubkey(
+        mut self,
+        attribute_by_pubkey : bool
+    ) -> Self
+    {
+        self.classification.attribute_by_pubkey = Some(attribute_by_pubkey);
+        self
+    }
+
+    pub fn evaluation_interval_ms(
+        mut self,
+        evaluation_interval_ms : u64
+    ) -> Self
+    {
+        self.classification.evaluation_interval_ms = Some(evaluation_interval_ms);
+        self
+    }
+
+    // Sets any other classification field directly
+    pub fn configure(
+        mut self,
+        configure : impl FnOnce(&mut Classification)
+    ) -> Self
+    {
+        configure(&mut self.classification);
+        self
+    }
+
+    pub fn build(self) -> Classification
+    {
+        self.classification
+    }
+}
+
+pub struct ThresholdBuilder
+{
+    threshold : Threshold
+}
+
+impl ThresholdBuilder
+{
+    // Starts a threshold met when the values of the last duration_ms, combined by value_operation, compare to value
+    // by threshold_type
+    pub fn new(
+        value_operation : ValueOperation,
+        threshold_type : ThresholdType,
+        value : i64,
+        duration_ms : u64
+    ) -> Self
+    {
+        Self { threshold : Threshold::new(value_operation, threshold_type, value, duration_ms) }
+    }
+
+    pub fn group_name(
+        mut self,
+        group_name : &str
+    ) -> Self
+    {
+        self.threshold.group_name = Some(group_name.to_string());
+        self
+    }
+
+    pub fn group_expiration_seconds(
+        mut self,
+        group_expiration_seconds : u64
+    ) -> Self
+    {
+        self.threshold.group_expiration_seconds = Some(group_expiration_seconds);
+        self
+    }
+
+    // Applies the threshold only to peers with stake within low_stake..=high_stake; None leaves a bound open
+    pub fn stake_range(
+        mut self,
+        low_stake : Option<u64>,
+        high_stake : Option<u64>
+    ) -> Self
+    {
+        self.threshold.low_stake = low_stake;
+        self.threshold.high_stake = high_stake;
+        self
+    }
+
+    pub fn min_value_count(
+        mut self,
+        min_value_count : u64
+    ) -> Self
+    {
+        self.threshold.min_value_count = Some(min_value_count);
+        self
+    }
+
+    pub fn continue_after_match(
+        mut self,
+        continue_after_match : bool
+    ) -> Self
+    {
+        self.threshold.continue_after_match = Some(continue_after_match);
+        self
+    }
+
+    pub fn auto_tune(
+        mut self,
+        auto_tune : AutoTune
+    ) -> Self
+    {
+        self.threshold.auto_tune = Some(auto_tune);
+        self
+    }
+
+    pub fn build(self) -> Threshold
+    {
+        self.threshold
+    }
+}
@@ -0,0 +1,267 @@
+use crate::sink::{GroupChange, Sink};
+use crate::state::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_CANARY_EVENTS_PER_SECOND : u64 = 10;
+const DEFAULT_CANARY_INTERVAL_MS : u64 = 10 * 60 * 1000; // 10 minutes
+const DEFAULT_CANARY_TIMEOUT_MS : u64 = 60 * 1000; // 1 minute
+
+// Source name given to the synthetic events of canaries
+pub const CANARY_SOURCE : &str = "canary";
+
+// The synthetic traffic of a canary
+#[derive(Clone, Copy, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanaryBehavior
+{
+    // Connections that fail, as counted by the failed_exceeded_quic_connections classification
+    Failed,
+
+    // Connections refused for exceeding connection limits
+    Exceeded,
+
+    // Connections that start and finish without submitting any tx
+    Idle
+}
+
+// A canary: a fake peer whose synthetic events are fed to the classifier on a schedule, each test passing if the
+// canary is then added to its expected group as seen by sinks, i.e. after classification, group management, and the
+// sink queue.  A failing canary means that the pipeline is broken (or that config no longer classifies the canary's
+// behavior into the group).
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig
+{
+    // Name of the canary, for logs and events
+    pub name : String,
+
+    // Address of the canary.  This should be an address from which no real traffic comes (e.g. from 192.0.2.0/24,
+    // which is reserved for documentation), since the canary is added to its group, and so acted on by sinks, like
+    // any other peer.
+    pub ip_addr : IpAddr,
+
+    // Group into which the canary's behavior is expected to be classified
+    pub group_name : String,
+
+    // The synthetic traffic generated during each test
+    pub behavior : CanaryBehavior,
+
+    // Number of synthetic events generated per second during each test.  If not present, a default of 10 is used.
+    pub events_per_second : Option<u64>,
+
+    // Milliseconds between the starts of tests.  If not present, a default of 10 minutes is used.
+    pub interval_ms : Option<u64>,
+
+    // Milliseconds after its start by which a test must pass, else it fails.  If not present, a default of 1 minute
+    // is used.
+    pub timeout_ms : Option<u64>
+}
+
+impl CanaryConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.events_per_second == Some(0) {
+            return Err(format!("Invalid canaries entry {}: zero events_per_second", self.name));
+        }
+
+        if self.timeout_ms == Some(0) {
+            return Err(format!("Invalid canaries entry {}: zero timeout_ms", self.name));
+        }
+
+        if self.timeout_ms() >= self.interval_ms() {
+            return Err(format!("Invalid canaries entry {}: timeout_ms must be less than interval_ms", self.name));
+        }
+
+        Ok(())
+    }
+
+    pub fn events_per_second(&self) -> u64
+    {
+        self.events_per_second.unwrap_or(DEFAULT_CANARY_EVENTS_PER_SECOND)
+    }
+
+    pub fn interval_ms(&self) -> u64
+    {
+        self.interval_ms.unwrap_or(DEFAULT_CANARY_INTERVAL_MS)
+    }
+
+    pub fn timeout_ms(&self) -> u64
+    {
+        self.timeout_ms.unwrap_or(DEFAULT_CANARY_TIMEOUT_MS)
+    }
+}
+
+// Results of the tests of a canary so far
+#[derive(Clone, Default, Serialize)]
+pub struct CanaryResults
+{
+    pub pass_count : u64,
+
+    pub fail_count : u64,
+
+    // Whether the most recent test passed, if any test has completed
+    pub last_passed : Option<bool>,
+
+    // Milliseconds from the start of the most recent passing test until the canary was seen by sinks
+    pub last_latency_ms : Option<u64>
+}
+
+// (ip_addr, group_name) of each canary currently a member of its group as seen by sinks
+type Observed = Arc<Mutex<HashSet<(IpAddr, String)>>>;
+
+// Sink that observes the group changes of canaries as they reach sinks
+pub struct CanarySink
+{
+    canaries : HashSet<(IpAddr, String)>,
+
+    observed : Observed
+}
+
+impl Sink for CanarySink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "canary", "canary_count" : self.canaries.len() })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        let (group_name, ip_addr, member) = match event {
+            GroupChange::Add { group_name, ip_addr, .. } |
+            GroupChange::Update { group_name, ip_addr, .. } |
+            GroupChange::Extend { group_name, ip_addr, .. } => (group_name, ip_addr, true),
+            GroupChange::Remove { group_name, ip_addr, .. } => (group_name, ip_addr, false),
+            _ => return Ok(())
+        };
+
+        let key = (*ip_addr, group_name.clone());
+
+        if self.canaries.contains(&key) {
+            let mut observed = self.observed.lock().unwrap();
+            if member {
+                observed.insert(key);
+            }
+            else {
+                observed.remove(&key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A test in progress
+struct CanaryTest
+{
+    start_timestamp : u64,
+
+    // Number of events generated so far
+    event_count : u64
+}
+
+struct Canary
+{
+    config : CanaryConfig,
+
+    // Timestamp at which the next test starts
+    next_start_timestamp : u64,
+
+    test : Option<CanaryTest>
+}
+
+// Runs the tests of all canaries
+pub struct Canaries
+{
+    canaries : Vec<Canary>,
+
+    observed : Observed
+}
+
+impl Canaries
+{
+    // Creates the canaries, the first tests of which start after one interval from now, so that the classifier has
+    // settled.  Returns also the sink that must be given to the sink worker for the tests to observe the canaries.
+    pub fn new(
+        configs : Vec<CanaryConfig>,
+        now : u64
+    ) -> (Self, CanarySink)
+    {
+        let observed = Observed::default();
+
+        let sink = CanarySink {
+            canaries : configs.iter().map(|config| (config.ip_addr, config.group_name.clone())).collect(),
+            observed : observed.clone()
+        };
+
+        let canaries = configs
+            .into_iter()
+            .map(|config| Canary { next_start_timestamp : now + config.interval_ms(), config, test : None })
+            .collect();
+
+        (Self { canaries, observed }, sink)
+    }
+
+    // Starts due tests, completes tests that passed or timed out, and returns the synthetic events due from running
+    // tests, to be handled as events from CANARY_SOURCE
+    pub fn poll(
+        &mut self,
+        state : &mut State,
+        now : u64
+    ) -> Vec<TxIngestMsg>
+    {
+        let mut msgs = vec![];
+
+        for canary in &mut self.canaries {
+            let config = &canary.config;
+
+            let Some(test) = &mut canary.test
+            else {
+                if now >= canary.next_start_timestamp {
+                    canary.test = Some(CanaryTest { start_timestamp : now, event_count : 0 });
+                    canary.next_start_timestamp = now + config.interval_ms();
+                }
+                continue;
+            };
+
+            let elapsed_ms = now.saturating_sub(test.start_timestamp);
+
+            let passed = self.observed.lock().unwrap().contains(&(config.ip_addr, config.group_name.clone()));
+
+            if passed || (elapsed_ms >= config.timeout_ms()) {
+                state.canary_completed(&config.name, config.ip_addr, &config.group_name, passed, elapsed_ms, now);
+                canary.test = None;
+                continue;
+            }
+
+            let due_count = (elapsed_ms * config.events_per_second()) / 1000;
+
+            for _ in test.event_count..due_count {
+                // Stamped as if read from the system clock, as the validator's events are
+                let (timestamp, peer_addr) = (now.saturating_add_signed(state.clock_offset_ms), config.ip_addr);
+                match config.behavior {
+                    CanaryBehavior::Failed => msgs.push(TxIngestMsg::Failed { timestamp, peer_addr }),
+                    CanaryBehavior::Exceeded => {
+                        msgs.push(TxIngestMsg::Exceeded { timestamp, peer_addr, peer_pubkey : None, stake : 0 })
+                    },
+                    CanaryBehavior::Idle => {
+                        msgs.push(TxIngestMsg::Started { timestamp, peer_addr, peer_pubkey : None, stake : 0 });
+                        msgs.push(TxIngestMsg::Finished { timestamp, peer_addr });
+                    }
+                }
+            }
+
+            test.event_count = test.event_count.max(due_count);
+        }
+
+        msgs
+    }
+}
@@ -0,0 +1,106 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+
+pub const DEFAULT_CHURN_BUCKET_MS : u64 = 60 * 1000; // 1 minute
+pub const DEFAULT_CHURN_BUCKET_COUNT : usize = 60;
+
+// Running totals of the membership changes of a group, from which its churn is computed
+#[derive(Clone, Copy, Default)]
+pub struct ChurnTotals
+{
+    pub add_count : u64,
+
+    pub remove_count : u64,
+
+    pub expire_count : u64
+}
+
+// The membership changes of a group over one bucket of time
+#[derive(Clone, Serialize)]
+pub struct ChurnBucket
+{
+    pub start_timestamp : u64,
+
+    pub end_timestamp : u64,
+
+    pub add_count : u64,
+
+    // Members removed other than by expiring, as when released from probation or removed via the API
+    pub remove_count : u64,
+
+    pub expire_count : u64,
+
+    // Number of members at the end of the bucket
+    pub member_count : usize
+}
+
+// History of the membership changes of a group, in buckets of roughly equal duration, oldest first.  A group whose
+// members are added and removed at high rates relative to its size is usually classified by a poorly tuned threshold,
+// with peers flapping in and out.
+#[derive(Default)]
+pub struct ChurnHistory
+{
+    buckets : VecDeque<ChurnBucket>,
+
+    // Start of the current bucket and the group's totals at that time, once first sampled
+    current : Option<(u64, ChurnTotals)>
+}
+
+impl ChurnHistory
+{
+    // Samples the group's totals at now, completing the current bucket if it has lasted at least bucket_ms, and
+    // keeping at most bucket_count completed buckets.  The first bucket starts at the first sample and counts only the
+    // changes after it, so that the changes made by then (e.g. members restored or imported at startup) are not counted
+    // as churn.
+    pub fn sample(
+        &mut self,
+        now : u64,
+        bucket_ms : u64,
+        bucket_count : usize,
+        totals : ChurnTotals,
+        member_count : usize
+    )
+    {
+        let (start_timestamp, start_totals) = *self.current.get_or_insert((now, totals));
+
+        if now < (start_timestamp + bucket_ms) {
+            return;
+        }
+
+        self.buckets.push_back(ChurnBucket {
+            start_timestamp,
+            end_timestamp : now,
+            add_count : totals.add_count - start_totals.add_count,
+            remove_count : totals.remove_count - start_totals.remove_count,
+            expire_count : totals.expire_count - start_totals.expire_count,
+            member_count
+        });
+
+        while self.buckets.len() > bucket_count {
+            self.buckets.pop_front();
+        }
+
+        self.current = Some((now, totals));
+    }
+
+    pub fn buckets(&self) -> &VecDeque<ChurnBucket>
+    {
+        &self.buckets
+    }
+
+    // Members added and removed (including by expiring) per minute over the completed buckets, or None if there are
+    // none
+    pub fn churn_per_minute(&self) -> Option<f64>
+    {
+        let duration_ms = self.buckets.iter().map(|bucket| bucket.end_timestamp - bucket.start_timestamp).sum::<u64>();
+
+        if duration_ms == 0 {
+            return None;
+        }
+
+        let change_count =
+            self.buckets.iter().map(|bucket| bucket.add_count + bucket.remove_count + bucket.expire_count).sum::<u64>();
+
+        Some(((change_count as f64) * 60_000.0) / (duration_ms as f64))
+    }
+}
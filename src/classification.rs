@@ -1,10 +1,28 @@
+use crate::config::TpuService;
 use crate::group::{Group, DEFAULT_GROUP_EXPIRATION_SECONDS};
-use crate::threshold::Threshold;
-use serde::Deserialize;
-use std::collections::{HashMap, VecDeque};
+use crate::shrink::Shrink;
+use crate::sink::SinkSender;
+use crate::threshold::{StakeLookup, Threshold};
+use crate::transform::ValueTransform;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
 use std::net::IpAddr;
+use std::sync::Arc;
 
-#[derive(Deserialize)]
+const DEFAULT_ALERT_DURATION_MS : u64 = 60 * 1000; // 1 minute
+
+// Maximum number of emptied value queues retained for reuse
+const MAX_POOLED_VALUE_QUEUES : usize = 256;
+
+// Number of half-lives after its most recent value at which a decayed value, having decayed to less than a
+// thousandth of what it was, is discarded
+const DECAYED_VALUE_RETENTION_HALF_LIVES : u64 = 10;
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Classification
 {
     // group_name to use for any contained Threshold which does not supply its own group_name; defaults to the
@@ -18,18 +36,309 @@ pub struct Classification
     // The thresholds to apply
     pub thresholds : Vec<Threshold>,
 
+    // Thresholds applied in place of thresholds while a group size or storm rule with the overload_thresholds action is
+    // firing, typically stricter so that enforcement scales with an attack.  If not present, thresholds always apply.
+    pub overload_thresholds : Option<Vec<Threshold>>,
+
+    // If present, only values derived from events received from these ingest sources are accumulated by this
+    // classification; values from all other sources are ignored.  If not present, values from all sources are
+    // accumulated.
+    pub sources : Option<Vec<String>>,
+
+    // If present, only values derived from events received from ingest sources reporting these services are
+    // accumulated by this classification.  Events from sources with no service configured are ignored.  If not
+    // present, values for all services are accumulated.
+    pub services : Option<Vec<TpuService>>,
+
+    // Names of the ingest sources reporting services, resolved when the config is validated
+    #[serde(skip)]
+    pub service_sources : Vec<String>,
+
+    // If present and true, values from ingest sources marked as relayers are accumulated separately from values
+    // from all other sources, and thresholds are applied to each separately, so that an ip address which is well
+    // behaved directly but abusive through a relayer (or vice versa) is classified by its behavior on each path
+    // rather than by the two combined
+    pub separate_relayer_path : Option<bool>,
+
+    // If present, ip addresses meeting thresholds by their values via relayers are added to this group rather than to
+    // the threshold's group, e.g. so that they are throttled at the relayer rather than blocked at the validator.
+    // Requires separate_relayer_path.
+    pub relayer_group_name : Option<String>,
+
+    // Names of the relayer ingest sources if separate_relayer_path, resolved when the config is validated
+    #[serde(skip)]
+    pub relayer_sources : Vec<String>,
+
+    // Fee classifications only: the time span in milliseconds after the first submission of a tx within which
+    // submissions of the tx are attributed to this classification.  Submissions after this window are not added.  If
+    // not present, a default of 2 minutes is used.
+    pub attribution_window_ms : Option<u64>,
+
+    // Fee classifications only: submissions within this many milliseconds of the first submission of a tx are
+    // considered to be ties with the first submission, and so are also credited with the full fee.  If not present,
+    // only the first submission is credited.
+    pub first_submitter_grace_ms : Option<u64>,
+
+    // Fee classifications only: how fee credit is divided among the submitters of a tx.  If not present, "first" is
+    // used.
+    pub fee_credit_policy : Option<FeeCreditPolicy>,
+
+    // Fee classifications only, with the "latency_weighted" fee credit policy: the submission latency relative to the
+    // first submission at which a submitter's weight is halved.  If not present, a default of 100 milliseconds is
+    // used.
+    pub fee_credit_half_life_ms : Option<u64>,
+
+    // Fee classifications only: how submissions credited with zero fee (those of tx that never landed, and those of
+    // landed tx not credited with the fee) are valued.  This matters most for per-CU fee classifications, where
+    // counting such submissions as zero pulls averages down.  If not present, "zero" is used.
+    pub zero_fee_attribution : Option<ZeroFeeAttribution>,
+
+    // If present, an alert is raised when more than this many ip addresses are added to groups by this
+    // classification within alert_duration_ms.  A high rate of additions usually indicates either an attack or a
+    // broken threshold.
+    pub alert_add_count : Option<u64>,
+
+    // The time span in milliseconds over which additions are counted for alert_add_count.  If not present, a default
+    // of 1 minute is used.
+    pub alert_duration_ms : Option<u64>,
+
+    // Transforms applied in order to each value as it is added, before it is accumulated.  If not present, values are
+    // accumulated as is.
+    pub transforms : Option<Vec<ValueTransform>>,
+
+    // If present, values are accumulated for each ip address into a single sum and count that decay exponentially
+    // with this half-life in milliseconds, instead of retaining every value within the longest threshold duration.
+    // Thresholds then compare the decayed sum (or for average thresholds, the decayed sum divided by the decayed
+    // count) and min_value_count is compared with the decayed count; threshold duration_ms values are ignored.  This
+    // trades exact windows for constant memory per ip address, which matters at relayer scale peer counts.
+    pub decay_half_life_ms : Option<u64>,
+
+    // If present, the maximum number of values retained per ip address.  When an ip address is at this limit, its two
+    // oldest values are merged into one to make room for each new value, so that sums and averages are preserved at
+    // the cost of timestamp precision for the oldest values.  If not present, the number of values is unlimited.
+    pub max_values_per_ip : Option<usize>,
+
+    // If present and true, values are accumulated separately for each identity (ip address and pubkey) where the
+    // pubkey is known, and thresholds are applied to each identity separately, so that one identity classified
+    // behind a shared address (e.g. a NAT) does not condemn the others there by their combined values.  An identity
+    // meeting a threshold is added to the group with its pubkey, for sinks that can act on identities.  Only values of
+    // events that carry the pubkey of their identity (as exceeded connections do) are attributed to it; values of
+    // events that carry none (failed connections, tx submissions, useless connections), and values via relayers, are
+    // accumulated for the address, so that no identity is condemned for whatever else spoke from its address.
+    pub attribute_by_pubkey : Option<bool>,
+
+    // If present, the classification is evaluated at most once per this many milliseconds rather than in every
+    // classification pass, so that classifications with long windows (e.g. fee classifications) need not be scanned
+    // as often as those that must act quickly.  Evaluations still happen only in classification passes, so the
+    // effective interval is this rounded up to a multiple of classification_interval_ms.  Values are accumulated as
+    // usual between evaluations.
+    pub evaluation_interval_ms : Option<u64>,
+
+    // True while overload_thresholds are applied in place of thresholds
+    #[serde(skip)]
+    pub overloaded : bool,
+
+    // Name of the classification, as given in the config
+    #[serde(skip)]
+    pub name : String,
+
+    // Number of times values were merged because an ip address was at max_values_per_ip
+    #[serde(skip)]
+    pub merged_value_count : u64,
+
+    // Duration in microseconds of the most recent periodic evaluation, and the longest seen
+    #[serde(skip)]
+    pub last_evaluation_us : u64,
+
+    #[serde(skip)]
+    pub max_evaluation_us : u64,
+
+    // Number of ip addresses scanned by the most recent periodic evaluation
+    #[serde(skip)]
+    pub last_scanned_ip_count : usize,
+
+    // Number of periodic evaluations deferred because the classification budget was exhausted
+    #[serde(skip)]
+    pub deferred_count : u64,
+
+    // Number of alerts raised
+    #[serde(skip)]
+    pub alert_count : u64,
+
     #[serde(skip)]
     max_duration_ms : u64,
 
+    // Timestamp at or after which the next periodic evaluation is due, per evaluation_interval_ms
+    #[serde(skip)]
+    next_evaluation_timestamp : u64,
+
+    // Timestamps and counts of additions to groups made by periodic passes within the alert duration
+    #[serde(skip)]
+    recent_adds : VecDeque<TimestampedValue>,
+
+    // Timestamp of the most recent alert
+    #[serde(skip)]
+    last_alert_timestamp : Option<u64>,
+
+    #[serde(skip)]
+    recent_values : HashMap<IpAddr, PeerValues>,
+
+    // Values via relayers, if separate_relayer_path; recent_values then holds only values from other sources
+    #[serde(skip)]
+    relayed_values : HashMap<IpAddr, PeerValues>,
+
+    // Values of identities behind addresses, if attribute_by_pubkey; recent_values then holds only values with no
+    // known pubkey
+    #[serde(skip)]
+    identity_values : HashMap<(IpAddr, Pubkey), PeerValues>,
+
+    // Addresses whose value accumulation and threshold evaluation are logged, as set via the API
+    #[serde(skip)]
+    pub traced_addresses : HashSet<IpAddr>,
+
+    // Emptied value queues, reused for newly tracked ip addresses rather than allocating new queues
     #[serde(skip)]
-    recent_values : HashMap<IpAddr, VecDeque<TimestampedValue>>
+    value_queue_pool : Vec<VecDeque<TimestampedValue>>
+}
+
+#[derive(Clone, Copy, Deserialize, JsonSchema)]
+pub enum FeeCreditPolicy
+{
+    // The first submitter (and any submitters tied with it within first_submitter_grace_ms) is credited with the full
+    // fee; all other submitters are credited with zero fee
+    #[serde(rename = "first")]
+    First,
+
+    // The fee is split among all submitters within the attribution window, weighted by each submission's latency
+    // relative to the first submission, so that fast redundant paths retain most of the credit while slow copies get
+    // little
+    #[serde(rename = "latency_weighted")]
+    LatencyWeighted
+}
+
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub enum ZeroFeeAttribution
+{
+    // Submissions credited with zero fee are not added at all, so that fee per CU values reflect only the fees
+    // actually paid
+    #[serde(rename = "exclude")]
+    Exclude,
+
+    // Submissions credited with zero fee are valued as a zero fee with placeholder compute units (a CU limit and CU
+    // used of 1)
+    #[serde(rename = "zero")]
+    Zero,
+
+    // Submissions credited with zero fee are valued as a zero fee with the compute units declared in the Fee event of
+    // their tx.  Submissions of tx that never landed have no declared compute units, and so are not added.
+    #[serde(rename = "declared_cu")]
+    DeclaredCu
 }
 
 pub struct TimestampedValue
 {
     pub timestamp : u64,
 
-    pub value : u64
+    // Values are signed so that adjustments may correct earlier values
+    pub value : i64,
+
+    // Number of values summed into value; more than 1 only for values merged due to max_values_per_ip, and 0 for
+    // adjustments, which change the sum of earlier values without counting as values themselves
+    pub count : u64
+}
+
+// The values accumulated for an ip address
+pub enum PeerValues
+{
+    // Every value within the longest threshold duration, oldest first
+    Window(VecDeque<TimestampedValue>),
+
+    // A decaying sum and count of all values, for classifications with decay_half_life_ms
+    Decayed(DecayedValue)
+}
+
+impl PeerValues
+{
+    // Returns the timestamp of the most recent value, if any
+    pub fn latest_timestamp(&self) -> Option<u64>
+    {
+        match self {
+            PeerValues::Window(values) => values.back().map(|value| value.timestamp),
+            PeerValues::Decayed(decayed_value) => Some(decayed_value.timestamp)
+        }
+    }
+}
+
+// An exponentially decaying sum and count of values
+pub struct DecayedValue
+{
+    pub sum : f64,
+
+    pub count : f64,
+
+    // Timestamp as of which sum and count have been decayed
+    pub timestamp : u64
+}
+
+impl DecayedValue
+{
+    // Adds value, given count, as of timestamp.  Values older than the decayed value are decayed to its timestamp
+    // rather than moving it back in time.
+    fn add(
+        &mut self,
+        value : i64,
+        count : u64,
+        timestamp : u64,
+        half_life_ms : u64
+    )
+    {
+        if timestamp > self.timestamp {
+            let (sum, count) = self.decayed(timestamp, half_life_ms);
+            self.sum = sum;
+            self.count = count;
+            self.timestamp = timestamp;
+        }
+
+        let factor = decay_factor(self.timestamp - timestamp.min(self.timestamp), half_life_ms);
+        self.sum += (value as f64) * factor;
+        self.count += (count as f64) * factor;
+    }
+
+    // Returns the sum and count decayed to now
+    pub fn decayed(
+        &self,
+        now : u64,
+        half_life_ms : u64
+    ) -> (f64, f64)
+    {
+        let factor = decay_factor(now.saturating_sub(self.timestamp), half_life_ms);
+
+        (self.sum * factor, self.count * factor)
+    }
+}
+
+// Returns the factor by which a value decays over elapsed_ms
+fn decay_factor(
+    elapsed_ms : u64,
+    half_life_ms : u64
+) -> f64
+{
+    0.5_f64.powf((elapsed_ms as f64) / (half_life_ms as f64))
+}
+
+// A value to be added to a classification by add_values
+pub struct SourcedValue
+{
+    pub ip_addr : IpAddr,
+
+    pub timestamp : u64,
+
+    pub value : i64,
+
+    pub source : Arc<str>,
+
+    // Pubkey of the identity to which the value is attributed, if known
+    pub pubkey : Option<Pubkey>
 }
 
 // Created by deserialization from config file.
@@ -41,72 +350,683 @@ impl Classification
         name : &str
     ) -> Result<(), String>
     {
+        self.name = name.to_string();
+
         if self.thresholds.is_empty() {
             return Err(format!("Classification {name} has no thresholds"));
         }
 
-        for index in 0..self.thresholds.len() {
-            let threshold = &mut self.thresholds[index];
-            threshold.validate(
-                name,
-                index,
-                self.group_name.as_ref().map(|str| str.as_str()).unwrap_or(name),
-                self.group_expiration_seconds.unwrap_or(DEFAULT_GROUP_EXPIRATION_SECONDS) * 1000
-            )?;
-            if threshold.duration_ms > self.max_duration_ms {
-                self.max_duration_ms = threshold.duration_ms;
+        if self.attribution_window_ms == Some(0) {
+            return Err(format!("Classification {name} has zero attribution_window_ms"));
+        }
+
+        if let (Some(first_submitter_grace_ms), Some(attribution_window_ms)) =
+            (self.first_submitter_grace_ms, self.attribution_window_ms)
+        {
+            if first_submitter_grace_ms > attribution_window_ms {
+                return Err(format!(
+                    "Classification {name} has first_submitter_grace_ms {first_submitter_grace_ms} greater than \
+                     attribution_window_ms {attribution_window_ms}"
+                ));
+            }
+        }
+
+        if self.fee_credit_half_life_ms == Some(0) {
+            return Err(format!("Classification {name} has zero fee_credit_half_life_ms"));
+        }
+
+        for transform in self.transforms.iter().flatten() {
+            transform.validate(name)?;
+        }
+
+        if let Some(max_values_per_ip) = self.max_values_per_ip {
+            if max_values_per_ip < 2 {
+                return Err(format!(
+                    "Classification {name} has max_values_per_ip {max_values_per_ip}; must be at least 2"
+                ));
+            }
+        }
+
+        if self.alert_duration_ms == Some(0) {
+            return Err(format!("Classification {name} has zero alert_duration_ms"));
+        }
+
+        if self.decay_half_life_ms == Some(0) {
+            return Err(format!("Classification {name} has zero decay_half_life_ms"));
+        }
+
+        if self.evaluation_interval_ms == Some(0) {
+            return Err(format!("Classification {name} has zero evaluation_interval_ms"));
+        }
+
+        if self.decay_half_life_ms.is_some() && self.max_values_per_ip.is_some() {
+            return Err(format!("Classification {name} cannot have both decay_half_life_ms and max_values_per_ip"));
+        }
+
+        let group_name = self.group_name.as_deref().unwrap_or(name);
+        let group_expiration_ms = self.group_expiration_seconds.unwrap_or(DEFAULT_GROUP_EXPIRATION_SECONDS) * 1000;
+
+        for (index, threshold) in self.thresholds.iter_mut().enumerate() {
+            threshold.validate(name, false, index, group_name, group_expiration_ms)?;
+            self.max_duration_ms = self.max_duration_ms.max(threshold.duration_ms);
+        }
+
+        for (index, threshold) in self.overload_thresholds.iter_mut().flatten().enumerate() {
+            threshold.validate(name, true, index, group_name, group_expiration_ms)?;
+            self.max_duration_ms = self.max_duration_ms.max(threshold.duration_ms);
+        }
+
+        if let Some(decay_half_life_ms) = self.decay_half_life_ms {
+            for threshold in self.thresholds.iter_mut().chain(self.overload_thresholds.iter_mut().flatten()) {
+                threshold.decay_half_life_ms = Some(decay_half_life_ms);
             }
+            self.max_duration_ms = decay_half_life_ms * DECAYED_VALUE_RETENTION_HALF_LIVES;
         }
 
         Ok(())
     }
 
+    // Replaces this classification's definition with new, which has been validated, as when the config is reloaded.
+    // The values accumulated so far are kept if new accumulates values the same way (decaying or not, and separately
+    // by relayer path and identity or not), so that reloading does not reset classification; values already
+    // accumulated are not re-transformed.  Otherwise they are discarded, and false is returned.
+    pub fn reload(
+        &mut self,
+        mut new : Classification
+    ) -> bool
+    {
+        let keep_values = (new.decay_half_life_ms == self.decay_half_life_ms) &&
+            (new.separate_relayer_path == self.separate_relayer_path) &&
+            (new.attribute_by_pubkey == self.attribute_by_pubkey);
+
+        if keep_values {
+            new.recent_values = std::mem::take(&mut self.recent_values);
+            new.relayed_values = std::mem::take(&mut self.relayed_values);
+            new.identity_values = std::mem::take(&mut self.identity_values);
+            new.value_queue_pool = std::mem::take(&mut self.value_queue_pool);
+        }
+
+        // Thresholds keep their counts where their rule is unchanged
+        for threshold in new.thresholds.iter_mut().chain(new.overload_thresholds.iter_mut().flatten()) {
+            threshold.add_count = self
+                .thresholds
+                .iter()
+                .chain(self.overload_thresholds.iter().flatten())
+                .find(|old| old.rule.name == threshold.rule.name)
+                .map(|old| old.add_count)
+                .unwrap_or(0);
+        }
+
+        new.overloaded = self.overloaded;
+        new.merged_value_count = self.merged_value_count;
+        new.last_evaluation_us = self.last_evaluation_us;
+        new.max_evaluation_us = self.max_evaluation_us;
+        new.last_scanned_ip_count = self.last_scanned_ip_count;
+        new.deferred_count = self.deferred_count;
+        new.alert_count = self.alert_count;
+        new.recent_adds = std::mem::take(&mut self.recent_adds);
+        new.last_alert_timestamp = self.last_alert_timestamp;
+        new.traced_addresses = std::mem::take(&mut self.traced_addresses);
+
+        *self = new;
+
+        keep_values
+    }
+
+    // Adds a value for ip_addr, attributed to the identity of pubkey at that address if known
     pub fn add_value(
         &mut self,
         ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
         timestamp : u64,
-        value : u64
+        value : i64,
+        source : &str
     )
     {
-        self.recent_values.entry(ip_addr).or_default().push_back(TimestampedValue { timestamp, value });
+        if !self.accepts_source(source) {
+            return;
+        }
+
+        let transformed_value = self.transform(value);
+
+        if self.traced_addresses.contains(&ip_addr) {
+            self.trace_value(ip_addr, timestamp, value, transformed_value, source);
+        }
+
+        let value = transformed_value;
+
+        let recent_values = match self.identity(pubkey, source) {
+            Some(pubkey) => self
+                .identity_values
+                .entry((ip_addr, pubkey))
+                .or_insert_with(|| Self::new_values(&mut self.value_queue_pool, self.decay_half_life_ms, timestamp)),
+            None => {
+                let values = if self.is_relayed(source) { &mut self.relayed_values } else { &mut self.recent_values };
+                values
+                    .entry(ip_addr)
+                    .or_insert_with(|| Self::new_values(&mut self.value_queue_pool, self.decay_half_life_ms, timestamp))
+            }
+        };
+
+        Self::push_value(
+            recent_values,
+            TimestampedValue { timestamp, value, count : 1 },
+            self.max_values_per_ip,
+            self.decay_half_life_ms,
+            &mut self.merged_value_count
+        );
+    }
+
+    // Corrects a value previously added for ip_addr from old_value to new_value, e.g. to refund a failure later
+    // found not to be one, by adding the (possibly negative) difference of their transformed values as an
+    // adjustment.  Adjustments change sums and averages without counting as values (for min_value_count or
+    // averaging).  Corrections for ip addresses with no retained values on the source's path are discarded, since
+    // there is nothing to correct.
+    pub fn correct_value(
+        &mut self,
+        ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
+        timestamp : u64,
+        old_value : i64,
+        new_value : i64,
+        source : &str
+    )
+    {
+        if !self.accepts_source(source) {
+            return;
+        }
+
+        let adjustment = self.transform(new_value) - self.transform(old_value);
+        if adjustment == 0 {
+            return;
+        }
+
+        if self.traced_addresses.contains(&ip_addr) {
+            println!(
+                "TRACE {ip_addr} {}: value corrected from {old_value} to {new_value} from {source} at {timestamp}, \
+                 adjusted by {adjustment}",
+                self.name
+            );
+        }
+
+        let recent_values = match self.identity(pubkey, source) {
+            Some(pubkey) => self.identity_values.get_mut(&(ip_addr, pubkey)),
+            None => {
+                let values = if self.is_relayed(source) { &mut self.relayed_values } else { &mut self.recent_values };
+                values.get_mut(&ip_addr)
+            }
+        };
+
+        if let Some(recent_values) = recent_values {
+            Self::push_value(
+                recent_values,
+                TimestampedValue { timestamp, value : adjustment, count : 0 },
+                self.max_values_per_ip,
+                self.decay_half_life_ms,
+                &mut self.merged_value_count
+            );
+        }
+    }
+
+    // Adds many values at once.  Values are grouped by ip address so that each address is looked up only once, and
+    // are added in timestamp order.
+    pub fn add_values(
+        &mut self,
+        mut values : Vec<SourcedValue>
+    )
+    {
+        values.retain(|value| self.accepts_source(&value.source));
+
+        for value in &mut values {
+            let transformed_value = self.transform(value.value);
+            if self.traced_addresses.contains(&value.ip_addr) {
+                self.trace_value(value.ip_addr, value.timestamp, value.value, transformed_value, &value.source);
+            }
+            value.value = transformed_value;
+        }
+
+        values.sort_unstable_by_key(|value| (value.ip_addr, value.pubkey, value.timestamp));
+
+        let (identified, values) : (Vec<SourcedValue>, Vec<SourcedValue>) =
+            values.into_iter().partition(|value| self.identity(value.pubkey, &value.source).is_some());
+
+        let (relayed, direct) : (Vec<SourcedValue>, Vec<SourcedValue>) =
+            values.into_iter().partition(|value| self.is_relayed(&value.source));
+
+        for (values, recent_values) in [(direct, &mut self.recent_values), (relayed, &mut self.relayed_values)] {
+            Self::push_values(
+                values,
+                |value| value.ip_addr,
+                recent_values,
+                &mut self.value_queue_pool,
+                self.max_values_per_ip,
+                self.decay_half_life_ms,
+                &mut self.merged_value_count
+            );
+        }
+
+        // Identified values all have a pubkey
+        Self::push_values(
+            identified,
+            |value| (value.ip_addr, value.pubkey.unwrap()),
+            &mut self.identity_values,
+            &mut self.value_queue_pool,
+            self.max_values_per_ip,
+            self.decay_half_life_ms,
+            &mut self.merged_value_count
+        );
+    }
+
+    // Pushes values, sorted by key, onto the values of their keys in recent_values, looking up each key only once
+    fn push_values<K : Eq + Hash>(
+        values : Vec<SourcedValue>,
+        key : impl Fn(&SourcedValue) -> K,
+        recent_values : &mut HashMap<K, PeerValues>,
+        value_queue_pool : &mut Vec<VecDeque<TimestampedValue>>,
+        max_values_per_ip : Option<usize>,
+        decay_half_life_ms : Option<u64>,
+        merged_value_count : &mut u64
+    )
+    {
+        for chunk in values.chunk_by(|a, b| key(a) == key(b)) {
+            let recent_values = recent_values
+                .entry(key(&chunk[0]))
+                .or_insert_with(|| Self::new_values(value_queue_pool, decay_half_life_ms, chunk[0].timestamp));
+
+            for value in chunk {
+                Self::push_value(
+                    recent_values,
+                    TimestampedValue { timestamp : value.timestamp, value : value.value, count : 1 },
+                    max_values_per_ip,
+                    decay_half_life_ms,
+                    merged_value_count
+                );
+            }
+        }
+    }
+
+    // Logs the accumulation of a value for a traced ip address
+    fn trace_value(
+        &self,
+        ip_addr : IpAddr,
+        timestamp : u64,
+        value : i64,
+        transformed_value : i64,
+        source : &str
+    )
+    {
+        println!(
+            "TRACE {ip_addr} {}: value {value} from {source} at {timestamp} accumulated as {transformed_value}{}",
+            self.name,
+            if self.is_relayed(source) { " (relayed)" } else { "" }
+        );
+    }
+
+    // Applies the classification's transforms to value
+    fn transform(
+        &self,
+        value : i64
+    ) -> i64
+    {
+        self.transforms.iter().flatten().fold(value, |value, transform| transform.apply(value))
+    }
+
+    // Returns true if values from source are accumulated by this classification
+    fn accepts_source(
+        &self,
+        source : &str
+    ) -> bool
+    {
+        self.sources.as_ref().map(|sources| sources.iter().any(|s| s == source)).unwrap_or(true) &&
+            (self.services.is_none() || self.service_sources.iter().any(|s| s == source))
     }
 
-    // To be called once per second
+    // Returns the pubkey of the identity to which a value from source is attributed, if values are attributed by
+    // pubkey and the pubkey is known.  Relayed values are attributed to the address of the relayer's client.
+    fn identity(
+        &self,
+        pubkey : Option<Pubkey>,
+        source : &str
+    ) -> Option<Pubkey>
+    {
+        pubkey.filter(|_| self.attribute_by_pubkey.unwrap_or(false) && !self.is_relayed(source))
+    }
+
+    // Returns true if values from source are accumulated separately as relayed values
+    fn is_relayed(
+        &self,
+        source : &str
+    ) -> bool
+    {
+        self.relayer_sources.iter().any(|s| s == source)
+    }
+
+    // Returns empty values for a newly tracked ip address, reusing a pooled value queue if values are retained
+    fn new_values(
+        value_queue_pool : &mut Vec<VecDeque<TimestampedValue>>,
+        decay_half_life_ms : Option<u64>,
+        timestamp : u64
+    ) -> PeerValues
+    {
+        match decay_half_life_ms {
+            Some(_) => PeerValues::Decayed(DecayedValue { sum : 0.0, count : 0.0, timestamp }),
+            None => PeerValues::Window(value_queue_pool.pop().unwrap_or_default())
+        }
+    }
+
+    fn push_value(
+        values : &mut PeerValues,
+        timestamped_value : TimestampedValue,
+        max_values_per_ip : Option<usize>,
+        decay_half_life_ms : Option<u64>,
+        merged_value_count : &mut u64
+    )
+    {
+        let recent_values = match values {
+            PeerValues::Window(recent_values) => recent_values,
+            PeerValues::Decayed(decayed_value) => {
+                // Decayed values only exist for classifications with a half-life
+                decayed_value.add(
+                    timestamped_value.value,
+                    timestamped_value.count,
+                    timestamped_value.timestamp,
+                    decay_half_life_ms.unwrap()
+                );
+                return;
+            }
+        };
+
+        if let Some(max_values_per_ip) = max_values_per_ip {
+            if recent_values.len() >= max_values_per_ip {
+                // Merge the two oldest values, taking the newer timestamp so that the merged value is not expired
+                // before any of its constituents would have been
+                let oldest = recent_values.pop_front().unwrap();
+                let next = recent_values.front_mut().unwrap();
+                next.value += oldest.value;
+                next.count += oldest.count;
+                *merged_value_count += 1;
+            }
+        }
+
+        recent_values.push_back(timestamped_value);
+    }
+
+    // Returns true if the classification is due to be evaluated at now, per its evaluation_interval_ms.  A reloaded
+    // classification is due immediately, so that its new thresholds apply at the next classification pass.
+    pub fn is_evaluation_due(
+        &self,
+        now : u64
+    ) -> bool
+    {
+        now >= self.next_evaluation_timestamp
+    }
+
+    // To be called periodically
     pub fn periodic(
         &mut self,
-        stakes : &HashMap<IpAddr, u64>,
+        stakes : StakeLookup,
         groups : &mut HashMap<String, Group>,
+        sink : &SinkSender,
         now : u64
     )
     {
+        let start = std::time::Instant::now();
+
+        if let Some(evaluation_interval_ms) = self.evaluation_interval_ms {
+            self.next_evaluation_timestamp = now + evaluation_interval_ms;
+        }
+
         let retain_timestamp = now - self.max_duration_ms;
 
         // Clear out values that are too old
-        for recent_values in self.recent_values.values_mut() {
-            loop {
-                if let Some(front) = recent_values.front() {
-                    if front.timestamp < retain_timestamp {
-                        recent_values.pop_front();
-                    }
-                    else {
-                        break;
-                    }
+        Self::expire_values(&mut self.recent_values, &mut self.value_queue_pool, retain_timestamp);
+        Self::expire_values(&mut self.relayed_values, &mut self.value_queue_pool, retain_timestamp);
+        Self::expire_values(&mut self.identity_values, &mut self.value_queue_pool, retain_timestamp);
+
+        let add_count_before = self.add_count();
+
+        // Call threshold periodic for each ip address, stopping if a threshold has been met for that address and
+        // the classification calls for stopping after the first matching threshold for an ip address
+        let thresholds = match &mut self.overload_thresholds {
+            Some(overload_thresholds) if self.overloaded => overload_thresholds,
+            _ => &mut self.thresholds
+        };
+
+        // Values via relayers are evaluated separately, adding to the relayer group if there is one, as are the values
+        // of each identity, adding with its pubkey
+        let relayer_group_name = self.relayer_group_name.as_deref();
+        let evaluated = self
+            .recent_values
+            .iter()
+            .map(|(ip_addr, recent_values)| (ip_addr, None, None, recent_values))
+            .chain(
+                self.relayed_values
+                    .iter()
+                    .map(|(ip_addr, recent_values)| (ip_addr, None, relayer_group_name, recent_values))
+            )
+            .chain(
+                self.identity_values
+                    .iter()
+                    .map(|((ip_addr, pubkey), recent_values)| (ip_addr, Some(*pubkey), None, recent_values))
+            );
+
+        for (ip_addr, pubkey, group_name, recent_values) in evaluated {
+            for threshold in thresholds.iter_mut() {
+                if self.traced_addresses.contains(ip_addr) {
+                    println!(
+                        "TRACE {ip_addr} {}{}{}: {}",
+                        self.name,
+                        if group_name.is_some() { " (relayed)" } else { "" },
+                        pubkey.map(|pubkey| format!(" ({pubkey})")).unwrap_or_default(),
+                        threshold.trace(stakes, now, ip_addr, recent_values)
+                    );
                 }
-                else {
+                // The most recent value is taken to be the one that triggered any addition
+                if threshold.is_exceeded(stakes, now, ip_addr, recent_values) &&
+                    sink.with_context(recent_values.latest_timestamp(), stakes.stake_of(ip_addr), || {
+                        threshold.stop_after_adding(now, ip_addr, pubkey, group_name, groups, sink)
+                    })
+                {
                     break;
                 }
             }
         }
-        self.recent_values.retain(|_, recent_values| !recent_values.is_empty());
 
-        // Call threshold periodic for each ip address, stopping if a threshold has been met for that address and
-        // the classification calls for stopping after the first matching threshold for an ip address
-        for (ip_addr, recent_values) in &self.recent_values {
-            for threshold in &mut self.thresholds {
-                if threshold.stop_after_exceeded(stakes, now, ip_addr, recent_values, groups) {
-                    break;
+        for threshold in thresholds.iter_mut() {
+            threshold.auto_tune(stakes, now, &self.recent_values);
+        }
+
+        if let Some(alert_add_count) = self.alert_add_count {
+            self.check_alert(now, self.add_count() - add_count_before, alert_add_count);
+        }
+
+        self.last_scanned_ip_count = self.recent_values.len() + self.relayed_values.len() + self.identity_values.len();
+        self.last_evaluation_us = start.elapsed().as_micros() as u64;
+        self.max_evaluation_us = self.max_evaluation_us.max(self.last_evaluation_us);
+    }
+
+    // Removes values older than retain_timestamp, and ip addresses left with no values.  Decayed values are removed
+    // once their most recent value is older than retain_timestamp.
+    fn expire_values<K : Eq + Hash>(
+        values : &mut HashMap<K, PeerValues>,
+        value_queue_pool : &mut Vec<VecDeque<TimestampedValue>>,
+        retain_timestamp : u64
+    )
+    {
+        for values in values.values_mut() {
+            if let PeerValues::Window(recent_values) = values {
+                while recent_values.front().map(|front| front.timestamp < retain_timestamp).unwrap_or(false) {
+                    recent_values.pop_front();
                 }
+                recent_values.shrink_idle();
             }
         }
+        values.retain(|_, values| match values {
+            PeerValues::Window(recent_values) if recent_values.is_empty() => {
+                Self::recycle(value_queue_pool, std::mem::take(recent_values));
+                false
+            },
+            PeerValues::Window(_) => true,
+            PeerValues::Decayed(decayed_value) => decayed_value.timestamp >= retain_timestamp
+        });
+        values.shrink_idle();
+    }
+
+    // Returns a config snippet for this classification's thresholds with values replaced by values suggested from the
+    // currently retained values at the given percentile.  Thresholds for which no value could be suggested retain
+    // their configured values.
+    pub fn suggested_thresholds(
+        &self,
+        stakes : StakeLookup,
+        now : u64,
+        percentile : f64
+    ) -> serde_json::Value
+    {
+        let thresholds = self
+            .thresholds
+            .iter()
+            .map(|threshold| {
+                let mut snippet = serde_json::json!({
+                    "value_operation" : threshold.value_operation,
+                    "threshold_type" : threshold.threshold_type,
+                    "value" : threshold.suggested_value(stakes, now, &self.recent_values, percentile)
+                        .unwrap_or(threshold.value),
+                    "duration_ms" : threshold.duration_ms
+                });
+                if let Some(low_stake) = threshold.low_stake {
+                    snippet["low_stake"] = low_stake.into();
+                }
+                if let Some(high_stake) = threshold.high_stake {
+                    snippet["high_stake"] = high_stake.into();
+                }
+                if let Some(min_value_count) = threshold.min_value_count {
+                    snippet["min_value_count"] = min_value_count.into();
+                }
+                snippet
+            })
+            .collect::<Vec<serde_json::Value>>();
+
+        serde_json::json!({ &self.name : { "thresholds" : thresholds } })
+    }
+
+    // Returns a machine readable description of the classification with defaults resolved, for the capability report
+    pub fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "name" : self.name,
+            "sources" : self.sources,
+            "services" : self.services,
+            "relayer_sources" : self.separate_relayer_path.unwrap_or(false).then_some(&self.relayer_sources),
+            "relayer_group_name" : self.relayer_group_name,
+            "thresholds" : self.thresholds.iter().map(Threshold::describe).collect::<Vec<_>>(),
+            "overload_thresholds" : self
+                .overload_thresholds
+                .as_ref()
+                .map(|thresholds| thresholds.iter().map(Threshold::describe).collect::<Vec<_>>()),
+            "transform_count" : self.transforms.as_ref().map(|transforms| transforms.len()).unwrap_or(0),
+            "max_values_per_ip" : self.max_values_per_ip,
+            "attribute_by_pubkey" : self.attribute_by_pubkey.unwrap_or(false),
+            "decay_half_life_ms" : self.decay_half_life_ms,
+            "zero_fee_attribution" : self.zero_fee_attribution,
+            "alert_add_count" : self.alert_add_count
+        })
+    }
+
+    // Returns the addresses for which values are retained, possibly more than once
+    pub fn value_addresses(&self) -> impl Iterator<Item = &IpAddr>
+    {
+        self.recent_values
+            .keys()
+            .chain(self.relayed_values.keys())
+            .chain(self.identity_values.keys().map(|(ip_addr, _)| ip_addr))
+    }
+
+    // Discards all values retained for ip_addr, including those of identities behind it
+    pub fn forget(
+        &mut self,
+        ip_addr : &IpAddr
+    )
+    {
+        for values in [self.recent_values.remove(ip_addr), self.relayed_values.remove(ip_addr)] {
+            if let Some(PeerValues::Window(recent_values)) = values {
+                Self::recycle(&mut self.value_queue_pool, recent_values);
+            }
+        }
+
+        self.identity_values.retain(|(identity_addr, _), values| {
+            if identity_addr != ip_addr {
+                return true;
+            }
+            if let PeerValues::Window(recent_values) = values {
+                Self::recycle(&mut self.value_queue_pool, std::mem::take(recent_values));
+            }
+            false
+        });
+    }
+
+    // Returns an emptied value queue to the pool, if the pool is not full
+    fn recycle(
+        value_queue_pool : &mut Vec<VecDeque<TimestampedValue>>,
+        mut recent_values : VecDeque<TimestampedValue>
+    )
+    {
+        if value_queue_pool.len() < MAX_POOLED_VALUE_QUEUES {
+            recent_values.clear();
+            // Queues grown large during a flood are not worth keeping at full size
+            recent_values.shrink_idle();
+            value_queue_pool.push(recent_values);
+        }
+    }
+
+    // Number of ip addresses for which values are currently retained, counting those with values on both paths
+    // twice
+    pub fn tracked_ip_count(&self) -> usize
+    {
+        self.recent_values.len() + self.relayed_values.len()
+    }
+
+    // Total number of ip addresses added to groups by the thresholds of this classification
+    pub fn add_count(&self) -> u64
+    {
+        self.thresholds
+            .iter()
+            .chain(self.overload_thresholds.iter().flatten())
+            .map(|threshold| threshold.add_count)
+            .sum()
+    }
+
+    // Raises an alert if the number of additions within the alert duration exceeds alert_add_count.  At most one
+    // alert is raised per alert duration.
+    fn check_alert(
+        &mut self,
+        now : u64,
+        add_count : u64,
+        alert_add_count : u64
+    )
+    {
+        let alert_duration_ms = self.alert_duration_ms.unwrap_or(DEFAULT_ALERT_DURATION_MS);
+
+        if add_count > 0 {
+            self.recent_adds.push_back(TimestampedValue { timestamp : now, value : add_count as i64, count : 1 });
+        }
+
+        let retain_timestamp = now.saturating_sub(alert_duration_ms);
+        while self.recent_adds.front().map(|front| front.timestamp < retain_timestamp).unwrap_or(false) {
+            self.recent_adds.pop_front();
+        }
+
+        let recent_add_count = self.recent_adds.iter().map(|recent_add| recent_add.value as u64).sum::<u64>();
+
+        if (recent_add_count > alert_add_count) &&
+            self.last_alert_timestamp.map(|last| now >= (last + alert_duration_ms)).unwrap_or(true)
+        {
+            println!(
+                "ALERT: classification {} added {recent_add_count} ip addresses to groups in the last \
+                 {alert_duration_ms} ms, exceeding alert_add_count {alert_add_count}",
+                self.name
+            );
+            self.alert_count += 1;
+            self.last_alert_timestamp = Some(now);
+        }
     }
 }
@@ -0,0 +1,484 @@
+#[cfg(feature = "async-ingest")]
+use crate::async_listener;
+use crate::canary::{Canaries, CANARY_SOURCE};
+use crate::clock::Clock;
+use crate::config::{Config, DEFAULT_INGEST_SOURCE};
+use crate::deny_list::DenyListSink;
+use crate::deny_list_import::DenyListImport;
+use crate::exec_hook::ExecHookSink;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::ipset::IpsetSink;
+use crate::json_output::JsonOutputSink;
+use crate::listener::{IngestCounters, IngestSources, MessageLayout};
+use crate::log::LogLevel;
+use crate::membership_file::MembershipFile;
+use crate::nftables::NftablesSink;
+use crate::origin::OriginReportSink;
+use crate::otlp::OtlpSink;
+use crate::peer_export::PeerExportSink;
+use crate::peer_notice::PeerNoticeSink;
+use crate::plugin::SubprocessPlugin;
+use crate::recording::Recorder;
+#[cfg(feature = "test-support")]
+use crate::scenario;
+use crate::sink::{
+    MembershipOutput, Sink, StdoutSink, DEFAULT_SINK_QUEUE_SIZE, DEFAULT_SINK_RETRY_COUNT, DEFAULT_SINK_RETRY_DELAY_MS
+};
+use crate::state::{State, DEFAULT_PERIODIC_INTERVAL_MS};
+use crate::verify::Verifier;
+use crate::webhook::WebhookSink;
+use crate::{admin, api, journal, listener, log, reload, replay, sink, TxIngestMsg};
+use crossbeam::channel::{unbounded, RecvTimeoutError};
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::Arc;
+
+// Runs the classifier binary, as directed by the arguments of the process
+pub fn run()
+{
+    let input_args = std::env::args().skip(1).collect::<Vec<String>>();
+
+    // The schema subcommand prints a JSON Schema describing the config file, for use by editors and config validation
+    if input_args.first().map(|arg| arg == "schema").unwrap_or(false) {
+        println!("{}", serde_json::to_string_pretty(&schemars::schema_for!(Config)).unwrap());
+        return;
+    }
+
+    // The scenario subcommand runs a canned scenario against a config and prints the resulting group members, so that
+    // config authors can check how their thresholds classify common traffic patterns
+    #[cfg(feature = "test-support")]
+    if input_args.first().map(|arg| arg == "scenario").unwrap_or(false) {
+        if input_args.len() != 3 {
+            eprintln!("ERROR: Incorrect number of arguments: must be: scenario <CONFIG_JSON_FILE> <SCENARIO_NAME>");
+            for canned in scenario::CANNED_SCENARIOS {
+                eprintln!("  {}: {}", canned.name, canned.description);
+            }
+            std::process::exit(-1);
+        }
+        let config = load_config(&input_args[1])
+            .unwrap_or_else(|e| error_exit(format!("ERROR: Failed to read config file {}: {e}", input_args[1])));
+        let members =
+            scenario::run_canned(config, &input_args[2]).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")));
+        println!("{}", serde_json::to_string_pretty(&members).unwrap());
+        return;
+    }
+
+    // The replay subcommand drives the classifier from recorded events instead of live ingest, printing the group
+    // changes that a config would have made and the resulting group members, for tuning thresholds against real traffic
+    if input_args.first().map(|arg| arg == "replay").unwrap_or(false) {
+        let (speed, args) = match &input_args[1..] {
+            [option, speed, args @ ..] if option == "--speed" => (
+                Some(
+                    speed
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|speed| speed.is_finite() && (*speed > 0.0))
+                        .unwrap_or_else(|| error_exit(format!("ERROR: Invalid replay speed {speed}")))
+                ),
+                args
+            ),
+            args => (None, args)
+        };
+        if args.len() < 2 {
+            error_exit(
+                "ERROR: Incorrect number of arguments: must be: replay [--speed <FACTOR>] <CONFIG_JSON_FILE> \
+                 <RECORDING_FILE>..."
+                    .to_string()
+            );
+        }
+        let config = load_config(&args[0])
+            .unwrap_or_else(|e| error_exit(format!("ERROR: Failed to read config file {}: {e}", args[0])));
+        let members = replay::run(config, &args[1..], speed).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")));
+        println!("{}", serde_json::to_string_pretty(&members).unwrap());
+        return;
+    }
+
+    if (input_args.len() < 2) || (input_args.len() > 3) {
+        eprintln!("ERROR: Incorrect number of arguments: must be: <LISTEN_ADDRESS> <LISTEN_PORT> [CONFIG_JSON_FILE]");
+        eprintln!("   or: schema");
+        eprintln!("   or: replay [--speed <FACTOR>] <CONFIG_JSON_FILE> <RECORDING_FILE>...");
+        eprintln!("Examples:");
+        eprintln!("  # To listen on localhost at port 15151, and use the default ./config.json file:");
+        eprintln!("  txingest-classifier 127.0.0.1 15151");
+        eprintln!("  # To listen on localhost at port 15151, and use the config file /etc/txingest.json file:");
+        eprintln!("  txingest-classifier 127.0.0.1 15151 /etc/txingest.json");
+        eprintln!("  # To print the JSON Schema of the config file:");
+        eprintln!("  txingest-classifier schema");
+        eprintln!("  # To replay recorded events against /etc/txingest.json at ten times the recorded rate:");
+        eprintln!("  txingest-classifier replay --speed 10 /etc/txingest.json events.rec");
+        std::process::exit(-1);
+    }
+
+    let host = input_args[0]
+        .parse::<Ipv4Addr>()
+        .unwrap_or_else(|e| error_exit(format!("ERROR: Invalid listen address {}: {e}", input_args[0])));
+    let port = input_args[1]
+        .parse::<u16>()
+        .unwrap_or_else(|e| error_exit(format!("ERROR: Invalid listen port {}: {e}", input_args[1])));
+    let config_path = if input_args.len() == 3 { input_args[2].clone() } else { "config.json".to_string() };
+    let mut config = load_config(&config_path)
+        .unwrap_or_else(|e| error_exit(format!("ERROR: Failed to read config file {config_path}: {e}")));
+
+    // Listen
+    let tcp_listener = loop {
+        match TcpListener::bind(std::net::SocketAddr::V4(std::net::SocketAddrV4::new(host, port))) {
+            Ok(tcp_listener) => break tcp_listener,
+            Err(e) => {
+                eprintln!("Failed bind because {e}, trying again in 1 second");
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+    };
+
+    let (sender, receiver) = unbounded::<(Arc<str>, TxIngestMsg)>();
+
+    let ingest_counters = Arc::new(IngestCounters::default());
+
+    // Map from sender address to the source name with which its events are tagged
+    let ingest_sources = Arc::new(IngestSources {
+        sources : config
+            .ingest_sources
+            .iter()
+            .flatten()
+            .flat_map(|ingest_source| {
+                let name = Arc::<str>::from(ingest_source.name.as_str());
+                ingest_source.addresses.iter().map(move |address| (*address, name.clone()))
+            })
+            .collect(),
+        default_source : Arc::<str>::from(DEFAULT_INGEST_SOURCE),
+        log_deprecated : config.log_deprecated_senders.unwrap_or(false),
+        rate_limit : config.ingest_rate_limit.clone(),
+        allowed_senders : config
+            .allowed_senders
+            .as_ref()
+            .map(|allowed_senders| allowed_senders.iter().cloned().collect()),
+        idle_timeout : config.ingest_idle_timeout_ms.map(std::time::Duration::from_millis),
+        counters : ingest_counters.clone()
+    });
+
+    // Listeners use a thread per connection, unless configured to multiplex connections on an async runtime (which
+    // validation ensures only when built with the async-ingest feature)
+    let ingest_async_threads = config.ingest_async_threads;
+    let spawn_listener = |tcp_listener, layout, ingest_sources, sender| match ingest_async_threads {
+        #[cfg(feature = "async-ingest")]
+        Some(worker_threads) => {
+            async_listener::spawn_listener(tcp_listener, layout, ingest_sources, sender, worker_threads)
+        },
+        _ => listener::spawn_listener(tcp_listener, layout, ingest_sources, sender)
+    };
+
+    // Spawn the listener
+    spawn_listener(tcp_listener, MessageLayout::Current, ingest_sources.clone(), sender.clone());
+
+    // Spawn the legacy listener if configured, for senders still using the older message layout
+    if let Some(legacy_listen_address) = &config.legacy_listen_address {
+        let legacy_tcp_listener = TcpListener::bind(legacy_listen_address.parse::<std::net::SocketAddr>().unwrap())
+            .unwrap_or_else(|e| error_exit(format!("ERROR: Failed to bind legacy listen address: {e}")));
+        spawn_listener(legacy_tcp_listener, MessageLayout::Legacy, ingest_sources, sender);
+    }
+
+    let mut sinks : Vec<Box<dyn Sink>> = vec![];
+
+    // JSON output to stdout takes the place of the free-form lines describing events
+    match config.json_output.take() {
+        Some(json_output) if json_output.is_stdout() => {
+            sinks.push(Box::new(MembershipOutput::without_snapshots(JsonOutputSink::new(json_output))))
+        },
+        json_output => {
+            sinks.push(Box::new(MembershipOutput::without_snapshots(StdoutSink)));
+            if let Some(json_output) = json_output {
+                sinks.push(Box::new(MembershipOutput::without_snapshots(JsonOutputSink::new(json_output))));
+            }
+        }
+    }
+
+    if let Some(deny_list) = config.deny_list.take() {
+        sinks.push(Box::new(MembershipOutput::without_snapshots(DenyListSink::new(deny_list))));
+    }
+
+    if let Some(peer_notices) = config.peer_notices.take() {
+        sinks.push(Box::new(PeerNoticeSink::new(peer_notices).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))));
+    }
+
+    if let Some(nftables) = config.nftables.take() {
+        sinks.push(Box::new(NftablesSink::new(nftables)));
+    }
+
+    if let Some(ipset) = config.ipset.take() {
+        sinks.push(Box::new(IpsetSink::new(ipset)));
+    }
+
+    if let Some(exec_hooks) = config.exec_hooks.take() {
+        sinks.push(Box::new(MembershipOutput::without_snapshots(ExecHookSink::new(exec_hooks))));
+    }
+
+    for webhook in config.webhooks.take().into_iter().flatten() {
+        sinks.push(Box::new(WebhookSink::new(webhook)));
+    }
+
+    if let Some(otlp) = config.otlp.take() {
+        sinks.push(Box::new(OtlpSink::new(otlp)));
+    }
+
+    if let Some(origin_report) = config.origin_report.take() {
+        sinks
+            .push(Box::new(OriginReportSink::new(origin_report).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))));
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_listen_address) = &config.grpc_listen_address {
+        sinks.push(Box::new(
+            grpc::spawn_server(
+                grpc_listen_address.parse().unwrap(),
+                config.grpc_queue_size.unwrap_or(grpc::DEFAULT_GRPC_QUEUE_SIZE)
+            )
+            .unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))
+        ));
+    }
+
+    // The peer export config is left in place, since State sends peer_evicted events only if it is present
+    if let Some(path) = config.peer_export.as_ref().and_then(|peer_export| peer_export.path.clone()) {
+        sinks.push(Box::new(PeerExportSink::new(path)));
+    }
+
+    if let Some(sink_journal) = config.sink_journal.take() {
+        sinks = journal::wrap(sink_journal, sinks).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")));
+    }
+
+    // Everything is scheduled on the internal clock, which system clock adjustments cannot distort
+    let mut clock = Clock::new(config.clock_jump.as_ref());
+
+    // Canaries observe their own group changes through a sink, so that each test covers the sink queue too
+    let mut canaries = config.canaries.take().map(|canaries| {
+        let (canaries, canary_sink) = Canaries::new(canaries, clock.now());
+        sinks.push(Box::new(canary_sink));
+        canaries
+    });
+
+    let sink_descriptions = sinks.iter().map(|sink| sink.describe()).collect::<Vec<_>>();
+
+    // Spawn the sink worker, which performs all event side effects off of the event processing thread
+    let sink = sink::spawn_worker(
+        config.sink_queue_size.unwrap_or(DEFAULT_SINK_QUEUE_SIZE),
+        config.sink_retry_count.unwrap_or(DEFAULT_SINK_RETRY_COUNT),
+        config.sink_retry_delay_ms.unwrap_or(DEFAULT_SINK_RETRY_DELAY_MS),
+        sinks
+    );
+
+    let periodic_interval_ms = config.periodic_interval_ms.unwrap_or(DEFAULT_PERIODIC_INTERVAL_MS);
+
+    // Don't wait for events for longer than the periodic interval, so that periodic work is not delayed
+    let receive_timeout = std::time::Duration::from_millis(std::cmp::min(100, periodic_interval_ms));
+
+    let api_listen_address = config.api_listen_address.clone();
+    let legacy_listen_address = config.legacy_listen_address.clone();
+    let admin_socket_path = config.admin_socket_path.clone();
+    let grpc_listen_address = config.grpc_listen_address.clone();
+
+    // Load the shadow config before config is consumed, since by default it is the same config file
+    let mut verifier = config.verification.take().map(|verification| {
+        let shadow_config_path = verification.shadow_config.clone().unwrap_or_else(|| config_path.clone());
+        let shadow_config = load_config(&shadow_config_path).unwrap_or_else(|e| {
+            error_exit(format!("ERROR: Failed to read shadow config file {shadow_config_path}: {e}"))
+        });
+        Verifier::new(&verification, shadow_config)
+    });
+
+    log::set_level(config.log_level.unwrap_or(LogLevel::Normal));
+    log::install_signal_handlers();
+    reload::install_signal_handler();
+
+    let deny_list_import = config.deny_list_import.take();
+
+    let membership_file = config.membership_file.take();
+
+    let mut recorder = config
+        .recording
+        .take()
+        .map(|recording| Recorder::new(recording).unwrap_or_else(|e| error_exit(format!("ERROR: {e}"))));
+
+    let plugins = config.plugins.take();
+
+    let mut state = State::new(config, sink, ingest_counters);
+
+    for plugin in plugins.into_iter().flatten() {
+        state.register_plugin(Box::new(
+            SubprocessPlugin::new(plugin).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))
+        ));
+    }
+
+    // Saved members are restored before any event is handled, so that they are enforced from the start
+    let mut membership_file = membership_file.map(|membership_file| {
+        MembershipFile::new(membership_file, &mut state, clock.now())
+            .unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))
+    });
+
+    // The deny list is imported before any event is handled, so that its members are enforced from the start
+    let mut deny_list_import = deny_list_import.map(|deny_list_import| {
+        DenyListImport::new(deny_list_import, &mut state, clock.now())
+            .unwrap_or_else(|e| error_exit(format!("ERROR: {e}")))
+    });
+
+    // Report what this process will do, with all defaults resolved, in a form that fleet tooling can parse and compare
+    // across nodes
+    let process_capabilities = serde_json::json!({
+        "periodic_interval_ms" : periodic_interval_ms,
+        "listen" : {
+            "ingest" : format!("{host}:{port}"),
+            "legacy" : legacy_listen_address,
+            "api" : api_listen_address,
+            "admin" : admin_socket_path,
+            "grpc" : grpc_listen_address
+        },
+        "sinks" : sink_descriptions,
+        "recording" : recorder.as_ref().map(Recorder::describe),
+        "membership_file" : membership_file.as_ref().map(MembershipFile::describe)
+    });
+    println!("Capabilities: {}", capabilities(&state, &process_capabilities));
+
+    // Spawn the API server if configured.  Queries are answered from snapshots published by this thread after each
+    // periodic pass; requests that change state are handled on this thread, between events.
+    let (api_sender, api_receiver) = unbounded::<api::ApiRequest>();
+
+    let snapshot_cell = api_listen_address.map(|api_listen_address| {
+        let snapshot_cell = Arc::new(api::SnapshotCell::new(capabilities(&state, &process_capabilities)));
+        api::spawn_server(api_listen_address.parse().unwrap(), snapshot_cell.clone(), api_sender)
+            .unwrap_or_else(|e| error_exit(e));
+        snapshot_cell
+    });
+
+    // Spawn the admin socket server if configured.  Its commands are handled on this thread, between events.
+    let (admin_sender, admin_receiver) = unbounded::<admin::AdminRequest>();
+
+    if let Some(admin_socket_path) = &admin_socket_path {
+        admin::spawn_server(admin_socket_path, admin_sender).unwrap_or_else(|e| error_exit(format!("ERROR: {e}")));
+    }
+
+    let mut last_periodic_timestamp = 0;
+
+    let canary_source = Arc::<str>::from(CANARY_SOURCE);
+
+    loop {
+        // Checked before every event, so that events reported after a jump are handled on the re-anchored clock
+        if let Some(jump_ms) = clock.check() {
+            state.clock_jumped(jump_ms);
+        }
+
+        state.clock_read(clock.now(), Clock::system_now());
+
+        // Receive with a timeout
+        match receiver.recv_timeout(receive_timeout) {
+            Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => (),
+            Ok((source, msg)) => {
+                if let Some(recorder) = &mut recorder {
+                    recorder.record(Clock::system_now(), &source, &msg);
+                }
+                if let Some(verifier) = &mut verifier {
+                    verifier.handle(&source, msg.clone());
+                }
+                state.handle(&source, msg)
+            }
+        }
+
+        // Canary events are handled exactly as received events are
+        if let Some(canaries) = &mut canaries {
+            for msg in canaries.poll(&mut state, clock.now()) {
+                if let Some(verifier) = &mut verifier {
+                    verifier.handle(&canary_source, msg.clone());
+                }
+                state.handle(&canary_source, msg);
+            }
+        }
+
+        if let Some(deny_list_import) = &mut deny_list_import {
+            deny_list_import.poll(&mut state, clock.now());
+        }
+
+        if let Some(membership_file) = &mut membership_file {
+            membership_file.poll(&state, clock.now());
+        }
+
+        let mut publish_snapshot = false;
+
+        if log::take_signaled() {
+            println!("Log level changed to {} by signal", log::level().as_str());
+            publish_snapshot = true;
+        }
+
+        if reload::take_signaled() {
+            match reload::reload(&mut state, &config_path) {
+                Ok(changes) => print!("{changes}"),
+                Err(e) => eprintln!("{e}")
+            }
+            publish_snapshot = true;
+        }
+
+        while let Ok(api_request) = api_receiver.try_recv() {
+            let api_response = api::handle(&mut state, &api_request);
+            api_request.respond(api_response);
+            publish_snapshot = true;
+        }
+
+        while let Ok(admin_request) = admin_receiver.try_recv() {
+            let admin_response = admin::handle(
+                &mut state,
+                deny_list_import.as_mut(),
+                recorder.as_mut(),
+                &config_path,
+                &admin_request.command,
+                clock.now()
+            );
+            admin_request.respond(admin_response);
+            publish_snapshot = true;
+        }
+
+        let now = clock.now();
+
+        state.flush_due_tx(now);
+
+        if now >= (last_periodic_timestamp + periodic_interval_ms) {
+            state.periodic(now);
+            if let Some(verifier) = &mut verifier {
+                verifier.periodic(&state, now);
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.flush();
+            }
+            last_periodic_timestamp = now;
+            publish_snapshot = true;
+        }
+
+        if publish_snapshot {
+            if let Some(snapshot_cell) = &snapshot_cell {
+                snapshot_cell.publish(api::snapshot(&state), capabilities(&state, &process_capabilities));
+            }
+        }
+    }
+}
+
+// Returns the capability report: that of state, which describes it as currently configured, with that of the process
+fn capabilities(
+    state : &State,
+    process_capabilities : &serde_json::Value
+) -> serde_json::Value
+{
+    let mut capabilities = state.capabilities();
+    for (key, value) in process_capabilities.as_object().into_iter().flatten() {
+        capabilities[key] = value.clone();
+    }
+    capabilities
+}
+
+fn error_exit(msg : String) -> !
+{
+    eprintln!("{msg}");
+    std::process::exit(-1);
+}
+
+fn load_config(path : &str) -> Result<Config, String>
+{
+    eprintln!("Reading {path}");
+
+    Config::load(path)
+}
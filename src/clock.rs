@@ -0,0 +1,107 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::time::Instant;
+
+pub const DEFAULT_CLOCK_JUMP_THRESHOLD_MS : u64 = 10 * 1000; // 10 seconds
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClockJumpConfig
+{
+    // Milliseconds by which the system clock must advance beyond (or fall short of) the time elapsed on the monotonic
+    // clock to be considered to have jumped.  If not present, a default of 10 seconds is used.
+    pub threshold_ms : Option<u64>,
+
+    // How a jump is handled: "reanchor" or "follow".  If not present, "reanchor" is used.
+    pub action : Option<ClockJumpAction>
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockJumpAction
+{
+    // Time continues from where it was before the jump: the jump is absorbed into an offset applied to every
+    // timestamp from then on, so that memberships are neither expired early by a forward jump nor held late by a
+    // backward one, and classification windows neither collapse nor stretch
+    #[default]
+    Reanchor,
+
+    // Time follows the system clock, as if it had not jumped; the jump is only logged
+    Follow
+}
+
+impl ClockJumpConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.threshold_ms == Some(0) {
+            return Err("Invalid clock_jump threshold_ms: zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// The internal clock, against which everything is scheduled: the system clock's time at startup, advanced from then on
+// by the monotonic clock, so that adjustments of the system clock (as from NTP corrections or VM pauses) cannot
+// distort periodic cadences or windows.  The offset of the system clock from it is watched, and changes of more than
+// the threshold are reported as jumps, so that event timestamps (read from the system clock of the validator) can be
+// translated onto it.  Slow drift of the system clock accumulates until it too is reported as a jump.
+pub struct Clock
+{
+    threshold_ms : u64,
+
+    action : ClockJumpAction,
+
+    // Monotonic clock time and internal clock time at which the internal clock was anchored
+    anchor : (Instant, u64),
+
+    // Offset of the system clock from the internal clock as of the most recent jump
+    offset_ms : i64
+}
+
+impl Clock
+{
+    pub fn new(config : Option<&ClockJumpConfig>) -> Self
+    {
+        Self {
+            threshold_ms : config.and_then(|config| config.threshold_ms).unwrap_or(DEFAULT_CLOCK_JUMP_THRESHOLD_MS),
+            action : config.and_then(|config| config.action).unwrap_or_default(),
+            anchor : (Instant::now(), Self::system_now()),
+            offset_ms : 0
+        }
+    }
+
+    // Milliseconds since the epoch on the system clock
+    pub fn system_now() -> u64
+    {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+
+    // Milliseconds since the epoch on the internal clock
+    pub fn now(&self) -> u64
+    {
+        self.anchor.1 + (self.anchor.0.elapsed().as_millis() as u64)
+    }
+
+    // Returns the size in milliseconds of the jump of the system clock since the previous one (negative for a
+    // backward jump), or None if it has not jumped.  Under the follow action, the internal clock is moved by the jump,
+    // so that it keeps reading the same as the system clock.
+    pub fn check(&mut self) -> Option<i64>
+    {
+        let offset_ms = (Self::system_now() as i64) - (self.now() as i64);
+
+        let jump_ms = offset_ms - self.offset_ms;
+
+        if jump_ms.unsigned_abs() <= self.threshold_ms {
+            return None;
+        }
+
+        match self.action {
+            ClockJumpAction::Reanchor => self.offset_ms = offset_ms,
+            ClockJumpAction::Follow => self.anchor.1 = self.anchor.1.saturating_add_signed(jump_ms)
+        }
+
+        Some(jump_ms)
+    }
+}
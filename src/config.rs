@@ -1,7 +1,44 @@
+use crate::canary::CanaryConfig;
 use crate::classification::Classification;
-use serde::Deserialize;
+use crate::clock::ClockJumpConfig;
+use crate::deny_list::DenyListConfig;
+use crate::deny_list_import::DenyListImportConfig;
+use crate::epoch::EpochRollupConfig;
+use crate::exec_hook::ExecHooksConfig;
+use crate::ipset::IpsetConfig;
+use crate::journal::SinkJournalConfig;
+use crate::json_output::JsonOutputConfig;
+use crate::log::LogLevel;
+use crate::membership_file::MembershipFileConfig;
+use crate::nftables::NftablesConfig;
+use crate::origin::OriginReportConfig;
+use crate::otlp::OtlpConfig;
+use crate::peer_export::PeerExportConfig;
+use crate::peer_notice::PeerNoticeConfig;
+use crate::plugin::{self, PluginConfig};
+use crate::recording::RecordingConfig;
+use crate::state::PeerCounters;
+use crate::storm::StormRule;
+use crate::verify::VerificationConfig;
+use crate::webhook::WebhookConfig;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_path_to_error::Segment;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 
-#[derive(Deserialize)]
+// Descriptive metadata for a group, for those who must act on its changes.  Any keys may be used; owner, runbook_url
+// and description are conventional.
+pub type GroupMetadata = BTreeMap<String, String>;
+
+// Source name given to events received from senders not listed in ingest_sources
+pub const DEFAULT_INGEST_SOURCE : &str = "default";
+
+// Maximum number of errors reported from a single parse of a config file
+const MAX_PARSE_ERRORS : usize = 100;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct PubkeyClassification
 {
     // If not provided, default of "known_pubkeys" is used
@@ -14,15 +51,390 @@ pub struct PubkeyClassification
     pub pubkey : String
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct LeaderSlotsClassification
 {
     pub group_name : Option<String>,
 
-    pub leader_slots : u64
+    pub leader_slots : u64,
+
+    // What leader status is assumed after startup until the first leader event is received.  If not present,
+    // assume_not_leader is used.
+    pub startup_leader_status : Option<StartupLeaderStatus>,
+
+    // Additional windows, each with its own number of slots before leader slots, allowing different groups to have
+    // different policies applied outside of leader slots (e.g. throttle unstaked peers at 20 slots before leader
+    // slots, but block known spammers at 100 slots before)
+    pub windows : Option<Vec<LeaderSlotsWindow>>
 }
 
-#[derive(Deserialize)]
+// The leader status assumed after startup, before any WillBeLeader, BeginLeader, or EndLeader event has been received
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, Serialize)]
+pub enum StartupLeaderStatus
+{
+    // Outside of leader slots, so that outside leader slots policies are applied immediately
+    #[default]
+    #[serde(rename = "assume_not_leader")]
+    AssumeNotLeader,
+
+    // Within leader slots, so that outside leader slots policies are not applied until an event says otherwise
+    #[serde(rename = "assume_leader")]
+    AssumeLeader,
+
+    // No leader status is reported for this many milliseconds after startup, giving the validator time to send a
+    // leader event; if none has been received by then, outside of leader slots is assumed
+    #[serde(rename = "wait")]
+    Wait(u64)
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LeaderSlotsWindow
+{
+    // Name of the group whose outside leader slots policy is controlled by this window
+    pub group_name : String,
+
+    // Number of slots before leader slots to apply the outside leader slots policy for the group
+    pub leader_slots : u64,
+
+    // Leader status for this window -- Some(true) if within leader_slots of leader slots, Some(false) if not; None
+    // until known
+    #[serde(skip)]
+    pub leader_status : Option<bool>
+}
+
+// Places never-before-seen unstaked peers into a short-lived probation group until they demonstrate a minimum tx
+// volume or fee contribution
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ProbationClassification
+{
+    // If not provided, default of "probation" is used
+    pub group_name : Option<String>,
+
+    // How long new peers are held in the probation group if they never demonstrate the minimum tx volume or fee
+    // contribution.  If not specified, a default value of 10 minutes is used.
+    pub group_expiration_seconds : Option<u64>,
+
+    // If present, peers are released from probation once they have submitted at least this many tx
+    pub min_tx_count : Option<u64>,
+
+    // If present, peers are released from probation once they have been credited with at least this many lamports
+    // of fees for landed tx
+    pub min_fee_lamports : Option<u64>
+}
+
+// Which of the tx submitted by a peer are counted, by kind
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq)]
+pub enum TxKind
+{
+    // Votes and user tx alike
+    #[default]
+    #[serde(rename = "all")]
+    All,
+
+    // Only user (non-vote) tx
+    #[serde(rename = "user")]
+    User,
+
+    // Only votes
+    #[serde(rename = "vote")]
+    Vote
+}
+
+// What is done with the history of an address when the pubkey reported for it changes
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+pub enum PubkeyChangeAction
+{
+    // Its counters and classification values are kept
+    #[serde(rename = "preserve")]
+    Preserve,
+
+    // Its counters and classification values are discarded, so that the new identity starts fresh
+    #[serde(rename = "reset")]
+    Reset
+}
+
+// How the expiration of an existing group membership is changed when its member is added to the group again, possibly
+// by a different rule with a different group_expiration_seconds
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq)]
+pub enum GroupTtlPolicy
+{
+    // The membership expires at the later of its current expiration and the expiration given by the new add
+    #[default]
+    #[serde(rename = "max")]
+    Max,
+
+    // The membership expires as given by the most recent add, which may shorten it
+    #[serde(rename = "latest")]
+    Latest,
+
+    // The membership is extended by the duration given by the rule that originally added the member; later adds by
+    // other rules only refresh it
+    #[serde(rename = "extend_only")]
+    ExtendOnly
+}
+
+// Which events are sent to sinks when the expiration of an existing group membership is extended, e.g. each time a
+// member still meeting a threshold is added again
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq)]
+pub enum ExtensionEvents
+{
+    // An update event for every extension
+    #[default]
+    #[serde(rename = "update")]
+    Update,
+
+    // No events; sinks see only adds and removes, so expirations reported to them are those given when added
+    #[serde(rename = "none")]
+    None,
+
+    // An update event for an extension only if none was sent for the member within this many milliseconds (adds
+    // included)
+    #[serde(rename = "every_ms")]
+    EveryMs(u64),
+
+    // An extend event, distinct from the update events re-sent on returning to enforce mode, for every extension
+    #[serde(rename = "extend")]
+    Extend
+}
+
+// Periodic summaries of the activity of each group, as a heartbeat of policy activity for log based monitoring
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSummaryConfig
+{
+    // Format of each summary: "line" for a human readable line per group, or "json" for a JSON object per group.  If
+    // not present, "line" is used.
+    pub format : Option<GroupSummaryFormat>,
+
+    // Milliseconds between summaries.  If not present, a summary is emitted every periodic pass.
+    pub interval_ms : Option<u64>,
+
+    // Number of rules listed in each summary, those that added the most members since the previous summary.  If not
+    // present, a default of 3 is used.
+    pub top_rule_count : Option<usize>
+}
+
+#[derive(Clone, Copy, Default, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupSummaryFormat
+{
+    #[default]
+    Line,
+
+    Json
+}
+
+// A rule that fires when a group's member count exceeds a limit, triggering actions that feed the scale of enforcement
+// back into policy
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GroupSizeRule
+{
+    // Group whose member count is watched
+    pub group_name : String,
+
+    // The rule fires when the group has more than this many members
+    pub max_members : usize,
+
+    // Once fired, the rule clears when the group has this many members or fewer, so that a group hovering around
+    // max_members does not toggle the actions.  If not present, max_members is used.
+    pub clear_members : Option<usize>,
+
+    // Actions taken while the rule is firing
+    pub actions : Vec<GroupSizeAction>
+}
+
+// Limits on how long memberships of a group last, whatever the rules adding members request
+#[derive(Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GroupExpirationLimits
+{
+    // If present, memberships are held for at most this long from when they were added or last extended; longer
+    // expirations requested by rules are clamped to this
+    pub max_expiration_seconds : Option<u64>,
+
+    // If present and true, members never expire, and are only removed manually via the API.  Permanent memberships
+    // are reported with the maximum expiration (18446744073709551615).
+    pub permanent : Option<bool>
+}
+
+// A rate to which downstream enforcement should limit the members of a group that is a throttle rather than a block.
+// At least one limit must be present.
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleRate
+{
+    // If present, the maximum number of new QUIC connections per minute to allow from each member
+    pub max_connections_per_minute : Option<u64>,
+
+    // If present, the maximum number of tx per second to accept from each member
+    pub max_tps : Option<u64>
+}
+
+impl ThrottleRate
+{
+    // Returns the stricter of each limit of self and other
+    pub fn min(
+        &self,
+        other : &ThrottleRate
+    ) -> ThrottleRate
+    {
+        let min = |a : Option<u64>, b : Option<u64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b)
+        };
+
+        ThrottleRate {
+            max_connections_per_minute : min(self.max_connections_per_minute, other.max_connections_per_minute),
+            max_tps : min(self.max_tps, other.max_tps)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+pub enum GroupSizeAction
+{
+    // An event is sent to sinks when the rule fires and when it clears
+    #[serde(rename = "notify")]
+    Notify,
+
+    // Classifications having overload_thresholds apply them in place of their thresholds
+    #[serde(rename = "overload_thresholds")]
+    OverloadThresholds,
+
+    // IPv4 peers are classified by /24 network rather than by address: events from every address in a /24 are
+    // attributed to the /24's network address, and group changes for the network address are also sent for each
+    // address in the /24 seen since
+    #[serde(rename = "coarse_grouping")]
+    CoarseGrouping
+}
+
+// How thresholds treat peers whose stake is unknown, because no connection reporting their stake has been seen
+#[derive(Clone, Copy, Deserialize, JsonSchema, Serialize)]
+pub enum UnknownStake
+{
+    // Unknown stake is treated as zero stake
+    #[serde(rename = "zero")]
+    Zero,
+
+    // Thresholds with a low_stake or high_stake do not apply to peers of unknown stake; thresholds without a stake
+    // range still do
+    #[serde(rename = "skip_stake_banded")]
+    SkipStakeBanded,
+
+    // Unknown stake is treated as this stake, in lamports
+    #[serde(rename = "assumed")]
+    Assumed(u64)
+}
+
+// Weights applied to the change in each of a peer's counters over a scoring interval to compute the peer's score.
+// Weights not present are zero.  Negative weights may be used for counters that indicate good behavior.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PeerScoreWeights
+{
+    // Weight per tx submitted (votes + user)
+    pub tx_submitted : Option<f64>,
+
+    // Weight per vote tx submitted, in addition to the tx_submitted weight
+    pub vote_tx_submitted : Option<f64>,
+
+    // Weight per user tx submitted, in addition to the tx_submitted weight
+    pub user_tx_submitted : Option<f64>,
+
+    // Weight per lamport of fees credited for landed tx
+    pub fee_lamports : Option<f64>,
+
+    // Weight per connection started (connection churn)
+    pub connection_count : Option<f64>,
+
+    // Weight per failed or refused connection
+    pub failed_count : Option<f64>,
+
+    // Weight per duplicate submission of a tx first submitted by another peer
+    pub duplicate_count : Option<f64>
+}
+
+// A set of addresses that are treated as one logical peer, e.g. the egress addresses of a relayer or load balancer.
+// Events from all of the addresses are accounted to the first address, and group decisions made for the peer apply
+// to all of the addresses.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EquivalentAddresses
+{
+    pub addresses : Vec<IpAddr>
+}
+
+// Names the events received from a set of senders, so that classifications may be scoped to specific feeds (e.g.
+// a validator's TPU feed vs a relayer's feed)
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IngestSource
+{
+    pub name : String,
+
+    // Addresses of the senders connecting to the ingest listener whose events are tagged with this source name
+    pub addresses : Vec<IpAddr>,
+
+    // The validator service whose connections this source reports.  The ingest protocol does not convey which port a
+    // peer connected to, so senders which report each service on a separate ingest connection identify the service
+    // here.  If not present, the service of this source's events is unknown.
+    pub service : Option<TpuService>,
+
+    // If present and true, this source reports traffic that arrived via a relayer rather than directly from peers,
+    // so that classifications may accumulate it separately
+    pub relayer : Option<bool>
+}
+
+// A validator service that peers connect to
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub enum TpuService
+{
+    #[serde(rename = "tpu")]
+    Tpu,
+
+    #[serde(rename = "tpu_forward")]
+    TpuForward,
+
+    #[serde(rename = "vote")]
+    Vote
+}
+
+impl TpuService
+{
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            TpuService::Tpu => "tpu",
+            TpuService::TpuForward => "tpu_forward",
+            TpuService::Vote => "vote"
+        }
+    }
+}
+
+// Limit on the rate of events accepted from each ingest connection, so that one runaway sender cannot starve the
+// others
+#[derive(Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IngestRateLimit
+{
+    // Maximum sustained number of events per second accepted from a single connection
+    pub events_per_second : u64,
+
+    // Number of events that may be accepted at once in excess of the sustained rate.  If not present, one second's
+    // worth of events is allowed.
+    pub burst : Option<u64>,
+
+    // If present and true, a connection exceeding the limit is closed; otherwise its events in excess of the limit are
+    // dropped
+    pub disconnect : Option<bool>
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Config
 {
     // Classification by known pubkey
@@ -34,6 +446,11 @@ pub struct Config
     // the connection to be considered "useless".  If not present, a default of 2 seconds is used.
     pub useless_quic_connection_duration_ms : Option<u64>,
 
+    // Which tx a peer must have submitted for its QUIC connections not to be considered "useless": "all", "user", or
+    // "vote".  With "user", peers that have only ever submitted votes are classified as if they had submitted nothing.
+    // If not present, "all" is used.
+    pub useless_quic_connection_tx : Option<TxKind>,
+
     pub useless_quic_connections : Option<Classification>,
 
     // Only the first submitter of a tx that is submitted by multiple sources gets fee credit for the tx.
@@ -55,9 +472,414 @@ pub struct Config
     // Note that only landed tx are included here.
     pub fee_microlamports_per_cu_used : Option<Classification>,
 
+    // Percentage of each peer's submitted tx that were credited with zero fee, either because they never landed or
+    // because they were duplicates of tx first submitted by another peer.  Each submission gets a value of 100 if
+    // credited with zero fee and 0 otherwise, so thresholds should use the "average" value_operation to evaluate the
+    // percentage.
+    pub zero_fee_share : Option<Classification>,
+
+    // If present, the submissions of peers with less stake than this, in lamports, are left out of the fee
+    // classifications above entirely, as if never submitted.  Unlike the low_stake of thresholds, this applies to every
+    // threshold of every fee classification, so that peers with very different fee expectations (e.g. unstaked RPC
+    // aggregators and staked validators) can be kept apart without configuring matching stake bands on each.  Peers of
+    // unknown stake are treated according to unknown_stake, with "skip_stake_banded" leaving them out.
+    pub fee_min_stake : Option<u64>,
+
+    // If present, the submissions of peers with more stake than this, in lamports, are left out of the fee
+    // classifications above entirely, as for fee_min_stake
+    pub fee_max_stake : Option<u64>,
+
+    // Changes of the pubkey reported for a peer's address.  Each change gets a value of 1, so that identity churn at an
+    // address can be classified.
+    pub pubkey_changes : Option<Classification>,
+
+    // What to do with the history of an address when the pubkey reported for it changes: "preserve" keeps its counters
+    // and classification values, "reset" discards them so that the new identity starts fresh.  If not present,
+    // "preserve" is used.
+    pub pubkey_change_action : Option<PubkeyChangeAction>,
+
+    // Percentage by which the stake reported for a peer must change between connections for the change to be
+    // material: recorded in the peer's stake history and sent to sinks as a stake_change event.  Changes to or from
+    // zero stake are always material.  If not present, a default of 10 is used.
+    pub stake_change_percent : Option<f64>,
+
+    // Weights used to compute a single score per peer each classification interval from all of its counters.  If not
+    // present, peers are not scored.
+    pub peer_score_weights : Option<PeerScoreWeights>,
+
+    // Peer scores, as computed using peer_score_weights.  Scores may be negative.
+    pub peer_score : Option<Classification>,
+
+    // Lower bounds of the stake tiers, in lamports, for which connection duration histograms are kept separately.
+    // Unstaked peers are always a tier of their own.  If not present, all staked peers form a single tier.
+    pub stake_tiers : Option<Vec<u64>>,
+
+    // How thresholds treat peers of unknown stake, which is common for staked peers whose connections are proxied by
+    // relayers: "zero" treats them as unstaked, "skip_stake_banded" exempts them from thresholds having a stake
+    // range, and { "assumed" : <lamports> } treats them as having the given stake.  If not present, "zero" is used.
+    pub unknown_stake : Option<UnknownStake>,
+
     // Number of slots before leader slots to apply the "outside leader slots" classifications.  If not present, then
     // this categorization is not performed.
-    pub outside_leader_slots : Option<LeaderSlotsClassification>
+    pub outside_leader_slots : Option<LeaderSlotsClassification>,
+
+    // If present, never-before-seen unstaked peers are placed on probation.  If not present, no probation is applied.
+    pub probation : Option<ProbationClassification>,
+
+    // Maximum number of events that may be queued for the sink worker thread.  If the queue is full, further
+    // events are dropped rather than stalling event processing, except for removals, which are always queued so that
+    // no member is left enforced for good.  If not present, a default of 100,000 is used.
+    pub sink_queue_size : Option<usize>,
+
+    // Number of times a sink will retry an event that it failed to apply.  If not present, a default of 3 is
+    // used.
+    pub sink_retry_count : Option<u32>,
+
+    // Milliseconds to wait between retries of a failed event.  If not present, a default of 100 is used.
+    pub sink_retry_delay_ms : Option<u64>,
+
+    // Milliseconds between periodic passes.  If not present, a default of 1 second is used.
+    pub periodic_interval_ms : Option<u64>,
+
+    // Milliseconds between flushes of retained tx into the fee classifications.  If not present, every periodic pass
+    // flushes tx.
+    pub tx_flush_interval_ms : Option<u64>,
+
+    // Maximum number of retained tx flushed at a time.  If present, tx are flushed incrementally between events as they
+    // become due, at most this many at a time, rather than all at once in each tx flush, so that heavy tx turnover
+    // does not cause bursts of work that delay event handling.  If not present, each tx flush flushes every due tx.
+    pub tx_flush_budget : Option<usize>,
+
+    // Milliseconds between evaluations of classification thresholds.  If not present, every periodic pass evaluates
+    // thresholds.
+    pub classification_interval_ms : Option<u64>,
+
+    // Milliseconds between expirations of group members and peers.  If not present, every periodic pass expires.
+    pub expiration_interval_ms : Option<u64>,
+
+    // Milliseconds between status reports.  If not present, a default of 1 minute is used.
+    pub reporting_interval_ms : Option<u64>,
+
+    // If present, a summary of each group's member count, members added and removed, and the rules that added the
+    // most members is printed periodically.  Summaries are printed at every log level, since they are requested
+    // explicitly.
+    pub group_summaries : Option<GroupSummaryConfig>,
+
+    // How jumps of the system clock (as from NTP corrections or VM pauses) are handled.  If not present, jumps of more
+    // than 10 seconds are re-anchored, so that time continues from where it was before the jump.
+    pub clock_jump : Option<ClockJumpConfig>,
+
+    // If present, the maximum milliseconds to spend evaluating classifications per pass.  Once the budget is
+    // exhausted, the remaining classifications are deferred to the next pass, which begins with them.  If not
+    // present, every classification is evaluated in every pass.
+    pub classification_budget_ms : Option<u64>,
+
+    // If present, each status report also prints, for every classification, a config snippet with threshold values
+    // suggested from the values observed for all peers: the value at this percentile (e.g. 99.0), so that roughly
+    // (100 - percentile)% of peers would meet each threshold.
+    pub threshold_suggestion_percentile : Option<f64>,
+
+    // Milliseconds of each bucket of the churn history kept for every group, i.e. the numbers of its members added,
+    // removed, and expired in each bucket.  If not present, a default of 1 minute is used.
+    pub churn_bucket_ms : Option<u64>,
+
+    // Number of buckets of churn history kept for every group.  If not present, a default of 60 is used.
+    pub churn_bucket_count : Option<usize>,
+
+    // Address (e.g. "127.0.0.1:15152") on which to serve the HTTP admin API.  If not present, the API is not served.
+    pub api_listen_address : Option<String>,
+
+    // Address (e.g. "127.0.0.1:15153") on which to serve the gRPC service defined by proto/txingest.proto, which
+    // streams group changes and answers group membership queries.  Requires a build with the grpc feature.  If not
+    // present, the service is not served.
+    pub grpc_listen_address : Option<String>,
+
+    // Number of events a subscriber to the gRPC service may fall behind before its stream is ended.  If not present, a
+    // default of 10000 is used.
+    pub grpc_queue_size : Option<usize>,
+
+    // Path of a Unix domain socket on which to accept admin commands, one per line: "show groups", "show peer <ip>",
+    // "add <ip> <group> <ttl seconds>", "remove <ip> <group>", and "reload" (which re-imports the deny list import
+    // file).  If not present, no admin socket is served.
+    pub admin_socket_path : Option<String>,
+
+    // Address (e.g. "127.0.0.1:15150") on which to accept ingest connections from senders using the legacy message
+    // layout of older validator patch versions.  If not present, legacy senders are not supported.
+    pub legacy_listen_address : Option<String>,
+
+    // If present and true, a message is logged the first time each ingest connection sends a Deprecated message, so
+    // that the sender needing an upgrade can be identified.  Deprecated messages are always counted.
+    pub log_deprecated_senders : Option<bool>,
+
+    // Source names for ingest connections, by sender address.  Events from senders not listed here have the source
+    // name "default".
+    pub ingest_sources : Option<Vec<IngestSource>>,
+
+    // If present, only these sender addresses may connect to the ingest listeners (including the legacy listener);
+    // connections from other addresses are closed as they are accepted, and counted.  This is a lightweight
+    // alternative to TLS for classifiers listening beyond loopback.  If not present, any sender may connect.
+    pub allowed_senders : Option<Vec<IpAddr>>,
+
+    // If present, ingest connections on which no message arrives for this many milliseconds are considered hung and
+    // closed, so that a sender that silently vanished does not hold a connection thread forever.  Validators send
+    // events continuously, so this can be short.  If not present, idle connections are never closed.
+    pub ingest_idle_timeout_ms : Option<u64>,
+
+    // If present, ingest connections are multiplexed on an async runtime of this many threads, rather than each being
+    // handled by a thread of its own, which scales better to many simultaneous senders.  Requires a build with the
+    // async-ingest feature.  If not present, a thread per connection is used.
+    pub ingest_async_threads : Option<usize>,
+
+    // If present, limits the rate of events accepted from each ingest connection.  Events dropped and connections
+    // closed due to the limit are counted.
+    pub ingest_rate_limit : Option<IngestRateLimit>,
+
+    // Addresses whose events are completely ignored at ingest (e.g. our own monitoring probes), so that they neither
+    // pollute classification values nor consume memory
+    pub ignored_addresses : Option<Vec<IpAddr>>,
+
+    // Initial log level: "quiet", "normal", or "debug".  The level can be changed at runtime via the API or by
+    // SIGUSR1 (more verbose) and SIGUSR2 (less verbose).  If not present, "normal" is used.
+    pub log_level : Option<LogLevel>,
+
+    // Addresses whose every event is logged while the log level is "debug".  The addresses can be changed at runtime
+    // via the API.
+    pub debug_addresses : Option<Vec<IpAddr>>,
+
+    // If present, group additions, removals, and expirations, and leader status changes, are written as lines of JSON
+    pub json_output : Option<JsonOutputConfig>,
+
+    // If present, the members of groups are written to a deny list file for consumption by the validator
+    pub deny_list : Option<DenyListConfig>,
+
+    // If present, a notice file is kept for each member of groups, describing why it is in them and until when, for
+    // the operator to publish to the peers concerned
+    pub peer_notices : Option<PeerNoticeConfig>,
+
+    // If present, a deny list maintained by other tooling is imported into a group at startup, and the group is kept
+    // synchronized with the file as it changes
+    pub deny_list_import : Option<DenyListImportConfig>,
+
+    // If present, group changes are journaled to disk for the sinks that act on them outside of the classifier
+    // (firewalls, hooks, and webhooks), so that changes are delivered despite sink outages and restarts
+    pub sink_journal : Option<SinkJournalConfig>,
+
+    // If present, the members of all groups are saved to a file with their expirations, and restored from it at
+    // startup, so that blocked addresses stay blocked across a restart
+    pub membership_file : Option<MembershipFileConfig>,
+
+    // If present, the members of groups are kept in nftables named sets, for firewall rules that refer to the sets
+    pub nftables : Option<NftablesConfig>,
+
+    // If present, the members of groups are kept in ipset sets, for iptables rules that match the sets
+    pub ipset : Option<IpsetConfig>,
+
+    // If present, commands are run as the membership of groups changes, e.g. to block and unblock addresses by script
+    pub exec_hooks : Option<ExecHooksConfig>,
+
+    // Classifier plugins, each run as a subprocess that is fed every event and decides group membership by its own
+    // logic
+    pub plugins : Option<Vec<PluginConfig>>,
+
+    // Webhooks to post events to.  Each webhook may be limited to the events of specific groups, so that different
+    // groups can be routed to different destinations.
+    pub webhooks : Option<Vec<WebhookConfig>>,
+
+    // Map from group name to the policy by which memberships are changed when a member is added again, e.g. by
+    // multiple thresholds with different group_expiration_seconds.  Groups not listed use "max".
+    pub group_ttl_policies : Option<HashMap<String, GroupTtlPolicy>>,
+
+    // Map from group name to limits on the expiration of its memberships: a maximum expiration clamping whatever
+    // rules request, or permanent membership.  Groups not listed hold members for as long as rules request.
+    pub group_expiration_limits : Option<HashMap<String, GroupExpirationLimits>>,
+
+    // Map from group name to which events are sent when a membership is extended: "update", "none", { "every_ms" :
+    // <milliseconds> }, or "extend".  Groups not listed use "update".
+    pub group_extension_events : Option<HashMap<String, ExtensionEvents>>,
+
+    // Rules that take actions while groups are larger than a limit, e.g. switching to overload thresholds when a
+    // spammers group grows past 1000 members
+    pub group_size_rules : Option<Vec<GroupSizeRule>>,
+
+    // Rules that take the same actions while a global rate across all peers exceeds a limit, e.g. switching to
+    // overload thresholds and coarse grouping during a connection attempt storm from thousands of distinct addresses
+    // that each stay under per-address thresholds.  Rates are measured at each classification pass.
+    pub storm_rules : Option<Vec<StormRule>>,
+
+    // Canaries: fake peers whose synthetic events are fed to the classifier on a schedule, as an end to end self test
+    // that alerts if they stop reaching sinks as members of their expected groups
+    pub canaries : Option<Vec<CanaryConfig>>,
+
+    // Number of milliseconds after a tx is flushed without a known fee during which a late Fee event for it is still
+    // credited to its first submitter, as is common under RPC or confirmation lag.  Only the first submitter and
+    // signature are retained for this time.  If not present, a default of 30 seconds is used; 0 disables.
+    pub late_fee_retention_ms : Option<u64>,
+
+    // Number of milliseconds of submissions over which the zero fee share of each peer is reported: the fraction of
+    // its attributed submissions that were credited with zero fee.  If not present, a default of 10 minutes is used.
+    pub zero_fee_share_window_ms : Option<u64>,
+
+    // Compute unit capacity of each block, against which the fill of the blocks of our leader slots by the fees
+    // received during them is measured.  If not present, a default of 48,000,000 is used.
+    pub block_cu_limit : Option<u64>,
+
+    // If present and true, fees for tx that were never seen submitted (e.g. received via gossip or forwarding, or after
+    // retention expired) are credited to a synthetic "unknown submitter" peer at address 0.0.0.0, so that the landed
+    // value invisible to peer scoring shows up alongside real peers.  Such fees are always counted.
+    pub attribute_unseen_fees : Option<bool>,
+
+    // Map from group name to metadata describing the group (e.g. owner, runbook_url, description), which is included
+    // in every event for the group and in API responses describing the group
+    pub group_metadata : Option<HashMap<String, GroupMetadata>>,
+
+    // Map from group name to the rate to which its members should be limited, for groups intended as throttles
+    // rather than blocks (e.g. { "max_connections_per_minute" : 10, "max_tps" : 100 }).  The rate is included in every
+    // add and update event for the group, and members of throttle groups are written to the deny list's throttle file
+    // if it has one.
+    pub group_throttles : Option<HashMap<String, ThrottleRate>>,
+
+    // If present, metrics (at each status report) and events are exported to an OpenTelemetry collector
+    pub otlp : Option<OtlpConfig>,
+
+    // If present, the origin (reverse DNS name, and country and provider from GeoIP) of each address added to a group
+    // is looked up, and where classified addresses come from is periodically reported
+    pub origin_report : Option<OriginReportConfig>,
+
+    // If present, the summary of each peer evicted after 3 days of inactivity is sent to sinks as a peer_evicted event
+    // before the peer is dropped, and optionally appended to a file, so that long horizon analysis is not truncated
+    // by in-memory retention
+    pub peer_export : Option<PeerExportConfig>,
+
+    // If present, every received event is recorded to disk with its arrival time, for replaying offline against other
+    // configs.  Recording can be turned on and off via the admin socket.
+    pub recording : Option<RecordingConfig>,
+
+    // If present, per-peer and per-group statistics are rolled up per Solana epoch, estimated from this epoch
+    // schedule, and a report is printed at the end of each epoch
+    pub epoch_rollup : Option<EpochRollupConfig>,
+
+    // If present, a shadow instance is fed every event alongside this one, and its group decisions are compared with
+    // this instance's after every periodic pass, with divergences logged.  Doubles the work done.
+    pub verification : Option<VerificationConfig>,
+
+    // Sets of addresses which are each treated as a single peer
+    pub equivalent_addresses : Option<Vec<EquivalentAddresses>>,
+
+    // If present and true, an event is sent to sinks for every peer seen for the first time (or for the first time
+    // since it was last retained), which is useful for spotting fresh botnets
+    pub emit_new_peer_events : Option<bool>,
+
+    // If present and true, the classifier starts in monitor mode, in which group changes are logged but not enforced
+    // by sinks.  The mode can be changed at runtime via the admin API.
+    pub monitor_mode : Option<bool>
+}
+
+impl Config
+{
+    // Reads, parses, and validates the config file at path
+    pub fn load(path : &str) -> Result<Self, String>
+    {
+        let json = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+
+        let mut config = Self::parse(&json)?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Deserializes a Config from JSON text.  Rather than stopping at the first error, each error is recorded along
+    // with the JSON pointer of the field at which it occurred, the offending field is removed, and deserialization is
+    // retried, so that as many errors as possible are reported in one pass.
+    pub fn parse(json : &str) -> Result<Self, String>
+    {
+        let mut value = serde_json::from_str::<serde_json::Value>(json).map_err(|e| e.to_string())?;
+
+        let mut errors = vec![];
+
+        // Paths of fields removed so far, as lists of JSON pointer tokens
+        let mut removed_paths = Vec::<Vec<String>>::new();
+
+        loop {
+            let e = match serde_path_to_error::deserialize::<_, Config>(&value) {
+                Ok(config) if errors.is_empty() => return Ok(config),
+                Ok(_) => break,
+                Err(e) => e
+            };
+
+            let segments = e.path().iter().cloned().collect::<Vec<Segment>>();
+            let tokens = segments.iter().map(pointer_token).collect::<Vec<String>>();
+
+            // An error at the parent of a removed field (e.g. a missing field) is a consequence of the removal rather
+            // than an error in the config, so is not reported
+            if !removed_paths.iter().any(|removed_path| removed_path.starts_with(&tokens)) {
+                errors.push(format!("{}: {}", json_pointer(&tokens), e.inner()));
+            }
+
+            // Remove the innermost object field containing the error; if there isn't one, no further progress can be
+            // made
+            let Some(removed_path) = remove_field(&mut value, &segments)
+            else {
+                break;
+            };
+
+            removed_paths.push(removed_path);
+
+            if errors.len() == MAX_PARSE_ERRORS {
+                errors.push("... too many errors; stopping".to_string());
+                break;
+            }
+        }
+
+        Err(errors.join("\n"))
+    }
+}
+
+// Returns the JSON pointer token for a path segment
+fn pointer_token(segment : &Segment) -> String
+{
+    match segment {
+        Segment::Seq { index } => index.to_string(),
+        Segment::Map { key } | Segment::Enum { variant: key } => key.replace('~', "~0").replace('/', "~1"),
+        Segment::Unknown => "?".to_string()
+    }
+}
+
+fn json_pointer(tokens : &[String]) -> String
+{
+    if tokens.is_empty() {
+        "(root)".to_string()
+    }
+    else {
+        tokens.iter().map(|token| format!("/{token}")).collect()
+    }
+}
+
+// Removes the innermost object field along the path from value, returning the path of the removed field as JSON pointer
+// tokens, or None if no object field along the path could be removed
+fn remove_field(
+    value : &mut serde_json::Value,
+    segments : &[Segment]
+) -> Option<Vec<String>>
+{
+    let tokens = segments.iter().map(pointer_token).collect::<Vec<String>>();
+
+    for len in (1..=segments.len()).rev() {
+        let Segment::Map { key } = &segments[len - 1]
+        else {
+            continue;
+        };
+
+        let parent_pointer = if len == 1 { "".to_string() } else { json_pointer(&tokens[..(len - 1)]) };
+
+        if let Some(object) = value.pointer_mut(&parent_pointer).and_then(|parent| parent.as_object_mut()) {
+            if object.remove(key).is_some() {
+                return Some(tokens[..len].to_vec());
+            }
+        }
+    }
+
+    None
 }
 
 // Must be called immediately after deserialization.  Validates that the Config has rational values.
@@ -89,14 +911,457 @@ impl Config
             fee_microlamports_per_cu_used.validate("fee_microlamports_per_cu_used")?;
         }
 
+        if let Some(zero_fee_share) = &mut self.zero_fee_share {
+            zero_fee_share.validate("zero_fee_share")?;
+        }
+
+        if let Some(pubkey_changes) = &mut self.pubkey_changes {
+            pubkey_changes.validate("pubkey_changes")?;
+        }
+
+        if let Some(peer_score) = &mut self.peer_score {
+            if self.peer_score_weights.is_none() {
+                return Err("peer_score classification requires peer_score_weights".to_string());
+            }
+            peer_score.validate("peer_score")?;
+        }
+
         if let Some(outside_leader_slots) = &mut self.outside_leader_slots {
             outside_leader_slots.validate()?;
         }
 
+        if let Some(probation) = &mut self.probation {
+            probation.validate()?;
+        }
+
+        if self.sink_queue_size.unwrap_or(1) == 0 {
+            return Err("Invalid zero sink_queue_size in config".to_string());
+        }
+
+        if self.periodic_interval_ms.unwrap_or(1) == 0 {
+            return Err("Invalid zero periodic_interval_ms in config".to_string());
+        }
+
+        if let Some(ingest_rate_limit) = &self.ingest_rate_limit {
+            if ingest_rate_limit.events_per_second == 0 {
+                return Err("Invalid zero ingest_rate_limit events_per_second".to_string());
+            }
+            if ingest_rate_limit.burst == Some(0) {
+                return Err("Invalid zero ingest_rate_limit burst".to_string());
+            }
+        }
+
+        if let Some(ingest_sources) = &self.ingest_sources {
+            for ingest_source in ingest_sources {
+                if ingest_source.name.is_empty() {
+                    return Err("Invalid ingest source name: empty string".to_string());
+                }
+            }
+        }
+
+        for classification in self.classifications() {
+            for source in classification.sources.iter().flatten() {
+                if (source != DEFAULT_INGEST_SOURCE) &&
+                    !self.ingest_sources.iter().flatten().any(|ingest_source| &ingest_source.name == source)
+                {
+                    return Err(format!("Classification {} names unknown ingest source {source}", classification.name));
+                }
+            }
+        }
+
+        // Resolve the services of each classification into the names of the ingest sources reporting those services
+        let source_services = self.source_services();
+        for classification in self.classifications_mut() {
+            if let Some(services) = &classification.services {
+                for service in services {
+                    if !source_services.values().any(|s| s == service) {
+                        return Err(format!(
+                            "Classification {} names service {} which no ingest source reports",
+                            classification.name,
+                            service.name()
+                        ));
+                    }
+                }
+                classification.service_sources = source_services
+                    .iter()
+                    .filter(|(_, service)| services.contains(service))
+                    .map(|(name, _)| name.clone())
+                    .collect();
+            }
+        }
+
+        // Resolve the relayer sources of each classification that separates the relayer path
+        let relayer_sources = self.relayer_sources();
+        for classification in self.classifications_mut() {
+            if classification.separate_relayer_path.unwrap_or(false) {
+                if relayer_sources.is_empty() {
+                    return Err(format!(
+                        "Classification {} separates the relayer path but no ingest source is a relayer",
+                        classification.name
+                    ));
+                }
+                classification.relayer_sources = relayer_sources.clone();
+            }
+            else if classification.relayer_group_name.is_some() {
+                return Err(format!(
+                    "Classification {} has relayer_group_name but does not separate the relayer path",
+                    classification.name
+                ));
+            }
+        }
+
+        let mut seen_addresses = std::collections::HashSet::new();
+        for equivalent_addresses in self.equivalent_addresses.iter().flatten() {
+            if equivalent_addresses.addresses.len() < 2 {
+                return Err("Invalid equivalent_addresses: must list at least two addresses".to_string());
+            }
+            for address in &equivalent_addresses.addresses {
+                if !seen_addresses.insert(*address) {
+                    return Err(format!("Invalid equivalent_addresses: {address} is listed more than once"));
+                }
+            }
+        }
+
+        if let Some(json_output) = &self.json_output {
+            json_output.validate()?;
+        }
+
+        if let Some(clock_jump) = &self.clock_jump {
+            clock_jump.validate()?;
+        }
+
+        if let Some(deny_list) = &self.deny_list {
+            if deny_list.path.is_empty() {
+                return Err("Invalid deny_list path: empty string".to_string());
+            }
+        }
+
+        if let Some(peer_notices) = &self.peer_notices {
+            peer_notices.validate()?;
+        }
+
+        if let Some(deny_list_import) = &self.deny_list_import {
+            deny_list_import.validate()?;
+        }
+
+        if let Some(sink_journal) = &self.sink_journal {
+            sink_journal.validate()?;
+        }
+
+        if let Some(membership_file) = &self.membership_file {
+            membership_file.validate()?;
+        }
+
+        if let Some(nftables) = &self.nftables {
+            nftables.validate()?;
+        }
+
+        if let Some(ipset) = &self.ipset {
+            ipset.validate()?;
+
+            // Timeouts are set from the expirations sent to sinks, so the sink must be told of every extension, lest
+            // the kernel expire entries of members that remain
+            if ipset.timeouts.unwrap_or(false) {
+                for set in &ipset.sets {
+                    let events = self.group_extension_events.as_ref().and_then(|events| events.get(&set.group_name));
+                    if matches!(events, Some(ExtensionEvents::None | ExtensionEvents::EveryMs(_))) {
+                        return Err(format!(
+                            "Invalid group_extension_events for group {}: ipset timeouts require \"update\" or \
+                             \"extend\"",
+                            set.group_name
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(exec_hooks) = &self.exec_hooks {
+            exec_hooks.validate()?;
+        }
+
+        if let Some(plugins) = &self.plugins {
+            plugin::validate(plugins)?;
+        }
+
+        for webhook in self.webhooks.iter().flatten() {
+            webhook.validate()?;
+        }
+
+        for (group_name, limits) in self.group_expiration_limits.iter().flatten() {
+            if limits.max_expiration_seconds == Some(0) {
+                return Err(format!("Invalid zero max_expiration_seconds for group {group_name}"));
+            }
+            if limits.permanent.unwrap_or(false) && limits.max_expiration_seconds.is_some() {
+                return Err(format!("Group {group_name} cannot be both permanent and have a max_expiration_seconds"));
+            }
+        }
+
+        for (group_name, throttle) in self.group_throttles.iter().flatten() {
+            if throttle.max_connections_per_minute.is_none() && throttle.max_tps.is_none() {
+                return Err(format!("Throttle for group {group_name} must have max_connections_per_minute or max_tps"));
+            }
+            if (throttle.max_connections_per_minute == Some(0)) || (throttle.max_tps == Some(0)) {
+                return Err(format!(
+                    "Invalid zero throttle rate for group {group_name}; use a deny list group instead"
+                ));
+            }
+        }
+
+        if let Some(origin_report) = &self.origin_report {
+            origin_report.validate()?;
+        }
+
+        if let Some(epoch_rollup) = &self.epoch_rollup {
+            epoch_rollup.validate()?;
+        }
+
+        if let Some(otlp) = &self.otlp {
+            otlp.validate()?;
+        }
+
+        for group_size_rule in self.group_size_rules.iter().flatten() {
+            if group_size_rule
+                .clear_members
+                .map(|clear_members| clear_members > group_size_rule.max_members)
+                .unwrap_or(false)
+            {
+                return Err(format!(
+                    "Invalid group_size_rules entry for group {}: clear_members greater than max_members",
+                    group_size_rule.group_name
+                ));
+            }
+            if group_size_rule.actions.is_empty() {
+                return Err(format!(
+                    "Invalid group_size_rules entry for group {}: no actions",
+                    group_size_rule.group_name
+                ));
+            }
+        }
+
+        for storm_rule in self.storm_rules.iter().flatten() {
+            storm_rule.validate()?;
+        }
+
+        for canary in self.canaries.iter().flatten() {
+            canary.validate()?;
+        }
+
+        if self.group_summaries.as_ref().and_then(|group_summaries| group_summaries.interval_ms) == Some(0) {
+            return Err("Invalid group_summaries interval_ms: zero".to_string());
+        }
+
+        if self.group_extension_events.iter().flatten().any(|(_, events)| *events == ExtensionEvents::EveryMs(0)) {
+            return Err("Invalid zero every_ms in group_extension_events".to_string());
+        }
+
+        if self.ingest_async_threads == Some(0) {
+            return Err("Invalid zero ingest_async_threads".to_string());
+        }
+
+        if cfg!(not(feature = "async-ingest")) && self.ingest_async_threads.is_some() {
+            return Err("ingest_async_threads requires a build with the async-ingest feature".to_string());
+        }
+
+        if self.ingest_idle_timeout_ms == Some(0) {
+            return Err("Invalid zero ingest_idle_timeout_ms".to_string());
+        }
+
+        if self.stake_change_percent.map(|stake_change_percent| stake_change_percent < 0.0).unwrap_or(false) {
+            return Err("Invalid negative stake_change_percent".to_string());
+        }
+
+        if let (Some(fee_min_stake), Some(fee_max_stake)) = (self.fee_min_stake, self.fee_max_stake) {
+            if fee_max_stake < fee_min_stake {
+                return Err(format!(
+                    "Invalid fee stake bounds: fee_max_stake {fee_max_stake} is lower than fee_min_stake \
+                     {fee_min_stake}"
+                ));
+            }
+        }
+
+        if let Some(stake_tiers) = &self.stake_tiers {
+            if stake_tiers.contains(&0) {
+                return Err("Invalid stake_tiers: unstaked peers are always a tier; 0 must not be listed".to_string());
+            }
+        }
+
+        if self.tx_flush_budget == Some(0) {
+            return Err("Invalid zero tx_flush_budget in config".to_string());
+        }
+
+        if self.classification_budget_ms == Some(0) {
+            return Err("Invalid zero classification_budget_ms in config".to_string());
+        }
+
+        if self.admin_socket_path.as_deref() == Some("") {
+            return Err("Invalid admin_socket_path: empty string".to_string());
+        }
+
+        if let Some(recording) = &self.recording {
+            recording.validate()?;
+        }
+
+        if self.zero_fee_share_window_ms == Some(0) {
+            return Err("Invalid zero zero_fee_share_window_ms in config".to_string());
+        }
+
+        if self.churn_bucket_ms == Some(0) {
+            return Err("Invalid zero churn_bucket_ms in config".to_string());
+        }
+
+        if self.churn_bucket_count == Some(0) {
+            return Err("Invalid zero churn_bucket_count in config".to_string());
+        }
+
+        if let Some(threshold_suggestion_percentile) = self.threshold_suggestion_percentile {
+            if !(0.0..=100.0).contains(&threshold_suggestion_percentile) {
+                return Err(format!(
+                    "Invalid threshold_suggestion_percentile {threshold_suggestion_percentile}: must be between 0 and \
+                     100"
+                ));
+            }
+        }
+
+        if let Some(legacy_listen_address) = &self.legacy_listen_address {
+            legacy_listen_address
+                .parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid legacy_listen_address {legacy_listen_address}: {e}"))?;
+        }
+
+        if let Some(api_listen_address) = &self.api_listen_address {
+            api_listen_address
+                .parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid api_listen_address {api_listen_address}: {e}"))?;
+        }
+
+        if let Some(grpc_listen_address) = &self.grpc_listen_address {
+            if cfg!(not(feature = "grpc")) {
+                return Err("grpc_listen_address requires a build with the grpc feature".to_string());
+            }
+            grpc_listen_address
+                .parse::<std::net::SocketAddr>()
+                .map_err(|e| format!("Invalid grpc_listen_address {grpc_listen_address}: {e}"))?;
+        }
+
+        if self.grpc_queue_size == Some(0) {
+            return Err("Invalid zero grpc_queue_size in config".to_string());
+        }
+
         Ok(())
     }
 }
 
+impl Config
+{
+    // Returns a map from each address listed in equivalent_addresses to the address that represents its peer (the
+    // first address in its set)
+    pub fn canonical_addresses(&self) -> HashMap<IpAddr, IpAddr>
+    {
+        self.equivalent_addresses
+            .iter()
+            .flatten()
+            .flat_map(|equivalent_addresses| {
+                let canonical = equivalent_addresses.addresses[0];
+                equivalent_addresses.addresses.iter().map(move |address| (*address, canonical))
+            })
+            .collect()
+    }
+
+    // Returns all configured classifications
+    pub fn classifications(&self) -> Vec<&Classification>
+    {
+        [
+            &self.failed_exceeded_quic_connections,
+            &self.useless_quic_connections,
+            &self.fee_lamports_submitted,
+            &self.fee_microlamports_per_cu_limit,
+            &self.fee_microlamports_per_cu_used,
+            &self.zero_fee_share,
+            &self.pubkey_changes,
+            &self.peer_score
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    // Returns the service of each ingest source that has one, by source name
+    pub fn source_services(&self) -> HashMap<String, TpuService>
+    {
+        self.ingest_sources
+            .iter()
+            .flatten()
+            .filter_map(|ingest_source| ingest_source.service.map(|service| (ingest_source.name.clone(), service)))
+            .collect()
+    }
+
+    // Returns the names of the ingest sources reporting traffic that arrived via a relayer
+    pub fn relayer_sources(&self) -> Vec<String>
+    {
+        self.ingest_sources
+            .iter()
+            .flatten()
+            .filter(|ingest_source| ingest_source.relayer.unwrap_or(false))
+            .map(|ingest_source| ingest_source.name.clone())
+            .collect()
+    }
+
+    // Returns all configured classifications, mutably
+    pub fn classifications_mut(&mut self) -> Vec<&mut Classification>
+    {
+        self.classification_slots_mut().into_iter().flatten().collect()
+    }
+
+    // Returns the fee classification fields, whether configured or not, always in the same order
+    pub fn fee_classifications_mut(&mut self) -> [&mut Option<Classification>; 4]
+    {
+        [
+            &mut self.zero_fee_share,
+            &mut self.fee_lamports_submitted,
+            &mut self.fee_microlamports_per_cu_limit,
+            &mut self.fee_microlamports_per_cu_used
+        ]
+    }
+
+    // Returns every classification field, whether configured or not, always in the same order
+    pub fn classification_slots_mut(&mut self) -> [&mut Option<Classification>; 8]
+    {
+        [
+            &mut self.failed_exceeded_quic_connections,
+            &mut self.useless_quic_connections,
+            &mut self.fee_lamports_submitted,
+            &mut self.fee_microlamports_per_cu_limit,
+            &mut self.fee_microlamports_per_cu_used,
+            &mut self.zero_fee_share,
+            &mut self.pubkey_changes,
+            &mut self.peer_score
+        ]
+    }
+}
+
+impl PeerScoreWeights
+{
+    // Computes a score from the change in counters from previous to current
+    pub fn score(
+        &self,
+        current : &PeerCounters,
+        previous : &PeerCounters
+    ) -> f64
+    {
+        let weighted = |weight : Option<f64>, current : u64, previous : u64| {
+            weight.map(|weight| weight * (current.saturating_sub(previous) as f64)).unwrap_or(0.0)
+        };
+
+        weighted(self.tx_submitted, current.tx_submitted(), previous.tx_submitted()) +
+            weighted(self.vote_tx_submitted, current.vote_tx_submitted, previous.vote_tx_submitted) +
+            weighted(self.user_tx_submitted, current.user_tx_submitted, previous.user_tx_submitted) +
+            weighted(self.fee_lamports, current.fee_lamports, previous.fee_lamports) +
+            weighted(self.connection_count, current.connection_count, previous.connection_count) +
+            weighted(self.failed_count, current.failed_count, previous.failed_count) +
+            weighted(self.duplicate_count, current.duplicate_count, previous.duplicate_count)
+    }
+}
+
 // Must be called immediately after deserialization.  Validates that the LeaderSlotsClassification has rational values.
 impl LeaderSlotsClassification
 {
@@ -114,6 +1379,46 @@ impl LeaderSlotsClassification
             return Err("Invalid outside_leader_slots leader_slots; must be <= 432000".to_string());
         }
 
+        for window in self.windows.iter().flatten() {
+            if window.group_name.is_empty() {
+                return Err("Invalid outside_leader_slots window group name: empty string".to_string());
+            }
+
+            if window.leader_slots > 432000 {
+                return Err(format!(
+                    "Invalid outside_leader_slots window {} leader_slots; must be <= 432000",
+                    window.group_name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Must be called immediately after deserialization.  Validates that the ProbationClassification has rational values.
+impl ProbationClassification
+{
+    pub fn validate(&mut self) -> Result<(), String>
+    {
+        if self.group_name.is_none() {
+            self.group_name = Some("probation".to_string());
+        }
+
+        if self.group_name.as_ref().unwrap() == "" {
+            return Err("Invalid probation group name: empty string".to_string());
+        }
+
+        if self.group_expiration_seconds == Some(0) {
+            return Err("Invalid zero probation group_expiration_seconds".to_string());
+        }
+
+        if self.min_tx_count.is_none() && self.min_fee_lamports.is_none() {
+            return Err(
+                "Invalid probation: at least one of min_tx_count and min_fee_lamports must be given".to_string()
+            );
+        }
+
         Ok(())
     }
 }
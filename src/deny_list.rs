@@ -0,0 +1,236 @@
+use crate::config::ThrottleRate;
+use crate::sink::{describe_throttle_rate, GroupChange, MemberChange, OutputSink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DenyListConfig
+{
+    // Path of the deny list file.  The file is replaced atomically (by writing a temporary file alongside it and
+    // renaming it into place) whenever the members of the deny list groups change.
+    pub path : String,
+
+    // Groups whose members are written to the deny list.  If not present, the members of all groups are written.
+    pub groups : Option<Vec<String>>,
+
+    // If present, members of deny list groups that have a throttle in group_throttles are written to this file
+    // instead of the deny list, one per line in sorted order in the form "<address> max_connections_per_minute=10
+    // max_tps=100" (limits without a value are omitted).  An address in several throttle groups is given the
+    // strictest of each limit; an address also in a non-throttle deny list group is only written to the deny list.
+    // The file is replaced atomically like the deny list file.  If not present, members of throttle groups are
+    // written to the deny list like any others.
+    pub throttle_path : Option<String>,
+
+    // If present, the process whose pid is contained in this file is signaled after each replacement of the deny
+    // list file, so that it re-reads the file
+    pub pid_file : Option<String>,
+
+    // Name of the signal to send to the process named by pid_file.  If not present, "HUP" is used.
+    pub signal : Option<String>
+}
+
+// Output that maintains a file listing every ip address that is a member of any deny list group, one address per line
+// in sorted order, in the format consumed by the validator's deny list support.  Optionally maintains a second file
+// listing the members of throttle groups along with the rates to which they should be limited.
+pub struct DenyListSink
+{
+    config : DenyListConfig,
+
+    // Map from member ip address to the deny list groups it is a member of
+    members : HashMap<IpAddr, HashSet<String>>,
+
+    // Map from member ip address to the throttle groups it is a member of, with their rates; only used if there is a
+    // throttle_path
+    throttled : HashMap<IpAddr, HashMap<String, ThrottleRate>>,
+
+    // True if members have changed since the file was last written
+    dirty : bool,
+
+    // False when monitoring, in which case the file is written empty
+    enforcing : bool
+}
+
+impl DenyListSink
+{
+    pub fn new(config : DenyListConfig) -> Self
+    {
+        // Write the file at startup even if empty, so that the validator never reads a stale list from a prior run
+        Self { config, members : Default::default(), throttled : Default::default(), dirty : true, enforcing : true }
+    }
+
+    fn is_deny_list_group(
+        &self,
+        group_name : &str
+    ) -> bool
+    {
+        self.config.groups.as_ref().map(|groups| groups.iter().any(|g| g == group_name)).unwrap_or(true)
+    }
+
+    fn write(&self) -> Result<(), String>
+    {
+        let mut ip_addrs =
+            if self.enforcing { self.members.keys().collect::<Vec<&IpAddr>>() } else { Default::default() };
+        ip_addrs.sort();
+
+        write_file(&self.config.path, ip_addrs.iter().map(|ip_addr| ip_addr.to_string()))?;
+
+        let Some(throttle_path) = &self.config.throttle_path
+        else {
+            return Ok(());
+        };
+
+        let mut throttled = if self.enforcing {
+            self.throttled
+                .iter()
+                .filter(|(ip_addr, _)| !self.members.contains_key(ip_addr))
+                .map(|(ip_addr, throttles)| {
+                    let mut rates = throttles.values();
+                    // Members are removed from throttled when their last throttle group is removed
+                    let first = *rates.next().unwrap();
+                    (ip_addr, rates.fold(first, |rate, other| rate.min(other)))
+                })
+                .collect::<Vec<(&IpAddr, ThrottleRate)>>()
+        }
+        else {
+            Default::default()
+        };
+        throttled.sort_by_key(|(ip_addr, _)| *ip_addr);
+
+        write_file(
+            throttle_path,
+            throttled.iter().map(|(ip_addr, rate)| format!("{ip_addr} {}", describe_throttle_rate(rate)))
+        )
+    }
+
+    fn signal(&self) -> Result<(), String>
+    {
+        let Some(pid_file) = &self.config.pid_file
+        else {
+            return Ok(());
+        };
+
+        let pid = std::fs::read_to_string(pid_file).map_err(|e| format!("Failed to read {pid_file}: {e}"))?;
+        let pid = pid.trim().parse::<u32>().map_err(|e| format!("Invalid pid in {pid_file}: {e}"))?;
+
+        let signal = self.config.signal.as_deref().unwrap_or("HUP");
+
+        let status = std::process::Command::new("kill")
+            .arg("-s")
+            .arg(signal)
+            .arg(pid.to_string())
+            .status()
+            .map_err(|e| format!("Failed to run kill: {e}"))?;
+
+        if status.success() {
+            Ok(())
+        }
+        else {
+            Err(format!("Failed to send signal {signal} to pid {pid}: kill exited with {status}"))
+        }
+    }
+}
+
+// Replaces the file at path with lines, atomically by writing a temporary file alongside it and renaming it into place
+pub fn write_file(
+    path : &str,
+    lines : impl Iterator<Item = String>
+) -> Result<(), String>
+{
+    let tmp_path = format!("{path}.tmp");
+
+    let mut file = std::fs::File::create(&tmp_path).map_err(|e| format!("Failed to create {tmp_path}: {e}"))?;
+    for line in lines {
+        writeln!(file, "{line}").map_err(|e| format!("Failed to write {tmp_path}: {e}"))?;
+    }
+    file.sync_all().map_err(|e| format!("Failed to sync {tmp_path}: {e}"))?;
+
+    std::fs::rename(&tmp_path, path).map_err(|e| format!("Failed to rename {tmp_path} to {path}: {e}"))
+}
+
+impl OutputSink for DenyListSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "deny_list",
+            "path" : self.config.path,
+            "groups" : self.config.groups,
+            "throttle_path" : self.config.throttle_path,
+            "pid_file" : self.config.pid_file
+        })
+    }
+
+    // Also called for updates (by the default on_update), which may change the throttle of a member
+    fn on_add(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        if !self.is_deny_list_group(change.group_name) {
+            return Ok(());
+        }
+
+        match change.throttle {
+            Some(throttle) if self.config.throttle_path.is_some() => {
+                let throttles = self.throttled.entry(change.ip_addr).or_default();
+                self.dirty |= throttles.insert(change.group_name.to_string(), throttle) != Some(throttle);
+            },
+            _ => self.dirty |= self.members.entry(change.ip_addr).or_default().insert(change.group_name.to_string())
+        }
+
+        Ok(())
+    }
+
+    fn on_remove(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        if !self.is_deny_list_group(change.group_name) {
+            return Ok(());
+        }
+
+        if let Some(groups) = self.members.get_mut(&change.ip_addr) {
+            self.dirty |= groups.remove(change.group_name);
+            if groups.is_empty() {
+                self.members.remove(&change.ip_addr);
+            }
+        }
+        if let Some(throttles) = self.throttled.get_mut(&change.ip_addr) {
+            self.dirty |= throttles.remove(change.group_name).is_some();
+            if throttles.is_empty() {
+                self.throttled.remove(&change.ip_addr);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_event(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if let GroupChange::Mode { enforcing, .. } = event {
+            self.dirty |= self.enforcing != *enforcing;
+            self.enforcing = *enforcing;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if self.dirty {
+            self.write()?;
+            self.dirty = false;
+            self.signal()?;
+        }
+
+        Ok(())
+    }
+}
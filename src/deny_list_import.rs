@@ -0,0 +1,205 @@
+use crate::group::PERMANENT_EXPIRATION;
+use crate::log::{self, LogLevel};
+use crate::state::State;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+const DEFAULT_CHECK_INTERVAL_MS : u64 = 10 * 1000; // 10 seconds
+
+// Rule on behalf of which imported addresses are added to their group
+const IMPORT_RULE : &str = "deny_list_import";
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct DenyListImportConfig
+{
+    // Path of the deny list to import, as maintained by other tooling: one ip address per line, optionally followed
+    // by whitespace and a TTL in seconds.  Blank lines and lines starting with '#' are ignored.
+    pub path : String,
+
+    // Group into which the listed addresses are imported
+    pub group_name : String,
+
+    // Milliseconds between checks of the file for changes.  If not present, a default of 10 seconds is used.
+    pub check_interval_ms : Option<u64>
+}
+
+impl DenyListImportConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.path.is_empty() {
+            return Err("Invalid deny_list_import path: empty string".to_string());
+        }
+
+        if self.group_name.is_empty() {
+            return Err("Invalid deny_list_import group_name: empty string".to_string());
+        }
+
+        if self.check_interval_ms == Some(0) {
+            return Err("Invalid deny_list_import check_interval_ms: zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Keeps a group synchronized with a deny list file, so that a list built up by other tooling (including manual
+// additions) carries over when the classifier becomes the source of truth.  The file is imported at startup and again
+// whenever its modification time changes.  Addresses listed without a TTL are members for as long as they remain
+// listed; addresses listed with a TTL are members until the TTL has passed since they were imported, as if added by
+// a rule.  Addresses removed from the file are removed from the group, but only addresses that were imported are
+// ever removed, so members added to the same group by classification are left alone.
+pub struct DenyListImport
+{
+    config : DenyListImportConfig,
+
+    // Modification time of the file when last imported
+    modified : Option<SystemTime>,
+
+    // Map from each imported address to the TTL in seconds with which it was listed, if any
+    imported : HashMap<IpAddr, Option<u64>>,
+
+    // Timestamp at which the file is next checked for changes
+    next_check_timestamp : u64
+}
+
+impl DenyListImport
+{
+    // Imports the file into its group.  Fails if the file cannot be read or is invalid, so that a broken deny list is
+    // noticed at startup rather than silently leaving the group empty.
+    pub fn new(
+        config : DenyListImportConfig,
+        state : &mut State,
+        now : u64
+    ) -> Result<Self, String>
+    {
+        let mut deny_list_import = Self {
+            next_check_timestamp : now + config.check_interval_ms.unwrap_or(DEFAULT_CHECK_INTERVAL_MS),
+            config,
+            modified : None,
+            imported : Default::default()
+        };
+
+        deny_list_import.import(state, now)?;
+
+        Ok(deny_list_import)
+    }
+
+    // Re-imports the file if it has changed since it was last imported.  Failures are logged and leave the group as
+    // it was, to be retried at the next check.
+    pub fn poll(
+        &mut self,
+        state : &mut State,
+        now : u64
+    )
+    {
+        if now < self.next_check_timestamp {
+            return;
+        }
+
+        self.next_check_timestamp = now + self.config.check_interval_ms.unwrap_or(DEFAULT_CHECK_INTERVAL_MS);
+
+        if let Err(e) = self.import(state, now) {
+            eprintln!("ERROR: {e}");
+        }
+    }
+
+    // Re-imports the file now, whether or not it has changed, as when requested by an operator
+    pub fn reload(
+        &mut self,
+        state : &mut State,
+        now : u64
+    ) -> Result<(), String>
+    {
+        self.modified = None;
+
+        self.import(state, now)
+    }
+
+    // Imports the file if its modification time differs from that of the last import
+    fn import(
+        &mut self,
+        state : &mut State,
+        now : u64
+    ) -> Result<(), String>
+    {
+        let path = &self.config.path;
+
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("Failed to read deny list {path}: {e}"))?;
+
+        if self.modified == Some(modified) {
+            return Ok(());
+        }
+
+        let listed = parse(path, &std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?)?;
+
+        let group_name = &self.config.group_name;
+
+        let (mut add_count, mut remove_count) = (0, 0);
+
+        for (ip_addr, ttl_seconds) in &listed {
+            if self.imported.get(ip_addr) == Some(ttl_seconds) {
+                continue;
+            }
+            // Addresses without a TTL expire at the permanent expiration, i.e. never, as do those with a TTL so large
+            // that it saturates
+            let ttl_ms =
+                ttl_seconds.map(|ttl_seconds| ttl_seconds.saturating_mul(1000)).unwrap_or(PERMANENT_EXPIRATION - now);
+            if state.add_group_member(group_name, *ip_addr, ttl_ms, IMPORT_RULE, now) {
+                add_count += 1;
+            }
+        }
+
+        for ip_addr in self.imported.keys().filter(|ip_addr| !listed.contains_key(ip_addr)) {
+            if state.remove_group_member(group_name, ip_addr) {
+                remove_count += 1;
+            }
+        }
+
+        if log::enabled(LogLevel::Normal) {
+            println!(
+                "Imported deny list {path} into group {group_name}: {} listed, {add_count} added, {remove_count} \
+                 removed",
+                listed.len()
+            );
+        }
+
+        self.imported = listed;
+        self.modified = Some(modified);
+
+        Ok(())
+    }
+}
+
+// Parses the contents of a deny list into a map from address to TTL in seconds, if any
+fn parse(
+    path : &str,
+    contents : &str
+) -> Result<HashMap<IpAddr, Option<u64>>, String>
+{
+    let mut listed = HashMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("Invalid line {} of deny list {path}: {line}", index + 1);
+        let mut fields = line.split_whitespace();
+        let ip_addr = fields.next().ok_or_else(invalid)?.parse::<IpAddr>().map_err(|_| invalid())?;
+        let ttl_seconds =
+            fields.next().map(|ttl_seconds| ttl_seconds.parse::<u64>()).transpose().map_err(|_| invalid())?;
+        if fields.next().is_some() || (ttl_seconds == Some(0)) {
+            return Err(invalid());
+        }
+        listed.insert(ip_addr, ttl_seconds);
+    }
+
+    Ok(listed)
+}
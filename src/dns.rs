@@ -0,0 +1,194 @@
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+const DNS_TYPE_PTR : u16 = 12;
+const DNS_CLASS_IN : u16 = 1;
+
+// Maximum number of compression pointers followed when reading a name, so that malicious responses cannot loop
+const MAX_NAME_POINTERS : usize = 16;
+
+// Resolves the reverse DNS (PTR) names of addresses by querying a single DNS server over UDP.  Only the first PTR
+// record of each answer is used.
+pub struct ReverseResolver
+{
+    server : SocketAddr,
+
+    timeout : Duration,
+
+    // Identifier of the next query
+    next_id : u16
+}
+
+impl ReverseResolver
+{
+    pub fn new(
+        server : &str,
+        timeout_ms : u64
+    ) -> Result<Self, String>
+    {
+        let server = server
+            .parse::<SocketAddr>()
+            .or_else(|_| server.parse::<IpAddr>().map(|ip_addr| SocketAddr::new(ip_addr, 53)))
+            .map_err(|_| format!("Invalid DNS server {server}: must be an address with optional port"))?;
+
+        if timeout_ms == 0 {
+            return Err(format!("Invalid zero timeout for DNS server {server}"));
+        }
+
+        Ok(Self { server, timeout : Duration::from_millis(timeout_ms), next_id : 1 })
+    }
+
+    pub fn server(&self) -> SocketAddr
+    {
+        self.server
+    }
+
+    // Returns the PTR name of ip_addr, or None if it has none
+    pub fn resolve(
+        &mut self,
+        ip_addr : &IpAddr
+    ) -> Result<Option<String>, String>
+    {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let bind_address = if self.server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_address).map_err(|e| format!("Failed to bind DNS socket: {e}"))?;
+        socket.set_read_timeout(Some(self.timeout)).ok();
+        socket.connect(self.server).map_err(|e| format!("Failed to connect to DNS server {}: {e}", self.server))?;
+
+        socket.send(&query(id, ip_addr)).map_err(|e| format!("Failed to query DNS server {}: {e}", self.server))?;
+
+        let mut response = [0_u8; 512];
+        loop {
+            let len = socket
+                .recv(&mut response)
+                .map_err(|e| format!("Failed to read from DNS server {}: {e}", self.server))?;
+            // Responses to earlier, timed out queries are ignored
+            if (len >= 2) && (u16::from_be_bytes([response[0], response[1]]) == id) {
+                return parse_ptr_response(&response[..len]);
+            }
+        }
+    }
+}
+
+// Returns the name under which the PTR record of ip_addr is published
+fn reverse_name(ip_addr : &IpAddr) -> String
+{
+    match ip_addr {
+        IpAddr::V4(v4) => {
+            let octets = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", octets[3], octets[2], octets[1], octets[0])
+        },
+        IpAddr::V6(v6) => {
+            let mut name = String::new();
+            for octet in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", octet & 0xf, octet >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// Returns a recursive PTR query for ip_addr
+fn query(
+    id : u16,
+    ip_addr : &IpAddr
+) -> Vec<u8>
+{
+    let mut query = vec![];
+
+    // Header: id, recursion desired, one question
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in reverse_name(ip_addr).split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+
+    query.extend_from_slice(&DNS_TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+
+    query
+}
+
+// Returns the name in the first PTR answer of a response, or None if there is none
+fn parse_ptr_response(response : &[u8]) -> Result<Option<String>, String>
+{
+    let field = |offset : usize| -> Result<u16, String> {
+        response
+            .get(offset..(offset + 2))
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| "Truncated DNS response".to_string())
+    };
+
+    // A response code of 3 (NXDOMAIN) means that there is no name; other non-zero codes are failures
+    match field(2)? & 0xf {
+        0 => (),
+        3 => return Ok(None),
+        rcode => return Err(format!("DNS query failed with response code {rcode}"))
+    }
+
+    let question_count = field(4)?;
+    let answer_count = field(6)?;
+
+    let mut offset = 12;
+
+    for _ in 0..question_count {
+        offset = read_name(response, offset)?.1 + 4;
+    }
+
+    for _ in 0..answer_count {
+        offset = read_name(response, offset)?.1;
+        let record_type = field(offset)?;
+        let data_length = field(offset + 8)? as usize;
+        offset += 10;
+        if record_type == DNS_TYPE_PTR {
+            return read_name(response, offset).map(|(name, _)| Some(name));
+        }
+        offset += data_length;
+    }
+
+    Ok(None)
+}
+
+// Reads the possibly compressed name at offset, returning it and the offset just past it
+fn read_name(
+    response : &[u8],
+    offset : usize
+) -> Result<(String, usize), String>
+{
+    let truncated = || "Truncated DNS response".to_string();
+
+    let mut labels = vec![];
+    let mut position = offset;
+    // Offset just past the name where it appears in place, known once the first pointer is followed
+    let mut end = None;
+    let mut pointer_count = 0;
+
+    loop {
+        let len = *response.get(position).ok_or_else(truncated)? as usize;
+        if len == 0 {
+            position += 1;
+            break;
+        }
+        if (len & 0xc0) == 0xc0 {
+            pointer_count += 1;
+            if pointer_count > MAX_NAME_POINTERS {
+                return Err("Invalid DNS response: too many name pointers".to_string());
+            }
+            let low = *response.get(position + 1).ok_or_else(truncated)? as usize;
+            end.get_or_insert(position + 2);
+            position = ((len & 0x3f) << 8) | low;
+            continue;
+        }
+        let label = response.get((position + 1)..(position + 1 + len)).ok_or_else(truncated)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        position += 1 + len;
+    }
+
+    Ok((labels.join("."), end.unwrap_or(position)))
+}
@@ -0,0 +1,83 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+// 432,000 slots of 400 milliseconds each, as on mainnet
+const DEFAULT_EPOCH_DURATION_MS : u64 = 432_000 * 400;
+const DEFAULT_EPOCH_TOP_PEER_COUNT : usize = 20;
+
+// The schedule of Solana epochs, from which the epoch of any timestamp is estimated.  The actual slot rate varies
+// somewhat, so the reference epoch should be updated from time to time (e.g. from the getEpochInfo RPC method) to
+// keep epoch boundaries accurate.
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct EpochRollupConfig
+{
+    // An epoch number
+    pub reference_epoch : u64,
+
+    // Timestamp in milliseconds since the Unix epoch at which reference_epoch began
+    pub reference_start_ms : u64,
+
+    // Duration of each epoch in milliseconds.  If not present, a default of 432,000 slots of 400 milliseconds each
+    // (2 days) is used.
+    pub epoch_duration_ms : Option<u64>,
+
+    // Number of peers listed in each top peers section of an end of epoch report.  If not present, a default of 20 is
+    // used.
+    pub top_peer_count : Option<usize>
+}
+
+impl EpochRollupConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.epoch_duration_ms == Some(0) {
+            return Err("Invalid zero epoch_rollup epoch_duration_ms".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn epoch_duration_ms(&self) -> u64
+    {
+        self.epoch_duration_ms.unwrap_or(DEFAULT_EPOCH_DURATION_MS)
+    }
+
+    pub fn top_peer_count(&self) -> usize
+    {
+        self.top_peer_count.unwrap_or(DEFAULT_EPOCH_TOP_PEER_COUNT)
+    }
+
+    // Returns the epoch containing timestamp.  Timestamps before the reference epoch are in epochs counted back from
+    // it, stopping at epoch 0.
+    pub fn epoch_of(
+        &self,
+        timestamp : u64
+    ) -> u64
+    {
+        let duration_ms = self.epoch_duration_ms();
+
+        if timestamp >= self.reference_start_ms {
+            self.reference_epoch + ((timestamp - self.reference_start_ms) / duration_ms)
+        }
+        else {
+            self.reference_epoch.saturating_sub((self.reference_start_ms - timestamp).div_ceil(duration_ms))
+        }
+    }
+
+    // Returns the timestamp at which epoch begins
+    pub fn start_of(
+        &self,
+        epoch : u64
+    ) -> u64
+    {
+        let duration_ms = self.epoch_duration_ms();
+
+        if epoch >= self.reference_epoch {
+            self.reference_start_ms + ((epoch - self.reference_epoch) * duration_ms)
+        }
+        else {
+            self.reference_start_ms.saturating_sub((self.reference_epoch - epoch) * duration_ms)
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::sink::{GroupChange, MemberChange, OutputSink};
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const DEFAULT_EXEC_HOOK_WORKER_COUNT : usize = 4;
+const DEFAULT_EXEC_HOOK_QUEUE_SIZE : usize = 1000;
+const DEFAULT_EXEC_HOOK_TIMEOUT_MS : u64 = 30 * 1000; // 30 seconds
+
+// How often a running command is checked for having exited
+const EXEC_HOOK_POLL_INTERVAL : Duration = Duration::from_millis(10);
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ExecHooksConfig
+{
+    // Map from group name to the commands run when its membership changes
+    pub groups : HashMap<String, GroupHooks>,
+
+    // Number of commands run concurrently.  If not present, a default of 4 is used.
+    pub worker_count : Option<usize>,
+
+    // Maximum number of commands waiting for each worker.  Add commands beyond this are dropped and logged, so that a
+    // backlog of slow commands cannot grow without bound; remove commands are never dropped, and wait for room
+    // instead, so that no member is left blocked for good.  If not present, a default of 1000 is used.
+    pub queue_size : Option<usize>,
+
+    // Milliseconds after which a command that has not exited is killed.  If not present, a default of 30 seconds is
+    // used.
+    pub timeout_ms : Option<u64>
+}
+
+// Command templates, split on whitespace into a program and its arguments, in each of which "{ip}", "{group}",
+// "{pubkey}" (empty if not known), and "{expiration}" (milliseconds since the epoch; empty for removals) are replaced
+// with the values of the member.  No shell is involved, so values cannot be interpreted as shell syntax; commands
+// needing a shell should be wrapped in a script.
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupHooks
+{
+    // Command run when an address is added to the group, e.g. "/usr/local/bin/block.sh {ip} {group}"
+    pub on_add : Option<String>,
+
+    // Command run when an address is removed from the group
+    pub on_remove : Option<String>
+}
+
+impl ExecHooksConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.worker_count == Some(0) {
+            return Err("Invalid exec_hooks worker_count: zero".to_string());
+        }
+
+        if self.queue_size == Some(0) {
+            return Err("Invalid exec_hooks queue_size: zero".to_string());
+        }
+
+        if self.timeout_ms == Some(0) {
+            return Err("Invalid exec_hooks timeout_ms: zero".to_string());
+        }
+
+        for (group_name, hooks) in &self.groups {
+            if hooks.on_add.iter().chain(hooks.on_remove.iter()).any(|command| command.trim().is_empty()) {
+                return Err(format!("Invalid exec_hooks command for group {group_name}: empty command"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Output that runs the commands of groups as their membership changes.  Commands are run by a pool of worker threads,
+// so that slow commands stall neither event processing nor the other sinks.  The commands for an address are always
+// run by the same worker, so that they run in order and never concurrently (a remove command never overtakes the add
+// command that it undoes).  Members are tracked so that each add
+// command is matched by a remove command: on switching to monitoring, the remove command is run for every member,
+// and on switching back to enforcing, the add command is run again for every member as it is re-sent.
+pub struct ExecHookSink
+{
+    groups : HashMap<String, GroupHooks>,
+
+    worker_count : usize,
+
+    timeout : Duration,
+
+    queue_size : usize,
+
+    // Queue of each worker
+    senders : Vec<Sender<Vec<String>>>,
+
+    // Map from (group name, address) of each member of a group with hooks to the pubkey by which it was added, if any
+    members : HashMap<(String, IpAddr), Option<Pubkey>>,
+
+    // False when monitoring, in which case no commands are run
+    enforcing : bool
+}
+
+impl ExecHookSink
+{
+    pub fn new(config : ExecHooksConfig) -> Self
+    {
+        let worker_count = config.worker_count.unwrap_or(DEFAULT_EXEC_HOOK_WORKER_COUNT);
+        let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_EXEC_HOOK_TIMEOUT_MS));
+
+        let queue_size = config.queue_size.unwrap_or(DEFAULT_EXEC_HOOK_QUEUE_SIZE);
+
+        let senders = (0..worker_count)
+            .map(|_| {
+                let (sender, receiver) = bounded::<Vec<String>>(queue_size);
+                std::thread::spawn(move || run_commands(receiver, timeout));
+                sender
+            })
+            .collect();
+
+        Self {
+            groups : config.groups,
+            worker_count,
+            timeout,
+            queue_size,
+            senders,
+            members : Default::default(),
+            enforcing : true
+        }
+    }
+
+    // Queues the command from template for a member to the worker of its address.  Add commands (those with an
+    // expiration) are dropped if the worker's queue is full; remove commands wait for room.
+    fn queue(
+        &self,
+        template : &str,
+        group_name : &str,
+        ip_addr : &IpAddr,
+        pubkey : &Option<Pubkey>,
+        expiration : Option<u64>
+    )
+    {
+        let mut hasher = DefaultHasher::new();
+        ip_addr.hash(&mut hasher);
+        let sender = &self.senders[(hasher.finish() as usize) % self.senders.len()];
+
+        let args = template
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{ip}", &ip_addr.to_string())
+                    .replace("{group}", group_name)
+                    .replace("{pubkey}", &pubkey.map(|pubkey| pubkey.to_string()).unwrap_or_default())
+                    .replace("{expiration}", &expiration.map(|expiration| expiration.to_string()).unwrap_or_default())
+            })
+            .collect::<Vec<String>>();
+
+        if expiration.is_none() {
+            if sender.send(args).is_err() {
+                eprintln!("ERROR: Exec hook workers exited");
+            }
+            return;
+        }
+
+        match sender.try_send(args) {
+            Ok(()) => (),
+            Err(TrySendError::Full(args)) => {
+                eprintln!("ERROR: Exec hook queue full; dropped command {}", args.join(" "))
+            },
+            Err(TrySendError::Disconnected(_)) => eprintln!("ERROR: Exec hook workers exited")
+        }
+    }
+
+    fn added(
+        &mut self,
+        group_name : &str,
+        ip_addr : &IpAddr,
+        pubkey : &Option<Pubkey>,
+        expiration : u64
+    )
+    {
+        let Some(hooks) = self.groups.get(group_name)
+        else {
+            return;
+        };
+
+        if self.members.insert((group_name.to_string(), *ip_addr), *pubkey).is_none() {
+            if let Some(on_add) = &hooks.on_add {
+                self.queue(on_add, group_name, ip_addr, pubkey, Some(expiration));
+            }
+        }
+    }
+
+    fn removed(
+        &mut self,
+        group_name : &str,
+        ip_addr : &IpAddr
+    )
+    {
+        let Some(hooks) = self.groups.get(group_name)
+        else {
+            return;
+        };
+
+        if let Some(pubkey) = self.members.remove(&(group_name.to_string(), *ip_addr)) {
+            if let Some(on_remove) = &hooks.on_remove {
+                self.queue(on_remove, group_name, ip_addr, &pubkey, None);
+            }
+        }
+    }
+}
+
+impl OutputSink for ExecHookSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "exec_hooks",
+            "groups" : self.groups,
+            "worker_count" : self.worker_count,
+            "queue_size" : self.queue_size,
+            "timeout_ms" : self.timeout.as_millis() as u64
+        })
+    }
+
+    // Also called for updates (by the default on_update), which add members not already known, as when members are
+    // re-sent on switching to enforcing
+    fn on_add(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        if let (true, Some(expiration)) = (self.enforcing, change.expiration) {
+            self.added(change.group_name, &change.ip_addr, &change.pubkey, expiration);
+        }
+
+        Ok(())
+    }
+
+    fn on_remove(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        if self.enforcing {
+            self.removed(change.group_name, &change.ip_addr);
+        }
+
+        Ok(())
+    }
+
+    fn on_event(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if let GroupChange::Mode { enforcing, .. } = event {
+            if *enforcing != self.enforcing {
+                if !*enforcing {
+                    for (group_name, ip_addr) in self.members.keys().cloned().collect::<Vec<_>>() {
+                        self.removed(&group_name, &ip_addr);
+                    }
+                }
+                self.enforcing = *enforcing;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Runs each command received, until the sink is dropped
+fn run_commands(
+    receiver : Receiver<Vec<String>>,
+    timeout : Duration
+)
+{
+    for args in receiver {
+        if let Err(e) = run_command(&args, timeout) {
+            eprintln!("ERROR: Exec hook {} failed: {e}", args.join(" "));
+        }
+    }
+}
+
+// Runs a command, killing it if it has not exited within timeout
+fn run_command(
+    args : &[String],
+    timeout : Duration
+) -> Result<(), String>
+{
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("failed to run: {e}"))?;
+
+    let start = Instant::now();
+
+    loop {
+        match child.try_wait().map_err(|e| format!("failed to wait: {e}"))? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(format!("exited with {status}")),
+            None if start.elapsed() >= timeout => {
+                // The child is reaped so that it does not linger as a zombie
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("killed after {} ms", timeout.as_millis()));
+            },
+            None => std::thread::sleep(EXEC_HOOK_POLL_INTERVAL)
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+// The sets of a sink keeping firewall sets (nftables or ipset) that hold the members of a group
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FirewallSetConfig
+{
+    // Group whose members are held in the sets
+    pub group_name : String,
+
+    // Name of the set holding the IPv4 members of the group (of type ipv4_addr for nftables, of family inet for
+    // ipset).  If not present, IPv4 members are not added to any set.
+    pub ipv4_set : Option<String>,
+
+    // Name of the set holding the IPv6 members of the group (of type ipv6_addr for nftables, of family inet6 for
+    // ipset).  If not present, IPv6 members are not added to any set.
+    pub ipv6_set : Option<String>
+}
+
+impl FirewallSetConfig
+{
+    // Returns the names of the sets
+    pub fn names(&self) -> impl Iterator<Item = &String>
+    {
+        self.ipv4_set.iter().chain(self.ipv6_set.iter())
+    }
+}
+
+// Names are written into the scripts of the firewall commands, so they are limited to characters that need no quoting
+pub fn is_valid_name(name : &str) -> bool
+{
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || (c == '_') || (c == '-'))
+}
+
+// Validates the sets of the kind of firewall ("nftables" or "ipset") named in errors
+pub fn validate_sets(
+    kind : &str,
+    sets : &[FirewallSetConfig]
+) -> Result<(), String>
+{
+    for set in sets {
+        if set.ipv4_set.is_none() && set.ipv6_set.is_none() {
+            return Err(format!("Invalid {kind} set for group {}: no ipv4_set or ipv6_set", set.group_name));
+        }
+        for name in set.names() {
+            if !is_valid_name(name) {
+                return Err(format!("Invalid {kind} set name {name} for group {}", set.group_name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The addresses that belong in each set of a sink keeping firewall sets, and those whose entries may have changed
+// since the last successful flush.  The sinks accumulate changes as events are applied and then make them on flush by
+// a single script.  Sets are emptied at startup, so that no member of a prior run lingers, and while monitoring.
+pub struct FirewallSetMembers
+{
+    // Map from group name to the names of the sets holding its IPv4 members and its IPv6 members
+    sets_of_group : HashMap<String, (Vec<String>, Vec<String>)>,
+
+    // Map from set name to the addresses that belong in it, each with the expiration of its membership in each group
+    // on behalf of which it belongs
+    members : HashMap<String, HashMap<IpAddr, HashMap<String, u64>>>,
+
+    // Map from set name to the addresses whose entries may have changed since the last successful flush
+    pub changed : HashMap<String, HashSet<IpAddr>>
+}
+
+impl FirewallSetMembers
+{
+    pub fn new(sets : &[FirewallSetConfig]) -> Self
+    {
+        let mut sets_of_group = HashMap::<String, (Vec<String>, Vec<String>)>::new();
+        for set in sets {
+            let (ipv4_sets, ipv6_sets) = sets_of_group.entry(set.group_name.clone()).or_default();
+            ipv4_sets.extend(set.ipv4_set.iter().cloned());
+            ipv6_sets.extend(set.ipv6_set.iter().cloned());
+        }
+
+        Self { sets_of_group, members : Default::default(), changed : Default::default() }
+    }
+
+    // Records that ip_addr is a member of group_name until expiration (if Some), or is not a member (if None), for
+    // each set holding the group's members, noting the address as changed in each set in which its entry changed
+    pub fn set_member(
+        &mut self,
+        group_name : &str,
+        ip_addr : IpAddr,
+        expiration : Option<u64>
+    )
+    {
+        let Some((ipv4_sets, ipv6_sets)) = self.sets_of_group.get(group_name)
+        else {
+            return;
+        };
+
+        for set in if ip_addr.is_ipv4() { ipv4_sets } else { ipv6_sets } {
+            let members = self.members.entry(set.clone()).or_default();
+            let changed = match expiration {
+                Some(expiration) => {
+                    members.entry(ip_addr).or_default().insert(group_name.to_string(), expiration) != Some(expiration)
+                },
+                None => {
+                    let removed =
+                        members.get_mut(&ip_addr).map(|groups| groups.remove(group_name).is_some()).unwrap_or(false);
+                    if members.get(&ip_addr).map(HashMap::is_empty).unwrap_or(false) {
+                        members.remove(&ip_addr);
+                    }
+                    removed
+                }
+            };
+            if changed {
+                self.changed.entry(set.clone()).or_default().insert(ip_addr);
+            }
+        }
+    }
+
+    // Returns the latest expiration of the memberships on behalf of which ip_addr belongs in set, or None if it
+    // doesn't belong in set
+    pub fn expiration(
+        &self,
+        set : &str,
+        ip_addr : &IpAddr
+    ) -> Option<u64>
+    {
+        self.members.get(set).and_then(|members| members.get(ip_addr)).and_then(|groups| groups.values().max().copied())
+    }
+
+    // Notes every address that belongs in any set as changed, so that all are re-evaluated by the next flush
+    pub fn change_all(&mut self)
+    {
+        for (set, members) in &self.members {
+            self.changed.entry(set.clone()).or_default().extend(members.keys());
+        }
+    }
+}
@@ -1,60 +1,333 @@
+use crate::churn::ChurnTotals;
+use crate::config::{ExtensionEvents, GroupExpirationLimits, GroupTtlPolicy};
+use crate::shrink::Shrink;
+use crate::sink::{GroupChange, SinkSender};
+use solana_sdk::pubkey::Pubkey;
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Arc;
 
 pub const DEFAULT_GROUP_EXPIRATION_SECONDS : u64 = 24 * 60 * 60; // One day
 
+// Expiration of the members of permanent groups, which never expire
+pub const PERMANENT_EXPIRATION : u64 = u64::MAX;
+
 // A Group manages the membership of ip addresses in a classifier group.
 #[derive(Default)]
 pub struct Group
 {
     name : String,
 
-    // Map from member to timestamp of when the member will expire (in milliseconds)
-    members : HashMap<IpAddr, u64>
+    // How the expiration of an existing membership is changed when its member is added again
+    ttl_policy : GroupTtlPolicy,
+
+    // Maximum duration of membership, in milliseconds, to which requested durations are clamped
+    max_ttl_ms : Option<u64>,
+
+    // If true, members never expire
+    permanent : bool,
+
+    // Which events are sent when a membership is extended
+    extension_events : ExtensionEvents,
+
+    // Map from member to its membership
+    members : HashMap<IpAddr, Membership>,
+
+    // Total number of members added, removed, and expired
+    add_count : u64,
+
+    remove_count : u64,
+
+    expire_count : u64,
+
+    // Number of members added by each rule, and the number of members removed, since activity was last taken
+    activity_add_counts : HashMap<Arc<str>, u64>,
+    activity_remove_count : u64
+}
+
+// A rule by which ip addresses are added to groups
+#[derive(Clone, Default)]
+pub struct Rule
+{
+    // Name of the rule, as recorded in memberships: e.g. "zero_fee_share[1]" for a classification threshold,
+    // "known_pubkeys", or "plugin:<name>"
+    pub name : Arc<str>,
+
+    // The classification threshold, if the rule is one
+    pub threshold : Option<ThresholdRule>
+}
+
+// Identifies a classification threshold as a rule
+#[derive(Clone)]
+pub struct ThresholdRule
+{
+    pub classification : Arc<str>,
+
+    // Index of the threshold in the classification's thresholds, or in its overload_thresholds if overload
+    pub index : usize,
+
+    pub overload : bool
+}
+
+impl Rule
+{
+    // A rule other than a classification threshold
+    pub fn named(name : &str) -> Self
+    {
+        Self { name : Arc::from(name), threshold : None }
+    }
+}
+
+// The membership of an ip address in a group
+#[derive(Clone)]
+pub struct Membership
+{
+    // Timestamp of when the member will expire (in milliseconds)
+    pub expiration : u64,
+
+    // Duration of membership given by the rule that added the member
+    pub ttl_ms : u64,
+
+    // The rule (e.g. a classification threshold) that added the member
+    pub added_by : Arc<str>,
+
+    // The rule that most recently extended (or, under the latest policy, shortened) the membership, if any
+    pub extended_by : Option<Arc<str>>,
+
+    // The pubkey by which the member was added, for members added by known pubkey or by the classification of an
+    // identity
+    pub pubkey : Option<Pubkey>,
+
+    // Timestamp of the most recent add or update event sent for the membership
+    pub event_timestamp : u64
 }
 
 impl Group
 {
     pub fn new(name : &str) -> Self
     {
-        Self { name : name.to_string(), members : Default::default() }
+        Self::with_policies(
+            name,
+            GroupTtlPolicy::default(),
+            &GroupExpirationLimits::default(),
+            ExtensionEvents::default()
+        )
+    }
+
+    pub fn with_policies(
+        name : &str,
+        ttl_policy : GroupTtlPolicy,
+        expiration_limits : &GroupExpirationLimits,
+        extension_events : ExtensionEvents
+    ) -> Self
+    {
+        Self {
+            name : name.to_string(),
+            ttl_policy,
+            max_ttl_ms : expiration_limits.max_expiration_seconds.map(|seconds| seconds * 1000),
+            permanent : expiration_limits.permanent.unwrap_or(false),
+            extension_events,
+            members : Default::default(),
+            add_count : 0,
+            remove_count : 0,
+            expire_count : 0,
+            activity_add_counts : Default::default(),
+            activity_remove_count : 0
+        }
     }
 
+    pub fn members(&self) -> &HashMap<IpAddr, Membership>
+    {
+        &self.members
+    }
+
+    // Returns the number of members added by each rule, and the number of members removed, since the previous call
+    pub fn take_activity(&mut self) -> (HashMap<Arc<str>, u64>, u64)
+    {
+        (std::mem::take(&mut self.activity_add_counts), std::mem::take(&mut self.activity_remove_count))
+    }
+
+    // Total number of members added since the group was created
+    pub fn add_count(&self) -> u64
+    {
+        self.add_count
+    }
+
+    // Total numbers of members added, removed, and expired since the group was created
+    pub fn churn_totals(&self) -> ChurnTotals
+    {
+        ChurnTotals { add_count : self.add_count, remove_count : self.remove_count, expire_count : self.expire_count }
+    }
+
+    // Adds ip_addr to the group for ttl_ms from now on behalf of rule, or changes its membership according to the
+    // group's ttl policy if it is already a member.  pubkey is the pubkey by which ip_addr is added, if it is added by
+    // known pubkey or for the classification of the identity of pubkey behind ip_addr.  Returns true if ip_addr was
+    // not already a member.
     pub fn add(
         &mut self,
         ip_addr : IpAddr,
-        expiration : u64
-    )
+        pubkey : Option<Pubkey>,
+        now : u64,
+        ttl_ms : u64,
+        rule : &Rule,
+        sink : &SinkSender
+    ) -> bool
     {
+        let mut added = false;
+
+        let ttl_ms = self.max_ttl_ms.map(|max_ttl_ms| ttl_ms.min(max_ttl_ms)).unwrap_or(ttl_ms);
+
+        // Members of permanent groups are added once and never extended
+        let permanent = self.permanent;
+
+        let extension_events = self.extension_events;
+
         self.members
             .entry(ip_addr)
-            .and_modify(|timestamp| {
-                if *timestamp < expiration {
-                    println!("Update {ip_addr} in group {} with expiration {expiration}", self.name);
-                    *timestamp = expiration
+            .and_modify(|membership| {
+                if pubkey.is_some() {
+                    membership.pubkey = pubkey;
+                }
+                if permanent {
+                    return;
+                }
+                let expiration = match self.ttl_policy {
+                    GroupTtlPolicy::Max => now.saturating_add(ttl_ms).max(membership.expiration),
+                    GroupTtlPolicy::Latest => now.saturating_add(ttl_ms),
+                    // Saturating, since members imported to never expire have a ttl reaching the permanent expiration
+                    GroupTtlPolicy::ExtendOnly => now.saturating_add(membership.ttl_ms).max(membership.expiration)
+                };
+                if expiration != membership.expiration {
+                    let (group_name, pubkey) = (self.name.clone(), membership.pubkey);
+                    let event = match extension_events {
+                        ExtensionEvents::None => None,
+                        ExtensionEvents::EveryMs(every_ms) if now < (membership.event_timestamp + every_ms) => None,
+                        ExtensionEvents::Update | ExtensionEvents::EveryMs(_) => Some(GroupChange::Update {
+                            group_name,
+                            ip_addr,
+                            pubkey,
+                            expiration,
+                            metadata : None,
+                            throttle : None
+                        }),
+                        ExtensionEvents::Extend => Some(GroupChange::Extend {
+                            group_name,
+                            ip_addr,
+                            pubkey,
+                            expiration,
+                            metadata : None,
+                            throttle : None
+                        })
+                    };
+                    if let Some(event) = event {
+                        sink.send(event);
+                        membership.event_timestamp = now;
+                    }
+                    membership.expiration = expiration;
+                    membership.extended_by = Some(rule.name.clone());
+                    if self.ttl_policy != GroupTtlPolicy::ExtendOnly {
+                        membership.ttl_ms = ttl_ms;
+                    }
                 }
             })
             .or_insert_with(|| {
-                println!("Add {ip_addr} to group {} with expiration {expiration}", self.name);
-                expiration
+                let expiration = if permanent { PERMANENT_EXPIRATION } else { now.saturating_add(ttl_ms) };
+                sink.send(GroupChange::Add {
+                    group_name : self.name.clone(),
+                    ip_addr,
+                    pubkey,
+                    expiration,
+                    metadata : None,
+                    throttle : None,
+                    rule : rule.clone(),
+                    timestamp : now,
+                    trigger_timestamp : None,
+                    stake : None,
+                    decision_id : 0
+                });
+                added = true;
+                self.add_count += 1;
+                *self.activity_add_counts.entry(rule.name.clone()).or_default() += 1;
+                Membership {
+                    expiration,
+                    ttl_ms,
+                    added_by : rule.name.clone(),
+                    extended_by : None,
+                    pubkey,
+                    event_timestamp : now
+                }
+            });
+
+        added
+    }
+
+    // Removes ip_addr from the group if it is a member.  Returns true if it was a member.
+    pub fn remove(
+        &mut self,
+        ip_addr : &IpAddr,
+        sink : &SinkSender
+    ) -> bool
+    {
+        if self.members.remove(ip_addr).is_some() {
+            sink.send(GroupChange::Remove {
+                group_name : self.name.clone(),
+                ip_addr : *ip_addr,
+                metadata : None,
+                expired : false
+            });
+            self.remove_count += 1;
+            self.activity_remove_count += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    // Sends an Update event for every member, so that sinks which did not act on earlier events can re-establish the
+    // group's membership
+    pub fn resend(
+        &self,
+        sink : &SinkSender
+    )
+    {
+        for (ip_addr, membership) in &self.members {
+            sink.send(GroupChange::Update {
+                group_name : self.name.clone(),
+                ip_addr : *ip_addr,
+                pubkey : membership.pubkey,
+                expiration : membership.expiration,
+                metadata : None,
+                throttle : None
             });
+        }
     }
 
-    // To be called once per second
+    // To be called periodically
     pub fn periodic(
         &mut self,
-        now : u64
+        now : u64,
+        sink : &SinkSender
     )
     {
-        // Expire group memberships that are too old
-        self.members.retain(|ip_addr, expire_timestamp| {
-            if *expire_timestamp < now {
-                println!("Remove {ip_addr} from group {}", self.name);
+        // Expire group memberships that are too old; permanent memberships have the maximum expiration, so never expire
+        self.members.retain(|ip_addr, membership| {
+            if membership.expiration < now {
+                sink.send(GroupChange::Remove {
+                    group_name : self.name.clone(),
+                    ip_addr : *ip_addr,
+                    metadata : None,
+                    expired : true
+                });
+                self.expire_count += 1;
+                self.activity_remove_count += 1;
                 false
             }
             else {
                 true
             }
         });
+
+        self.members.shrink_idle();
     }
 }
@@ -0,0 +1,417 @@
+// gRPC service streaming classification events and answering group membership queries, for downstream automation
+// (e.g. firewall controllers and dashboards) that would otherwise tail stdout.  The service is defined by
+// proto/txingest.proto, whose messages are mirrored here by hand so that building needs no protobuf compiler.  A sink
+// feeds the service: it broadcasts each group change to subscribers and keeps a copy of the membership of every group
+// from which queries are answered, so that the service never touches State.
+
+use crate::sink::{self, Sink};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::codegen::{http, BoxFuture, StdError};
+use tonic::{Request, Response, Status};
+
+pub const DEFAULT_GRPC_QUEUE_SIZE : usize = 10000;
+
+const SERVICE_NAME : &str = "txingest.v1.Classifier";
+
+const STREAM_GROUP_CHANGES_PATH : &str = "/txingest.v1.Classifier/StreamGroupChanges";
+
+const GET_GROUP_MEMBERS_PATH : &str = "/txingest.v1.Classifier/GetGroupMembers";
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamGroupChangesRequest
+{
+    #[prost(string, repeated, tag = "1")]
+    pub group_names : Vec<String>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ClassificationEvent
+{
+    #[prost(oneof = "Event", tags = "1, 2")]
+    pub event : Option<Event>
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Event
+{
+    #[prost(message, tag = "1")]
+    GroupChange(GroupChange),
+
+    #[prost(message, tag = "2")]
+    ModeChange(ModeChange)
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GroupChange
+{
+    #[prost(enumeration = "GroupChangeKind", tag = "1")]
+    pub kind : i32,
+
+    #[prost(string, tag = "2")]
+    pub group_name : String,
+
+    #[prost(string, tag = "3")]
+    pub ip_addr : String,
+
+    #[prost(string, optional, tag = "4")]
+    pub pubkey : Option<String>,
+
+    #[prost(uint64, tag = "5")]
+    pub expiration : u64,
+
+    #[prost(bool, tag = "6")]
+    pub expired : bool,
+
+    #[prost(string, tag = "7")]
+    pub rule : String,
+
+    #[prost(uint64, tag = "8")]
+    pub timestamp : u64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum GroupChangeKind
+{
+    Added = 0,
+
+    Updated = 1,
+
+    Removed = 2
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ModeChange
+{
+    #[prost(bool, tag = "1")]
+    pub enforcing : bool,
+
+    #[prost(uint64, tag = "2")]
+    pub timestamp : u64
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetGroupMembersRequest
+{
+    #[prost(string, tag = "1")]
+    pub group_name : String
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetGroupMembersResponse
+{
+    #[prost(bool, tag = "1")]
+    pub enforcing : bool,
+
+    #[prost(message, repeated, tag = "2")]
+    pub members : Vec<GroupMember>
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GroupMember
+{
+    #[prost(string, tag = "1")]
+    pub ip_addr : String,
+
+    #[prost(string, optional, tag = "2")]
+    pub pubkey : Option<String>,
+
+    #[prost(uint64, tag = "3")]
+    pub expiration : u64,
+
+    #[prost(string, tag = "4")]
+    pub added_by : String
+}
+
+// The membership of every group as seen by the sink, from which queries are answered
+struct Membership
+{
+    enforcing : bool,
+
+    groups : HashMap<String, HashMap<IpAddr, GroupMember>>
+}
+
+// State shared between the sink and the service
+struct Shared
+{
+    events : broadcast::Sender<ClassificationEvent>,
+
+    membership : Mutex<Membership>
+}
+
+// Sink feeding the gRPC service
+pub struct GrpcSink
+{
+    listen_address : SocketAddr,
+
+    queue_size : usize,
+
+    shared : Arc<Shared>
+}
+
+#[derive(Clone)]
+struct ClassifierService
+{
+    shared : Arc<Shared>
+}
+
+// Binds listen_address and spawns a thread running the gRPC service on an async runtime, returning the sink that
+// feeds it.  Each subscriber may fall up to queue_size events behind before its stream is ended.
+pub fn spawn_server(
+    listen_address : SocketAddr,
+    queue_size : usize
+) -> Result<GrpcSink, String>
+{
+    let tcp_listener = std::net::TcpListener::bind(listen_address)
+        .and_then(|tcp_listener| tcp_listener.set_nonblocking(true).map(|_| tcp_listener))
+        .map_err(|e| format!("Failed to bind gRPC address {listen_address}: {e}"))?;
+
+    let shared = Arc::new(Shared {
+        events : broadcast::channel(queue_size).0,
+        membership : Mutex::new(Membership { enforcing : true, groups : Default::default() })
+    });
+
+    let service = ClassifierService { shared : shared.clone() };
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .expect("Failed to build gRPC runtime");
+        runtime.block_on(async move {
+            let incoming = match tokio::net::TcpListener::from_std(tcp_listener) {
+                Ok(tcp_listener) => TcpListenerStream::new(tcp_listener),
+                Err(e) => {
+                    eprintln!("ERROR: gRPC server failed to listen because {e}");
+                    return;
+                }
+            };
+            if let Err(e) = tonic::transport::Server::builder().add_service(service).serve_with_incoming(incoming).await
+            {
+                eprintln!("ERROR: gRPC server failed because {e}");
+            }
+        });
+    });
+
+    Ok(GrpcSink { listen_address, queue_size, shared })
+}
+
+impl Sink for GrpcSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "grpc",
+            "listen_address" : self.listen_address.to_string(),
+            "queue_size" : self.queue_size
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &sink::GroupChange
+    ) -> Result<(), String>
+    {
+        let mut membership = self.shared.membership.lock().unwrap();
+
+        let event = match event {
+            sink::GroupChange::Add { group_name, ip_addr, pubkey, expiration, rule, timestamp, .. } => {
+                membership.groups.entry(group_name.clone()).or_default().insert(*ip_addr, GroupMember {
+                    ip_addr : ip_addr.to_string(),
+                    pubkey : pubkey.map(|pubkey| pubkey.to_string()),
+                    expiration : *expiration,
+                    added_by : rule.name.to_string()
+                });
+                Event::GroupChange(GroupChange {
+                    kind : GroupChangeKind::Added as i32,
+                    group_name : group_name.clone(),
+                    ip_addr : ip_addr.to_string(),
+                    pubkey : pubkey.map(|pubkey| pubkey.to_string()),
+                    expiration : *expiration,
+                    expired : false,
+                    rule : rule.name.to_string(),
+                    timestamp : *timestamp
+                })
+            },
+            // Members not yet known are added, as when an add event was dropped
+            sink::GroupChange::Update { group_name, ip_addr, pubkey, expiration, .. } |
+            sink::GroupChange::Extend { group_name, ip_addr, pubkey, expiration, .. } => {
+                let member = membership
+                    .groups
+                    .entry(group_name.clone())
+                    .or_default()
+                    .entry(*ip_addr)
+                    .or_insert_with(|| GroupMember { ip_addr : ip_addr.to_string(), ..Default::default() });
+                member.expiration = *expiration;
+                if pubkey.is_some() {
+                    member.pubkey = pubkey.map(|pubkey| pubkey.to_string());
+                }
+                Event::GroupChange(GroupChange {
+                    kind : GroupChangeKind::Updated as i32,
+                    group_name : group_name.clone(),
+                    ip_addr : ip_addr.to_string(),
+                    pubkey : pubkey.map(|pubkey| pubkey.to_string()),
+                    expiration : *expiration,
+                    ..Default::default()
+                })
+            },
+            sink::GroupChange::Remove { group_name, ip_addr, expired, .. } => {
+                if let Some(members) = membership.groups.get_mut(group_name) {
+                    members.remove(ip_addr);
+                }
+                Event::GroupChange(GroupChange {
+                    kind : GroupChangeKind::Removed as i32,
+                    group_name : group_name.clone(),
+                    ip_addr : ip_addr.to_string(),
+                    expired : *expired,
+                    ..Default::default()
+                })
+            },
+            sink::GroupChange::Mode { enforcing, timestamp } => {
+                membership.enforcing = *enforcing;
+                Event::ModeChange(ModeChange { enforcing : *enforcing, timestamp : *timestamp })
+            },
+            _ => return Ok(())
+        };
+
+        // Sending fails only if there are no subscribers, which is of no concern
+        self.shared.events.send(ClassificationEvent { event : Some(event) }).ok();
+
+        Ok(())
+    }
+}
+
+impl ClassifierService
+{
+    fn group_members(
+        &self,
+        group_name : &str
+    ) -> Option<GetGroupMembersResponse>
+    {
+        let membership = self.shared.membership.lock().unwrap();
+
+        let mut members = membership.groups.get(group_name)?.iter().collect::<Vec<_>>();
+
+        members.sort_by_key(|(ip_addr, _)| **ip_addr);
+
+        Some(GetGroupMembersResponse {
+            enforcing : membership.enforcing,
+            members : members.into_iter().map(|(_, member)| member.clone()).collect()
+        })
+    }
+}
+
+struct StreamGroupChanges(ClassifierService);
+
+impl tonic::server::ServerStreamingService<StreamGroupChangesRequest> for StreamGroupChanges
+{
+    type Response = ClassificationEvent;
+
+    type ResponseStream = Pin<Box<dyn Stream<Item = Result<ClassificationEvent, Status>> + Send>>;
+
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(
+        &mut self,
+        request : Request<StreamGroupChangesRequest>
+    ) -> Self::Future
+    {
+        let group_names = request.into_inner().group_names.into_iter().collect::<HashSet<String>>();
+
+        let stream = BroadcastStream::new(self.0.shared.events.subscribe()).filter_map(move |event| match event {
+            Ok(event) => match &event.event {
+                Some(Event::GroupChange(group_change))
+                    if !group_names.is_empty() && !group_names.contains(&group_change.group_name) =>
+                {
+                    None
+                },
+                _ => Some(Ok(event))
+            },
+            // Ending the stream tells the subscriber to resubscribe and re-query, rather than silently missing changes
+            Err(BroadcastStreamRecvError::Lagged(count)) => {
+                Some(Err(Status::data_loss(format!("Subscriber fell behind; {count} events dropped"))))
+            },
+        });
+
+        Box::pin(std::future::ready(Ok(Response::new(Box::pin(stream) as Self::ResponseStream))))
+    }
+}
+
+struct GetGroupMembers(ClassifierService);
+
+impl tonic::server::UnaryService<GetGroupMembersRequest> for GetGroupMembers
+{
+    type Response = GetGroupMembersResponse;
+
+    type Future = BoxFuture<Response<Self::Response>, Status>;
+
+    fn call(
+        &mut self,
+        request : Request<GetGroupMembersRequest>
+    ) -> Self::Future
+    {
+        let group_name = request.into_inner().group_name;
+
+        Box::pin(std::future::ready(
+            self.0
+                .group_members(&group_name)
+                .map(Response::new)
+                .ok_or_else(|| Status::not_found(format!("No such group: {group_name}")))
+        ))
+    }
+}
+
+// Routes requests to their methods, as generated code would
+impl<B> tonic::codegen::Service<http::Request<B>> for ClassifierService
+where
+    B : tonic::codegen::Body + Send + 'static,
+    B::Error : Into<StdError> + Send + 'static
+{
+    type Response = http::Response<tonic::body::BoxBody>;
+
+    type Error = std::convert::Infallible;
+
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        _cx : &mut Context<'_>
+    ) -> Poll<Result<(), Self::Error>>
+    {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(
+        &mut self,
+        request : http::Request<B>
+    ) -> Self::Future
+    {
+        let service = self.clone();
+
+        match request.uri().path() {
+            STREAM_GROUP_CHANGES_PATH => Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+                Ok(grpc.server_streaming(StreamGroupChanges(service), request).await)
+            }),
+            GET_GROUP_MEMBERS_PATH => Box::pin(async move {
+                let mut grpc = tonic::server::Grpc::new(tonic::codec::ProstCodec::default());
+                Ok(grpc.unary(GetGroupMembers(service), request).await)
+            }),
+            _ => Box::pin(async move { Ok(Status::unimplemented("No such method").into_http()) })
+        }
+    }
+}
+
+impl tonic::server::NamedService for ClassifierService
+{
+    const NAME : &'static str = SERVICE_NAME;
+}
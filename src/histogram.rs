@@ -0,0 +1,86 @@
+use serde::Serialize;
+
+// Upper bounds (inclusive) of the buckets of connection duration histograms, in milliseconds.  A final bucket holds
+// all durations above the last bound.
+pub const CONNECTION_DURATION_BOUNDS_MS : &[u64] =
+    &[10, 50, 100, 250, 500, 1000, 2000, 5000, 10_000, 30_000, 60_000, 300_000];
+
+// Upper bounds (inclusive) of the buckets of decision latency histograms, in milliseconds
+pub const DECISION_LATENCY_BOUNDS_MS : &[u64] =
+    &[10, 50, 100, 250, 500, 1000, 2000, 5000, 10_000, 30_000, 60_000, 300_000, 600_000];
+
+// Histogram of values in fixed buckets
+#[derive(Clone, Serialize)]
+pub struct Histogram
+{
+    // Upper bound (inclusive) of each bucket but the last, which is unbounded
+    bounds : &'static [u64],
+
+    // Number of values in each bucket; has one more entry than bounds
+    counts : Vec<u64>,
+
+    count : u64,
+
+    sum : u64
+}
+
+impl Histogram
+{
+    pub fn new(bounds : &'static [u64]) -> Self
+    {
+        Self { bounds, counts : vec![0; bounds.len() + 1], count : 0, sum : 0 }
+    }
+
+    pub fn add(
+        &mut self,
+        value : u64
+    )
+    {
+        let index = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+
+    pub fn count(&self) -> u64
+    {
+        self.count
+    }
+
+    // Returns the upper bound of the bucket containing the given percentile of values, or None if there are no values
+    // or the percentile falls in the unbounded bucket
+    pub fn percentile(
+        &self,
+        percentile : f64
+    ) -> Option<u64>
+    {
+        if self.count == 0 {
+            return None;
+        }
+
+        let target = ((percentile / 100.0) * (self.count as f64)).ceil().max(1.0) as u64;
+
+        let mut cumulative = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= target {
+                return self.bounds.get(index).copied();
+            }
+        }
+
+        None
+    }
+
+    // Describes the given percentile for reports, e.g. "<=250ms", or ">300000ms" if it falls in the unbounded bucket
+    pub fn describe_percentile(
+        &self,
+        percentile : f64
+    ) -> String
+    {
+        match self.percentile(percentile) {
+            Some(ms) => format!("<={ms}ms"),
+            None if self.count > 0 => format!(">{}ms", self.bounds.last().unwrap()),
+            None => "-".to_string()
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// An http endpoint to which JSON bodies are posted.  Only plain http URLs are supported; use a local relay for https
+// endpoints.
+pub struct HttpEndpoint
+{
+    url : String,
+
+    // Host (with port) and path, from the url
+    host : String,
+
+    path : String,
+
+    // Timeout for connecting to and exchanging data with the endpoint
+    timeout : Duration
+}
+
+impl HttpEndpoint
+{
+    pub fn new(
+        url : &str,
+        timeout_ms : u64
+    ) -> Result<Self, String>
+    {
+        let rest = url.strip_prefix("http://").ok_or_else(|| format!("Invalid url {url}: must begin with http://"))?;
+
+        let (host, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/")
+        };
+
+        if host.is_empty() {
+            return Err(format!("Invalid url {url}: no host"));
+        }
+
+        if timeout_ms == 0 {
+            return Err(format!("Invalid zero timeout for {url}"));
+        }
+
+        Ok(Self {
+            url : url.to_string(),
+            host : if host.contains(':') { host.to_string() } else { format!("{host}:80") },
+            path : path.to_string(),
+            timeout : Duration::from_millis(timeout_ms)
+        })
+    }
+
+    pub fn url(&self) -> &str
+    {
+        &self.url
+    }
+
+    // Posts body as JSON, returning an error if the post failed or the response status was not 2xx
+    pub fn post_json(
+        &self,
+        body : &str
+    ) -> Result<(), String>
+    {
+        let address = self
+            .host
+            .to_socket_addrs()
+            .map_err(|e| format!("Failed to resolve {}: {e}", self.host))?
+            .next()
+            .ok_or_else(|| format!("Failed to resolve {}: no addresses", self.host))?;
+
+        let mut stream = TcpStream::connect_timeout(&address, self.timeout)
+            .map_err(|e| format!("Failed to connect to {}: {e}", self.url))?;
+        stream.set_read_timeout(Some(self.timeout)).ok();
+        stream.set_write_timeout(Some(self.timeout)).ok();
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: \
+             close\r\n\r\n{body}",
+            self.path,
+            self.host,
+            body.len()
+        )
+        .map_err(|e| format!("Failed to post to {}: {e}", self.url))?;
+
+        // Only the status line is of interest
+        let mut response = [0_u8; 64];
+        let len = stream.read(&mut response).map_err(|e| format!("Failed to read from {}: {e}", self.url))?;
+        let status_line = String::from_utf8_lossy(&response[..len]);
+        let status = status_line.split_whitespace().nth(1).unwrap_or("");
+
+        if status.starts_with('2') {
+            Ok(())
+        }
+        else {
+            Err(format!("{} responded with status {status}", self.url))
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::clock::Clock;
+use crate::firewall_sets::{validate_sets, FirewallSetConfig, FirewallSetMembers};
+use crate::group::PERMANENT_EXPIRATION;
+use crate::sink::{run_script, GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const DEFAULT_IPSET_PATH : &str = "ipset";
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct IpsetConfig
+{
+    // The sets to manage, each holding the members of a group.  The sets, and the iptables rules that use them, must
+    // already exist; only the entries of the sets are managed.
+    pub sets : Vec<FirewallSetConfig>,
+
+    // If present and true, entries are added with a timeout of the time remaining in their membership, so that the
+    // kernel expires them even if the classifier stops; the sets must then have been created with the timeout option.
+    // Members that never expire are added with a timeout of 0, i.e. none.  Groups of the sets must then send events
+    // for every extension ("update" or "extend" group_extension_events).  If not present, entries have no timeout and
+    // are deleted only when their members are removed.
+    pub timeouts : Option<bool>,
+
+    // Path of the ipset command.  If not present, "ipset" is found on the PATH.
+    pub ipset_path : Option<String>
+}
+
+impl IpsetConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        validate_sets("ipset", &self.sets)
+    }
+}
+
+// Sink that keeps ipset sets holding the members of groups, so that iptables rules matching the sets firewall
+// classified peers directly.  The changes of a flush are made by a single ipset restore script.  Every command is
+// given -exist, so that adding a present entry (which updates its timeout) and deleting an absent one both succeed,
+// and a retried flush can simply repeat its script.
+pub struct IpsetSink
+{
+    config : IpsetConfig,
+
+    // The addresses that belong in each set, and those whose entries may have changed since the last successful flush
+    members : FirewallSetMembers,
+
+    // True if the sets are to be emptied by the next flush, as at startup and on switching to monitoring
+    flush_sets : bool,
+
+    // False when monitoring, in which case the sets are kept empty and changes are not made
+    enforcing : bool
+}
+
+impl IpsetSink
+{
+    pub fn new(config : IpsetConfig) -> Self
+    {
+        Self { members : FirewallSetMembers::new(&config.sets), config, flush_sets : true, enforcing : true }
+    }
+
+    // Returns the ipset restore script that brings the changed entries of the sets to their intended state, or None
+    // if nothing changed
+    fn script(&self) -> Option<String>
+    {
+        let mut script = String::new();
+
+        if self.flush_sets {
+            for set in self.config.sets.iter().flat_map(FirewallSetConfig::names) {
+                script.push_str(&format!("flush {set}\n"));
+            }
+        }
+
+        // While monitoring the sets stay empty, and every member is re-evaluated on switching back to enforcing
+        if !self.enforcing {
+            return (!script.is_empty()).then_some(script);
+        }
+
+        // Expirations are on the system clock, as sent to sinks, as are the kernel's timeouts
+        let now = Clock::system_now();
+
+        for (set, ip_addrs) in &self.members.changed {
+            for ip_addr in ip_addrs {
+                match self.members.expiration(set, ip_addr) {
+                    Some(expiration) if self.config.timeouts.unwrap_or(false) => {
+                        // A timeout of 0 means no timeout; members about to expire are given at least a second
+                        let timeout_seconds = if expiration == PERMANENT_EXPIRATION {
+                            0
+                        }
+                        else {
+                            expiration.saturating_sub(now).div_ceil(1000).max(1)
+                        };
+                        script.push_str(&format!("add {set} {ip_addr} timeout {timeout_seconds}\n"));
+                    },
+                    Some(_) => script.push_str(&format!("add {set} {ip_addr}\n")),
+                    // Entries already flushed need no deleting
+                    None if !self.flush_sets => script.push_str(&format!("del {set} {ip_addr}\n")),
+                    None => ()
+                }
+            }
+        }
+
+        (!script.is_empty()).then_some(script)
+    }
+}
+
+impl Sink for IpsetSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "ipset",
+            "sets" : self.config.sets,
+            "timeouts" : self.config.timeouts.unwrap_or(false)
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        match event {
+            GroupChange::Mode { enforcing, .. } if *enforcing != self.enforcing => {
+                self.enforcing = *enforcing;
+                if *enforcing {
+                    self.members.change_all();
+                }
+                else {
+                    self.flush_sets = true;
+                }
+            },
+            // Updates and extensions change the timeouts of entries, and also add members whose add event was dropped
+            GroupChange::Add { group_name, ip_addr, expiration, .. } |
+            GroupChange::Update { group_name, ip_addr, expiration, .. } |
+            GroupChange::Extend { group_name, ip_addr, expiration, .. } => {
+                self.members.set_member(group_name, *ip_addr, Some(*expiration))
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } => self.members.set_member(group_name, *ip_addr, None),
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if let Some(script) = self.script() {
+            run_script(
+                self.config.ipset_path.as_deref().unwrap_or(DEFAULT_IPSET_PATH),
+                &["-exist", "restore"],
+                &script
+            )?;
+        }
+
+        self.members.changed.clear();
+        self.flush_sets = false;
+
+        Ok(())
+    }
+}
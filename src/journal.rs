@@ -0,0 +1,490 @@
+use crate::config::{GroupMetadata, ThrottleRate};
+use crate::deny_list::write_file;
+use crate::group::Rule;
+use crate::sink::{GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_JOURNAL_MAX_EVENTS : usize = 1_000_000;
+pub const DEFAULT_JOURNAL_RETRY_INTERVAL_MS : u64 = 5 * 1000; // 5 seconds
+
+// Types of sink (as given by their descriptions) whose events may be journaled, being those that act on group changes
+// outside of the classifier
+const JOURNALED_SINK_TYPES : &[&str] = &["deny_list", "nftables", "ipset", "exec_hooks", "webhook"];
+
+// Types of sink journaled if sinks is not present, being those whose delivery depends on something that may be down
+const DEFAULT_JOURNALED_SINK_TYPES : &[&str] = &["nftables", "ipset", "exec_hooks", "webhook"];
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SinkJournalConfig
+{
+    // Directory in which a journal is kept for each journaled sink, as "<sink type>-<index>.journal".  Group changes
+    // and mode changes are appended to the journal before being delivered to the sink, and remain in it until the
+    // sink has successfully flushed them, so that none are lost to a sink outage.  Of the events still in a journal at
+    // startup, only removals are delivered again, since members of a prior run are members of this run only if
+    // restored (as by membership_file), in which case this run adds them again.  Delivery is at least once: an event
+    // may be delivered again if the classifier stops between its delivery and its removal from the journal.  The
+    // directory is created if needed.
+    pub directory : String,
+
+    // Types of sink whose events are journaled, of "deny_list", "nftables", "ipset", "exec_hooks", and "webhook".  If
+    // not present, all but "deny_list" are journaled.
+    pub sinks : Option<Vec<String>>,
+
+    // Maximum number of events held in each journal while its sink is failing, beyond which the oldest events other
+    // than removals are discarded; removals are never discarded, so that no member is left enforced for good.  If not
+    // present, a default of 1,000,000 is used.
+    pub max_events : Option<usize>,
+
+    // Milliseconds between attempts to deliver journaled events to a failing sink.  If not present, a default of 5
+    // seconds is used.
+    pub retry_interval_ms : Option<u64>
+}
+
+impl SinkJournalConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.directory.is_empty() {
+            return Err("Invalid sink_journal directory: empty string".to_string());
+        }
+
+        if let Some(sink_type) =
+            self.sinks.iter().flatten().find(|sink_type| !JOURNALED_SINK_TYPES.contains(&sink_type.as_str()))
+        {
+            return Err(format!(
+                "Invalid sink_journal sink type {sink_type}: must be one of {}",
+                JOURNALED_SINK_TYPES.join(", ")
+            ));
+        }
+
+        if self.max_events == Some(0) {
+            return Err("Invalid sink_journal max_events: zero".to_string());
+        }
+
+        if self.retry_interval_ms == Some(0) {
+            return Err("Invalid sink_journal retry_interval_ms: zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// An event as read back from a journal, in which events are written as their JSON representation
+#[derive(Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JournaledEvent
+{
+    Add
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        pubkey : Option<String>,
+        expiration : u64,
+        metadata : Option<GroupMetadata>,
+        throttle : Option<ThrottleRate>,
+        rule : String,
+        timestamp : u64,
+        trigger_timestamp : Option<u64>,
+        stake : Option<u64>,
+        decision_id : u64
+    },
+
+    Update
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        pubkey : Option<String>,
+        expiration : u64,
+        metadata : Option<GroupMetadata>,
+        throttle : Option<ThrottleRate>
+    },
+
+    Extend
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        pubkey : Option<String>,
+        expiration : u64,
+        metadata : Option<GroupMetadata>,
+        throttle : Option<ThrottleRate>
+    },
+
+    Remove
+    {
+        group_name : String, ip_addr : IpAddr, metadata : Option<GroupMetadata>, expired : bool
+    },
+
+    Mode
+    {
+        enforcing : bool, timestamp : u64
+    }
+}
+
+impl JournaledEvent
+{
+    fn into_sink_event(self) -> Result<GroupChange, String>
+    {
+        let pubkey = |pubkey : Option<String>| {
+            pubkey.map(|pubkey| Pubkey::from_str(&pubkey).map_err(|_| format!("invalid pubkey {pubkey}"))).transpose()
+        };
+
+        Ok(match self {
+            JournaledEvent::Add {
+                group_name,
+                ip_addr,
+                pubkey: add_pubkey,
+                expiration,
+                metadata,
+                throttle,
+                rule,
+                timestamp,
+                trigger_timestamp,
+                stake,
+                decision_id
+            } => GroupChange::Add {
+                group_name,
+                ip_addr,
+                pubkey : pubkey(add_pubkey)?,
+                expiration,
+                metadata : metadata.map(Arc::new),
+                throttle,
+                rule : Rule::named(&rule),
+                timestamp,
+                trigger_timestamp,
+                stake,
+                decision_id
+            },
+            JournaledEvent::Update { group_name, ip_addr, pubkey: update_pubkey, expiration, metadata, throttle } => {
+                GroupChange::Update {
+                    group_name,
+                    ip_addr,
+                    pubkey : pubkey(update_pubkey)?,
+                    expiration,
+                    metadata : metadata.map(Arc::new),
+                    throttle
+                }
+            },
+            JournaledEvent::Extend { group_name, ip_addr, pubkey: extend_pubkey, expiration, metadata, throttle } => {
+                GroupChange::Extend {
+                    group_name,
+                    ip_addr,
+                    pubkey : pubkey(extend_pubkey)?,
+                    expiration,
+                    metadata : metadata.map(Arc::new),
+                    throttle
+                }
+            },
+            JournaledEvent::Remove { group_name, ip_addr, metadata, expired } => {
+                GroupChange::Remove { group_name, ip_addr, metadata : metadata.map(Arc::new), expired }
+            },
+            JournaledEvent::Mode { enforcing, timestamp } => GroupChange::Mode { enforcing, timestamp }
+        })
+    }
+}
+
+// Returns true for the events that are journaled: those that change what a sink enforces
+fn is_journaled(event : &GroupChange) -> bool
+{
+    matches!(
+        event,
+        GroupChange::Add { .. } |
+            GroupChange::Update { .. } |
+            GroupChange::Extend { .. } |
+            GroupChange::Remove { .. } |
+            GroupChange::Mode { .. }
+    )
+}
+
+// Wraps each sink of a journaled type in a JournaledSink, creating the journal directory if needed
+pub fn wrap(
+    config : SinkJournalConfig,
+    sinks : Vec<Box<dyn Sink>>
+) -> Result<Vec<Box<dyn Sink>>, String>
+{
+    let directory = &config.directory;
+
+    std::fs::create_dir_all(directory)
+        .map_err(|e| format!("Failed to create sink journal directory {directory}: {e}"))?;
+
+    let journaled_types = config
+        .sinks
+        .clone()
+        .unwrap_or_else(|| DEFAULT_JOURNALED_SINK_TYPES.iter().map(|sink_type| sink_type.to_string()).collect());
+
+    // Sinks of the same type are told apart by their order in the config
+    let mut type_counts = HashMap::<String, usize>::new();
+
+    sinks
+        .into_iter()
+        .map(|sink| {
+            let sink_type = sink.describe()["type"].as_str().unwrap_or_default().to_string();
+            if !journaled_types.contains(&sink_type) {
+                return Ok(sink);
+            }
+            let index = type_counts.entry(sink_type.clone()).or_default();
+            let path = format!("{directory}/{sink_type}-{index}.journal");
+            *index += 1;
+            Ok(Box::new(JournaledSink::new(
+                sink,
+                path,
+                config.max_events.unwrap_or(DEFAULT_JOURNAL_MAX_EVENTS),
+                config.retry_interval_ms.unwrap_or(DEFAULT_JOURNAL_RETRY_INTERVAL_MS)
+            )?) as Box<dyn Sink>)
+        })
+        .collect()
+}
+
+// Sink that journals the group changes and mode changes for another sink, delivering them in order and holding on to
+// them until the sink has flushed them successfully.  While the sink is failing, events are only journaled, and
+// delivery is retried at flushes every retry interval.  Failures are reported here rather than to the sink worker, so
+// that the worker neither retries nor reports every failed flush of an outage.
+pub struct JournaledSink
+{
+    inner : Box<dyn Sink>,
+
+    path : String,
+
+    // The journal, open for appending
+    file : std::fs::File,
+
+    // Journaled events that the inner sink has not yet flushed successfully, oldest first
+    pending : VecDeque<GroupChange>,
+
+    // Number of the pending events, from the front, that have been applied to the inner sink
+    applied_count : usize,
+
+    max_events : usize,
+
+    // True if events were discarded beyond max_events since the journal was last rewritten, in which case it is
+    // rewritten with just the pending events
+    discarded : bool,
+
+    retry_interval : Duration,
+
+    // The error with which delivery is failing, if it is, and when delivery is next attempted; an outage is reported
+    // once rather than for every event
+    failure : Option<(String, Instant)>
+}
+
+impl JournaledSink
+{
+    // Opens the journal at path, to be delivered again any events left in it by a prior run
+    pub fn new(
+        inner : Box<dyn Sink>,
+        path : String,
+        max_events : usize,
+        retry_interval_ms : u64
+    ) -> Result<Self, String>
+    {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Failed to read sink journal {path}: {e}"))
+        };
+
+        let mut pending = VecDeque::new();
+
+        let lines = contents.lines().collect::<Vec<&str>>();
+
+        for (index, line) in lines.iter().enumerate() {
+            match serde_json::from_str::<JournaledEvent>(line)
+                .map_err(|e| e.to_string())
+                .and_then(|journaled_event| journaled_event.into_sink_event())
+            {
+                // Additions, updates, and mode changes of a prior run are not delivered again: the sink enforces what
+                // this run's groups and mode call for, and a prior member delivered again would be one that nothing
+                // here tracks and so never removes.  Removals are, so that members whose removal was not delivered
+                // are not left enforced for good.
+                Ok(event @ GroupChange::Remove { .. }) => pending.push_back(event),
+                Ok(_) => (),
+                // The last line is incomplete if the classifier stopped while writing it; the event it would have
+                // held was never delivered
+                Err(_) if (index + 1) == lines.len() && !contents.ends_with('\n') => {
+                    eprintln!("WARNING: Sink journal {path} ends with a truncated event, which is ignored")
+                },
+                Err(e) => return Err(format!("Invalid line {} of sink journal {path}: {e}", index + 1))
+            }
+        }
+
+        if !pending.is_empty() {
+            eprintln!("Sink journal {path} holds {} undelivered removals, to be delivered again", pending.len());
+        }
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open sink journal {path}: {e}"))?;
+
+        let mut journaled_sink = Self {
+            inner,
+            path,
+            file,
+            pending,
+            applied_count : 0,
+            max_events,
+            discarded : false,
+            retry_interval : Duration::from_millis(retry_interval_ms),
+            failure : None
+        };
+
+        // The journal is rewritten with just the removals, which also removes any truncated event, so that events
+        // appended after it are read back intact
+        if !contents.is_empty() {
+            journaled_sink.rewrite()?;
+        }
+
+        Ok(journaled_sink)
+    }
+
+    // Applies the pending events not yet applied to the inner sink, in order, stopping at the first failure
+    fn deliver(&mut self) -> Result<(), String>
+    {
+        while let Some(event) = self.pending.get(self.applied_count) {
+            self.inner.apply(event)?;
+            self.applied_count += 1;
+        }
+
+        Ok(())
+    }
+
+    // Replaces the journal with the pending events
+    fn rewrite(&mut self) -> Result<(), String>
+    {
+        write_file(&self.path, self.pending.iter().map(|event| event.to_json().to_string()))?;
+
+        self.file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open sink journal {}: {e}", self.path))?;
+
+        self.discarded = false;
+
+        Ok(())
+    }
+
+    // Discards the oldest pending event that is not a removal, if there is one
+    fn discard_oldest(&mut self)
+    {
+        let Some(index) = self.pending.iter().position(|event| !matches!(event, GroupChange::Remove { .. }))
+        else {
+            return;
+        };
+
+        self.pending.remove(index);
+
+        if index < self.applied_count {
+            self.applied_count -= 1;
+        }
+
+        if !self.discarded {
+            eprintln!("ERROR: Sink journal {} is full; discarding its oldest events other than removals", self.path);
+        }
+
+        self.discarded = true;
+    }
+
+    // Records a failure of delivery, reporting it if delivery was not already failing
+    fn failed(
+        &mut self,
+        e : String
+    )
+    {
+        if self.failure.is_none() {
+            eprintln!(
+                "ERROR: Delivery to {} sink failed ({e}); journaling its events to {} until it recovers",
+                self.inner.describe()["type"].as_str().unwrap_or_default(),
+                self.path
+            );
+        }
+
+        self.failure = Some((e, Instant::now() + self.retry_interval));
+    }
+}
+
+impl Sink for JournaledSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        let mut description = self.inner.describe();
+
+        description["journal"] = serde_json::json!(self.path);
+
+        description
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if !is_journaled(event) {
+            return self.inner.apply(event);
+        }
+
+        // A failure to journal does not hold up delivery, which is what the journal is for
+        if let Err(e) = writeln!(self.file, "{}", event.to_json()) {
+            eprintln!("ERROR: Failed to write sink journal {}: {e}", self.path);
+        }
+
+        if self.pending.len() >= self.max_events {
+            self.discard_oldest();
+        }
+
+        self.pending.push_back(event.clone());
+
+        // While failing, delivery is left to the next flush, so that a sink that is down does not hold up every event
+        if self.failure.is_none() {
+            if let Err(e) = self.deliver() {
+                self.failed(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if self.failure.as_ref().is_some_and(|(_, retry)| Instant::now() < *retry) {
+            return Ok(());
+        }
+
+        // Rewriting the journal is left to retries, as events are only discarded while failing
+        if self.discarded {
+            if let Err(e) = self.rewrite() {
+                eprintln!("ERROR: {e}");
+            }
+        }
+
+        // Events applied but not flushed are not applied again, as sinks retain what their failed flush did not do
+        if let Err(e) = self.deliver().and_then(|_| self.inner.flush()) {
+            self.failed(e);
+            return Ok(());
+        }
+
+        if self.failure.take().is_some() {
+            eprintln!(
+                "Delivery to {} sink recovered; delivered {} journaled events",
+                self.inner.describe()["type"].as_str().unwrap_or_default(),
+                self.pending.len()
+            );
+        }
+
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.applied_count = 0;
+            self.file.set_len(0).map_err(|e| format!("Failed to truncate sink journal {}: {e}", self.path))?;
+        }
+
+        Ok(())
+    }
+}
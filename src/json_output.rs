@@ -0,0 +1,198 @@
+use crate::sink::{is_printed, GroupChange, MemberChange, OutputSink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct JsonOutputConfig
+{
+    // Path of a file to which events are appended.  The file is reopened for each write, so that it may be rotated.
+    pub path : Option<String>,
+
+    // Path of a Unix domain socket (e.g. of a log shipper) to which events are written, reconnecting as needed.  If
+    // neither path nor socket_path is present, events are written to stdout in place of the free-form lines that
+    // would otherwise describe them.
+    pub socket_path : Option<String>
+}
+
+impl JsonOutputConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.path.is_some() && self.socket_path.is_some() {
+            return Err("Invalid json_output: both path and socket_path are present".to_string());
+        }
+
+        if (self.path.as_deref() == Some("")) || (self.socket_path.as_deref() == Some("")) {
+            return Err("Invalid json_output path: empty string".to_string());
+        }
+
+        Ok(())
+    }
+
+    // Returns true if events are written to stdout
+    pub fn is_stdout(&self) -> bool
+    {
+        self.path.is_none() && self.socket_path.is_none()
+    }
+}
+
+// Output that writes every group addition, removal, and expiration, and every leader status change, as a single line of
+// JSON, so that classification decisions can be consumed by tooling without parsing free-form output.  Each line is
+// the event as posted to webhooks, with a timestamp added to events that lack one, and for additions by classification
+// thresholds, the classification and index of the threshold, and whether it is one of the overload thresholds.  When
+// writing to stdout, in place of the free-form lines, every event that would have been printed is written, so that
+// nothing printed is lost to JSON output.
+pub struct JsonOutputSink
+{
+    config : JsonOutputConfig,
+
+    // Connection to socket_path, if connected
+    socket : Option<UnixStream>,
+
+    // Lines not yet written, written when the sink queue drains
+    pending_lines : Vec<String>
+}
+
+impl JsonOutputSink
+{
+    pub fn new(config : JsonOutputConfig) -> Self
+    {
+        Self { config, socket : None, pending_lines : vec![] }
+    }
+
+    fn write(
+        &mut self,
+        lines : &str
+    ) -> Result<(), String>
+    {
+        if let Some(path) = &self.config.path {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(lines.as_bytes()))
+                .map_err(|e| format!("Failed to write {path}: {e}"))
+        }
+        else if let Some(socket_path) = &self.config.socket_path {
+            let socket = match &mut self.socket {
+                Some(socket) => socket,
+                None => self.socket.insert(
+                    UnixStream::connect(socket_path).map_err(|e| format!("Failed to connect to {socket_path}: {e}"))?
+                )
+            };
+            // A failed connection is dropped, so that the retry reconnects
+            socket.write_all(lines.as_bytes()).map_err(|e| {
+                self.socket = None;
+                format!("Failed to write to {socket_path}: {e}")
+            })
+        }
+        else {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(lines.as_bytes()).and_then(|_| stdout.flush()).map_err(|e| format!("Failed to write: {e}"))
+        }
+    }
+
+    // Queues the line for event, if it is one that is written
+    fn record(
+        &mut self,
+        event : &GroupChange
+    )
+    {
+        if self.config.is_stdout() && !is_printed(event) {
+            return;
+        }
+
+        let mut json = match event {
+            GroupChange::Add { rule, .. } => {
+                let mut json = event.to_json();
+                if let Some(threshold) = &rule.threshold {
+                    json["classification"] = serde_json::json!(threshold.classification.as_ref());
+                    json["threshold_index"] = serde_json::json!(threshold.index);
+                    json["overload_threshold"] = serde_json::json!(threshold.overload);
+                }
+                json
+            },
+            GroupChange::Remove { .. } | GroupChange::LeaderStatus { .. } => event.to_json(),
+            // Metrics are printed in the status report rather than as events
+            GroupChange::Metrics { .. } => return,
+            _ if self.config.is_stdout() => event.to_json(),
+            _ => return
+        };
+
+        if json.get("timestamp").is_none() {
+            json["timestamp"] =
+                serde_json::json!(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64);
+        }
+
+        self.pending_lines.push(json.to_string());
+    }
+}
+
+impl OutputSink for JsonOutputSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "json_output",
+            "path" : self.config.path,
+            "socket_path" : self.config.socket_path
+        })
+    }
+
+    fn on_add(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        self.record(change.event);
+        Ok(())
+    }
+
+    // Updates are only written to stdout, in place of the lines printed for them
+    fn on_update(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        self.record(change.event);
+        Ok(())
+    }
+
+    fn on_remove(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        self.record(change.event);
+        Ok(())
+    }
+
+    fn on_event(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        self.record(event);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if self.pending_lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = self.pending_lines.join("\n");
+        lines.push('\n');
+
+        self.write(&lines)?;
+
+        self.pending_lines.clear();
+
+        Ok(())
+    }
+}
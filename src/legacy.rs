@@ -0,0 +1,127 @@
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::txingest::TxIngestMsg;
+use std::net::IpAddr;
+
+// Layout of TxIngestMsg as sent by older validator patch versions, in which Exceeded and Started did not carry the
+// peer's identity and stake.  Variant order must match the older layout exactly, as bincode encodes variants by index.
+#[derive(Deserialize)]
+pub enum LegacyTxIngestMsg
+{
+    Failed
+    {
+        timestamp : u64, peer_addr : IpAddr
+    },
+    Exceeded
+    {
+        timestamp : u64, peer_addr : IpAddr
+    },
+    Started
+    {
+        timestamp : u64, peer_addr : IpAddr
+    },
+    Finished
+    {
+        timestamp : u64, peer_addr : IpAddr
+    },
+    VoteTx
+    {
+        timestamp : u64, peer_addr : IpAddr
+    },
+    UserTx
+    {
+        timestamp : u64, peer_addr : IpAddr, signature : Signature
+    },
+    Forwarded
+    {
+        timestamp : u64, signature : Signature
+    },
+    BadFee
+    {
+        timestamp : u64, signature : Signature
+    },
+    Fee
+    {
+        timestamp : u64, signature : Signature, cu_limit : u64, cu_used : u64, fee : u64
+    },
+    WillBeLeader
+    {
+        timestamp : u64, slots : u8
+    },
+    BeginLeader
+    {
+        timestamp : u64
+    },
+    EndLeader
+    {
+        timestamp : u64
+    }
+}
+
+// Translates a legacy message into the current layout.  Peer identity is unknown and stake is treated as zero, which
+// is the same treatment given to peers whose stake was never reported.
+impl From<LegacyTxIngestMsg> for TxIngestMsg
+{
+    fn from(legacy : LegacyTxIngestMsg) -> Self
+    {
+        let no_pubkey : Option<Pubkey> = None;
+
+        match legacy {
+            LegacyTxIngestMsg::Failed { timestamp, peer_addr } => TxIngestMsg::Failed { timestamp, peer_addr },
+            LegacyTxIngestMsg::Exceeded { timestamp, peer_addr } => {
+                TxIngestMsg::Exceeded { timestamp, peer_addr, peer_pubkey : no_pubkey, stake : 0 }
+            },
+            LegacyTxIngestMsg::Started { timestamp, peer_addr } => {
+                TxIngestMsg::Started { timestamp, peer_addr, peer_pubkey : no_pubkey, stake : 0 }
+            },
+            LegacyTxIngestMsg::Finished { timestamp, peer_addr } => TxIngestMsg::Finished { timestamp, peer_addr },
+            LegacyTxIngestMsg::VoteTx { timestamp, peer_addr } => TxIngestMsg::VoteTx { timestamp, peer_addr },
+            LegacyTxIngestMsg::UserTx { timestamp, peer_addr, signature } => {
+                TxIngestMsg::UserTx { timestamp, peer_addr, signature }
+            },
+            LegacyTxIngestMsg::Forwarded { timestamp, signature } => TxIngestMsg::Forwarded { timestamp, signature },
+            LegacyTxIngestMsg::BadFee { timestamp, signature } => TxIngestMsg::BadFee { timestamp, signature },
+            LegacyTxIngestMsg::Fee { timestamp, signature, cu_limit, cu_used, fee } => {
+                TxIngestMsg::Fee { timestamp, signature, cu_limit, cu_used, fee }
+            },
+            LegacyTxIngestMsg::WillBeLeader { timestamp, slots } => TxIngestMsg::WillBeLeader { timestamp, slots },
+            LegacyTxIngestMsg::BeginLeader { timestamp } => TxIngestMsg::BeginLeader { timestamp },
+            LegacyTxIngestMsg::EndLeader { timestamp } => TxIngestMsg::EndLeader { timestamp }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use bincode::Options;
+    use std::net::Ipv4Addr;
+
+    // A Started message for 10.0.0.1 at timestamp 1000 followed by an EndLeader message at timestamp 2000, as encoded
+    // by older validator patch versions: a varint variant index, then each field (varint timestamps, with 251
+    // prefixing a two byte little endian value; the IpAddr variant index and then the address octets)
+    const LEGACY_SAMPLE : [u8; 13] = [2, 251, 232, 3, 0, 10, 0, 0, 1, 11, 251, 208, 7];
+
+    #[test]
+    fn legacy_sample_decodes_into_current_layout()
+    {
+        let options = bincode::DefaultOptions::new();
+        let mut remaining = &LEGACY_SAMPLE[..];
+
+        let started : TxIngestMsg = options.deserialize_from::<_, LegacyTxIngestMsg>(&mut remaining).unwrap().into();
+        assert!(matches!(
+            started,
+            TxIngestMsg::Started { timestamp : 1000, peer_addr, peer_pubkey : None, stake : 0 }
+                if peer_addr == IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))
+        ));
+
+        let end_leader : TxIngestMsg = options.deserialize_from::<_, LegacyTxIngestMsg>(&mut remaining).unwrap().into();
+        assert!(matches!(end_leader, TxIngestMsg::EndLeader { timestamp : 2000 }));
+        assert!(remaining.is_empty());
+
+        // The current layout's Started carries the peer's identity and stake, so the sample does not decode as it
+        assert!(options.deserialize_from::<_, TxIngestMsg>(&mut &LEGACY_SAMPLE[..9]).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+// The classifier as a library, for embedding in another process that feeds it events directly rather than over TCP.
+// An embedder builds a Config (by Config::parse or Config::load, or with builder::ConfigBuilder), spawns the sinks
+// that act on group changes (implementations of sink::Sink, or of the simpler sink::OutputSink wrapped in
+// sink::MembershipOutput) with sink::spawn_worker, and creates a State from them.  Events are then fed to
+// State::ingest, and State::flush_due_tx and State::periodic are called regularly (as often as
+// periodic_interval_ms) with the current time, as the binary does.
+
+pub(crate) mod admin;
+pub(crate) mod api;
+#[cfg(feature = "async-ingest")]
+pub(crate) mod async_listener;
+// For embedders constructing configs programmatically; not used by the classifier binary itself
+pub mod builder;
+pub(crate) mod canary;
+pub(crate) mod churn;
+pub(crate) mod classification;
+mod cli;
+pub(crate) mod clock;
+pub(crate) mod config;
+pub(crate) mod deny_list;
+pub(crate) mod deny_list_import;
+pub(crate) mod dns;
+pub(crate) mod epoch;
+pub(crate) mod exec_hook;
+pub(crate) mod firewall_sets;
+pub(crate) mod group;
+#[cfg(feature = "grpc")]
+pub(crate) mod grpc;
+pub(crate) mod histogram;
+pub(crate) mod http;
+pub(crate) mod ipset;
+pub(crate) mod journal;
+pub(crate) mod json_output;
+pub(crate) mod legacy;
+pub(crate) mod listener;
+pub(crate) mod log;
+pub(crate) mod membership_file;
+pub(crate) mod nftables;
+pub(crate) mod origin;
+pub(crate) mod otlp;
+pub(crate) mod peer_export;
+pub(crate) mod peer_notice;
+pub(crate) mod plugin;
+pub(crate) mod recording;
+pub(crate) mod reload;
+pub(crate) mod replay;
+// Scenario harness for scripted tests of configs; outside of tests, only the canned scenarios run by the scenario
+// subcommand are used
+#[cfg(any(test, feature = "test-support"))]
+#[cfg_attr(not(test), allow(dead_code))]
+pub(crate) mod scenario;
+pub(crate) mod schedule;
+pub(crate) mod shrink;
+pub mod sink;
+pub(crate) mod state;
+pub(crate) mod storm;
+// For embedders reacting to classification decisions in process; not used by the classifier binary itself
+pub(crate) mod subscription;
+pub(crate) mod threshold;
+pub(crate) mod transform;
+pub(crate) mod verify;
+pub(crate) mod webhook;
+
+pub use classification::Classification;
+// The classifier binary, which is no more than this
+pub use cli::run;
+pub use config::Config;
+pub use group::Group;
+pub use listener::IngestCounters;
+pub use solana_sdk::txingest::TxIngestMsg;
+pub use state::State;
+pub use subscription::{CallbackSink, ChannelSink, ClassificationEvent};
@@ -0,0 +1,362 @@
+use crate::config::IngestRateLimit;
+use crate::legacy::LegacyTxIngestMsg;
+use bincode::Options;
+use crossbeam::channel::Sender;
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Delay before restarting an accept thread that died, so that a persistent failure does not spin
+const LISTENER_RESTART_DELAY_MS : u64 = 1000;
+
+// The layout of messages sent to a listener
+#[derive(Clone, Copy)]
+pub enum MessageLayout
+{
+    Current,
+
+    // Older layout, translated into the current layout as messages are received
+    Legacy
+}
+
+// Sources with which events are tagged, by sender address
+pub struct IngestSources
+{
+    pub sources : HashMap<IpAddr, Arc<str>>,
+
+    // Source for senders not in sources
+    pub default_source : Arc<str>,
+
+    // If true, the first Deprecated message received on each connection is logged
+    pub log_deprecated : bool,
+
+    // Limit on the rate of events accepted from each connection, if any
+    pub rate_limit : Option<IngestRateLimit>,
+
+    // If present, the only sender addresses from which connections are accepted
+    pub allowed_senders : Option<HashSet<IpAddr>>,
+
+    // If present, connections on which no message arrives for this long are closed as hung
+    pub idle_timeout : Option<Duration>,
+
+    pub counters : Arc<IngestCounters>
+}
+
+// Counts of ingest events and connections affected by the rate limit, shared by all connection threads
+#[derive(Default)]
+pub struct IngestCounters
+{
+    pub rate_limited_event_count : AtomicU64,
+
+    pub rate_limited_disconnect_count : AtomicU64,
+
+    // Connections closed on accept because their sender is not allowed
+    pub rejected_connection_count : AtomicU64,
+
+    // Number of connections currently being handled
+    pub open_connection_count : AtomicU64,
+
+    // Connections closed because no message arrived on them within the idle timeout
+    pub idle_disconnect_count : AtomicU64,
+
+    // Connection threads that panicked
+    pub connection_panic_count : AtomicU64,
+
+    // Accept threads that died and were restarted
+    pub listener_restart_count : AtomicU64
+}
+
+// Token bucket limiting the rate of events accepted from a connection
+struct RateLimiter
+{
+    // Tokens added per second, and the maximum number of tokens held
+    events_per_second : f64,
+
+    burst : f64,
+
+    tokens : f64,
+
+    last_refill : Instant
+}
+
+impl RateLimiter
+{
+    fn new(rate_limit : &IngestRateLimit) -> Self
+    {
+        let burst = rate_limit.burst.unwrap_or(rate_limit.events_per_second) as f64;
+
+        Self {
+            events_per_second : rate_limit.events_per_second as f64,
+            burst,
+            tokens : burst,
+            last_refill : Instant::now()
+        }
+    }
+
+    // Returns true if an event may be accepted now, consuming a token if so
+    fn allow(&mut self) -> bool
+    {
+        let now = Instant::now();
+        self.tokens = (self.tokens + (now.duration_since(self.last_refill).as_secs_f64() * self.events_per_second))
+            .min(self.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        }
+        else {
+            false
+        }
+    }
+}
+
+// Spawns a thread which accepts connections on tcp_listener and spawns a thread per connection that decodes messages
+// of the given layout and sends them, tagged with the connection's ingest source, to sender.  The accepting thread is
+// watched, and restarted if it dies, so that ingest does not silently stop.
+pub fn spawn_listener(
+    tcp_listener : TcpListener,
+    layout : MessageLayout,
+    ingest_sources : Arc<IngestSources>,
+    sender : Sender<(Arc<str>, TxIngestMsg)>
+)
+{
+    supervise(tcp_listener, ingest_sources.counters.clone(), move |accept_listener| {
+        accept(accept_listener, layout, ingest_sources.clone(), sender.clone())
+    });
+}
+
+// Spawns a thread which runs run on a clone of tcp_listener in a thread of its own, and runs it again on a fresh
+// clone whenever that thread exits or panics
+pub fn supervise(
+    tcp_listener : TcpListener,
+    counters : Arc<IngestCounters>,
+    run : impl Fn(TcpListener) + Clone + Send + 'static
+)
+{
+    std::thread::spawn(move || loop {
+        let accept_listener = match tcp_listener.try_clone() {
+            Ok(accept_listener) => accept_listener,
+            Err(e) => {
+                eprintln!("Failed to clone ingest listener because {e}; trying again");
+                std::thread::sleep(Duration::from_millis(LISTENER_RESTART_DELAY_MS));
+                continue;
+            }
+        };
+
+        let run = run.clone();
+
+        let result = std::thread::spawn(move || run(accept_listener)).join();
+
+        counters.listener_restart_count.fetch_add(1, Ordering::Relaxed);
+        eprintln!("Ingest accept thread {}; restarting", if result.is_err() { "panicked" } else { "exited" });
+        std::thread::sleep(Duration::from_millis(LISTENER_RESTART_DELAY_MS));
+    });
+}
+
+// Returns the ingest source of a connection from sender_address, or None if the sender is not allowed, in which case
+// the rejection is counted and logged
+pub fn connection_source(
+    ingest_sources : &IngestSources,
+    sender_address : &SocketAddr
+) -> Option<Arc<str>>
+{
+    if ingest_sources
+        .allowed_senders
+        .as_ref()
+        .map(|allowed_senders| !allowed_senders.contains(&sender_address.ip()))
+        .unwrap_or(false)
+    {
+        ingest_sources.counters.rejected_connection_count.fetch_add(1, Ordering::Relaxed);
+        eprintln!("Rejected ingest connection from {sender_address}: sender is not allowed");
+        return None;
+    }
+
+    Some(ingest_sources.sources.get(&sender_address.ip()).unwrap_or(&ingest_sources.default_source).clone())
+}
+
+// The handling of the messages decoded from one connection: rate limiting, logging of deprecated messages, and
+// sending on to the event processing thread
+pub struct ConnectionHandler
+{
+    pub sender_address : SocketAddr,
+
+    pub source : Arc<str>,
+
+    ingest_sources : Arc<IngestSources>,
+
+    sender : Sender<(Arc<str>, TxIngestMsg)>,
+
+    log_deprecated : bool,
+
+    rate_limiter : Option<RateLimiter>,
+
+    // If true, exceeding the rate limit closes the connection rather than dropping events
+    disconnect : bool,
+
+    // True once the rate limit has been exceeded and logged, so that it is logged only once per connection
+    logged_rate_limited : bool
+}
+
+impl ConnectionHandler
+{
+    pub fn new(
+        sender_address : SocketAddr,
+        source : Arc<str>,
+        ingest_sources : Arc<IngestSources>,
+        sender : Sender<(Arc<str>, TxIngestMsg)>
+    ) -> Self
+    {
+        Self {
+            sender_address,
+            source,
+            log_deprecated : ingest_sources.log_deprecated,
+            rate_limiter : ingest_sources.rate_limit.as_ref().map(RateLimiter::new),
+            disconnect : ingest_sources
+                .rate_limit
+                .as_ref()
+                .and_then(|rate_limit| rate_limit.disconnect)
+                .unwrap_or(false),
+            logged_rate_limited : false,
+            ingest_sources,
+            sender
+        }
+    }
+
+    // Handles a message decoded from the connection.  Returns false if the connection is to be closed.
+    pub fn receive(
+        &mut self,
+        tx_ingest_msg : TxIngestMsg
+    ) -> bool
+    {
+        let (sender_address, source) = (&self.sender_address, &self.source);
+
+        if !self.rate_limiter.as_mut().map(RateLimiter::allow).unwrap_or(true) {
+            if self.disconnect {
+                self.ingest_sources.counters.rate_limited_disconnect_count.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Sender {sender_address} (source {source}) exceeded the rate limit; closing connection");
+                return false;
+            }
+            self.ingest_sources.counters.rate_limited_event_count.fetch_add(1, Ordering::Relaxed);
+            if !self.logged_rate_limited {
+                eprintln!("Sender {sender_address} (source {source}) exceeded the rate limit; dropping events");
+                self.logged_rate_limited = true;
+            }
+            return true;
+        }
+
+        if self.log_deprecated && matches!(tx_ingest_msg, TxIngestMsg::Deprecated) {
+            eprintln!("Sender {sender_address} (source {source}) sent a deprecated message and needs upgrading");
+            // Only log once per connection
+            self.log_deprecated = false;
+        }
+
+        self.sender.send((source.clone(), tx_ingest_msg)).expect("crossbeam failed");
+
+        true
+    }
+
+    // Counts and logs the closing of the connection because nothing arrived on it within the idle timeout
+    pub fn idle(&self)
+    {
+        self.ingest_sources.counters.idle_disconnect_count.fetch_add(1, Ordering::Relaxed);
+        eprintln!(
+            "Sender {} (source {}) sent nothing within the idle timeout; closing connection",
+            self.sender_address, self.source
+        );
+    }
+}
+
+// Accepts connections on tcp_listener, spawning a thread to handle each
+fn accept(
+    tcp_listener : TcpListener,
+    layout : MessageLayout,
+    ingest_sources : Arc<IngestSources>,
+    sender : Sender<(Arc<str>, TxIngestMsg)>
+)
+{
+    loop {
+        let (tcp_stream, sender_address, source) = loop {
+            match tcp_listener.accept() {
+                // If the sender is not allowed, the stream is dropped, closing the connection
+                Ok((tcp_stream, sender_address)) => {
+                    if let Some(source) = connection_source(&ingest_sources, &sender_address) {
+                        break (tcp_stream, sender_address, source);
+                    }
+                },
+                Err(e) => eprintln!("Failed accept because {e}")
+            }
+        };
+
+        let handler = ConnectionHandler::new(sender_address, source, ingest_sources.clone(), sender.clone());
+        let counters = ingest_sources.counters.clone();
+        let idle_timeout = ingest_sources.idle_timeout;
+
+        // Spawn a thread to handle this TCP stream.  Multiple streams are accepted at once, to allow e.g.
+        // a JITO relayer and a validator to both connect.  A panic ends only this connection, which the sender
+        // is expected to re-establish.
+        std::thread::spawn(move || {
+            counters.open_connection_count.fetch_add(1, Ordering::Relaxed);
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                handle_connection(tcp_stream, layout, idle_timeout, handler)
+            }));
+            counters.open_connection_count.fetch_sub(1, Ordering::Relaxed);
+            if result.is_err() {
+                counters.connection_panic_count.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Ingest connection thread for {sender_address} panicked; connection closed");
+            }
+        });
+    }
+}
+
+fn handle_connection(
+    mut tcp_stream : TcpStream,
+    layout : MessageLayout,
+    idle_timeout : Option<Duration>,
+    mut handler : ConnectionHandler
+)
+{
+    let options = bincode::DefaultOptions::new();
+
+    if let Some(idle_timeout) = idle_timeout {
+        tcp_stream.set_read_timeout(Some(idle_timeout)).ok();
+    }
+
+    loop {
+        let result = match layout {
+            MessageLayout::Current => options.deserialize_from::<_, TxIngestMsg>(&mut tcp_stream),
+            MessageLayout::Legacy => options.deserialize_from::<_, LegacyTxIngestMsg>(&mut tcp_stream).map(Into::into)
+        };
+
+        match result {
+            Ok(tx_ingest_msg) => {
+                if !handler.receive(tx_ingest_msg) {
+                    tcp_stream.shutdown(std::net::Shutdown::Both).ok();
+                    break;
+                }
+            },
+            Err(e) if is_timeout(&e) => {
+                handler.idle();
+                tcp_stream.shutdown(std::net::Shutdown::Both).ok();
+                break;
+            },
+            Err(e) => {
+                eprintln!("Failed deserialize because {e}; closing connection");
+                tcp_stream.shutdown(std::net::Shutdown::Both).ok();
+                break;
+            }
+        }
+    }
+}
+
+// Returns true if a deserialization error is a read timing out
+fn is_timeout(e : &bincode::Error) -> bool
+{
+    match e.as_ref() {
+        bincode::ErrorKind::Io(e) => matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut),
+        _ => false
+    }
+}
@@ -0,0 +1,112 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+// How much is logged.  The level can be changed at runtime via the API, or by signal: SIGUSR1 raises it one step and
+// SIGUSR2 lowers it one step.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel
+{
+    // Only errors and state changes that affect enforcement (mode changes, rules firing) are logged
+    Quiet,
+
+    // Additionally, group changes and other sink events, and status reports are logged
+    Normal,
+
+    // Additionally, every event from the debug addresses is logged as it is handled
+    Debug
+}
+
+const SIGUSR1 : i32 = 10;
+const SIGUSR2 : i32 = 12;
+
+static LOG_LEVEL : AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+
+// Set by the signal handler, so that the change can be logged outside of it
+static SIGNALED : AtomicBool = AtomicBool::new(false);
+
+impl LogLevel
+{
+    pub fn as_str(&self) -> &'static str
+    {
+        match self {
+            LogLevel::Quiet => "quiet",
+            LogLevel::Normal => "normal",
+            LogLevel::Debug => "debug"
+        }
+    }
+
+    fn from_u8(level : u8) -> Self
+    {
+        match level {
+            0 => LogLevel::Quiet,
+            1 => LogLevel::Normal,
+            _ => LogLevel::Debug
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel
+{
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "quiet" => Ok(LogLevel::Quiet),
+            "normal" => Ok(LogLevel::Normal),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("Invalid log level {s}: must be quiet, normal, or debug"))
+        }
+    }
+}
+
+pub fn level() -> LogLevel
+{
+    LogLevel::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
+pub fn set_level(level : LogLevel)
+{
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Returns true if messages of level are logged at the current level
+pub fn enabled(level : LogLevel) -> bool
+{
+    LOG_LEVEL.load(Ordering::Relaxed) >= (level as u8)
+}
+
+// Returns true, once, if the level has been changed by signal since the previous call
+pub fn take_signaled() -> bool
+{
+    SIGNALED.swap(false, Ordering::Relaxed)
+}
+
+extern "C" {
+    fn signal(
+        signum : i32,
+        handler : extern "C" fn(i32)
+    ) -> usize;
+}
+
+// Only atomics are touched here, since little else is safe within a signal handler
+extern "C" fn handle_signal(signum : i32)
+{
+    let level = LOG_LEVEL.load(Ordering::Relaxed);
+
+    let level = if signum == SIGUSR1 { (level + 1).min(LogLevel::Debug as u8) } else { level.saturating_sub(1) };
+
+    LOG_LEVEL.store(level, Ordering::Relaxed);
+    SIGNALED.store(true, Ordering::Relaxed);
+}
+
+// Installs the handlers of the signals that change the log level
+pub fn install_signal_handlers()
+{
+    unsafe {
+        signal(SIGUSR1, handle_signal);
+        signal(SIGUSR2, handle_signal);
+    }
+}
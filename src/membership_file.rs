@@ -0,0 +1,243 @@
+use crate::deny_list::write_file;
+use crate::group::PERMANENT_EXPIRATION;
+use crate::log::{self, LogLevel};
+use crate::state::State;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+const DEFAULT_SAVE_INTERVAL_MS : u64 = 10 * 1000; // 10 seconds
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MembershipFileConfig
+{
+    // Path of the file in which the members of all groups are saved with their expirations, so that members remain
+    // members across a restart of the classifier rather than being forgotten.  Members saved by a prior run are
+    // restored at startup, until their saved expirations; the file need not exist at first.
+    pub path : String,
+
+    // Milliseconds between saves of the file, which bounds the membership changes that a restart can lose.  If not
+    // present, a default of 10 seconds is used.
+    pub save_interval_ms : Option<u64>
+}
+
+impl MembershipFileConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.path.is_empty() {
+            return Err("Invalid membership_file path: empty string".to_string());
+        }
+
+        if self.save_interval_ms == Some(0) {
+            return Err("Invalid membership_file save_interval_ms: zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// A membership as saved, one per line of the file as JSON
+#[derive(Deserialize, Serialize)]
+struct SavedMembership
+{
+    group_name : String,
+
+    ip_addr : IpAddr,
+
+    // On the system clock, against which it is restored; absent for memberships that never expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration : Option<u64>,
+
+    // The rule that added the member, by which it is added again when restored
+    rule : String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey : Option<String>
+}
+
+// Saves group membership to a file periodically, and restores it from the file at startup
+pub struct MembershipFile
+{
+    config : MembershipFileConfig,
+
+    // Timestamp at which the file is next saved
+    next_save_timestamp : u64
+}
+
+impl MembershipFile
+{
+    // Restores the members saved in the file that have not since expired.  Fails if the file exists but cannot be
+    // read or is invalid, so that members are not silently forgotten.
+    pub fn new(
+        config : MembershipFileConfig,
+        state : &mut State,
+        now : u64
+    ) -> Result<Self, String>
+    {
+        let path = &config.path;
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Failed to read membership file {path}: {e}"))
+        };
+
+        let (mut saved_count, mut restored_count) = (0, 0);
+
+        for (index, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let invalid = |e : String| format!("Invalid line {} of membership file {path}: {e}", index + 1);
+            let saved = serde_json::from_str::<SavedMembership>(line).map_err(|e| invalid(e.to_string()))?;
+            let pubkey = saved
+                .pubkey
+                .as_deref()
+                .map(Pubkey::from_str)
+                .transpose()
+                .map_err(|e| invalid(format!("invalid pubkey: {e}")))?;
+            saved_count += 1;
+            if state.restore_group_member(
+                &saved.group_name,
+                saved.ip_addr,
+                pubkey,
+                saved.expiration.unwrap_or(PERMANENT_EXPIRATION),
+                &saved.rule,
+                now
+            ) {
+                restored_count += 1;
+            }
+        }
+
+        if log::enabled(LogLevel::Normal) {
+            println!("Restored {restored_count} of {saved_count} saved group members from {path}");
+        }
+
+        Ok(Self { next_save_timestamp : now + config.save_interval_ms.unwrap_or(DEFAULT_SAVE_INTERVAL_MS), config })
+    }
+
+    pub fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "path" : self.config.path,
+            "save_interval_ms" : self.config.save_interval_ms.unwrap_or(DEFAULT_SAVE_INTERVAL_MS)
+        })
+    }
+
+    // Saves the file if a save is due.  Failures are logged, and the save is retried at the next interval.
+    pub fn poll(
+        &mut self,
+        state : &State,
+        now : u64
+    )
+    {
+        if now < self.next_save_timestamp {
+            return;
+        }
+
+        self.next_save_timestamp = now + self.config.save_interval_ms.unwrap_or(DEFAULT_SAVE_INTERVAL_MS);
+
+        if let Err(e) = self.save(state) {
+            eprintln!("ERROR: {e}");
+        }
+    }
+
+    // Replaces the file with the current members of every group
+    fn save(
+        &self,
+        state : &State
+    ) -> Result<(), String>
+    {
+        let mut group_names = state.groups.keys().collect::<Vec<&String>>();
+        group_names.sort();
+
+        let lines = group_names.into_iter().flat_map(|group_name| {
+            state.groups[group_name].members().iter().map(move |(ip_addr, membership)| {
+                serde_json::to_string(&SavedMembership {
+                    group_name : group_name.clone(),
+                    ip_addr : *ip_addr,
+                    expiration : Some(membership.expiration)
+                        .filter(|expiration| *expiration != PERMANENT_EXPIRATION)
+                        .map(|expiration| expiration.saturating_add_signed(state.clock_offset_ms)),
+                    rule : membership.added_by.to_string(),
+                    pubkey : membership.pubkey.map(|pubkey| pubkey.to_string())
+                })
+                .unwrap()
+            })
+        });
+
+        write_file(&self.config.path, lines)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::config::{Config, DEFAULT_INGEST_SOURCE};
+    use crate::listener::IngestCounters;
+    use crate::sink::{self, SinkReceiver};
+    use std::net::Ipv4Addr;
+    use std::sync::Arc;
+
+    const START_TIMESTAMP : u64 = 1_700_000_000_000;
+
+    const SPAMMER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1));
+
+    const PEER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    // A state classifying peers with more than 100 failed connections within 10 seconds into group "spammers", with
+    // the receiver of its sink events
+    fn new_state() -> (State, SinkReceiver)
+    {
+        let mut config = Config::parse(
+            r#"{
+                "failed_exceeded_quic_connections" : {
+                    "group_name" : "spammers",
+                    "thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 100,
+                          "duration_ms" : 10000 }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let (sink_sender, sink_receiver) = sink::channel(sink::DEFAULT_SINK_QUEUE_SIZE);
+
+        (State::new(config, sink_sender, Arc::new(IngestCounters::default())), sink_receiver)
+    }
+
+    #[test]
+    fn membership_file_restores_saved_members()
+    {
+        let path = std::env::temp_dir().join(format!("txingest-members-{}.jsonl", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::remove_file(&path).ok();
+        let config = || MembershipFileConfig { path : path.clone(), save_interval_ms : Some(1000) };
+
+        let (mut state, _sink_receiver) = new_state();
+        for _ in 0..150 {
+            state.failed(DEFAULT_INGEST_SOURCE, START_TIMESTAMP, SPAMMER, None);
+        }
+        let now = START_TIMESTAMP + 1000;
+        state.periodic(now);
+        state.add_group_member("manual", PEER, PERMANENT_EXPIRATION - now, "admin", now);
+        let mut membership_file = MembershipFile::new(config(), &mut state, now).unwrap();
+        membership_file.poll(&state, now + 1000);
+
+        // A restarted classifier restores every member, by the rule that added it, until its saved expiration
+        let (mut restarted, _sink_receiver) = new_state();
+        MembershipFile::new(config(), &mut restarted, START_TIMESTAMP).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        for (group_name, ip_addr) in [("spammers", SPAMMER), ("manual", PEER)] {
+            let (saved, restored) =
+                (&state.groups[group_name].members()[&ip_addr], &restarted.groups[group_name].members()[&ip_addr]);
+            assert_eq!((&saved.added_by, saved.expiration), (&restored.added_by, restored.expiration), "{group_name}");
+        }
+        assert_eq!(restarted.group_member_counts(), state.group_member_counts());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::firewall_sets::{is_valid_name, validate_sets, FirewallSetConfig, FirewallSetMembers};
+use crate::sink::{run_script, GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+const DEFAULT_NFT_FAMILY : &str = "inet";
+const DEFAULT_NFT_PATH : &str = "nft";
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct NftablesConfig
+{
+    // Address family of the table containing the sets: "ip", "ip6", "inet", "bridge", or "netdev".  If not present,
+    // "inet" is used.
+    pub family : Option<String>,
+
+    // Name of the table containing the sets.  The table, the sets, and the rules that use the sets must already exist;
+    // only the elements of the sets are managed.
+    pub table : String,
+
+    // The sets to manage, each holding the members of a group
+    pub sets : Vec<FirewallSetConfig>,
+
+    // Path of the nft command.  If not present, "nft" is found on the PATH.
+    pub nft_path : Option<String>
+}
+
+impl NftablesConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        let family = self.family.as_deref().unwrap_or(DEFAULT_NFT_FAMILY);
+        if !["ip", "ip6", "inet", "bridge", "netdev"].contains(&family) {
+            return Err(format!("Invalid nftables family {family}"));
+        }
+
+        if !is_valid_name(&self.table) {
+            return Err(format!("Invalid nftables table name {}", self.table));
+        }
+
+        validate_sets("nftables", &self.sets)
+    }
+}
+
+// Sink that keeps nftables named sets holding the members of groups, so that rules referring to the sets firewall
+// classified peers directly.  Changes are accumulated as events are applied and then made on flush by a single nft
+// script, which nft applies as one transaction.
+pub struct NftablesSink
+{
+    config : NftablesConfig,
+
+    // The addresses that belong in each set, and those whose entries may have changed since the last successful flush
+    members : FirewallSetMembers,
+
+    // Map from set name to the addresses that are in it, as of the last successful flush
+    applied : HashMap<String, HashSet<IpAddr>>,
+
+    // True until the sets have been emptied at startup
+    reset : bool,
+
+    // False when monitoring, in which case the sets are kept empty
+    enforcing : bool
+}
+
+impl NftablesSink
+{
+    pub fn new(config : NftablesConfig) -> Self
+    {
+        Self {
+            members : FirewallSetMembers::new(&config.sets),
+            config,
+            applied : Default::default(),
+            reset : true,
+            enforcing : true
+        }
+    }
+
+    // Returns the nft script that brings the sets to their intended contents, or None if they already have them
+    fn script(&self) -> Option<String>
+    {
+        let family = self.config.family.as_deref().unwrap_or(DEFAULT_NFT_FAMILY);
+        let table = &self.config.table;
+
+        let mut script = String::new();
+
+        if self.reset {
+            for set in self.config.sets.iter().flat_map(FirewallSetConfig::names) {
+                script.push_str(&format!("flush set {family} {table} {set}\n"));
+            }
+        }
+
+        for (set, ip_addrs) in &self.members.changed {
+            let applied = self.applied.get(set);
+            let (mut adds, mut deletes) = (vec![], vec![]);
+            for ip_addr in ip_addrs {
+                let member = self.enforcing && self.members.expiration(set, ip_addr).is_some();
+                let present = applied.map(|applied| applied.contains(ip_addr)).unwrap_or(false);
+                if member && !present {
+                    adds.push(ip_addr.to_string());
+                }
+                else if !member && present {
+                    deletes.push(ip_addr.to_string());
+                }
+            }
+            // Deleting an element not in a set fails the whole transaction, so only elements known to be present are
+            // deleted
+            if !deletes.is_empty() {
+                script.push_str(&format!("delete element {family} {table} {set} {{ {} }}\n", deletes.join(", ")));
+            }
+            if !adds.is_empty() {
+                script.push_str(&format!("add element {family} {table} {set} {{ {} }}\n", adds.join(", ")));
+            }
+        }
+
+        (!script.is_empty()).then_some(script)
+    }
+}
+
+impl Sink for NftablesSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "nftables",
+            "family" : self.config.family.as_deref().unwrap_or(DEFAULT_NFT_FAMILY),
+            "table" : self.config.table,
+            "sets" : self.config.sets
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        match event {
+            GroupChange::Mode { enforcing, .. } if *enforcing != self.enforcing => {
+                self.enforcing = *enforcing;
+                // Every address in or belonging in a set must be re-evaluated
+                self.members.change_all();
+                for (set, applied) in &self.applied {
+                    self.members.changed.entry(set.clone()).or_default().extend(applied.iter());
+                }
+            },
+            // Updates are treated as additions too, so that a member whose add event was dropped is added when next
+            // updated
+            GroupChange::Add { group_name, ip_addr, expiration, .. } |
+            GroupChange::Update { group_name, ip_addr, expiration, .. } |
+            GroupChange::Extend { group_name, ip_addr, expiration, .. } => {
+                self.members.set_member(group_name, *ip_addr, Some(*expiration))
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } => self.members.set_member(group_name, *ip_addr, None),
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        let Some(script) = self.script()
+        else {
+            self.members.changed.clear();
+            return Ok(());
+        };
+
+        // On failure nothing is recorded as applied, so the retried flush makes the same changes
+        run_script(self.config.nft_path.as_deref().unwrap_or(DEFAULT_NFT_PATH), &["-f", "-"], &script)?;
+
+        if self.reset {
+            self.applied.clear();
+            self.reset = false;
+        }
+
+        for (set, ip_addrs) in std::mem::take(&mut self.members.changed) {
+            let applied = self.applied.entry(set.clone()).or_default();
+            for ip_addr in ip_addrs {
+                if self.enforcing && self.members.expiration(&set, &ip_addr).is_some() {
+                    applied.insert(ip_addr);
+                }
+                else {
+                    applied.remove(&ip_addr);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+use crate::dns::ReverseResolver;
+use crate::sink::{GroupChange, Sink};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const DEFAULT_DNS_TIMEOUT_MS : u64 = 1000; // 1 second
+const DEFAULT_ORIGIN_REPORT_INTERVAL_SECONDS : u64 = 7 * 24 * 60 * 60; // One week
+const DEFAULT_ORIGIN_REPORT_TOP_COUNT : usize = 10;
+
+// Maximum number of addresses waiting to be looked up in reverse DNS; addresses beyond this are reported with an
+// unknown reverse DNS name, so that a slow DNS server cannot grow the backlog without bound
+const REVERSE_DNS_QUEUE_SIZE : usize = 1000;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OriginReportConfig
+{
+    // Address of the DNS server (e.g. "127.0.0.53" or "1.1.1.1:53") queried for the reverse DNS names of classified
+    // addresses.  If not present, reverse DNS names are not looked up.
+    pub dns_server : Option<String>,
+
+    // Timeout in milliseconds of each reverse DNS query.  If not present, a default of 1 second is used.
+    pub dns_timeout_ms : Option<u64>,
+
+    // Path of a CSV file mapping networks to their country and provider, one network per line in the form
+    // "<network>,<country>,<provider>" (e.g. "192.0.2.0/24,US,Example Hosting").  Lines beginning with # are ignored.
+    // The most specific network containing an address is used.  If not present, countries and providers are not
+    // reported.
+    pub geoip_csv : Option<String>,
+
+    // Names of the groups whose additions are reported.  If not present, additions to all groups are reported.
+    pub groups : Option<Vec<String>>,
+
+    // Seconds between reports.  If not present, a default of one week is used.
+    pub report_interval_seconds : Option<u64>,
+
+    // Number of entries listed in each section of a report.  If not present, a default of 10 is used.
+    pub top_count : Option<usize>,
+
+    // If present and true, each addition is also logged along with the origin of its address
+    pub log_additions : Option<bool>
+}
+
+impl OriginReportConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if let Some(dns_server) = &self.dns_server {
+            ReverseResolver::new(dns_server, self.dns_timeout_ms.unwrap_or(DEFAULT_DNS_TIMEOUT_MS))?;
+        }
+
+        if self.report_interval_seconds == Some(0) {
+            return Err("Invalid zero origin_report report_interval_seconds".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Where an address comes from
+#[derive(Clone, Default)]
+struct Origin
+{
+    reverse_dns : Option<String>,
+
+    country : Option<String>,
+
+    provider : Option<String>
+}
+
+impl Origin
+{
+    // Returns the registered domain of the reverse DNS name (e.g. "example.com" for "host-1.dc.example.com")
+    fn domain(&self) -> Option<String>
+    {
+        self.reverse_dns.as_ref().map(|name| {
+            let labels = name.trim_end_matches('.').split('.').collect::<Vec<&str>>();
+            labels[labels.len().saturating_sub(2)..].join(".")
+        })
+    }
+}
+
+// Networks with their country and provider, from a GeoIP CSV file
+struct GeoIpTable
+{
+    // Map from (prefix length, network address) to (country, provider)
+    networks : HashMap<(u8, IpAddr), (String, String)>,
+
+    // The distinct prefix lengths of networks, longest first, so that the most specific network is found first
+    prefix_lengths : Vec<u8>
+}
+
+impl GeoIpTable
+{
+    fn load(path : &str) -> Result<Self, String>
+    {
+        let csv = std::fs::read_to_string(path).map_err(|e| format!("Failed to read GeoIP file {path}: {e}"))?;
+
+        let mut networks = HashMap::new();
+
+        for (index, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("Invalid line {} of GeoIP file {path}: {line}", index + 1);
+            let mut fields = line.splitn(3, ',');
+            let (Some(network), Some(country), Some(provider)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let (address, prefix_length) = network.split_once('/').ok_or_else(invalid)?;
+            let address = address.trim().parse::<IpAddr>().map_err(|_| invalid())?;
+            let prefix_length = prefix_length.trim().parse::<u8>().map_err(|_| invalid())?;
+            if prefix_length > (if address.is_ipv4() { 32 } else { 128 }) {
+                return Err(invalid());
+            }
+            networks.insert(
+                (prefix_length, mask(&address, prefix_length)),
+                (country.trim().to_string(), provider.trim().to_string())
+            );
+        }
+
+        let mut prefix_lengths = networks
+            .keys()
+            .map(|(prefix_length, _)| *prefix_length)
+            .collect::<HashSet<u8>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        prefix_lengths.sort_by(|a, b| b.cmp(a));
+
+        Ok(Self { networks, prefix_lengths })
+    }
+
+    // Returns the country and provider of the most specific network containing ip_addr
+    fn lookup(
+        &self,
+        ip_addr : &IpAddr
+    ) -> Option<&(String, String)>
+    {
+        self.prefix_lengths
+            .iter()
+            .filter(|prefix_length| **prefix_length <= (if ip_addr.is_ipv4() { 32 } else { 128 }))
+            .find_map(|prefix_length| self.networks.get(&(*prefix_length, mask(ip_addr, *prefix_length))))
+    }
+}
+
+// Returns ip_addr with all but the first prefix_length bits cleared
+fn mask(
+    ip_addr : &IpAddr,
+    prefix_length : u8
+) -> IpAddr
+{
+    match ip_addr {
+        IpAddr::V4(v4) => {
+            let mask = u32::MAX.checked_shl(32 - (prefix_length as u32)).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(*v4) & mask))
+        },
+        IpAddr::V6(v6) => {
+            let mask = u128::MAX.checked_shl(128 - (prefix_length as u32)).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(*v6) & mask))
+        }
+    }
+}
+
+// The reverse DNS lookup thread: the DNS server it queries, the queue of addresses to be looked up by it, and the names
+// it has looked up
+struct ReverseDnsLookups
+{
+    server : SocketAddr,
+
+    sender : Sender<IpAddr>,
+
+    receiver : Receiver<(IpAddr, Option<String>)>
+}
+
+// Sink that looks up the origin (reverse DNS name, and country and provider from GeoIP) of each address added to a
+// group, and periodically reports where classified addresses came from.  Reverse DNS lookups are done by a thread of
+// their own, so that the other sinks never wait on DNS, and their results are filled in as they arrive; each address
+// is looked up at most once per report.
+pub struct OriginReportSink
+{
+    // None if reverse DNS names are not looked up
+    resolver : Option<ReverseDnsLookups>,
+
+    // Addresses being looked up, each with the groups whose additions of it are logged once it has been
+    resolving : HashMap<IpAddr, Vec<String>>,
+
+    geoip : Option<GeoIpTable>,
+
+    groups : Option<Vec<String>>,
+
+    report_interval_ms : u64,
+
+    top_count : usize,
+
+    log_additions : bool,
+
+    // Timestamp at which the current report period began
+    period_start : u64,
+
+    // Origins of the addresses added during the current report period
+    origins : HashMap<IpAddr, Origin>,
+
+    // Number of additions to each group during the current report period
+    group_add_counts : HashMap<String, u64>
+}
+
+impl OriginReportSink
+{
+    pub fn new(config : OriginReportConfig) -> Result<Self, String>
+    {
+        // The DNS server was validated when the config was loaded
+        let resolver = config.dns_server.as_ref().map(|dns_server| {
+            let resolver =
+                ReverseResolver::new(dns_server, config.dns_timeout_ms.unwrap_or(DEFAULT_DNS_TIMEOUT_MS)).unwrap();
+            let server = resolver.server();
+            let (lookup_sender, lookup_receiver) = bounded::<IpAddr>(REVERSE_DNS_QUEUE_SIZE);
+            let (name_sender, name_receiver) = unbounded::<(IpAddr, Option<String>)>();
+            std::thread::spawn(move || resolve_addresses(resolver, lookup_receiver, name_sender));
+            ReverseDnsLookups { server, sender : lookup_sender, receiver : name_receiver }
+        });
+
+        Ok(Self {
+            resolver,
+            resolving : Default::default(),
+            geoip : config.geoip_csv.as_deref().map(GeoIpTable::load).transpose()?,
+            groups : config.groups,
+            report_interval_ms : config.report_interval_seconds.unwrap_or(DEFAULT_ORIGIN_REPORT_INTERVAL_SECONDS) *
+                1000,
+            top_count : config.top_count.unwrap_or(DEFAULT_ORIGIN_REPORT_TOP_COUNT),
+            log_additions : config.log_additions.unwrap_or(false),
+            period_start : crate::clock::Clock::system_now(),
+            origins : Default::default(),
+            group_add_counts : Default::default()
+        })
+    }
+
+    // Returns the origin of ip_addr from GeoIP, queueing its reverse DNS lookup.  Returns true along with it if the
+    // lookup was queued.
+    fn lookup(
+        &mut self,
+        ip_addr : &IpAddr
+    ) -> (Origin, bool)
+    {
+        let queued = match &self.resolver {
+            Some(lookups) => match lookups.sender.try_send(*ip_addr) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    eprintln!("Reverse DNS lookup queue full; origin of {ip_addr} reported without its name");
+                    false
+                },
+                Err(TrySendError::Disconnected(_)) => false
+            },
+            None => false
+        };
+
+        let (country, provider) = match self.geoip.as_ref().and_then(|geoip| geoip.lookup(ip_addr)) {
+            Some((country, provider)) => (Some(country.clone()), Some(provider.clone())),
+            None => (None, None)
+        };
+
+        (Origin { reverse_dns : None, country, provider }, queued)
+    }
+
+    // Fills in the reverse DNS names looked up since the previous call, logging the additions that awaited them
+    fn receive_names(&mut self)
+    {
+        let Some(lookups) = &self.resolver
+        else {
+            return;
+        };
+
+        for (ip_addr, reverse_dns) in lookups.receiver.try_iter().collect::<Vec<_>>() {
+            // The address's origin is gone if a report has been made since it was queued
+            if let Some(origin) = self.origins.get_mut(&ip_addr) {
+                origin.reverse_dns = reverse_dns;
+            }
+            for group_name in self.resolving.remove(&ip_addr).unwrap_or_default() {
+                self.log_addition(&ip_addr, &group_name);
+            }
+        }
+    }
+
+    fn log_addition(
+        &self,
+        ip_addr : &IpAddr,
+        group_name : &str
+    )
+    {
+        let origin = self.origins.get(ip_addr).cloned().unwrap_or_default();
+
+        println!(
+            "Origin of {ip_addr} added to {group_name}: reverse DNS {}, country {}, provider {}",
+            origin.reverse_dns.as_deref().unwrap_or("-"),
+            origin.country.as_deref().unwrap_or("-"),
+            origin.provider.as_deref().unwrap_or("-")
+        );
+    }
+
+    fn report(
+        &mut self,
+        now : u64
+    )
+    {
+        println!(
+            "Origin report for {} addresses added to groups between {} and {now}: {}",
+            self.origins.len(),
+            self.period_start,
+            top(self.group_add_counts.iter().map(|(group_name, count)| (group_name.clone(), *count)), self.top_count)
+        );
+
+        if self.geoip.is_some() {
+            println!("  Countries: {}", self.top_origins(|origin| origin.country.clone()));
+            println!("  Providers: {}", self.top_origins(|origin| origin.provider.clone()));
+        }
+
+        if self.resolver.is_some() {
+            println!("  Reverse DNS domains: {}", self.top_origins(Origin::domain));
+        }
+
+        self.period_start = now;
+        self.origins.clear();
+        self.group_add_counts.clear();
+    }
+
+    // Returns the most common values of an attribute of the origins of this period, formatted for a report
+    fn top_origins(
+        &self,
+        attribute : impl Fn(&Origin) -> Option<String>
+    ) -> String
+    {
+        let mut counts = HashMap::<String, u64>::new();
+        for origin in self.origins.values() {
+            *counts.entry(attribute(origin).unwrap_or_else(|| "(unknown)".to_string())).or_default() += 1;
+        }
+
+        top(counts.into_iter(), self.top_count)
+    }
+}
+
+// Looks up the reverse DNS name of each address received, until the sink is dropped
+fn resolve_addresses(
+    mut resolver : ReverseResolver,
+    receiver : Receiver<IpAddr>,
+    sender : Sender<(IpAddr, Option<String>)>
+)
+{
+    for ip_addr in receiver {
+        let reverse_dns = resolver.resolve(&ip_addr).unwrap_or_else(|e| {
+            eprintln!("Reverse DNS lookup of {ip_addr} failed: {e}");
+            None
+        });
+        if sender.send((ip_addr, reverse_dns)).is_err() {
+            return;
+        }
+    }
+}
+
+// Formats the count_limit largest counts, largest first
+fn top(
+    counts : impl Iterator<Item = (String, u64)>,
+    count_limit : usize
+) -> String
+{
+    let mut counts = counts.collect::<Vec<(String, u64)>>();
+
+    if counts.is_empty() {
+        return "none".to_string();
+    }
+
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    counts.iter().take(count_limit).map(|(name, count)| format!("{name} {count}")).collect::<Vec<String>>().join(", ")
+}
+
+impl Sink for OriginReportSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "origin_report",
+            "dns_server" : self.resolver.as_ref().map(|lookups| lookups.server.to_string()),
+            "geoip" : self.geoip.is_some(),
+            "groups" : self.groups,
+            "report_interval_ms" : self.report_interval_ms
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        self.receive_names();
+
+        if let GroupChange::Add { group_name, ip_addr, .. } = event {
+            if self.groups.as_ref().map(|groups| groups.contains(group_name)).unwrap_or(true) {
+                *self.group_add_counts.entry(group_name.clone()).or_default() += 1;
+
+                if !self.origins.contains_key(ip_addr) {
+                    let (origin, queued) = self.lookup(ip_addr);
+                    self.origins.insert(*ip_addr, origin);
+                    if queued {
+                        self.resolving.insert(*ip_addr, vec![]);
+                    }
+                }
+
+                // Additions of addresses being looked up are logged once their names are known
+                if self.log_additions {
+                    match self.resolving.get_mut(ip_addr) {
+                        Some(group_names) => group_names.push(group_name.clone()),
+                        None => self.log_addition(ip_addr, group_name)
+                    }
+                }
+            }
+        }
+
+        // Reports are made on the first event after they are due; status reports ensure that events arrive regularly
+        let now = crate::clock::Clock::system_now();
+        if now >= (self.period_start + self.report_interval_ms) {
+            self.report(now);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        self.receive_names();
+
+        Ok(())
+    }
+}
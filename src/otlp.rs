@@ -0,0 +1,343 @@
+use crate::http::HttpEndpoint;
+use crate::sink::{GroupChange, Metric, Sink};
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const DEFAULT_OTLP_SERVICE_NAME : &str = "txingest-classifier";
+const DEFAULT_OTLP_TIMEOUT_MS : u64 = 5 * 1000; // 5 seconds
+
+// Number of pending log records at which they are exported without waiting for the sink queue to drain
+const OTLP_LOG_BATCH_SIZE : usize = 1000;
+
+// Maximum number of exports (of metrics, or of a batch of log records) waiting for the export thread; exports beyond
+// this are dropped, so that an unreachable collector cannot hold up the other sinks or grow memory without bound
+const OTLP_EXPORT_QUEUE_SIZE : usize = 100;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig
+{
+    // Base URL of an OpenTelemetry collector accepting OTLP/HTTP with JSON encoding (e.g. "http://127.0.0.1:4318").
+    // Metrics are posted to /v1/metrics and events to /v1/logs under this URL.  Only plain http URLs are supported.
+    pub endpoint : String,
+
+    // Value of the service.name resource attribute.  If not present, "txingest-classifier" is used.
+    pub service_name : Option<String>,
+
+    // Timeout in milliseconds for connecting to and exchanging data with the collector.  If not present, a default of
+    // 5 seconds is used.
+    pub timeout_ms : Option<u64>,
+
+    // If present and false, events (group changes, new peers, leader status, etc) are not exported as log records;
+    // only metrics are exported
+    pub export_events : Option<bool>
+}
+
+impl OtlpConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        self.endpoints().map(|_| ()).map_err(|e| format!("Invalid otlp endpoint: {e}"))
+    }
+
+    // Returns the metrics and logs endpoints
+    fn endpoints(&self) -> Result<(HttpEndpoint, HttpEndpoint), String>
+    {
+        let base = self.endpoint.trim_end_matches('/');
+        let timeout_ms = self.timeout_ms.unwrap_or(DEFAULT_OTLP_TIMEOUT_MS);
+
+        Ok((
+            HttpEndpoint::new(&format!("{base}/v1/metrics"), timeout_ms)?,
+            HttpEndpoint::new(&format!("{base}/v1/logs"), timeout_ms)?
+        ))
+    }
+}
+
+// An export waiting for the export thread: the JSON body to post, to the metrics endpoint or the logs endpoint
+enum Export
+{
+    Metrics(String),
+
+    Logs(String)
+}
+
+// Sink that exports metrics and events to an OpenTelemetry collector.  Metrics are exported as gauges as soon as
+// they are received.  Events are exported as log records, batched until the sink queue drains.  Exports are posted by
+// a thread of their own, so that a slow or unreachable collector never stalls the sink worker; exports that fail or
+// that find the export queue full are dropped and counted rather than retried, as metrics are re-sent at every report.
+pub struct OtlpSink
+{
+    metrics_url : String,
+
+    logs_url : String,
+
+    // The resource describing this process, included in every export
+    resource : serde_json::Value,
+
+    export_events : bool,
+
+    // Log records not yet handed to the export thread
+    pending_log_records : Vec<serde_json::Value>,
+
+    sender : Sender<Export>,
+
+    // Number of exports dropped because the export queue was full, and whether that has been reported since the queue
+    // last had room
+    dropped_count : u64,
+
+    dropped_reported : bool
+}
+
+impl OtlpSink
+{
+    pub fn new(config : OtlpConfig) -> Self
+    {
+        // The endpoint was validated when the config was loaded
+        let (metrics_endpoint, logs_endpoint) = config.endpoints().unwrap();
+
+        let service_name = config.service_name.as_deref().unwrap_or(DEFAULT_OTLP_SERVICE_NAME);
+
+        let (sender, receiver) = bounded::<Export>(OTLP_EXPORT_QUEUE_SIZE);
+
+        let (metrics_url, logs_url) = (metrics_endpoint.url().to_string(), logs_endpoint.url().to_string());
+
+        std::thread::spawn(move || run_exports(receiver, metrics_endpoint, logs_endpoint));
+
+        Self {
+            metrics_url,
+            logs_url,
+            resource : serde_json::json!({
+                "attributes" : [ attribute("service.name", &serde_json::json!(service_name)) ]
+            }),
+            export_events : config.export_events.unwrap_or(true),
+            pending_log_records : vec![],
+            sender,
+            dropped_count : 0,
+            dropped_reported : false
+        }
+    }
+
+    // Hands an export to the export thread, dropping it if the export queue is full
+    fn queue(
+        &mut self,
+        export : Export
+    )
+    {
+        match self.sender.try_send(export) {
+            Ok(()) => self.dropped_reported = false,
+            Err(TrySendError::Full(_)) => {
+                self.dropped_count += 1;
+                if !self.dropped_reported {
+                    eprintln!(
+                        "ERROR: OTLP export queue full; dropping exports ({} dropped since startup)",
+                        self.dropped_count
+                    );
+                }
+                self.dropped_reported = true;
+            },
+            Err(TrySendError::Disconnected(_)) => ()
+        }
+    }
+
+    fn export_metrics(
+        &mut self,
+        metrics : &[Metric],
+        timestamp : u64
+    )
+    {
+        let time_unix_nano = unix_nano(timestamp);
+
+        // All instances of a metric are data points of a single gauge
+        let mut gauges = Vec::<(&str, Vec<serde_json::Value>)>::new();
+
+        for metric in metrics {
+            let mut data_point = serde_json::json!({
+                "timeUnixNano" : time_unix_nano,
+                "asDouble" : metric.value,
+                "attributes" : metric
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, &serde_json::json!(value)))
+                    .collect::<Vec<_>>()
+            });
+
+            // Exemplars carry the decision as attributes rather than as a trace id, as decisions are not traced
+            if let Some(exemplar) = &metric.exemplar {
+                data_point["exemplars"] = serde_json::json!([{
+                    "timeUnixNano" : unix_nano(exemplar.timestamp),
+                    "asDouble" : exemplar.value,
+                    "filteredAttributes" : [
+                        attribute("decision_id", &serde_json::json!(exemplar.decision_id)),
+                        attribute("ip_addr", &serde_json::json!(exemplar.ip_addr))
+                    ]
+                }]);
+            }
+
+            match gauges.iter_mut().find(|(name, _)| *name == metric.name) {
+                Some((_, data_points)) => data_points.push(data_point),
+                None => gauges.push((metric.name, vec![data_point]))
+            }
+        }
+
+        let body = serde_json::json!({
+            "resourceMetrics" : [{
+                "resource" : self.resource,
+                "scopeMetrics" : [{
+                    "scope" : { "name" : DEFAULT_OTLP_SERVICE_NAME },
+                    "metrics" : gauges
+                        .into_iter()
+                        .map(|(name, data_points)| serde_json::json!({
+                            "name" : name,
+                            "gauge" : { "dataPoints" : data_points }
+                        }))
+                        .collect::<Vec<_>>()
+                }]
+            }]
+        });
+
+        self.queue(Export::Metrics(body.to_string()));
+    }
+
+    // Exports all pending log records
+    fn export_log_records(&mut self)
+    {
+        if self.pending_log_records.is_empty() {
+            return;
+        }
+
+        let body = serde_json::json!({
+            "resourceLogs" : [{
+                "resource" : self.resource,
+                "scopeLogs" : [{
+                    "scope" : { "name" : DEFAULT_OTLP_SERVICE_NAME },
+                    "logRecords" : self.pending_log_records
+                }]
+            }]
+        });
+
+        self.pending_log_records.clear();
+
+        self.queue(Export::Logs(body.to_string()));
+    }
+}
+
+// Posts each export received, until the sink is dropped.  A failing collector is reported once, and again on recovery
+// with the number of exports dropped meanwhile.
+fn run_exports(
+    receiver : Receiver<Export>,
+    metrics_endpoint : HttpEndpoint,
+    logs_endpoint : HttpEndpoint
+)
+{
+    let mut failed_count = 0_u64;
+
+    for export in receiver {
+        let result = match &export {
+            Export::Metrics(body) => metrics_endpoint.post_json(body),
+            Export::Logs(body) => logs_endpoint.post_json(body)
+        };
+
+        match result {
+            Ok(()) if failed_count > 0 => {
+                eprintln!("OTLP export recovered; dropped {failed_count} failed exports");
+                failed_count = 0;
+            },
+            Ok(()) => (),
+            Err(e) => {
+                if failed_count == 0 {
+                    eprintln!("ERROR: OTLP export failed ({e}); dropping exports until the collector recovers");
+                }
+                failed_count += 1;
+            }
+        }
+    }
+}
+
+// Returns a log record for an event, with the event name as the body and the other event fields as attributes
+fn log_record(event : &GroupChange) -> serde_json::Value
+{
+    let json = event.to_json();
+
+    let name = json["event"].as_str().unwrap_or_default().to_string();
+
+    // Group change events carry no timestamp of their own, so the time of export is used
+    let timestamp = json["timestamp"].as_u64().unwrap_or_else(crate::clock::Clock::system_now);
+
+    serde_json::json!({
+        "timeUnixNano" : unix_nano(timestamp),
+        "severityText" : "INFO",
+        "body" : { "stringValue" : name },
+        "attributes" : json
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, value)| (key.as_str() != "event") && (key.as_str() != "timestamp") && !value.is_null())
+            .map(|(key, value)| attribute(key, value))
+            .collect::<Vec<_>>()
+    })
+}
+
+// Returns an OTLP key/value attribute for a JSON value
+fn attribute(
+    key : &str,
+    value : &serde_json::Value
+) -> serde_json::Value
+{
+    let value = match value {
+        serde_json::Value::Bool(b) => serde_json::json!({ "boolValue" : b }),
+        // 64 bit integers are encoded as strings in OTLP JSON
+        serde_json::Value::Number(n) if n.is_u64() || n.is_i64() => serde_json::json!({ "intValue" : n.to_string() }),
+        serde_json::Value::Number(n) => serde_json::json!({ "doubleValue" : n.as_f64() }),
+        serde_json::Value::String(s) => serde_json::json!({ "stringValue" : s }),
+        value => serde_json::json!({ "stringValue" : value.to_string() })
+    };
+
+    serde_json::json!({ "key" : key, "value" : value })
+}
+
+// Converts a millisecond timestamp to the nanosecond string form used by OTLP JSON
+fn unix_nano(timestamp : u64) -> String
+{
+    format!("{}", (timestamp as u128) * 1_000_000)
+}
+
+impl Sink for OtlpSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "otlp",
+            "metrics_url" : self.metrics_url,
+            "logs_url" : self.logs_url,
+            "export_events" : self.export_events
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        match event {
+            GroupChange::Metrics { metrics, timestamp } => self.export_metrics(metrics, *timestamp),
+            _ if !self.export_events => (),
+            event => {
+                self.pending_log_records.push(log_record(event));
+
+                if self.pending_log_records.len() >= OTLP_LOG_BATCH_SIZE {
+                    self.export_log_records();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        self.export_log_records();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use crate::sink::{GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::io::Write;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PeerExportConfig
+{
+    // Path of a file to which the summary of each evicted peer is appended as a line of JSON.  If not present, evicted
+    // peers are only sent to sinks as peer_evicted events (e.g. for export to OpenTelemetry or webhooks).
+    pub path : Option<String>
+}
+
+// Sink that appends the summaries of evicted peers to a file, one JSON object per line
+pub struct PeerExportSink
+{
+    path : String,
+
+    // Lines not yet written, written when the sink queue drains
+    pending_lines : Vec<String>
+}
+
+impl PeerExportSink
+{
+    pub fn new(path : String) -> Self
+    {
+        Self { path, pending_lines : vec![] }
+    }
+}
+
+impl Sink for PeerExportSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "peer_export", "path" : self.path })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if let GroupChange::PeerEvicted { .. } = event {
+            self.pending_lines.push(event.to_json().to_string());
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if self.pending_lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| format!("Failed to open {}: {e}", self.path))?;
+
+        let mut lines = self.pending_lines.join("\n");
+        lines.push('\n');
+
+        file.write_all(lines.as_bytes()).map_err(|e| format!("Failed to write {}: {e}", self.path))?;
+
+        self.pending_lines.clear();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use crate::config::ThrottleRate;
+use crate::deny_list::write_file;
+use crate::group::PERMANENT_EXPIRATION;
+use crate::sink::{describe_throttle_rate, GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::IpAddr;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PeerNoticeConfig
+{
+    // Directory in which a notice is kept for each classified address, as "<address>.json", for the operator to serve
+    // (e.g. from a gateway at a URL including the address) so that relayers and searchers whose traffic is limited can
+    // find out why.  Each notice is replaced atomically when it changes, and removed once the address is in no notice
+    // group.  The directory is created if needed, and notices left in it by a prior run are removed at startup.
+    pub directory : String,
+
+    // Groups whose members are given notices.  If not present, the members of all groups are.
+    pub groups : Option<Vec<String>>,
+
+    // Map from group name to an explanation of the group for those in it, in the operator's words (e.g. "More than
+    // 100 failed connections in 10 seconds; reduce your connection rate"), included in each notice of the group
+    pub explanations : Option<HashMap<String, String>>,
+
+    // If present, included in every notice, e.g. where to ask about being limited
+    pub contact : Option<String>
+}
+
+impl PeerNoticeConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.directory.is_empty() {
+            return Err("Invalid peer_notices directory: empty string".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// The contents of a notice
+#[derive(Serialize)]
+struct Notice<'a>
+{
+    ip_addr : IpAddr,
+
+    memberships : Vec<NoticeMembership<'a>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contact : Option<&'a str>
+}
+
+#[derive(Serialize)]
+struct NoticeMembership<'a>
+{
+    group_name : &'a str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation : Option<&'a str>,
+
+    // The rule that added the member; absent if it was first seen by an update, as when switching to enforce mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule : Option<&'a str>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added_timestamp : Option<u64>,
+
+    // Absent for memberships that never expire
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration : Option<u64>,
+
+    // The limits applied to the member, for throttle groups
+    #[serde(skip_serializing_if = "Option::is_none")]
+    throttle : Option<String>
+}
+
+// A member of a notice group as known to the sink
+#[derive(Default)]
+struct Member
+{
+    rule : Option<String>,
+
+    added_timestamp : Option<u64>,
+
+    expiration : u64,
+
+    throttle : Option<ThrottleRate>
+}
+
+// Sink that maintains a notice file for each member of the notice groups, describing which groups the address is in,
+// why, and when each membership expires.  No notices are kept while monitoring, since nothing is enforced then.
+pub struct PeerNoticeSink
+{
+    config : PeerNoticeConfig,
+
+    // Map from member ip address to the notice groups it is a member of
+    members : HashMap<IpAddr, BTreeMap<String, Member>>,
+
+    // Addresses whose notices have changed since they were last written
+    dirty : HashSet<IpAddr>,
+
+    // False when monitoring, in which case no notices are kept
+    enforcing : bool
+}
+
+impl PeerNoticeSink
+{
+    pub fn new(config : PeerNoticeConfig) -> Result<Self, String>
+    {
+        let directory = &config.directory;
+
+        std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create {directory}: {e}"))?;
+
+        let entries = std::fs::read_dir(directory).map_err(|e| format!("Failed to read {directory}: {e}"))?;
+
+        // Only files named as notices are removed, so that nothing else served from the directory is touched
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_notice = path.extension().map(|extension| extension == "json").unwrap_or(false) &&
+                path.file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(|stem| stem.parse::<IpAddr>().is_ok())
+                    .unwrap_or(false);
+            if is_notice {
+                std::fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {e}", path.display()))?;
+            }
+        }
+
+        Ok(Self { config, members : Default::default(), dirty : Default::default(), enforcing : true })
+    }
+
+    fn is_notice_group(
+        &self,
+        group_name : &str
+    ) -> bool
+    {
+        self.config.groups.as_ref().map(|groups| groups.iter().any(|g| g == group_name)).unwrap_or(true)
+    }
+
+    fn path(
+        &self,
+        ip_addr : &IpAddr
+    ) -> String
+    {
+        format!("{}/{ip_addr}.json", self.config.directory)
+    }
+
+    // Writes the notice of ip_addr, or removes it if the address is in no notice group or the classifier is monitoring
+    fn write(
+        &self,
+        ip_addr : &IpAddr
+    ) -> Result<(), String>
+    {
+        let path = self.path(ip_addr);
+
+        let Some(groups) = self.members.get(ip_addr).filter(|_| self.enforcing)
+        else {
+            return match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to remove {path}: {e}")),
+                _ => Ok(())
+            };
+        };
+
+        let notice = Notice {
+            ip_addr : *ip_addr,
+            memberships : groups
+                .iter()
+                .map(|(group_name, member)| NoticeMembership {
+                    group_name,
+                    explanation : self
+                        .config
+                        .explanations
+                        .as_ref()
+                        .and_then(|explanations| explanations.get(group_name))
+                        .map(String::as_str),
+                    rule : member.rule.as_deref(),
+                    added_timestamp : member.added_timestamp,
+                    expiration : Some(member.expiration).filter(|expiration| *expiration != PERMANENT_EXPIRATION),
+                    throttle : member.throttle.as_ref().map(describe_throttle_rate)
+                })
+                .collect(),
+            contact : self.config.contact.as_deref()
+        };
+
+        write_file(&path, std::iter::once(serde_json::to_string_pretty(&notice).unwrap()))
+    }
+}
+
+impl Sink for PeerNoticeSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "type" : "peer_notices",
+            "directory" : self.config.directory,
+            "groups" : self.config.groups
+        })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        match event {
+            GroupChange::Mode { enforcing, .. } => {
+                if self.enforcing != *enforcing {
+                    self.dirty.extend(self.members.keys());
+                }
+                self.enforcing = *enforcing;
+            },
+            GroupChange::Add { group_name, ip_addr, expiration, throttle, rule, timestamp, .. }
+                if self.is_notice_group(group_name) =>
+            {
+                self.members.entry(*ip_addr).or_default().insert(group_name.clone(), Member {
+                    rule : Some(rule.name.to_string()),
+                    added_timestamp : Some(*timestamp),
+                    expiration : *expiration,
+                    throttle : *throttle
+                });
+                self.dirty.insert(*ip_addr);
+            },
+            GroupChange::Update { group_name, ip_addr, expiration, throttle, .. } |
+            GroupChange::Extend { group_name, ip_addr, expiration, throttle, .. }
+                if self.is_notice_group(group_name) =>
+            {
+                let member = self.members.entry(*ip_addr).or_default().entry(group_name.clone()).or_default();
+                member.expiration = *expiration;
+                member.throttle = *throttle;
+                self.dirty.insert(*ip_addr);
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } if self.is_notice_group(group_name) => {
+                if let Some(groups) = self.members.get_mut(ip_addr) {
+                    if groups.remove(group_name).is_some() {
+                        self.dirty.insert(*ip_addr);
+                    }
+                    if groups.is_empty() {
+                        self.members.remove(ip_addr);
+                    }
+                }
+            },
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        // Addresses whose notices failed to be written stay dirty, so that the retried flush writes them
+        let mut ip_addrs = self.dirty.iter().copied().collect::<Vec<IpAddr>>();
+        ip_addrs.sort();
+
+        for ip_addr in ip_addrs {
+            self.write(&ip_addr)?;
+            self.dirty.remove(&ip_addr);
+        }
+
+        Ok(())
+    }
+}
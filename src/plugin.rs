@@ -0,0 +1,327 @@
+// Classifier plugins, by which scoring logic outside of this crate decides group membership.  A plugin is fed every
+// event received and is asked for its decisions at each classification pass; its decisions are applied as if made by
+// a rule named "plugin:<name>".  Library users register implementations of ClassifierPlugin directly with State;
+// the binary runs each configured plugin as a subprocess speaking a JSON lines protocol over its stdin and stdout.
+
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const DEFAULT_PLUGIN_QUEUE_SIZE : usize = 10000;
+
+pub trait ClassifierPlugin
+{
+    // Name of the plugin, which names the rule on behalf of which its decisions are made
+    fn name(&self) -> &str;
+
+    // Feeds an event received from the named ingest source
+    fn handle(
+        &mut self,
+        source : &Arc<str>,
+        msg : &TxIngestMsg
+    );
+
+    // Returns the decisions made since the last call; called once per classification pass
+    fn classify(
+        &mut self,
+        now : u64
+    ) -> Vec<PluginDecision>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PluginDecision
+{
+    // Adds ip_addr to the named group for ttl_ms, or permanently if None
+    Add
+    {
+        group_name : String, ip_addr : IpAddr, pubkey : Option<Pubkey>, ttl_ms : Option<u64>
+    },
+
+    // Removes ip_addr from the named group
+    Remove
+    {
+        group_name : String, ip_addr : IpAddr
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig
+{
+    // Name of the plugin, unique among plugins
+    pub name : String,
+
+    // Command run as the plugin, split on whitespace into a program and its arguments.  Every event is written to its
+    // stdin as a line of JSON, e.g. {"source":"default","event":"failed","timestamp":...,"peer_addr":"1.2.3.4"}, and
+    // every line it writes to stdout is read as a decision, either
+    // {"add":{"group_name":...,"ip_addr":...,"pubkey":...,"ttl_seconds":...}} (pubkey and ttl_seconds optional; no
+    // ttl_seconds means permanently) or {"remove":{"group_name":...,"ip_addr":...}}.  Its stderr is inherited.
+    pub command : String,
+
+    // Maximum number of events waiting to be written to the plugin.  Events beyond this are dropped, so that a slow
+    // plugin cannot stall event processing.  If not present, a default of 10000 is used.
+    pub queue_size : Option<usize>
+}
+
+pub fn validate(plugins : &[PluginConfig]) -> Result<(), String>
+{
+    let mut names = HashSet::new();
+
+    for plugin in plugins {
+        if plugin.name.is_empty() {
+            return Err("Invalid plugin name: empty string".to_string());
+        }
+        if !names.insert(&plugin.name) {
+            return Err(format!("Invalid plugin name {}: used more than once", plugin.name));
+        }
+        if plugin.command.trim().is_empty() {
+            return Err(format!("Invalid command for plugin {}: empty command", plugin.name));
+        }
+        if plugin.queue_size == Some(0) {
+            return Err(format!("Invalid queue_size for plugin {}: zero", plugin.name));
+        }
+    }
+
+    Ok(())
+}
+
+// A decision as written by a subprocess plugin
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum WireDecision
+{
+    Add
+    {
+        group_name : String, ip_addr : IpAddr, pubkey : Option<String>, ttl_seconds : Option<u64>
+    },
+
+    Remove
+    {
+        group_name : String, ip_addr : IpAddr
+    }
+}
+
+// Plugin run as a subprocess.  Events are written to the subprocess by a writer thread from a bounded queue, and
+// decisions are read from it by a reader thread, so that a slow or stuck plugin stalls neither event processing nor
+// classification.  If the subprocess exits, the error is logged and the plugin makes no further decisions.
+pub struct SubprocessPlugin
+{
+    name : String,
+
+    child : Child,
+
+    events : Sender<(Arc<str>, TxIngestMsg)>,
+
+    decisions : Receiver<PluginDecision>,
+
+    // Number of events dropped because the queue was full, since the last classification pass
+    dropped_count : u64,
+
+    // True once the subprocess has been seen to have exited
+    exited : bool
+}
+
+impl SubprocessPlugin
+{
+    pub fn new(config : PluginConfig) -> Result<Self, String>
+    {
+        let name = config.name;
+
+        let args = config.command.split_whitespace().collect::<Vec<_>>();
+
+        let mut child = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run plugin {name}: {e}"))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (events, event_receiver) =
+            bounded::<(Arc<str>, TxIngestMsg)>(config.queue_size.unwrap_or(DEFAULT_PLUGIN_QUEUE_SIZE));
+
+        let (decision_sender, decisions) = unbounded::<PluginDecision>();
+
+        let writer_name = name.clone();
+        std::thread::spawn(move || {
+            for (source, msg) in event_receiver {
+                let Some(json) = event_json(&source, &msg)
+                else {
+                    continue;
+                };
+                if let Err(e) = writeln!(stdin, "{json}") {
+                    eprintln!("ERROR: Failed to write to plugin {writer_name}: {e}");
+                    break;
+                }
+            }
+        });
+
+        let reader_name = name.clone();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        eprintln!("ERROR: Failed to read from plugin {reader_name}: {e}");
+                        break;
+                    }
+                };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match parse_decision(&line) {
+                    Ok(decision) => {
+                        if decision_sender.send(decision).is_err() {
+                            break;
+                        }
+                    },
+                    Err(e) => eprintln!("ERROR: Invalid decision from plugin {reader_name}: {e}: {line}")
+                }
+            }
+            eprintln!("ERROR: Plugin {reader_name} closed its output");
+        });
+
+        Ok(Self { name, child, events, decisions, dropped_count : 0, exited : false })
+    }
+}
+
+impl ClassifierPlugin for SubprocessPlugin
+{
+    fn name(&self) -> &str
+    {
+        &self.name
+    }
+
+    fn handle(
+        &mut self,
+        source : &Arc<str>,
+        msg : &TxIngestMsg
+    )
+    {
+        if let Err(TrySendError::Full(_)) = self.events.try_send((source.clone(), msg.clone())) {
+            self.dropped_count += 1;
+        }
+    }
+
+    fn classify(
+        &mut self,
+        _now : u64
+    ) -> Vec<PluginDecision>
+    {
+        if self.dropped_count > 0 {
+            eprintln!("ERROR: Plugin {} queue full; dropped {} events", self.name, self.dropped_count);
+            self.dropped_count = 0;
+        }
+
+        if !self.exited {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                eprintln!("ERROR: Plugin {} exited with {status}", self.name);
+                self.exited = true;
+            }
+        }
+
+        self.decisions.try_iter().collect()
+    }
+}
+
+impl Drop for SubprocessPlugin
+{
+    fn drop(&mut self)
+    {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Returns the JSON written to subprocess plugins for an event, or None for events that carry nothing
+fn event_json(
+    source : &str,
+    msg : &TxIngestMsg
+) -> Option<serde_json::Value>
+{
+    let pubkey = |pubkey : &Option<Pubkey>| pubkey.map(|pubkey| pubkey.to_string());
+
+    let mut json = match msg {
+        TxIngestMsg::Failed { timestamp, peer_addr } => {
+            serde_json::json!({ "event" : "failed", "timestamp" : timestamp, "peer_addr" : peer_addr })
+        },
+        TxIngestMsg::Exceeded { timestamp, peer_addr, peer_pubkey, stake } => serde_json::json!({
+            "event" : "exceeded",
+            "timestamp" : timestamp,
+            "peer_addr" : peer_addr,
+            "peer_pubkey" : pubkey(peer_pubkey),
+            "stake" : stake
+        }),
+        TxIngestMsg::Started { timestamp, peer_addr, peer_pubkey, stake } => serde_json::json!({
+            "event" : "started",
+            "timestamp" : timestamp,
+            "peer_addr" : peer_addr,
+            "peer_pubkey" : pubkey(peer_pubkey),
+            "stake" : stake
+        }),
+        TxIngestMsg::Finished { timestamp, peer_addr } => {
+            serde_json::json!({ "event" : "finished", "timestamp" : timestamp, "peer_addr" : peer_addr })
+        },
+        TxIngestMsg::VoteTx { timestamp, peer_addr } => {
+            serde_json::json!({ "event" : "vote_tx", "timestamp" : timestamp, "peer_addr" : peer_addr })
+        },
+        TxIngestMsg::UserTx { timestamp, peer_addr, signature } => serde_json::json!({
+            "event" : "user_tx",
+            "timestamp" : timestamp,
+            "peer_addr" : peer_addr,
+            "signature" : signature.to_string()
+        }),
+        TxIngestMsg::Forwarded { timestamp, signature } => {
+            serde_json::json!({ "event" : "forwarded", "timestamp" : timestamp, "signature" : signature.to_string() })
+        },
+        TxIngestMsg::BadFee { timestamp, signature } => {
+            serde_json::json!({ "event" : "bad_fee", "timestamp" : timestamp, "signature" : signature.to_string() })
+        },
+        TxIngestMsg::Fee { timestamp, signature, cu_limit, cu_used, fee } => serde_json::json!({
+            "event" : "fee",
+            "timestamp" : timestamp,
+            "signature" : signature.to_string(),
+            "cu_limit" : cu_limit,
+            "cu_used" : cu_used,
+            "fee" : fee
+        }),
+        TxIngestMsg::WillBeLeader { timestamp, slots } => {
+            serde_json::json!({ "event" : "will_be_leader", "timestamp" : timestamp, "slots" : slots })
+        },
+        TxIngestMsg::BeginLeader { timestamp } => {
+            serde_json::json!({ "event" : "begin_leader", "timestamp" : timestamp })
+        },
+        TxIngestMsg::EndLeader { timestamp } => serde_json::json!({ "event" : "end_leader", "timestamp" : timestamp }),
+        TxIngestMsg::Deprecated => return None
+    };
+
+    json["source"] = serde_json::json!(source);
+
+    Some(json)
+}
+
+// Parses a decision written by a subprocess plugin
+fn parse_decision(line : &str) -> Result<PluginDecision, String>
+{
+    match serde_json::from_str::<WireDecision>(line).map_err(|e| e.to_string())? {
+        WireDecision::Add { group_name, ip_addr, pubkey, ttl_seconds } => Ok(PluginDecision::Add {
+            group_name,
+            ip_addr,
+            pubkey : pubkey
+                .map(|pubkey| Pubkey::from_str(&pubkey).map_err(|_| format!("invalid pubkey {pubkey}")))
+                .transpose()?,
+            ttl_ms : ttl_seconds.map(|ttl_seconds| ttl_seconds.saturating_mul(1000))
+        }),
+        WireDecision::Remove { group_name, ip_addr } => Ok(PluginDecision::Remove { group_name, ip_addr })
+    }
+}
@@ -0,0 +1,355 @@
+use bincode::Options;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::txingest::TxIngestMsg;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+pub const DEFAULT_RECORDING_MAX_FILE_BYTES : u64 = 1024 * 1024 * 1024; // 1 GiB
+pub const DEFAULT_RECORDING_MAX_FILE_SECONDS : u64 = 60 * 60; // 1 hour
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RecordingConfig
+{
+    // Directory in which every received event is recorded, with its arrival time, for replay by the replay subcommand.
+    // Events are appended to files named "events-<start timestamp>.rec", so that replaying the files in name order
+    // replays the events in order of arrival.  The directory is created if needed.
+    pub directory : String,
+
+    // If present and false, recording is off at startup, to be turned on via the admin socket
+    pub enabled : Option<bool>,
+
+    // Size in bytes beyond which a file is closed and the next event starts a new one.  If not present, a default of
+    // 1 GiB is used.
+    pub max_file_bytes : Option<u64>,
+
+    // Seconds after which a file is closed and the next event starts a new one.  If not present, a default of 1 hour
+    // is used.
+    pub max_file_seconds : Option<u64>,
+
+    // If present, the oldest files are removed whenever a new one would make more than this many
+    pub max_file_count : Option<usize>
+}
+
+impl RecordingConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.directory.is_empty() {
+            return Err("Invalid recording directory: empty string".to_string());
+        }
+
+        if self.max_file_bytes == Some(0) {
+            return Err("Invalid recording max_file_bytes: zero".to_string());
+        }
+
+        if self.max_file_seconds == Some(0) {
+            return Err("Invalid recording max_file_seconds: zero".to_string());
+        }
+
+        if self.max_file_count == Some(0) {
+            return Err("Invalid recording max_file_count: zero".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// An event as received by the classifier, with the time at which it arrived.  A recording is a sequence of these, each
+// serialized with bincode's default options (as TxIngestMsg is on the wire), in order of arrival.
+#[derive(Deserialize, Serialize)]
+pub struct RecordedEvent
+{
+    // Milliseconds since the epoch on the system clock at which the event was received
+    pub arrival_timestamp : u64,
+
+    // Name of the ingest source from which the event was received
+    pub source : String,
+
+    pub msg : TxIngestMsg
+}
+
+// Reads the events of a recording in order
+pub struct RecordingReader
+{
+    path : String,
+
+    reader : BufReader<std::fs::File>
+}
+
+impl RecordingReader
+{
+    pub fn open(path : &str) -> Result<Self, String>
+    {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open recording {path}: {e}"))?;
+
+        Ok(Self { path : path.to_string(), reader : BufReader::new(file) })
+    }
+}
+
+impl Iterator for RecordingReader
+{
+    type Item = Result<RecordedEvent, String>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+        // The end of the file is only expected between events; a file ending within one was truncated, as when the
+        // recording process died mid-write, and only its complete events are read
+        let mut first = [0_u8; 1];
+        match self.reader.read(&mut first) {
+            Ok(0) => return None,
+            Ok(_) => (),
+            Err(e) => return Some(Err(format!("Failed to read recording {}: {e}", self.path)))
+        }
+
+        match bincode::DefaultOptions::new().deserialize_from::<_, RecordedEvent>((&first[..]).chain(&mut self.reader))
+        {
+            Ok(recorded_event) => Some(Ok(recorded_event)),
+            Err(e) => match e.as_ref() {
+                bincode::ErrorKind::Io(io_e) if io_e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    eprintln!("WARNING: Recording {} ends with a truncated event, which is ignored", self.path);
+                    None
+                },
+                _ => Some(Err(format!("Invalid recording {}: {e}", self.path)))
+            }
+        }
+    }
+}
+
+// The file to which events are being recorded
+struct RecordingFile
+{
+    path : String,
+
+    writer : BufWriter<std::fs::File>,
+
+    start_timestamp : u64,
+
+    byte_count : u64
+}
+
+// Records received events to rotating files in the recording directory
+pub struct Recorder
+{
+    config : RecordingConfig,
+
+    enabled : bool,
+
+    // The file being written; None while recording is off, and until the first event after a file is closed
+    file : Option<RecordingFile>,
+
+    // Number of events recorded since startup
+    event_count : u64
+}
+
+impl Recorder
+{
+    pub fn new(config : RecordingConfig) -> Result<Self, String>
+    {
+        std::fs::create_dir_all(&config.directory)
+            .map_err(|e| format!("Failed to create recording directory {}: {e}", config.directory))?;
+
+        Ok(Self { enabled : config.enabled.unwrap_or(true), config, file : None, event_count : 0 })
+    }
+
+    pub fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "directory" : self.config.directory,
+            "enabled" : self.enabled,
+            "max_file_bytes" : self.config.max_file_bytes.unwrap_or(DEFAULT_RECORDING_MAX_FILE_BYTES),
+            "max_file_seconds" : self.config.max_file_seconds.unwrap_or(DEFAULT_RECORDING_MAX_FILE_SECONDS),
+            "max_file_count" : self.config.max_file_count
+        })
+    }
+
+    // Turns recording on or off; turning it off closes the file being written
+    pub fn set_enabled(
+        &mut self,
+        enabled : bool
+    )
+    {
+        if !enabled {
+            self.close();
+        }
+
+        self.enabled = enabled;
+    }
+
+    // Describes whether recording is on, and to which file
+    pub fn status(&self) -> String
+    {
+        let state = if self.enabled { "on" } else { "off" };
+
+        match &self.file {
+            Some(file) => format!(
+                "Recording is {state}: {} events recorded, writing {} ({} bytes)\n",
+                self.event_count, file.path, file.byte_count
+            ),
+            None => format!("Recording is {state}: {} events recorded to {}\n", self.event_count, self.config.directory)
+        }
+    }
+
+    // Appends an event received from source at arrival_timestamp to the current file, starting a new file first if
+    // the current one is full or old enough.  Recording is turned off if writing fails, so that a full disk is
+    // reported once rather than for every event.
+    pub fn record(
+        &mut self,
+        arrival_timestamp : u64,
+        source : &str,
+        msg : &TxIngestMsg
+    )
+    {
+        if !self.enabled {
+            return;
+        }
+
+        if let Err(e) = self.write(arrival_timestamp, source, msg) {
+            eprintln!("ERROR: {e}; recording turned off");
+            self.set_enabled(false);
+        }
+    }
+
+    fn write(
+        &mut self,
+        arrival_timestamp : u64,
+        source : &str,
+        msg : &TxIngestMsg
+    ) -> Result<(), String>
+    {
+        let max_file_bytes = self.config.max_file_bytes.unwrap_or(DEFAULT_RECORDING_MAX_FILE_BYTES);
+        let max_file_ms = self.config.max_file_seconds.unwrap_or(DEFAULT_RECORDING_MAX_FILE_SECONDS) * 1000;
+
+        if self.file.as_ref().is_some_and(|file| {
+            (file.byte_count >= max_file_bytes) || (arrival_timestamp >= (file.start_timestamp + max_file_ms))
+        }) {
+            self.close();
+        }
+
+        if self.file.is_none() {
+            self.file = Some(self.open(arrival_timestamp)?);
+        }
+
+        let file = self.file.as_mut().unwrap();
+
+        // A tuple of the fields is encoded exactly as RecordedEvent is, without copying the event into one
+        let bytes = bincode::DefaultOptions::new()
+            .serialize(&(arrival_timestamp, source, msg))
+            .map_err(|e| format!("Failed to encode event for recording: {e}"))?;
+
+        file.writer.write_all(&bytes).map_err(|e| format!("Failed to write recording {}: {e}", file.path))?;
+
+        file.byte_count += bytes.len() as u64;
+
+        self.event_count += 1;
+
+        Ok(())
+    }
+
+    // Starts a new file, first removing the oldest files beyond max_file_count
+    fn open(
+        &self,
+        start_timestamp : u64
+    ) -> Result<RecordingFile, String>
+    {
+        let directory = &self.config.directory;
+
+        if let Some(max_file_count) = self.config.max_file_count {
+            let mut names = std::fs::read_dir(directory)
+                .map_err(|e| format!("Failed to read recording directory {directory}: {e}"))?
+                .flatten()
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .filter(|name| name.starts_with("events-") && name.ends_with(".rec"))
+                .collect::<Vec<String>>();
+            names.sort();
+            let remove_count = (names.len() + 1).saturating_sub(max_file_count);
+            for name in names.into_iter().take(remove_count) {
+                std::fs::remove_file(format!("{directory}/{name}"))
+                    .map_err(|e| format!("Failed to remove recording {directory}/{name}: {e}"))?;
+            }
+        }
+
+        let path = format!("{directory}/events-{start_timestamp}.rec");
+
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open recording {path}: {e}"))?;
+
+        Ok(RecordingFile { path, writer : BufWriter::new(file), start_timestamp, byte_count : 0 })
+    }
+
+    // Writes out buffered events, so that a recording read while being written is at most this stale
+    pub fn flush(&mut self)
+    {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.writer.flush() {
+                eprintln!("ERROR: Failed to write recording {}: {e}; recording turned off", file.path);
+                self.file = None;
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn close(&mut self)
+    {
+        self.flush();
+
+        self.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const START_TIMESTAMP : u64 = 1_700_000_000_000;
+
+    const PEER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 1, 0, 1));
+
+    #[test]
+    fn recordings_rotate_and_read_back_in_order()
+    {
+        let directory = std::env::temp_dir().join(format!("txingest-recordings-{}", std::process::id()));
+        let directory = directory.to_string_lossy().to_string();
+        std::fs::remove_dir_all(&directory).ok();
+
+        // Each second of events goes to a file of its own, of which only the newest two are kept
+        let mut recorder = Recorder::new(RecordingConfig {
+            directory : directory.clone(),
+            enabled : None,
+            max_file_bytes : None,
+            max_file_seconds : Some(1),
+            max_file_count : Some(2)
+        })
+        .unwrap();
+        for index in 0..40 {
+            let timestamp = START_TIMESTAMP + (index * 100);
+            recorder.record(timestamp, "relay_feed", &TxIngestMsg::Failed { timestamp, peer_addr : PEER });
+        }
+        recorder.set_enabled(false);
+
+        let mut names = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        names.sort();
+        let events = names
+            .iter()
+            .flat_map(|name| RecordingReader::open(&format!("{directory}/{name}")).unwrap())
+            .collect::<Result<Vec<_>, String>>()
+            .unwrap();
+        std::fs::remove_dir_all(&directory).ok();
+
+        assert_eq!(names, [2, 3].map(|second| format!("events-{}.rec", START_TIMESTAMP + (second * 1000))));
+        assert_eq!(
+            events.iter().map(|event| event.arrival_timestamp).collect::<Vec<u64>>(),
+            (20..40).map(|index| START_TIMESTAMP + (index * 100)).collect::<Vec<u64>>()
+        );
+        assert!(events.iter().all(|event| (event.source == "relay_feed") &&
+            matches!(event.msg, TxIngestMsg::Failed { peer_addr, .. } if peer_addr == PEER)));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::config::Config;
+use crate::state::State;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGHUP : i32 = 1;
+
+// Set by the signal handler, so that the reload is done outside of it
+static SIGNALED : AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    fn signal(
+        signum : i32,
+        handler : extern "C" fn(i32)
+    ) -> usize;
+}
+
+// Only atomics are touched here, since little else is safe within a signal handler
+extern "C" fn handle_signal(_signum : i32)
+{
+    SIGNALED.store(true, Ordering::Relaxed);
+}
+
+// Installs the handler of SIGHUP, which requests a reload of the config file
+pub fn install_signal_handler()
+{
+    unsafe {
+        signal(SIGHUP, handle_signal);
+    }
+}
+
+// Returns true, once, if a reload has been requested by signal since the previous call
+pub fn take_signaled() -> bool
+{
+    SIGNALED.swap(false, Ordering::Relaxed)
+}
+
+// Re-reads the config file at config_path and swaps its classifications into state without losing accumulated
+// history, as requested by SIGHUP or the admin socket.  A config that fails to load or validate leaves state as it
+// was.  Returns a description of the changes as lines of text.
+pub fn reload(
+    state : &mut State,
+    config_path : &str
+) -> Result<String, String>
+{
+    let config = Config::load(config_path)
+        .map_err(|e| format!("ERROR: Failed to reload config file {config_path}: {e}; keeping current config"))?;
+
+    let mut lines =
+        format!("Reloaded classifications from {config_path}; other config changes take effect only on restart\n");
+
+    for change in state.reload_classifications(config) {
+        lines.push_str(&format!("  {change}\n"));
+    }
+
+    Ok(lines)
+}
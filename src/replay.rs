@@ -0,0 +1,137 @@
+use crate::config::Config;
+use crate::json_output::JsonOutputSink;
+use crate::listener::IngestCounters;
+use crate::plugin::SubprocessPlugin;
+use crate::recording::RecordingReader;
+use crate::sink::{self, MembershipOutput, Sink, SinkMessage, SinkReceiver, StdoutSink, DEFAULT_SINK_QUEUE_SIZE};
+use crate::state::{State, DEFAULT_PERIODIC_INTERVAL_MS};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Replays recordings against a config, driving State on a virtual clock that reads each event's arrival time as the
+// event is handled, and running every periodic pass that falls due between events.  If speed is present, events are
+// paced to arrive that many times faster than they were recorded; otherwise they are replayed as fast as possible.
+// Group changes are printed as the binary prints them, but no other sink (firewall, webhook, etc) is driven.  Returns
+// the members of each group once all tx have been flushed after the last event, for comparing thresholds offline.
+pub fn run(
+    mut config : Config,
+    paths : &[String],
+    speed : Option<f64>
+) -> Result<BTreeMap<String, Vec<IpAddr>>, String>
+{
+    let periodic_interval_ms = config.periodic_interval_ms.unwrap_or(DEFAULT_PERIODIC_INTERVAL_MS);
+
+    let mut sinks : Vec<Box<dyn Sink>> = match config.json_output.take() {
+        Some(json_output) if json_output.is_stdout() => {
+            vec![Box::new(MembershipOutput::without_snapshots(JsonOutputSink::new(json_output)))]
+        },
+        _ => vec![Box::new(MembershipOutput::without_snapshots(StdoutSink))]
+    };
+
+    // Sink events are applied between events rather than on a worker thread, so that none are dropped however fast
+    // the replay runs
+    let (sink_sender, sink_receiver) = sink::channel(config.sink_queue_size.unwrap_or(DEFAULT_SINK_QUEUE_SIZE));
+
+    let plugins = config.plugins.take();
+
+    let mut state = State::new(config, sink_sender, Arc::new(IngestCounters::default()));
+
+    for plugin in plugins.into_iter().flatten() {
+        state.register_plugin(Box::new(SubprocessPlugin::new(plugin)?));
+    }
+
+    let mut sources = BTreeMap::<String, Arc<str>>::new();
+
+    // Arrival time of the first event and when it was replayed, and virtual time of the next periodic pass
+    let mut start : Option<(u64, Instant)> = None;
+    let mut next_periodic_timestamp = 0;
+    let mut now = 0;
+    let mut event_count = 0_u64;
+
+    for path in paths {
+        for recorded_event in RecordingReader::open(path)? {
+            let recorded_event = recorded_event?;
+
+            // Events are never replayed earlier than a previous one, as the virtual clock must not go backwards
+            now = now.max(recorded_event.arrival_timestamp);
+
+            let (start_timestamp, start_instant) = *start.get_or_insert_with(|| {
+                next_periodic_timestamp = now + periodic_interval_ms;
+                (now, Instant::now())
+            });
+
+            while next_periodic_timestamp <= now {
+                state.periodic(next_periodic_timestamp);
+                next_periodic_timestamp += periodic_interval_ms;
+                apply_sink_events(&sink_receiver, &mut sinks);
+            }
+
+            if let Some(speed) = speed {
+                let due = start_instant + Duration::from_secs_f64(((now - start_timestamp) as f64) / (speed * 1000.0));
+                std::thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+
+            let source = sources
+                .entry(recorded_event.source)
+                .or_insert_with_key(|source| Arc::<str>::from(source.as_str()))
+                .clone();
+
+            state.handle(&source, recorded_event.msg);
+            state.flush_due_tx(now);
+            apply_sink_events(&sink_receiver, &mut sinks);
+
+            event_count += 1;
+        }
+    }
+
+    // Run on until every tx has been flushed, so that fee classifications see all of them
+    let end = now + state.tx_retention_duration_ms + periodic_interval_ms;
+    while (start.is_some()) && (next_periodic_timestamp <= end) {
+        state.periodic(next_periodic_timestamp);
+        next_periodic_timestamp += periodic_interval_ms;
+        apply_sink_events(&sink_receiver, &mut sinks);
+    }
+
+    eprintln!(
+        "Replayed {event_count} events spanning {} seconds",
+        start.map(|(start_timestamp, _)| (now - start_timestamp) / 1000).unwrap_or(0)
+    );
+
+    Ok(state
+        .group_member_counts()
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(group_name, _)| {
+            let mut members = state
+                .group_members(&group_name)
+                .into_iter()
+                .flatten()
+                .map(|(ip_addr, _)| ip_addr)
+                .collect::<Vec<IpAddr>>();
+            members.sort();
+            (group_name, members)
+        })
+        .collect())
+}
+
+fn apply_sink_events(
+    sink_receiver : &SinkReceiver,
+    sinks : &mut [Box<dyn Sink>]
+)
+{
+    for event in sink_receiver.drain().flat_map(SinkMessage::into_events) {
+        for sink in sinks.iter_mut() {
+            if let Err(e) = sink.apply(&event) {
+                eprintln!("Sink failed to apply event: {e}");
+            }
+        }
+    }
+
+    for sink in sinks.iter_mut() {
+        if let Err(e) = sink.flush() {
+            eprintln!("Sink failed to flush: {e}");
+        }
+    }
+}
@@ -0,0 +1,1355 @@
+// Scripted scenarios for testing configs: synthetic event sequences are fed to a State on a virtual clock, and the
+// resulting group memberships can then be inspected or asserted.  Also provides a library of canned scenarios
+// exercising common traffic patterns, which can be run against any config with the scenario subcommand.
+
+use crate::config::Config;
+use crate::listener::IngestCounters;
+use crate::plugin::ClassifierPlugin;
+use crate::sink::{self, GroupChange, SinkMessage, SinkReceiver, DEFAULT_SINK_QUEUE_SIZE};
+use crate::state::{State, DEFAULT_PERIODIC_INTERVAL_MS};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+// Virtual time at which every scenario starts: a realistic wall clock time, so that durations subtracted from the
+// current time do not underflow
+const SCENARIO_START_TIMESTAMP : u64 = 1_700_000_000_000;
+
+// A peer that submits a modest number of tx which all land, and so should never be classified into any group
+pub const HONEST_PEER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+// Stake of HONEST_PEER, in lamports
+pub const HONEST_PEER_STAKE : u64 = 1_000_000_000_000;
+
+// Number of peers flooding connections in the spam_flood scenario
+pub const SPAMMER_COUNT : u8 = 20;
+
+// A relayer forwarding tx on behalf of others, none of which land, in the zero_fee_relayer scenario
+pub const RELAYER : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 2, 0, 1));
+
+// A staked validator whose reported pubkey keeps changing, in the staked_validator_churn scenario
+pub const CHURNING_VALIDATOR : IpAddr = IpAddr::V4(Ipv4Addr::new(10, 3, 0, 1));
+
+// Stake of CHURNING_VALIDATOR, in lamports
+pub const CHURNING_VALIDATOR_STAKE : u64 = 50_000_000_000_000;
+
+// A State driven by scripted events on a virtual clock.  Event methods submit an event at the current virtual time;
+// advance moves the clock forward, running every periodic pass that falls due along the way.
+pub struct Scenario
+{
+    state : State,
+
+    // Current virtual time (in milliseconds)
+    now : u64,
+
+    periodic_interval_ms : u64,
+
+    // Virtual time at which the next periodic pass is due
+    next_periodic_timestamp : u64,
+
+    // Source name given to every event
+    source : Arc<str>,
+
+    // Receives the events sent by state to sinks
+    sink_receiver : SinkReceiver,
+
+    // Events sent to sinks so far
+    sink_events : Vec<GroupChange>,
+
+    // Used to generate a distinct signature for every submitted tx, and a distinct pubkey for every generated pubkey
+    next_unique : u64
+}
+
+impl Scenario
+{
+    // Creates a scenario from a config that has already been validated
+    pub fn new(config : Config) -> Self
+    {
+        let periodic_interval_ms = config.periodic_interval_ms.unwrap_or(DEFAULT_PERIODIC_INTERVAL_MS);
+
+        let (sink_sender, sink_receiver) = sink::channel(config.sink_queue_size.unwrap_or(DEFAULT_SINK_QUEUE_SIZE));
+
+        Self {
+            state : State::new(config, sink_sender, Arc::new(IngestCounters::default())),
+            now : SCENARIO_START_TIMESTAMP,
+            periodic_interval_ms,
+            next_periodic_timestamp : SCENARIO_START_TIMESTAMP + periodic_interval_ms,
+            source : Arc::from(crate::config::DEFAULT_INGEST_SOURCE),
+            sink_receiver,
+            sink_events : vec![],
+            next_unique : 1
+        }
+    }
+
+    // Creates a scenario from a JSON config, which is parsed and validated exactly as a config file would be
+    pub fn from_json(json : &str) -> Result<Self, String>
+    {
+        let mut config = Config::parse(json)?;
+
+        config.validate()?;
+
+        Ok(Self::new(config))
+    }
+
+    // Tags subsequent events with the named ingest source
+    pub fn with_source(
+        &mut self,
+        source : &str
+    ) -> &mut Self
+    {
+        self.source = Arc::from(source);
+        self
+    }
+
+    // Registers a classifier plugin, which is fed every subsequent event
+    pub fn register_plugin(
+        &mut self,
+        plugin : Box<dyn ClassifierPlugin>
+    ) -> &mut Self
+    {
+        self.state.register_plugin(plugin);
+        self
+    }
+
+    // Moves the virtual clock forward by duration_ms, running each periodic pass that falls due
+    pub fn advance(
+        &mut self,
+        duration_ms : u64
+    ) -> &mut Self
+    {
+        let end = self.now + duration_ms;
+
+        while self.next_periodic_timestamp <= end {
+            self.now = self.next_periodic_timestamp;
+            // Periodic passes run on the internal clock, which a re-anchored jump does not move
+            self.state.periodic(self.now.saturating_add_signed(-self.state.clock_offset_ms));
+            self.next_periodic_timestamp += self.periodic_interval_ms;
+            self.receive_sink_events();
+        }
+
+        self.now = end;
+
+        self
+    }
+
+    // Jumps the virtual clock by jump_ms (negative if backward), telling state of the jump as the binary does on
+    // detecting it
+    pub fn jump_clock(
+        &mut self,
+        jump_ms : i64
+    ) -> &mut Self
+    {
+        self.now = self.now.saturating_add_signed(jump_ms);
+        self.next_periodic_timestamp = self.next_periodic_timestamp.saturating_add_signed(jump_ms);
+        self.state.clock_jumped(jump_ms);
+        self
+    }
+
+    // Swaps in the classifications of a JSON config, which is parsed and validated exactly as a reloaded config file
+    // would be
+    pub fn reload_config(
+        &mut self,
+        json : &str
+    ) -> Result<&mut Self, String>
+    {
+        let mut config = Config::parse(json)?;
+
+        config.validate()?;
+
+        self.state.reload_classifications(config);
+
+        Ok(self)
+    }
+
+    // An event built by msg from the current virtual time, handled as the classifier handles every event it receives
+    // (feeding it to plugins too), rather than just applied to state as the other event methods do
+    pub fn handle(
+        &mut self,
+        msg : impl FnOnce(u64) -> TxIngestMsg
+    ) -> &mut Self
+    {
+        self.state.handle(&self.source, msg(self.now));
+        self
+    }
+
+    // A connection from peer_addr that was refused for exceeding connection limits
+    pub fn exceed(
+        &mut self,
+        peer_addr : IpAddr,
+        peer_pubkey : Option<Pubkey>,
+        stake : u64
+    ) -> &mut Self
+    {
+        self.state.exceeded(&self.source, self.now, peer_addr, peer_pubkey, stake);
+        self
+    }
+
+    // A connection from peer_addr that failed
+    pub fn fail(
+        &mut self,
+        peer_addr : IpAddr
+    ) -> &mut Self
+    {
+        self.state.failed(&self.source, self.now, peer_addr, None);
+        self
+    }
+
+    // A connection from peer_addr started
+    pub fn connect(
+        &mut self,
+        peer_addr : IpAddr,
+        peer_pubkey : Option<Pubkey>,
+        stake : u64
+    ) -> &mut Self
+    {
+        self.state.started(&self.source, self.now, peer_addr, peer_pubkey, stake);
+        self
+    }
+
+    // A connection from peer_addr finished
+    pub fn disconnect(
+        &mut self,
+        peer_addr : IpAddr
+    ) -> &mut Self
+    {
+        self.state.finished(&self.source, self.now, peer_addr);
+        self
+    }
+
+    // A vote tx submitted by peer_addr
+    pub fn vote(
+        &mut self,
+        peer_addr : IpAddr
+    ) -> &mut Self
+    {
+        self.state.votetx(self.now, peer_addr);
+        self
+    }
+
+    // A user tx with a new signature submitted by peer_addr; returns the signature, so that the tx may later be landed
+    // or submitted again by other peers
+    pub fn submit(
+        &mut self,
+        peer_addr : IpAddr
+    ) -> Signature
+    {
+        let signature = self.unique_signature();
+        self.submit_signature(peer_addr, signature);
+        signature
+    }
+
+    // A user tx with the given signature submitted by peer_addr
+    pub fn submit_signature(
+        &mut self,
+        peer_addr : IpAddr,
+        signature : Signature
+    ) -> &mut Self
+    {
+        self.state.usertx(&self.source, self.now, peer_addr, signature);
+        self
+    }
+
+    // The tx with the given signature landed, paying fee lamports for cu_used of cu_limit compute units
+    pub fn land(
+        &mut self,
+        signature : Signature,
+        cu_limit : u64,
+        cu_used : u64,
+        fee : u64
+    ) -> &mut Self
+    {
+        self.state.fee(self.now, signature, cu_limit, cu_used, fee);
+        self
+    }
+
+    // The tx with the given signature is reported to have landed in the block of slot, one of ours if our_block
+    pub fn landed(
+        &mut self,
+        signature : Signature,
+        slot : u64,
+        our_block : bool
+    ) -> &mut Self
+    {
+        self.state.landed(&signature, slot, our_block);
+        self
+    }
+
+    // Our leader slots begin
+    pub fn begin_leader(&mut self) -> &mut Self
+    {
+        self.state.begin_leader(self.now);
+        self
+    }
+
+    // Our leader slots end
+    pub fn end_leader(&mut self) -> &mut Self
+    {
+        self.state.end_leader(self.now);
+        self
+    }
+
+    // Returns a pubkey distinct from every other pubkey returned by this scenario
+    pub fn unique_pubkey(&mut self) -> Pubkey
+    {
+        // Pubkey is serialized as its raw bytes
+        bincode::deserialize(&self.unique_bytes::<32>()).unwrap()
+    }
+
+    fn unique_signature(&mut self) -> Signature
+    {
+        // Signature is serialized as its raw bytes
+        bincode::deserialize(&self.unique_bytes::<64>()).unwrap()
+    }
+
+    fn unique_bytes<const N: usize>(&mut self) -> [u8; N]
+    {
+        let mut bytes = [0_u8; N];
+        bytes[..8].copy_from_slice(&self.next_unique.to_le_bytes());
+        self.next_unique += 1;
+        bytes
+    }
+
+    // Returns the current members of group_name, in sorted order
+    pub fn members(
+        &self,
+        group_name : &str
+    ) -> Vec<IpAddr>
+    {
+        let mut members = self
+            .state
+            .group_members(group_name)
+            .into_iter()
+            .flatten()
+            .map(|(ip_addr, _)| ip_addr)
+            .collect::<Vec<IpAddr>>();
+
+        members.sort();
+
+        members
+    }
+
+    // Returns the current members of every non-empty group, by group name
+    pub fn all_members(&self) -> BTreeMap<String, Vec<IpAddr>>
+    {
+        self.state
+            .group_member_counts()
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(group_name, _)| {
+                let members = self.members(&group_name);
+                (group_name, members)
+            })
+            .collect()
+    }
+
+    pub fn is_member(
+        &self,
+        group_name : &str,
+        ip_addr : IpAddr
+    ) -> bool
+    {
+        self.members(group_name).contains(&ip_addr)
+    }
+
+    // Panics unless ip_addr is a member of group_name
+    pub fn assert_member(
+        &self,
+        group_name : &str,
+        ip_addr : IpAddr
+    ) -> &Self
+    {
+        assert!(
+            self.is_member(group_name, ip_addr),
+            "Expected {ip_addr} to be a member of {group_name}; members are {:?}",
+            self.members(group_name)
+        );
+        self
+    }
+
+    // Panics if ip_addr is a member of group_name
+    pub fn assert_not_member(
+        &self,
+        group_name : &str,
+        ip_addr : IpAddr
+    ) -> &Self
+    {
+        assert!(!self.is_member(group_name, ip_addr), "Expected {ip_addr} not to be a member of {group_name}");
+        self
+    }
+
+    // Returns every event sent to sinks so far
+    pub fn sink_events(&mut self) -> &[GroupChange]
+    {
+        self.receive_sink_events();
+        &self.sink_events
+    }
+
+    fn receive_sink_events(&mut self)
+    {
+        self.sink_events.extend(self.sink_receiver.drain().flat_map(SinkMessage::into_events));
+    }
+}
+
+// A named, scripted sequence of events exercising a common traffic pattern
+pub struct CannedScenario
+{
+    pub name : &'static str,
+
+    pub description : &'static str,
+
+    pub run : fn(&mut Scenario)
+}
+
+pub const CANNED_SCENARIOS : &[CannedScenario] = &[
+    CannedScenario {
+        name : "spam_flood",
+        description : "SPAMMER_COUNT unstaked peers each have 50 connections per second refused or failed for 30 \
+                       seconds",
+        run : spam_flood
+    },
+    CannedScenario {
+        name : "zero_fee_relayer",
+        description : "RELAYER submits 20 tx per second for 30 seconds, none of which land",
+        run : zero_fee_relayer
+    },
+    CannedScenario {
+        name : "staked_validator_churn",
+        description : "CHURNING_VALIDATOR reconnects every 5 seconds for 30 seconds, with a new pubkey each time",
+        run : staked_validator_churn
+    }
+];
+
+// Returns the address of the spammer with the given index, which is less than SPAMMER_COUNT
+pub fn spammer(index : u8) -> IpAddr
+{
+    IpAddr::V4(Ipv4Addr::new(10, 1, 0, index + 1))
+}
+
+// HONEST_PEER connects and submits a tx that lands, each second for duration_ms; every other scenario runs this
+// alongside its own traffic, and then allows time for the last tx to be flushed
+fn honest_traffic(
+    scenario : &mut Scenario,
+    duration_ms : u64,
+    mut each_second : impl FnMut(&mut Scenario)
+)
+{
+    for _ in 0..(duration_ms / 1000) {
+        scenario.connect(HONEST_PEER, None, HONEST_PEER_STAKE);
+        let signature = scenario.submit(HONEST_PEER);
+        scenario.land(signature, 200_000, 150_000, 100_000);
+        scenario.disconnect(HONEST_PEER);
+        each_second(scenario);
+        scenario.advance(1000);
+    }
+}
+
+pub fn spam_flood(scenario : &mut Scenario)
+{
+    honest_traffic(scenario, 30 * 1000, |scenario| {
+        for _ in 0..40 {
+            for index in 0..SPAMMER_COUNT {
+                scenario.exceed(spammer(index), None, 0);
+            }
+        }
+        for _ in 0..10 {
+            for index in 0..SPAMMER_COUNT {
+                scenario.fail(spammer(index));
+            }
+        }
+    });
+}
+
+pub fn zero_fee_relayer(scenario : &mut Scenario)
+{
+    honest_traffic(scenario, 30 * 1000, |scenario| {
+        scenario.connect(RELAYER, None, 0);
+        for _ in 0..20 {
+            scenario.submit(RELAYER);
+        }
+        scenario.disconnect(RELAYER);
+    });
+}
+
+pub fn staked_validator_churn(scenario : &mut Scenario)
+{
+    let mut elapsed_ms = 0;
+
+    honest_traffic(scenario, 30 * 1000, |scenario| {
+        if (elapsed_ms % 5000) == 0 {
+            let pubkey = scenario.unique_pubkey();
+            scenario.connect(CHURNING_VALIDATOR, Some(pubkey), CHURNING_VALIDATOR_STAKE);
+            scenario.vote(CHURNING_VALIDATOR);
+            scenario.disconnect(CHURNING_VALIDATOR);
+        }
+        elapsed_ms += 1000;
+    });
+}
+
+// Runs the named canned scenario against config, followed by enough time for submitted tx to be flushed, and returns
+// the resulting members of every group
+pub fn run_canned(
+    config : Config,
+    name : &str
+) -> Result<BTreeMap<String, Vec<IpAddr>>, String>
+{
+    let canned = CANNED_SCENARIOS.iter().find(|canned| canned.name == name).ok_or_else(|| {
+        format!(
+            "Unknown scenario {name}; must be one of: {}",
+            CANNED_SCENARIOS.iter().map(|canned| canned.name).collect::<Vec<_>>().join(", ")
+        )
+    })?;
+
+    let mut scenario = Scenario::new(config);
+
+    (canned.run)(&mut scenario);
+
+    let tx_retention_duration_ms = scenario.state.tx_retention_duration_ms;
+    scenario.advance(tx_retention_duration_ms + scenario.periodic_interval_ms);
+
+    Ok(scenario.all_members())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const CONFIG : &str = r#"{
+        "failed_exceeded_quic_connections" : {
+            "group_name" : "spammers",
+            "thresholds" : [
+                { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 100, "duration_ms" : 10000 }
+            ]
+        },
+        "zero_fee_share" : {
+            "group_name" : "zero_fee",
+            "attribution_window_ms" : 5000,
+            "thresholds" : [
+                {
+                    "min_value_count" : 50,
+                    "value_operation" : "average",
+                    "threshold_type" : "greater_than_or_equal_to",
+                    "value" : 90,
+                    "duration_ms" : 60000
+                }
+            ]
+        },
+        "pubkey_changes" : {
+            "group_name" : "pubkey_churn",
+            "thresholds" : [
+                { "value_operation" : "sum", "threshold_type" : "greater_than_or_equal_to", "value" : 3, "duration_ms" : 60000 }
+            ]
+        }
+    }"#;
+
+    // A zero_fee_share classification into group "zero_fee" of peers with at least 90% of their submissions (of at
+    // least 10 within an hour) credited with zero fee
+    const ZERO_FEE_SHARE : &str = r#""zero_fee_share" : {
+        "group_name" : "zero_fee",
+        "thresholds" : [
+            {
+                "min_value_count" : 10,
+                "value_operation" : "average",
+                "threshold_type" : "greater_than_or_equal_to",
+                "value" : 90,
+                "duration_ms" : 3600000
+            }
+        ]
+    }"#;
+
+    // Config, after the given top level fields (each followed by a comma), of a useless_quic_connections classification
+    // of peers making more than value useless connections within duration_ms into group "useless" for 60 seconds
+    fn useless_config(
+        fields : &str,
+        value : u64,
+        duration_ms : u64
+    ) -> String
+    {
+        format!(
+            r#"{{
+                {fields}
+                "useless_quic_connections" : {{
+                    "group_name" : "useless",
+                    "group_expiration_seconds" : 60,
+                    "thresholds" : [
+                        {{ "value_operation" : "sum", "threshold_type" : "greater_than", "value" : {value},
+                           "duration_ms" : {duration_ms} }}
+                    ]
+                }}
+            }}"#
+        )
+    }
+
+    // Reconnects peer ten times once its current connection has lasted 3 seconds, each time closing a connection over
+    // which it sent no tx, and then advances 2 seconds for the connections to be classified
+    fn reconnect_ten_times(
+        scenario : &mut Scenario,
+        peer : IpAddr
+    )
+    {
+        scenario.advance(3 * 1000);
+        for _ in 0..10 {
+            scenario.disconnect(peer).connect(peer, None, 0);
+        }
+        scenario.advance(2 * 1000);
+    }
+
+    #[test]
+    fn built_config_classifies_like_json_config()
+    {
+        use crate::builder::{ClassificationBuilder, ConfigBuilder, ThresholdBuilder};
+        use crate::threshold::{ThresholdType, ValueOperation};
+
+        let config = ConfigBuilder::new()
+            .failed_exceeded_quic_connections(
+                ClassificationBuilder::new().group_name("spammers").threshold(ThresholdBuilder::new(
+                    ValueOperation::Sum,
+                    ThresholdType::GreaterThan,
+                    100,
+                    10 * 1000
+                ))
+            )
+            .build()
+            .unwrap();
+
+        let mut scenario = Scenario::new(config);
+        spam_flood(&mut scenario);
+        scenario.advance(10 * 1000);
+
+        for index in 0..SPAMMER_COUNT {
+            scenario.assert_member("spammers", spammer(index));
+        }
+        scenario.assert_not_member("spammers", HONEST_PEER);
+    }
+
+    #[test]
+    fn channel_subscription_receives_group_changes()
+    {
+        use crate::sink::Sink;
+        use crate::subscription::{ChannelSink, ClassificationEvent};
+
+        let mut scenario = run(spam_flood);
+
+        let (mut channel_sink, receiver) = ChannelSink::subscribe(1000);
+        for event in scenario.sink_events() {
+            channel_sink.apply(event).unwrap();
+        }
+
+        let added = receiver
+            .try_iter()
+            .filter_map(|event| match event {
+                ClassificationEvent::MemberAdded { group_name, ip_addr, .. } => Some((group_name, ip_addr)),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(added.len(), SPAMMER_COUNT as usize);
+        assert!(added.contains(&("spammers".to_string(), spammer(0))));
+    }
+
+    #[test]
+    fn identities_behind_shared_address_are_classified_separately()
+    {
+        use crate::builder::{ClassificationBuilder, ConfigBuilder, ThresholdBuilder};
+        use crate::threshold::{ThresholdType, ValueOperation};
+
+        let shared = spammer(0);
+
+        let mut scenario = Scenario::new(
+            ConfigBuilder::new()
+                .failed_exceeded_quic_connections(
+                    ClassificationBuilder::new().group_name("spammers").attribute_by_pubkey(true).threshold(
+                        ThresholdBuilder::new(ValueOperation::Sum, ThresholdType::GreaterThan, 100, 10 * 1000)
+                    )
+                )
+                .build()
+                .unwrap()
+        );
+        let (noisy, quiet) = (scenario.unique_pubkey(), scenario.unique_pubkey());
+
+        // Combined, the two identities exceed the threshold, but neither does alone
+        for _ in 0..60 {
+            scenario.exceed(shared, Some(noisy), 0).exceed(shared, Some(quiet), 0);
+        }
+        scenario.advance(1000);
+        scenario.assert_not_member("spammers", shared);
+
+        for _ in 0..60 {
+            scenario.exceed(shared, Some(noisy), 0);
+        }
+        scenario.advance(1000);
+        scenario.assert_member("spammers", shared);
+
+        // Failures that carry no pubkey are the address's, not those of the identity last reported from it
+        let other = spammer(1);
+        scenario.exceed(other, Some(quiet), 0);
+        for _ in 0..150 {
+            scenario.fail(other);
+        }
+        scenario.advance(1000);
+
+        let added_pubkeys = scenario
+            .sink_events()
+            .iter()
+            .filter_map(|event| match event {
+                GroupChange::Add { pubkey, .. } => Some(*pubkey),
+                _ => None
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(added_pubkeys, vec![Some(noisy), None]);
+    }
+
+    #[test]
+    fn plugin_decisions_change_group_membership()
+    {
+        use crate::plugin::PluginDecision;
+        use std::collections::HashMap;
+
+        // Adds peers to "flaky" after their third failed connection, and removes them once they finish a connection
+        #[derive(Default)]
+        struct FlakyPlugin
+        {
+            failed_counts : HashMap<IpAddr, u64>,
+
+            decisions : Vec<PluginDecision>
+        }
+
+        impl ClassifierPlugin for FlakyPlugin
+        {
+            fn name(&self) -> &str
+            {
+                "flaky"
+            }
+
+            fn handle(
+                &mut self,
+                _source : &Arc<str>,
+                msg : &TxIngestMsg
+            )
+            {
+                let group_name = "flaky".to_string();
+                match msg {
+                    TxIngestMsg::Failed { peer_addr, .. } => {
+                        let failed_count = self.failed_counts.entry(*peer_addr).or_default();
+                        *failed_count += 1;
+                        if *failed_count == 3 {
+                            self.decisions.push(PluginDecision::Add {
+                                group_name,
+                                ip_addr : *peer_addr,
+                                pubkey : None,
+                                ttl_ms : Some(60 * 1000)
+                            });
+                        }
+                    },
+                    TxIngestMsg::Finished { peer_addr, .. } => {
+                        self.failed_counts.remove(peer_addr);
+                        self.decisions.push(PluginDecision::Remove { group_name, ip_addr : *peer_addr });
+                    },
+                    _ => ()
+                }
+            }
+
+            fn classify(
+                &mut self,
+                _now : u64
+            ) -> Vec<PluginDecision>
+            {
+                std::mem::take(&mut self.decisions)
+            }
+        }
+
+        let mut scenario = Scenario::from_json("{}").unwrap();
+        scenario.register_plugin(Box::<FlakyPlugin>::default());
+
+        let (flaky, steady) = (spammer(0), spammer(1));
+        let failed = |peer_addr| move |timestamp| TxIngestMsg::Failed { timestamp, peer_addr };
+        scenario.handle(failed(flaky)).handle(failed(flaky)).handle(failed(flaky)).handle(failed(steady));
+        scenario.advance(1000);
+        scenario.assert_member("flaky", flaky).assert_not_member("flaky", steady);
+
+        let membership = scenario.state.group_members("flaky").unwrap().into_iter().next().unwrap().1;
+        assert_eq!(&*membership.added_by, "plugin:flaky");
+
+        scenario
+            .handle(|timestamp| TxIngestMsg::Started { timestamp, peer_addr : flaky, peer_pubkey : None, stake : 0 })
+            .handle(|timestamp| TxIngestMsg::Finished { timestamp, peer_addr : flaky });
+        scenario.advance(1000);
+        scenario.assert_not_member("flaky", flaky);
+    }
+
+    fn run(canned : fn(&mut Scenario)) -> Scenario
+    {
+        let mut scenario = Scenario::from_json(CONFIG).unwrap();
+        canned(&mut scenario);
+        scenario.advance(10 * 1000);
+        scenario
+    }
+
+    #[test]
+    fn spam_flood_classifies_every_spammer()
+    {
+        let mut scenario = run(spam_flood);
+
+        for index in 0..SPAMMER_COUNT {
+            scenario.assert_member("spammers", spammer(index));
+        }
+        scenario.assert_not_member("spammers", HONEST_PEER);
+
+        let add_count = scenario.sink_events().iter().filter(|event| matches!(event, GroupChange::Add { .. })).count();
+        assert_eq!(add_count, SPAMMER_COUNT as usize);
+    }
+
+    #[test]
+    fn zero_fee_relayer_classifies_only_the_relayer()
+    {
+        let scenario = run(zero_fee_relayer);
+
+        scenario.assert_member("zero_fee", RELAYER).assert_not_member("zero_fee", HONEST_PEER);
+        assert!(scenario.members("spammers").is_empty());
+    }
+
+    #[test]
+    fn staked_validator_churn_classifies_pubkey_churn()
+    {
+        let scenario = run(staked_validator_churn);
+
+        scenario.assert_member("pubkey_churn", CHURNING_VALIDATOR).assert_not_member("pubkey_churn", HONEST_PEER);
+    }
+
+    #[test]
+    fn members_expire_on_the_virtual_clock()
+    {
+        let mut scenario = Scenario::from_json(&useless_config("", 5, 10 * 1000)).unwrap();
+
+        let peer = spammer(0);
+        scenario.connect(peer, None, 0);
+        reconnect_ten_times(&mut scenario, peer);
+        assert_eq!(scenario.all_members().get("useless"), Some(&vec![peer]));
+
+        scenario.advance(70 * 1000);
+        assert!(!scenario.is_member("useless", peer));
+    }
+
+    #[test]
+    fn removals_of_a_batch_outgrowing_the_sink_queue_are_queued()
+    {
+        let mut scenario = Scenario::from_json(&useless_config(r#""sink_queue_size" : 1,"#, 5, 10 * 1000)).unwrap();
+
+        // All three peers are classified in the same periodic pass, whose batch holds more events than the queue
+        let peers = [spammer(0), spammer(1), spammer(2)];
+        for peer in peers {
+            scenario.connect(peer, None, 0);
+        }
+        scenario.advance(3 * 1000);
+        for peer in peers {
+            for _ in 0..10 {
+                scenario.disconnect(peer).connect(peer, None, 0);
+            }
+        }
+        scenario.advance(2 * 1000);
+        assert_eq!(scenario.members("useless").len(), peers.len());
+
+        // Additions beyond the room in the queue are dropped, but none of the removals are
+        scenario.advance(70 * 1000);
+        let count = |scenario : &mut Scenario, is_match : fn(&GroupChange) -> bool| {
+            scenario.sink_events().iter().filter(|event| is_match(event)).count()
+        };
+        assert_eq!(count(&mut scenario, |event| matches!(event, GroupChange::Add { .. })), 1);
+        assert_eq!(count(&mut scenario, |event| matches!(event, GroupChange::Remove { .. })), peers.len());
+    }
+
+    #[test]
+    fn members_outlive_a_reanchored_clock_jump()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "useless_quic_connections" : {
+                    "group_name" : "useless",
+                    "group_expiration_seconds" : 60,
+                    "thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 5, "duration_ms" : 10000 }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+
+        let peer = spammer(0);
+        scenario.connect(peer, None, 0).advance(3 * 1000);
+        for _ in 0..10 {
+            scenario.disconnect(peer).connect(peer, None, 0);
+        }
+        scenario.advance(2 * 1000);
+
+        // An hour's jump forward does not expire the member
+        scenario.jump_clock(60 * 60 * 1000).advance(2 * 1000);
+        assert!(scenario.is_member("useless", peer));
+
+        // Members added after the jump are sent to sinks with expirations on the system clock
+        let other = spammer(1);
+        scenario.connect(other, None, 0);
+        reconnect_ten_times(&mut scenario, other);
+        let now = scenario.now;
+        assert!(scenario.sink_events().iter().any(|event| matches!(
+            event,
+            GroupChange::Add { ip_addr, expiration, .. } if (*ip_addr == other) && (*expiration > now)
+        )));
+
+        scenario.advance(70 * 1000);
+        assert!(!scenario.is_member("useless", peer));
+    }
+
+    #[test]
+    fn reloaded_threshold_applies_to_values_accumulated_before_reload()
+    {
+        let mut scenario = Scenario::from_json(&useless_config("", 20, 60 * 1000)).unwrap();
+
+        let peer = spammer(0);
+        scenario.connect(peer, None, 0);
+        reconnect_ten_times(&mut scenario, peer);
+        assert!(!scenario.is_member("useless", peer));
+
+        // The lowered threshold is met by the connections made before the reload, without any more
+        scenario.reload_config(&useless_config("", 5, 60 * 1000)).unwrap().advance(2 * 1000);
+        assert!(scenario.is_member("useless", peer));
+    }
+
+    #[test]
+    fn vote_only_peers_are_useless_when_only_user_tx_count()
+    {
+        for (useless_quic_connection_tx, is_useless) in [("all", false), ("user", true)] {
+            let fields = format!(r#""useless_quic_connection_tx" : "{useless_quic_connection_tx}","#);
+            let mut scenario = Scenario::from_json(&useless_config(&fields, 5, 10 * 1000)).unwrap();
+
+            let peer = spammer(0);
+            scenario.connect(peer, None, 0).vote(peer);
+            reconnect_ten_times(&mut scenario, peer);
+
+            assert_eq!(scenario.is_member("useless", peer), is_useless, "{useless_quic_connection_tx}");
+        }
+    }
+
+    #[test]
+    fn duplicate_submissions_from_a_scoped_source_are_classified()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "ingest_sources" : [ { "name" : "relay_feed", "addresses" : [ "192.0.2.1" ] } ],
+                "zero_fee_share" : {
+                    "group_name" : "duplicators",
+                    "attribution_window_ms" : 5000,
+                    "sources" : [ "relay_feed" ],
+                    "thresholds" : [
+                        { "value_operation" : "average", "threshold_type" : "greater_than", "value" : 50, "duration_ms" : 60000 }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+
+        for _ in 0..10 {
+            let signature = scenario.with_source("default").submit(HONEST_PEER);
+            scenario.land(signature, 200_000, 150_000, 100_000);
+            scenario.with_source("relay_feed").submit_signature(RELAYER, signature).advance(1000);
+        }
+        scenario.advance(10 * 1000);
+
+        scenario.assert_member("duplicators", RELAYER).assert_not_member("duplicators", HONEST_PEER);
+    }
+
+    #[test]
+    fn late_fees_correct_zero_fee_share()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "zero_fee_share" : {
+                    "group_name" : "fee_payers",
+                    "first_submitter_grace_ms" : 100,
+                    "thresholds" : [
+                        {
+                            "min_value_count" : 10,
+                            "value_operation" : "average",
+                            "threshold_type" : "less_than_or_equal_to",
+                            "value" : 10,
+                            "duration_ms" : 3600000
+                        }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+
+        // The relayer submits every tx at the same time as the honest peer, tying with it as first submitter
+        let signatures = (0..10).map(|_| scenario.submit(HONEST_PEER)).collect::<Vec<Signature>>();
+        for signature in &signatures {
+            scenario.submit_signature(RELAYER, *signature);
+        }
+
+        // Every tx is flushed before its fee is known, and so is at first credited with zero fee
+        let tx_retention_duration_ms = scenario.state.tx_retention_duration_ms;
+        scenario.advance(tx_retention_duration_ms + scenario.periodic_interval_ms);
+        scenario.assert_not_member("fee_payers", HONEST_PEER).assert_not_member("fee_payers", RELAYER);
+
+        for signature in signatures {
+            scenario.land(signature, 200_000, 150_000, 100_000);
+        }
+        scenario.advance(10 * 1000);
+
+        scenario.assert_member("fee_payers", HONEST_PEER).assert_member("fee_payers", RELAYER);
+    }
+
+    #[test]
+    fn tx_landed_elsewhere_are_not_credited_with_zero_fee()
+    {
+        let mut scenario = Scenario::from_json(&format!("{{ {ZERO_FEE_SHARE} }}")).unwrap();
+
+        for slot in 0..10 {
+            let signature = scenario.submit(HONEST_PEER);
+            scenario.submit(RELAYER);
+            scenario.landed(signature, slot, false);
+        }
+
+        let tx_retention_duration_ms = scenario.state.tx_retention_duration_ms;
+        scenario.advance(tx_retention_duration_ms + scenario.periodic_interval_ms);
+
+        scenario.assert_member("zero_fee", RELAYER).assert_not_member("zero_fee", HONEST_PEER);
+        assert_eq!(scenario.state.landed_elsewhere_count, 10);
+    }
+
+    #[test]
+    fn peers_below_fee_min_stake_are_left_out_of_fee_classifications()
+    {
+        let mut scenario = Scenario::from_json(&format!(r#"{{ "fee_min_stake" : 1, {ZERO_FEE_SHARE} }}"#)).unwrap();
+
+        // Neither peer's tx land, but only the staked peer is classified
+        scenario.connect(HONEST_PEER, None, HONEST_PEER_STAKE).connect(RELAYER, None, 0);
+        for _ in 0..10 {
+            scenario.submit(HONEST_PEER);
+            scenario.submit(RELAYER);
+        }
+
+        let tx_retention_duration_ms = scenario.state.tx_retention_duration_ms;
+        scenario.advance(tx_retention_duration_ms + scenario.periodic_interval_ms);
+
+        scenario.assert_member("zero_fee", HONEST_PEER).assert_not_member("zero_fee", RELAYER);
+        assert_eq!(scenario.state.fee_stake_excluded_count, 10);
+    }
+
+    #[test]
+    fn decayed_values_forget_old_failures()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "failed_exceeded_quic_connections" : {
+                    "group_name" : "spammers",
+                    "decay_half_life_ms" : 10000,
+                    "thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 50, "duration_ms" : 60000 }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+
+        // The first 40 failures of the relayer decay to 10 by the time of its next 40, which are not enough to
+        // exceed the threshold, although 80 failures fall within duration_ms
+        for _ in 0..40 {
+            scenario.fail(RELAYER);
+        }
+        scenario.advance(20 * 1000);
+        for _ in 0..40 {
+            scenario.fail(RELAYER);
+        }
+        for _ in 0..60 {
+            scenario.fail(spammer(0));
+        }
+        scenario.advance(1000);
+
+        scenario.assert_member("spammers", spammer(0)).assert_not_member("spammers", RELAYER);
+    }
+
+    #[test]
+    fn classification_is_evaluated_at_its_own_interval()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "failed_exceeded_quic_connections" : {
+                    "group_name" : "spammers",
+                    "evaluation_interval_ms" : 10000,
+                    "thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 50, "duration_ms" : 60000 }
+                    ]
+                }
+            }"#
+        )
+        .unwrap();
+
+        scenario.advance(1000);
+        for _ in 0..60 {
+            scenario.fail(spammer(0));
+        }
+
+        // Passes before the next evaluation leave the spammer unclassified; the evaluation then classifies it
+        scenario.advance(5000);
+        scenario.assert_not_member("spammers", spammer(0));
+        scenario.advance(5000);
+        scenario.assert_member("spammers", spammer(0));
+    }
+
+    #[test]
+    fn output_sink_is_given_snapshots_of_changed_groups()
+    {
+        use crate::sink::{MemberChange, MembershipOutput, OutputSink, Sink};
+
+        #[derive(Default)]
+        struct Snapshots(Vec<(String, Vec<IpAddr>)>);
+
+        impl OutputSink for Snapshots
+        {
+            fn describe(&self) -> serde_json::Value
+            {
+                serde_json::json!({ "type" : "snapshots" })
+            }
+
+            fn on_add(
+                &mut self,
+                _change : &MemberChange
+            ) -> Result<(), String>
+            {
+                Ok(())
+            }
+
+            fn on_remove(
+                &mut self,
+                _change : &MemberChange
+            ) -> Result<(), String>
+            {
+                Ok(())
+            }
+
+            fn on_snapshot(
+                &mut self,
+                group_name : &str,
+                members : &[IpAddr]
+            ) -> Result<(), String>
+            {
+                self.0.push((group_name.to_string(), members.to_vec()));
+                Ok(())
+            }
+        }
+
+        let mut scenario = Scenario::from_json(CONFIG).unwrap();
+        for index in [1, 0] {
+            for _ in 0..150 {
+                scenario.fail(spammer(index));
+            }
+        }
+        scenario.advance(1000);
+
+        let mut output = MembershipOutput::new(Snapshots::default());
+        for event in scenario.sink_events() {
+            output.apply(event).unwrap();
+        }
+        output.flush().unwrap();
+        output.flush().unwrap();
+
+        assert_eq!(output.output().0, vec![("spammers".to_string(), vec![spammer(0), spammer(1)])]);
+    }
+
+    #[test]
+    fn storm_of_distinct_addresses_applies_overload_thresholds()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{
+                "failed_exceeded_quic_connections" : {
+                    "group_name" : "spammers",
+                    "thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 50, "duration_ms" : 10000 }
+                    ],
+                    "overload_thresholds" : [
+                        { "value_operation" : "sum", "threshold_type" : "greater_than", "value" : 5, "duration_ms" : 10000 }
+                    ]
+                },
+                "storm_rules" : [
+                    {
+                        "name" : "failure_storm",
+                        "measure" : "failing_addresses",
+                        "max_per_second" : 10,
+                        "window_ms" : 5000,
+                        "actions" : ["notify", "overload_thresholds"]
+                    }
+                ]
+            }"#
+        )
+        .unwrap();
+
+        // 200 distinct addresses each fail once per second, far under the per-address threshold but together a storm
+        scenario.advance(1000);
+        for _ in 0..10 {
+            for index in 0..200 {
+                scenario.fail(spammer(index));
+            }
+            scenario.advance(1000);
+        }
+
+        assert!(scenario.sink_events().iter().any(|event| matches!(event, GroupChange::Storm { exceeded : true, .. })));
+        scenario.assert_member("spammers", spammer(0)).assert_member("spammers", spammer(199));
+        assert!(scenario.sink_events().iter().all(|event| match event {
+            GroupChange::Add { rule, .. } => rule.threshold.as_ref().is_some_and(|threshold| {
+                (threshold.classification.as_ref() == "failed_exceeded_quic_connections") && threshold.overload
+            }),
+            _ => true
+        }));
+
+        // Once the storm passes, the rule clears
+        scenario.advance(10 * 1000);
+
+        assert!(scenario
+            .sink_events()
+            .iter()
+            .any(|event| matches!(event, GroupChange::Storm { exceeded : false, .. })));
+    }
+
+    #[test]
+    fn startup_leader_status_waits_for_grace_period()
+    {
+        let mut scenario = Scenario::from_json(
+            r#"{ "outside_leader_slots" : { "leader_slots" : 10, "startup_leader_status" : { "wait" : 5000 } } }"#
+        )
+        .unwrap();
+
+        let leader_statuses = |scenario : &mut Scenario| {
+            scenario
+                .sink_events()
+                .iter()
+                .filter_map(|event| match event {
+                    GroupChange::LeaderStatus { group_name: None, leader, .. } => Some(*leader),
+                    _ => None
+                })
+                .collect::<Vec<bool>>()
+        };
+
+        scenario.advance(3 * 1000);
+        assert!(leader_statuses(&mut scenario).is_empty());
+
+        scenario.advance(3 * 1000);
+        assert_eq!(leader_statuses(&mut scenario), vec![false]);
+
+        scenario.begin_leader().end_leader();
+        assert_eq!(leader_statuses(&mut scenario), vec![false, true, false]);
+    }
+
+    #[test]
+    fn canned_scenarios_run_by_name()
+    {
+        let members = run_canned(
+            Config::parse(CONFIG).and_then(|mut config| config.validate().map(|_| config)).unwrap(),
+            "spam_flood"
+        )
+        .unwrap();
+
+        assert_eq!(members.get("spammers").map(|members| members.len()), Some(SPAMMER_COUNT as usize));
+        assert!(CANNED_SCENARIOS.iter().all(|canned| !canned.description.is_empty()));
+        assert!(run_canned(Config::parse("{}").unwrap(), "unknown").is_err());
+    }
+
+    #[test]
+    fn replayed_recording_classifies_recorded_spam()
+    {
+        use crate::recording::RecordedEvent;
+        use bincode::Options;
+
+        let path = std::env::temp_dir().join(format!("txingest-replay-{}.rec", std::process::id()));
+
+        let mut bytes = vec![];
+        for index in 0..200 {
+            let timestamp = SCENARIO_START_TIMESTAMP + (index * 10);
+            let recorded_event = RecordedEvent {
+                arrival_timestamp : timestamp,
+                source : crate::config::DEFAULT_INGEST_SOURCE.to_string(),
+                msg : TxIngestMsg::Failed { timestamp, peer_addr : spammer(0) }
+            };
+            bytes.extend(bincode::DefaultOptions::new().serialize(&recorded_event).unwrap());
+        }
+        // A recording cut off mid-event still replays its complete events
+        bytes.extend([0_u8, 1]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let config = Config::parse(CONFIG).and_then(|mut config| config.validate().map(|_| config)).unwrap();
+        let members = crate::replay::run(config, &[path.to_string_lossy().to_string()], None);
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(members.unwrap().get("spammers"), Some(&vec![spammer(0)]));
+    }
+
+    #[test]
+    fn sink_journal_delivers_only_removals_again_after_restart()
+    {
+        use crate::journal::JournaledSink;
+        use crate::sink::Sink;
+        use std::sync::Mutex;
+
+        // Records the type of each event applied, failing every one if failing
+        struct Recording(Arc<Mutex<Vec<String>>>, bool);
+
+        impl Sink for Recording
+        {
+            fn describe(&self) -> serde_json::Value
+            {
+                serde_json::json!({ "type" : "recording" })
+            }
+
+            fn apply(
+                &mut self,
+                event : &GroupChange
+            ) -> Result<(), String>
+            {
+                if self.1 {
+                    return Err("down".to_string());
+                }
+                self.0.lock().unwrap().push(event.to_json()["event"].as_str().unwrap().to_string());
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!("txingest-journal-{}.journal", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::remove_file(&path).ok();
+
+        let mut scenario = Scenario::from_json(CONFIG).unwrap();
+        for _ in 0..150 {
+            scenario.fail(spammer(0));
+        }
+        scenario.advance(1000);
+        let add = scenario.sink_events().iter().find(|event| matches!(event, GroupChange::Add { .. })).unwrap().clone();
+        let remove = GroupChange::Remove {
+            group_name : "spammers".to_string(),
+            ip_addr : spammer(1),
+            metadata : None,
+            expired : true
+        };
+
+        let applied = Arc::new(Mutex::new(vec![]));
+
+        // While the sink is down, events are only journaled
+        let mut journaled =
+            JournaledSink::new(Box::new(Recording(applied.clone(), true)), path.clone(), 10, 1).unwrap();
+        for event in [&add, &remove, &add] {
+            journaled.apply(event).unwrap();
+        }
+        journaled.flush().unwrap();
+        drop(journaled);
+
+        // After a restart, the removal is delivered again but the additions of the prior run are not
+        let mut journaled =
+            JournaledSink::new(Box::new(Recording(applied.clone(), false)), path.clone(), 10, 1).unwrap();
+        journaled.flush().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(*applied.lock().unwrap(), vec!["remove".to_string()]);
+        assert!(contents.is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+const MS_PER_MINUTE : u64 = 60 * 1000;
+const MINUTES_PER_DAY : u64 = 24 * 60;
+
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday
+{
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday
+}
+
+// A recurring window of UTC time, e.g. { "days" : ["saturday", "sunday"], "start" : "14:00", "end" : "18:00" }
+#[derive(Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindow
+{
+    // Days of the week on which the window begins.  If not present, the window begins every day.
+    pub days : Option<Vec<Weekday>>,
+
+    // Dates (in the form "YYYY-MM-DD") on which the window begins, e.g. the dates of scheduled mints.  If not
+    // present, the window begins on any date.  If both days and dates are present, the window begins only on listed
+    // dates falling on listed days.
+    pub dates : Option<Vec<String>>,
+
+    // Time of day (in the form "HH:MM") at which the window begins.  If not present, "00:00" is used.
+    pub start : Option<String>,
+
+    // Time of day (in the form "HH:MM") at which the window ends.  If not present, "24:00" is used.  If not later than
+    // start, the window ends at this time on the following day.
+    pub end : Option<String>,
+
+    // Minute of the day of start and end, resolved when the config is validated
+    #[serde(skip)]
+    start_minute : u64,
+
+    #[serde(skip)]
+    end_minute : u64,
+
+    // Days since the Unix epoch of dates, resolved when the config is validated
+    #[serde(skip)]
+    date_days : Option<Vec<u64>>
+}
+
+impl TimeWindow
+{
+    pub fn validate(&mut self) -> Result<(), String>
+    {
+        self.start_minute = match &self.start {
+            Some(start) => parse_time(start)?,
+            None => 0
+        };
+
+        self.end_minute = match &self.end {
+            Some(end) => parse_time(end)?,
+            None => MINUTES_PER_DAY
+        };
+
+        self.date_days = self
+            .dates
+            .as_ref()
+            .map(|dates| dates.iter().map(|date| parse_date(date)).collect::<Result<Vec<u64>, String>>())
+            .transpose()?;
+
+        Ok(())
+    }
+
+    // Returns true if timestamp (in milliseconds since the Unix epoch) is within the window
+    pub fn contains(
+        &self,
+        timestamp : u64
+    ) -> bool
+    {
+        let minutes = timestamp / MS_PER_MINUTE;
+        let day = minutes / MINUTES_PER_DAY;
+        let minute = minutes % MINUTES_PER_DAY;
+
+        if self.start_minute < self.end_minute {
+            self.begins_on(day) && (minute >= self.start_minute) && (minute < self.end_minute)
+        }
+        else {
+            // The window wraps past midnight, so it is either the part begun today or the part begun yesterday
+            (self.begins_on(day) && (minute >= self.start_minute)) ||
+                ((day > 0) && self.begins_on(day - 1) && (minute < self.end_minute))
+        }
+    }
+
+    fn begins_on(
+        &self,
+        day : u64
+    ) -> bool
+    {
+        self.days.as_ref().map(|days| days.contains(&weekday(day))).unwrap_or(true) &&
+            self.date_days.as_ref().map(|date_days| date_days.contains(&day)).unwrap_or(true)
+    }
+}
+
+// Returns true if there is no schedule, or timestamp is within any window of the schedule
+pub fn is_scheduled(
+    schedule : &Option<Vec<TimeWindow>>,
+    timestamp : u64
+) -> bool
+{
+    schedule.as_ref().map(|windows| windows.iter().any(|window| window.contains(timestamp))).unwrap_or(true)
+}
+
+// Returns the day of the week of a number of days since the Unix epoch, which was a Thursday
+fn weekday(day : u64) -> Weekday
+{
+    const WEEKDAYS : [Weekday; 7] = [
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday
+    ];
+
+    WEEKDAYS[(day % 7) as usize]
+}
+
+// Parses "HH:MM" into a minute of the day; "24:00" is allowed as the end of the day
+fn parse_time(time : &str) -> Result<u64, String>
+{
+    let invalid = || format!("Invalid time {time}: must be HH:MM");
+
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours = hours.parse::<u64>().map_err(|_| invalid())?;
+    let minutes = minutes.parse::<u64>().map_err(|_| invalid())?;
+
+    if (minutes >= 60) || ((hours * 60) + minutes > MINUTES_PER_DAY) {
+        return Err(invalid());
+    }
+
+    Ok((hours * 60) + minutes)
+}
+
+// Parses "YYYY-MM-DD" into a number of days since the Unix epoch
+fn parse_date(date : &str) -> Result<u64, String>
+{
+    let invalid = || format!("Invalid date {date}: must be YYYY-MM-DD no earlier than 1970-01-01");
+
+    let mut fields = date.splitn(3, '-').map(|field| field.parse::<i64>().map_err(|_| invalid()));
+    let (Some(year), Some(month), Some(day)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (year?, month?, day?);
+
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    // Days from civil date, with years beginning in March so that the leap day is last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - (era * 400);
+    let day_of_year = (((153 * (if month > 2 { month - 3 } else { month + 9 })) + 2) / 5) + day - 1;
+    let day_of_era = (year_of_era * 365) + (year_of_era / 4) - (year_of_era / 100) + day_of_year;
+    let days = (era * 146_097) + day_of_era - 719_468;
+
+    u64::try_from(days).map_err(|_| invalid())
+}
+
+// Returns the number of days in a month (1 - 12) of a year of the Gregorian calendar
+fn days_in_month(
+    year : i64,
+    month : i64
+) -> i64
+{
+    match month {
+        2 if (year % 4 == 0) && ((year % 100 != 0) || (year % 400 == 0)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
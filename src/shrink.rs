@@ -0,0 +1,82 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+
+// Collections with at most this capacity are never shrunk, as there is too little memory to be recovered
+const MIN_SHRINK_CAPACITY : usize = 64;
+
+// A collection is shrunk when its capacity exceeds SHRINK_TRIGGER_RATIO times its length, and then only down to
+// SHRINK_TARGET_RATIO times its length.  The gap between the two provides hysteresis, so that a collection whose
+// length fluctuates is not repeatedly shrunk and regrown.
+const SHRINK_TRIGGER_RATIO : usize = 4;
+const SHRINK_TARGET_RATIO : usize = 2;
+
+// A collection whose capacity can be released once it is mostly unused, so that memory grown during a flood of events
+// is returned after the flood has passed
+pub trait Shrink
+{
+    fn item_count(&self) -> usize;
+
+    fn allocated_capacity(&self) -> usize;
+
+    fn shrink_capacity_to(
+        &mut self,
+        capacity : usize
+    );
+
+    // Shrinks the collection if it is mostly unused capacity.  Returns true if it was shrunk.
+    fn shrink_idle(&mut self) -> bool
+    {
+        let item_count = self.item_count();
+        let capacity = self.allocated_capacity();
+
+        if (capacity <= MIN_SHRINK_CAPACITY) || (capacity <= item_count.saturating_mul(SHRINK_TRIGGER_RATIO)) {
+            return false;
+        }
+
+        self.shrink_capacity_to(std::cmp::max(item_count * SHRINK_TARGET_RATIO, MIN_SHRINK_CAPACITY));
+
+        true
+    }
+}
+
+impl<K : Eq + Hash, V, S : BuildHasher> Shrink for HashMap<K, V, S>
+{
+    fn item_count(&self) -> usize
+    {
+        self.len()
+    }
+
+    fn allocated_capacity(&self) -> usize
+    {
+        self.capacity()
+    }
+
+    fn shrink_capacity_to(
+        &mut self,
+        capacity : usize
+    )
+    {
+        self.shrink_to(capacity)
+    }
+}
+
+impl<T> Shrink for VecDeque<T>
+{
+    fn item_count(&self) -> usize
+    {
+        self.len()
+    }
+
+    fn allocated_capacity(&self) -> usize
+    {
+        self.capacity()
+    }
+
+    fn shrink_capacity_to(
+        &mut self,
+        capacity : usize
+    )
+    {
+        self.shrink_to(capacity)
+    }
+}
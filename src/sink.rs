@@ -0,0 +1,1391 @@
+use crate::config::{GroupMetadata, ThrottleRate, TpuService};
+use crate::group::{Rule, PERMANENT_EXPIRATION};
+use crate::histogram::{Histogram, DECISION_LATENCY_BOUNDS_MS};
+use crate::log::{self, LogLevel};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub const DEFAULT_SINK_QUEUE_SIZE : usize = 100_000;
+pub const DEFAULT_SINK_RETRY_COUNT : u32 = 3;
+pub const DEFAULT_SINK_RETRY_DELAY_MS : u64 = 100;
+
+// Most additions awaiting a flush of the sinks whose sink latency is to be recorded; additions beyond this many are
+// not recorded, so that a queue that never drains does not grow the pending latencies without bound
+const MAX_PENDING_SINK_LATENCIES : usize = 100_000;
+
+// An event to be acted upon by sinks: a change to the membership of a group, or some other notable occurrence
+#[derive(Clone)]
+pub enum GroupChange
+{
+    Add
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        // Only present for groups derived from known pubkeys
+        pubkey : Option<Pubkey>,
+        // On the system clock, to which SinkSender translates it from the internal clock
+        expiration : u64,
+        // Metadata of the group from config, if any; attached by SinkSender
+        metadata : Option<Arc<GroupMetadata>>,
+        // Rate to which the member should be limited, if the group is a throttle; attached by SinkSender
+        throttle : Option<ThrottleRate>,
+        // The rule (e.g. a classification threshold) that added the member
+        rule : Rule,
+        // Time at which the member was added
+        timestamp : u64,
+        // Timestamp of the event that triggered the addition, and the stake of the member, if known; attached by
+        // SinkSender
+        trigger_timestamp : Option<u64>,
+        stake : Option<u64>,
+        // Identifies the decision to add, as referenced by the exemplars of exported metrics; unique within a run, and
+        // shared by the adds of equivalent addresses.  Attached by SinkSender.
+        decision_id : u64
+    },
+
+    Update
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
+        expiration : u64,
+        metadata : Option<Arc<GroupMetadata>>,
+        throttle : Option<ThrottleRate>
+    },
+
+    // The expiration of an existing member was extended; sent in place of Update for groups configured with "extend"
+    // extension events
+    Extend
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
+        expiration : u64,
+        metadata : Option<Arc<GroupMetadata>>,
+        throttle : Option<ThrottleRate>
+    },
+
+    Remove
+    {
+        group_name : String,
+        ip_addr : IpAddr,
+        metadata : Option<Arc<GroupMetadata>>,
+        // True if the membership expired, rather than being removed
+        expired : bool
+    },
+
+    // The leader classification changed, either globally (group_name None) or for the outside leader slots window
+    // of a group
+    LeaderStatus
+    {
+        group_name : Option<String>,
+        // True if within leader slots (or the window's number of slots before leader slots)
+        leader : bool,
+        // Number of slots until leader slots, as most recently reported
+        slots_until_leader : Option<u8>,
+        timestamp : u64
+    },
+
+    // A peer was seen for the first time
+    NewPeer
+    {
+        ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
+        stake : u64,
+        service : Option<TpuService>,
+        timestamp : u64
+    },
+
+    // A peer was evicted from memory after a period of inactivity; summary is its final state, as reported by the
+    // API for peers.  Only sent if peer_export is configured.
+    PeerEvicted
+    {
+        ip_addr : IpAddr, summary : serde_json::Value, timestamp : u64
+    },
+
+    // The pubkey reported for a peer's address changed
+    PubkeyChange
+    {
+        ip_addr : IpAddr, old_pubkey : Pubkey, new_pubkey : Pubkey, timestamp : u64
+    },
+
+    // The stake reported for a peer changed materially between connections
+    StakeChange
+    {
+        ip_addr : IpAddr, old_stake : u64, new_stake : u64, timestamp : u64
+    },
+
+    // The classifier switched between enforcing (sinks act on group changes) and monitoring (group changes are only
+    // logged).  Sinks with external effects must undo them on switching to monitoring; on switching to enforcing, an
+    // Update is sent for every current group member.
+    Mode
+    {
+        enforcing : bool, timestamp : u64
+    },
+
+    // A group size rule fired (exceeded true) because the group grew past max_members, or cleared (exceeded false)
+    GroupSize
+    {
+        group_name : String, member_count : usize, max_members : usize, exceeded : bool, timestamp : u64
+    },
+
+    // A storm rule fired (exceeded true) because its global rate grew past max_per_second, or cleared (exceeded false)
+    Storm
+    {
+        rule_name : String, per_second : f64, max_per_second : f64, exceeded : bool, timestamp : u64
+    },
+
+    // A canary started failing (passed false) because its synthetic events did not get it added to group_name in
+    // time, or recovered (passed true)
+    Canary
+    {
+        canary_name : String, ip_addr : IpAddr, group_name : String, passed : bool, timestamp : u64
+    },
+
+    // Current values of classifier metrics, sent at each status report for sinks that export metrics
+    Metrics
+    {
+        metrics : Vec<Metric>, timestamp : u64
+    }
+}
+
+// A named metric value, with attributes distinguishing the instances of the metric (e.g. the classification or group
+// name)
+#[derive(Clone)]
+pub struct Metric
+{
+    pub name : &'static str,
+
+    pub value : f64,
+
+    pub attributes : Vec<(&'static str, String)>,
+
+    // A recent addition that contributed to the value, so that a change in the metric can be traced to the decisions
+    // behind it
+    pub exemplar : Option<Exemplar>
+}
+
+// A group addition exemplifying a metric: the decision, the added address, when it was added, and the value it
+// contributed (1 for counts of additions, or the latency in milliseconds for latencies)
+#[derive(Clone, Serialize)]
+pub struct Exemplar
+{
+    pub decision_id : u64,
+
+    pub ip_addr : IpAddr,
+
+    pub timestamp : u64,
+
+    pub value : f64
+}
+
+// The exemplars recorded by SinkSender since they were last taken
+#[derive(Default)]
+pub struct Exemplars
+{
+    // Map from group name to the most recent addition to the group
+    pub by_group : HashMap<String, Exemplar>,
+
+    // Map from classification name to the most recent addition by a threshold of the classification
+    pub by_classification : HashMap<String, Exemplar>,
+
+    // The addition with the greatest latency from its triggering event to the decision
+    pub slowest_decision : Option<Exemplar>
+}
+
+impl GroupChange
+{
+    // Returns a copy of a group change event for a different address
+    fn with_ip_addr(
+        &self,
+        ip_addr : IpAddr
+    ) -> Self
+    {
+        match self {
+            GroupChange::Add {
+                group_name,
+                pubkey,
+                expiration,
+                metadata,
+                throttle,
+                rule,
+                timestamp,
+                trigger_timestamp,
+                stake,
+                decision_id,
+                ..
+            } => GroupChange::Add {
+                group_name : group_name.clone(),
+                ip_addr,
+                pubkey : *pubkey,
+                expiration : *expiration,
+                metadata : metadata.clone(),
+                throttle : *throttle,
+                rule : rule.clone(),
+                timestamp : *timestamp,
+                trigger_timestamp : *trigger_timestamp,
+                stake : *stake,
+                decision_id : *decision_id
+            },
+            GroupChange::Update { group_name, pubkey, expiration, metadata, throttle, .. } => GroupChange::Update {
+                group_name : group_name.clone(),
+                ip_addr,
+                pubkey : *pubkey,
+                expiration : *expiration,
+                metadata : metadata.clone(),
+                throttle : *throttle
+            },
+            GroupChange::Extend { group_name, pubkey, expiration, metadata, throttle, .. } => GroupChange::Extend {
+                group_name : group_name.clone(),
+                ip_addr,
+                pubkey : *pubkey,
+                expiration : *expiration,
+                metadata : metadata.clone(),
+                throttle : *throttle
+            },
+            GroupChange::Remove { group_name, metadata, expired, .. } => GroupChange::Remove {
+                group_name : group_name.clone(),
+                ip_addr,
+                metadata : metadata.clone(),
+                expired : *expired
+            },
+            _ => unreachable!("with_ip_addr is only called on group change events")
+        }
+    }
+
+    // Returns the JSON representation of the event, as posted to webhooks and exported to OpenTelemetry
+    pub fn to_json(&self) -> serde_json::Value
+    {
+        match self {
+            GroupChange::Add {
+                group_name,
+                ip_addr,
+                pubkey,
+                expiration,
+                metadata,
+                throttle,
+                rule,
+                timestamp,
+                trigger_timestamp,
+                stake,
+                decision_id
+            } => serde_json::json!({
+                "event" : "add",
+                "decision_id" : decision_id,
+                "group_name" : group_name,
+                "ip_addr" : ip_addr,
+                "pubkey" : pubkey.map(|pubkey| pubkey.to_string()),
+                "expiration" : expiration,
+                "metadata" : metadata.as_deref(),
+                "throttle" : throttle,
+                "rule" : rule.name.as_ref(),
+                "timestamp" : timestamp,
+                "trigger_timestamp" : trigger_timestamp,
+                "stake" : stake
+            }),
+            GroupChange::Update { group_name, ip_addr, pubkey, expiration, metadata, throttle } => serde_json::json!({
+                "event" : "update",
+                "group_name" : group_name,
+                "ip_addr" : ip_addr,
+                "pubkey" : pubkey.map(|pubkey| pubkey.to_string()),
+                "expiration" : expiration,
+                "metadata" : metadata.as_deref(),
+                "throttle" : throttle
+            }),
+            GroupChange::Extend { group_name, ip_addr, pubkey, expiration, metadata, throttle } => serde_json::json!({
+                "event" : "extend",
+                "group_name" : group_name,
+                "ip_addr" : ip_addr,
+                "pubkey" : pubkey.map(|pubkey| pubkey.to_string()),
+                "expiration" : expiration,
+                "metadata" : metadata.as_deref(),
+                "throttle" : throttle
+            }),
+            GroupChange::Remove { group_name, ip_addr, metadata, expired } => serde_json::json!({
+                "event" : "remove",
+                "group_name" : group_name,
+                "ip_addr" : ip_addr,
+                "metadata" : metadata.as_deref(),
+                "expired" : expired
+            }),
+            GroupChange::LeaderStatus { group_name, leader, slots_until_leader, timestamp } => serde_json::json!({
+                "event" : "leader_status",
+                "group_name" : group_name,
+                "leader" : leader,
+                "slots_until_leader" : slots_until_leader,
+                "timestamp" : timestamp
+            }),
+            GroupChange::NewPeer { ip_addr, pubkey, stake, service, timestamp } => serde_json::json!({
+                "event" : "new_peer",
+                "ip_addr" : ip_addr,
+                "pubkey" : pubkey.map(|pubkey| pubkey.to_string()),
+                "stake" : stake,
+                "service" : service,
+                "timestamp" : timestamp
+            }),
+            GroupChange::PeerEvicted { ip_addr, summary, timestamp } => serde_json::json!({
+                "event" : "peer_evicted",
+                "ip_addr" : ip_addr,
+                "summary" : summary,
+                "timestamp" : timestamp
+            }),
+            GroupChange::PubkeyChange { ip_addr, old_pubkey, new_pubkey, timestamp } => serde_json::json!({
+                "event" : "pubkey_change",
+                "ip_addr" : ip_addr,
+                "old_pubkey" : old_pubkey.to_string(),
+                "new_pubkey" : new_pubkey.to_string(),
+                "timestamp" : timestamp
+            }),
+            GroupChange::StakeChange { ip_addr, old_stake, new_stake, timestamp } => serde_json::json!({
+                "event" : "stake_change",
+                "ip_addr" : ip_addr.to_string(),
+                "old_stake" : old_stake,
+                "new_stake" : new_stake,
+                "timestamp" : timestamp
+            }),
+            GroupChange::Mode { enforcing, timestamp } => serde_json::json!({
+                "event" : "mode",
+                "enforcing" : enforcing,
+                "timestamp" : timestamp
+            }),
+            GroupChange::GroupSize { group_name, member_count, max_members, exceeded, timestamp } => {
+                serde_json::json!({
+                    "event" : "group_size",
+                    "group_name" : group_name,
+                    "member_count" : member_count,
+                    "max_members" : max_members,
+                    "exceeded" : exceeded,
+                    "timestamp" : timestamp
+                })
+            },
+            GroupChange::Storm { rule_name, per_second, max_per_second, exceeded, timestamp } => serde_json::json!({
+                "event" : "storm",
+                "rule_name" : rule_name,
+                "per_second" : per_second,
+                "max_per_second" : max_per_second,
+                "exceeded" : exceeded,
+                "timestamp" : timestamp
+            }),
+            GroupChange::Canary { canary_name, ip_addr, group_name, passed, timestamp } => serde_json::json!({
+                "event" : "canary",
+                "canary_name" : canary_name,
+                "ip_addr" : ip_addr,
+                "group_name" : group_name,
+                "passed" : passed,
+                "timestamp" : timestamp
+            }),
+            GroupChange::Metrics { metrics, timestamp } => serde_json::json!({
+                "event" : "metrics",
+                "metrics" : metrics
+                    .iter()
+                    .map(|metric| serde_json::json!({
+                        "name" : metric.name,
+                        "value" : metric.value,
+                        "attributes" : metric.attributes.iter().cloned().collect::<HashMap<_, _>>(),
+                        "exemplar" : metric.exemplar
+                    }))
+                    .collect::<Vec<_>>(),
+                "timestamp" : timestamp
+            })
+        }
+    }
+}
+
+// A message on the sink queue: a single event, or the events of a batch (e.g. all of the group changes of one periodic
+// pass), which are all applied before sinks are flushed, so that sinks which defer their side effects to flush apply
+// the whole batch at once rather than in parts.  Each is sent with the time at which it was queued.
+pub enum SinkMessage
+{
+    Event(GroupChange, Instant),
+
+    Batch(Vec<GroupChange>, Instant)
+}
+
+impl SinkMessage
+{
+    pub fn into_events(self) -> Vec<GroupChange>
+    {
+        match self {
+            SinkMessage::Event(event, _) => vec![event],
+            SinkMessage::Batch(events, _) => events
+        }
+    }
+
+    pub fn enqueued(&self) -> Instant
+    {
+        match self {
+            SinkMessage::Event(_, enqueued) | SinkMessage::Batch(_, enqueued) => *enqueued
+        }
+    }
+
+    pub fn event_count(&self) -> usize
+    {
+        match self {
+            SinkMessage::Event(..) => 1,
+            SinkMessage::Batch(events, _) => events.len()
+        }
+    }
+}
+
+// A Sink performs the side effects of events (printing, firewall calls, webhooks, file writes, etc).  Sinks
+// are only ever invoked from the sink worker thread, so they may block without stalling event processing.
+pub trait Sink: Send
+{
+    // Returns a machine readable description of the sink and its settings, for the capability report
+    fn describe(&self) -> serde_json::Value;
+
+    // Returns an error if the event could not be applied; the event will be retried according to the retry policy
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>;
+
+    // Called whenever the queue of events has been drained, allowing sinks to batch work across many events.  Never
+    // called within a batch, so sinks that defer their side effects to flush (e.g. by a single firewall command for
+    // all changes) apply each batch atomically.  Returns an error if the flush failed; the flush will be retried
+    // according to the retry policy.
+    fn flush(&mut self) -> Result<(), String>
+    {
+        Ok(())
+    }
+}
+
+// Output that prints events to stdout
+pub struct StdoutSink;
+
+impl StdoutSink
+{
+    fn print(event : &GroupChange)
+    {
+        if !is_printed(event) {
+            return;
+        }
+
+        match event {
+            GroupChange::Add { group_name, ip_addr, pubkey: Some(pubkey), expiration, metadata, throttle, .. } => {
+                println!(
+                    "Add {pubkey} to {group_name} at address {ip_addr} with expiration {expiration}{}{}",
+                    describe_throttle(throttle),
+                    describe_metadata(metadata)
+                )
+            },
+            GroupChange::Add { group_name, ip_addr, pubkey: None, expiration, metadata, throttle, .. } => println!(
+                "Add {ip_addr} to group {group_name} with expiration {expiration}{}{}",
+                describe_throttle(throttle),
+                describe_metadata(metadata)
+            ),
+            GroupChange::Update { group_name, ip_addr, pubkey: Some(pubkey), expiration, metadata, throttle } => {
+                println!(
+                    "Update {pubkey} to {group_name} at address {ip_addr} with expiration {expiration}{}{}",
+                    describe_throttle(throttle),
+                    describe_metadata(metadata)
+                )
+            },
+            GroupChange::Update { group_name, ip_addr, pubkey: None, expiration, metadata, throttle } => println!(
+                "Update {ip_addr} in group {group_name} with expiration {expiration}{}{}",
+                describe_throttle(throttle),
+                describe_metadata(metadata)
+            ),
+            GroupChange::Extend { group_name, ip_addr, pubkey: Some(pubkey), expiration, .. } => {
+                println!("Extend {pubkey} in {group_name} at address {ip_addr} to expiration {expiration}")
+            },
+            GroupChange::Extend { group_name, ip_addr, pubkey: None, expiration, .. } => {
+                println!("Extend {ip_addr} in group {group_name} to expiration {expiration}")
+            },
+            GroupChange::Remove { group_name, ip_addr, metadata, expired } => println!(
+                "Remove {ip_addr} from group {group_name}{}{}",
+                if *expired { " (expired)" } else { "" },
+                describe_metadata(metadata)
+            ),
+            GroupChange::LeaderStatus { group_name, leader, slots_until_leader, timestamp } => {
+                let status = if *leader { "LEADER CLASSIFICATION" } else { "NOT LEADER CLASSIFICATION" };
+                let group_name = group_name.as_ref().map(|group_name| format!(" {group_name}")).unwrap_or_default();
+                match slots_until_leader {
+                    Some(slots) => println!("{status}{group_name} at {timestamp} with {slots} slots until leader"),
+                    None => println!("{status}{group_name} at {timestamp}")
+                }
+            },
+            GroupChange::NewPeer { ip_addr, pubkey: Some(pubkey), stake, service, timestamp } => {
+                println!(
+                    "New peer {ip_addr} with pubkey {pubkey} and stake {stake}{} at {timestamp}",
+                    describe_service(service)
+                )
+            },
+            GroupChange::NewPeer { ip_addr, pubkey: None, stake, service, timestamp } => {
+                println!("New peer {ip_addr} with stake {stake}{} at {timestamp}", describe_service(service))
+            },
+            GroupChange::PeerEvicted { ip_addr, timestamp, .. } => println!("Evicted peer {ip_addr} at {timestamp}"),
+            GroupChange::PubkeyChange { ip_addr, old_pubkey, new_pubkey, timestamp } => {
+                println!("Pubkey of {ip_addr} changed from {old_pubkey} to {new_pubkey} at {timestamp}")
+            },
+            GroupChange::StakeChange { ip_addr, old_stake, new_stake, timestamp } => {
+                println!("Stake of {ip_addr} changed from {old_stake} to {new_stake} at {timestamp}")
+            },
+            GroupChange::Mode { enforcing, timestamp } => {
+                println!("MODE {} at {timestamp}", if *enforcing { "ENFORCE" } else { "MONITOR" })
+            },
+            GroupChange::GroupSize { group_name, member_count, max_members, exceeded: true, timestamp } => {
+                println!(
+                    "GROUP SIZE EXCEEDED {group_name} has {member_count} members (max {max_members}) at {timestamp}"
+                )
+            },
+            GroupChange::GroupSize { group_name, member_count, exceeded: false, timestamp, .. } => {
+                println!("GROUP SIZE CLEARED {group_name} has {member_count} members at {timestamp}")
+            },
+            GroupChange::Storm { rule_name, per_second, max_per_second, exceeded: true, timestamp } => {
+                println!("STORM {rule_name} at {per_second:.1}/s (max {max_per_second}/s) at {timestamp}")
+            },
+            GroupChange::Storm { rule_name, per_second, exceeded: false, timestamp, .. } => {
+                println!("STORM CLEARED {rule_name} at {per_second:.1}/s at {timestamp}")
+            },
+            GroupChange::Canary { canary_name, ip_addr, group_name, passed: false, timestamp } => {
+                println!("CANARY FAILING {canary_name} at {ip_addr} not added to {group_name} at {timestamp}")
+            },
+            GroupChange::Canary { canary_name, passed: true, timestamp, .. } => {
+                println!("CANARY RECOVERED {canary_name} at {timestamp}")
+            },
+            // Metrics are already printed in the status report
+            GroupChange::Metrics { .. } => ()
+        }
+    }
+}
+
+impl OutputSink for StdoutSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "stdout" })
+    }
+
+    fn on_add(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        Self::print(change.event);
+        Ok(())
+    }
+
+    fn on_update(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        Self::print(change.event);
+        Ok(())
+    }
+
+    fn on_remove(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        Self::print(change.event);
+        Ok(())
+    }
+
+    fn on_event(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        Self::print(event);
+        Ok(())
+    }
+}
+
+// Returns true if event is to be printed to stdout at the current log level: when quiet, only changes affecting
+// enforcement as a whole, and canary alerts, are printed
+pub fn is_printed(event : &GroupChange) -> bool
+{
+    log::enabled(LogLevel::Normal) ||
+        matches!(
+            event,
+            GroupChange::Mode { .. } |
+                GroupChange::GroupSize { .. } |
+                GroupChange::Storm { .. } |
+                GroupChange::Canary { .. }
+        )
+}
+
+// A change to the membership of a group, as given to an OutputSink
+pub struct MemberChange<'a>
+{
+    pub group_name : &'a str,
+    pub ip_addr : IpAddr,
+    // Only present for groups derived from known pubkeys; never present for removals
+    pub pubkey : Option<Pubkey>,
+    // Expiration of the membership on the system clock (PERMANENT_EXPIRATION if it never expires); None for removals
+    pub expiration : Option<u64>,
+    // Rate to which the member should be limited, if the group is a throttle; None for removals
+    pub throttle : Option<ThrottleRate>,
+    // The event from which the change was taken, for outputs that record more of it than the above
+    pub event : &'a GroupChange
+}
+
+// A simpler interface than Sink, for outputs concerned mostly with group membership (as the built-in stdout, JSON,
+// deny list and exec hook outputs, and most integrations embedding the classifier, are): each addition, update and
+// removal, and if wanted, the full membership of each changed group once a batch of changes has been applied.
+// Membership is reported as is in monitor mode; outputs that enforce watch for Mode events in on_event.  An
+// OutputSink is used as a Sink by wrapping it in MembershipOutput.
+pub trait OutputSink: Send
+{
+    // Returns a machine readable description of the output and its settings, for the capability report
+    fn describe(&self) -> serde_json::Value;
+
+    // Called when ip_addr is added to group_name.  Returns an error if the addition could not be output; it will be
+    // retried as a failed Sink event is.
+    fn on_add(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>;
+
+    // Called when the membership of ip_addr in group_name is updated or extended, changing its expiration, metadata or
+    // throttle.  Members are also re-sent as updates on switching to enforcing, so an update may be for a member whose
+    // addition this output never saw; by default, updates are output as additions.
+    fn on_update(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>
+    {
+        self.on_add(change)
+    }
+
+    // Called when ip_addr is removed from group_name, as on expiration
+    fn on_remove(
+        &mut self,
+        change : &MemberChange
+    ) -> Result<(), String>;
+
+    // Called after each batch of changes with the members of each group changed by it, in sorted order, for outputs
+    // that replace a whole list at once rather than tracking changes
+    fn on_snapshot(
+        &mut self,
+        _group_name : &str,
+        _members : &[IpAddr]
+    ) -> Result<(), String>
+    {
+        Ok(())
+    }
+
+    // Called for every event other than a change to group membership (mode, leader status, peers, alerts and metrics)
+    fn on_event(
+        &mut self,
+        _event : &GroupChange
+    ) -> Result<(), String>
+    {
+        Ok(())
+    }
+
+    // Called whenever the queue of events has been drained, after any snapshots, as Sink::flush is
+    fn flush(&mut self) -> Result<(), String>
+    {
+        Ok(())
+    }
+}
+
+// Sink that feeds group changes to an OutputSink, keeping the membership of each group for its snapshots if it wants
+// them
+pub struct MembershipOutput<T>
+{
+    output : T,
+
+    // Members of each group; None if the output is not given snapshots
+    members : Option<HashMap<String, BTreeSet<IpAddr>>>,
+
+    // Groups changed since they were last snapshotted
+    changed : BTreeSet<String>
+}
+
+impl<T : OutputSink> MembershipOutput<T>
+{
+    pub fn new(output : T) -> Self
+    {
+        Self { output, members : Some(Default::default()), changed : Default::default() }
+    }
+
+    // For outputs that do not implement on_snapshot, sparing the copy of every group's membership kept for snapshots
+    pub fn without_snapshots(output : T) -> Self
+    {
+        Self { output, members : None, changed : Default::default() }
+    }
+
+    pub fn output(&self) -> &T
+    {
+        &self.output
+    }
+}
+
+impl<T : OutputSink> Sink for MembershipOutput<T>
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        self.output.describe()
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        match event {
+            GroupChange::Add { group_name, ip_addr, pubkey, expiration, throttle, .. } => {
+                let change = MemberChange {
+                    group_name,
+                    ip_addr : *ip_addr,
+                    pubkey : *pubkey,
+                    expiration : Some(*expiration),
+                    throttle : *throttle,
+                    event
+                };
+                self.output.on_add(&change)?;
+            },
+            GroupChange::Update { group_name, ip_addr, pubkey, expiration, throttle, .. } |
+            GroupChange::Extend { group_name, ip_addr, pubkey, expiration, throttle, .. } => {
+                let change = MemberChange {
+                    group_name,
+                    ip_addr : *ip_addr,
+                    pubkey : *pubkey,
+                    expiration : Some(*expiration),
+                    throttle : *throttle,
+                    event
+                };
+                self.output.on_update(&change)?;
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } => {
+                let change = MemberChange {
+                    group_name,
+                    ip_addr : *ip_addr,
+                    pubkey : None,
+                    expiration : None,
+                    throttle : None,
+                    event
+                };
+                self.output.on_remove(&change)?;
+            },
+            _ => return self.output.on_event(event)
+        }
+
+        let Some(members) = &mut self.members
+        else {
+            return Ok(());
+        };
+
+        let (group_name, changed) = match event {
+            // An update adds a member not already known, as when members are re-sent on switching to enforcing
+            GroupChange::Add { group_name, ip_addr, .. } |
+            GroupChange::Update { group_name, ip_addr, .. } |
+            GroupChange::Extend { group_name, ip_addr, .. } => {
+                (group_name, members.entry(group_name.clone()).or_default().insert(*ip_addr))
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } => {
+                (group_name, members.get_mut(group_name).map(|members| members.remove(ip_addr)).unwrap_or(false))
+            },
+            _ => return Ok(())
+        };
+
+        if changed {
+            self.changed.insert(group_name.clone());
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), String>
+    {
+        if let Some(members) = &self.members {
+            // Groups whose snapshots failed stay changed, so that the retried flush snapshots them
+            while let Some(group_name) = self.changed.first() {
+                let members = members
+                    .get(group_name)
+                    .map(|members| members.iter().copied().collect::<Vec<IpAddr>>())
+                    .unwrap_or_default();
+                self.output.on_snapshot(group_name, &members)?;
+                self.changed.pop_first();
+            }
+        }
+
+        self.output.flush()
+    }
+}
+
+// Returns a throttle rate formatted for appending to a printed event, or an empty string if there is none
+fn describe_throttle(throttle : &Option<ThrottleRate>) -> String
+{
+    match throttle {
+        Some(throttle) => format!(" throttled to {}", describe_throttle_rate(throttle)),
+        None => "".to_string()
+    }
+}
+
+// Returns the limits of a throttle rate in the form "max_connections_per_minute=10 max_tps=100", omitting absent limits
+pub fn describe_throttle_rate(throttle : &ThrottleRate) -> String
+{
+    [("max_connections_per_minute", throttle.max_connections_per_minute), ("max_tps", throttle.max_tps)]
+        .iter()
+        .filter_map(|(name, limit)| limit.map(|limit| format!("{name}={limit}")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// Returns group metadata formatted for appending to a printed event, or an empty string if there is none
+fn describe_metadata(metadata : &Option<Arc<GroupMetadata>>) -> String
+{
+    match metadata {
+        Some(metadata) => format!(
+            " [{}]",
+            metadata.iter().map(|(key, value)| format!("{key}: {value}")).collect::<Vec<String>>().join(", ")
+        ),
+        None => String::new()
+    }
+}
+
+// Returns a service formatted for appending to a printed event, or an empty string if it is not known
+fn describe_service(service : &Option<TpuService>) -> String
+{
+    service.map(|service| format!(" via {}", service.name())).unwrap_or_default()
+}
+
+// Runs program with args, writing script to its standard input, for sinks that drive firewall tools by script.  Fails
+// with the program's error output if it exits unsuccessfully.
+pub fn run_script(
+    program : &str,
+    args : &[&str],
+    script : &str
+) -> Result<(), String>
+{
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {program}: {e}"))?;
+
+    // Dropping stdin after writing closes it, so that the program sees the end of the script
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .map_err(|e| format!("Failed to write to {program}: {e}"))?;
+
+    let output = child.wait_with_output().map_err(|e| format!("Failed to run {program}: {e}"))?;
+
+    if output.status.success() {
+        Ok(())
+    }
+    else {
+        Err(format!("{program} exited with {}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+// The sending side of the sink queue, held by State.  Sending never blocks: if the queue is full, the event is
+// dropped and counted, except for removals, which are always queued so that no member is left enforced for good.
+// Removals undo memberships, so the removals queued beyond the bound are limited by the number of members.
+pub struct SinkSender
+{
+    sender : Sender<SinkMessage>,
+
+    // Maximum number of events queued, other than removals
+    queue_size : usize,
+
+    // Number of events queued, shared with the SinkReceiver
+    queued : Arc<AtomicUsize>,
+
+    // Events sent since begin_batch, while a batch is open
+    batch : RefCell<Option<Vec<GroupChange>>>,
+
+    // Map from the address representing a set of equivalent addresses to the other addresses in the set.  Group
+    // changes for the representative address are also sent for each of the other addresses.
+    equivalent_addresses : HashMap<IpAddr, Vec<IpAddr>>,
+
+    // Likewise, map from each network address standing for addresses under coarse grouping to the addresses it has
+    // stood for.  Networks are removed once they are no longer members of any group and can no longer be added to one
+    // without being seen again.
+    grouped_addresses : HashMap<IpAddr, Vec<IpAddr>>,
+
+    // Map from group name to the group's metadata, attached to every group change event for the group
+    group_metadata : HashMap<String, Arc<GroupMetadata>>,
+
+    // Map from group name to the group's throttle rate, attached to every add and update event for the group
+    group_throttles : HashMap<String, ThrottleRate>,
+
+    // Number of events dropped because the queue was full
+    dropped : Cell<u64>,
+
+    // While within with_context, the timestamp of the triggering event, if known, and the stake of the member, if
+    // known, attached to every Add event sent
+    context : Cell<Option<(Option<u64>, Option<u64>)>>,
+
+    // Latencies of additions, shared with the sink worker thread
+    latencies : Arc<DecisionLatencies>,
+
+    // Identifier of the next decision to add
+    next_decision_id : Cell<u64>,
+
+    // Exemplars of the additions sent since the exemplars were last taken
+    exemplars : RefCell<Exemplars>,
+
+    // Offset of the system clock from the internal clock, added to the expirations of the events sent so that sinks,
+    // which act against the system clock (e.g. by kernel timeouts), see them on it
+    clock_offset_ms : Cell<i64>
+}
+
+// Distributions of the latency of group additions from the events that triggered them: to the decision to add, as
+// recorded by SinkSender, and to the completion of the addition by every sink, as recorded by the sink worker thread
+// once it has flushed the sinks after applying the addition, as the latency to decision plus the time since queueing.
+// Additions made on receipt of their triggering event (e.g. of known pubkeys) are decided as of its timestamp, and
+// additions with no triggering event (e.g. imported deny list entries) are not recorded.
+pub struct DecisionLatencies
+{
+    decision : Mutex<Histogram>,
+
+    sink : Mutex<Histogram>
+}
+
+impl SinkSender
+{
+    pub fn set_equivalent_addresses(
+        &mut self,
+        equivalent_addresses : HashMap<IpAddr, Vec<IpAddr>>
+    )
+    {
+        self.equivalent_addresses = equivalent_addresses;
+    }
+
+    // Adds address to the set of addresses that network stands for under coarse grouping, if not already present
+    pub fn add_grouped_address(
+        &mut self,
+        network : IpAddr,
+        address : IpAddr
+    )
+    {
+        let addresses = self.grouped_addresses.entry(network).or_default();
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+
+    pub fn grouped_networks(&self) -> impl Iterator<Item = &IpAddr>
+    {
+        self.grouped_addresses.keys()
+    }
+
+    // Forgets the addresses that each of networks stands for under coarse grouping
+    pub fn remove_grouped_networks(
+        &mut self,
+        networks : &HashSet<IpAddr>
+    )
+    {
+        self.grouped_addresses.retain(|network, _| !networks.contains(network));
+        self.grouped_addresses.shrink_to_fit();
+    }
+
+    pub fn set_group_metadata(
+        &mut self,
+        group_metadata : HashMap<String, Arc<GroupMetadata>>
+    )
+    {
+        self.group_metadata = group_metadata;
+    }
+
+    pub fn set_group_throttles(
+        &mut self,
+        group_throttles : HashMap<String, ThrottleRate>
+    )
+    {
+        self.group_throttles = group_throttles;
+    }
+
+    // Calls f, attaching trigger_timestamp and stake to the Add events that it sends, and recording the latency of each
+    // from trigger_timestamp to its addition
+    pub fn with_context<R>(
+        &self,
+        trigger_timestamp : Option<u64>,
+        stake : Option<u64>,
+        f : impl FnOnce() -> R
+    ) -> R
+    {
+        self.context.set(Some((trigger_timestamp, stake)));
+
+        let result = f();
+
+        self.context.set(None);
+
+        result
+    }
+
+    // Returns the distributions of latency to decision and to sink completion, each with the name of its stage
+    pub fn decision_latencies(&self) -> [(&'static str, Histogram); 2]
+    {
+        [
+            ("decision", self.latencies.decision.lock().unwrap().clone()),
+            ("sink", self.latencies.sink.lock().unwrap().clone())
+        ]
+    }
+
+    // Sets the offset of the system clock from the internal clock, as changed by a re-anchored clock jump
+    pub fn set_clock_offset(
+        &self,
+        clock_offset_ms : i64
+    )
+    {
+        self.clock_offset_ms.set(clock_offset_ms);
+    }
+
+    // Returns the exemplars recorded since the previous call
+    pub fn take_exemplars(&self) -> Exemplars
+    {
+        self.exemplars.take()
+    }
+
+    pub fn send(
+        &self,
+        mut event : GroupChange
+    )
+    {
+        if let GroupChange::Add { group_name, metadata, .. } |
+        GroupChange::Update { group_name, metadata, .. } |
+        GroupChange::Extend { group_name, metadata, .. } |
+        GroupChange::Remove { group_name, metadata, .. } = &mut event
+        {
+            *metadata = self.group_metadata.get(group_name).cloned();
+        }
+
+        if let GroupChange::Add { group_name, throttle, expiration, .. } |
+        GroupChange::Update { group_name, throttle, expiration, .. } |
+        GroupChange::Extend { group_name, throttle, expiration, .. } = &mut event
+        {
+            *throttle = self.group_throttles.get(group_name).copied();
+            if *expiration != PERMANENT_EXPIRATION {
+                *expiration = expiration.saturating_add_signed(self.clock_offset_ms.get());
+            }
+        }
+
+        if let (GroupChange::Add { timestamp, trigger_timestamp, stake, .. }, Some(context)) =
+            (&mut event, self.context.get())
+        {
+            (*trigger_timestamp, *stake) = context;
+            if let Some(trigger_timestamp) = trigger_timestamp {
+                self.latencies.decision.lock().unwrap().add(timestamp.saturating_sub(*trigger_timestamp));
+            }
+        }
+
+        if let GroupChange::Add { group_name, ip_addr, rule, timestamp, trigger_timestamp, decision_id, .. } =
+            &mut event
+        {
+            *decision_id = self.next_decision_id.get();
+            self.next_decision_id.set(*decision_id + 1);
+            let exemplar =
+                |value| Exemplar { decision_id : *decision_id, ip_addr : *ip_addr, timestamp : *timestamp, value };
+            let mut exemplars = self.exemplars.borrow_mut();
+            exemplars.by_group.insert(group_name.clone(), exemplar(1.0));
+            if let Some(threshold) = &rule.threshold {
+                exemplars.by_classification.insert(threshold.classification.to_string(), exemplar(1.0));
+            }
+            if let Some(trigger_timestamp) = trigger_timestamp {
+                let latency = timestamp.saturating_sub(*trigger_timestamp) as f64;
+                if exemplars.slowest_decision.as_ref().map(|slowest| latency > slowest.value).unwrap_or(true) {
+                    exemplars.slowest_decision = Some(exemplar(latency));
+                }
+            }
+        }
+
+        // Send the same group change for each address equivalent to the changed address
+        let ip_addr = match &event {
+            GroupChange::Add { ip_addr, .. } |
+            GroupChange::Update { ip_addr, .. } |
+            GroupChange::Extend { ip_addr, .. } |
+            GroupChange::Remove { ip_addr, .. } => Some(ip_addr),
+            _ => None
+        };
+
+        for equivalent_address in ip_addr
+            .into_iter()
+            .flat_map(|ip_addr| [self.equivalent_addresses.get(ip_addr), self.grouped_addresses.get(ip_addr)])
+            .flatten()
+            .flatten()
+        {
+            self.try_send(event.with_ip_addr(*equivalent_address));
+        }
+
+        self.try_send(event);
+    }
+
+    // Opens a batch: events sent until end_batch are queued together as one message
+    pub fn begin_batch(&self)
+    {
+        self.batch.borrow_mut().get_or_insert_with(Vec::new);
+    }
+
+    // Closes the open batch, if any, queueing its events.  Events of the batch beyond the room left in the queue are
+    // dropped, other than removals, which are queued regardless, so that no member is left enforced for good.
+    pub fn end_batch(&self)
+    {
+        let Some(mut events) = self.batch.borrow_mut().take()
+        else {
+            return;
+        };
+
+        let mut room = self.queue_size.saturating_sub(self.queued.load(Ordering::Relaxed));
+        let event_count = events.len();
+        events.retain(|event| {
+            if matches!(event, GroupChange::Remove { .. }) {
+                true
+            }
+            else if room > 0 {
+                room -= 1;
+                true
+            }
+            else {
+                false
+            }
+        });
+        self.count_dropped(event_count - events.len());
+
+        match events.len() {
+            0 => (),
+            1 => self.send_message(SinkMessage::Event(events.into_iter().next().unwrap(), Instant::now())),
+            _ => self.send_message(SinkMessage::Batch(events, Instant::now()))
+        }
+    }
+
+    fn try_send(
+        &self,
+        event : GroupChange
+    )
+    {
+        if let Some(events) = self.batch.borrow_mut().as_mut() {
+            events.push(event);
+            return;
+        }
+
+        if !matches!(event, GroupChange::Remove { .. }) && (self.queued.load(Ordering::Relaxed) >= self.queue_size) {
+            self.count_dropped(1);
+            return;
+        }
+
+        self.send_message(SinkMessage::Event(event, Instant::now()));
+    }
+
+    fn send_message(
+        &self,
+        message : SinkMessage
+    )
+    {
+        let event_count = message.event_count();
+        self.queued.fetch_add(event_count, Ordering::Relaxed);
+        if self.sender.send(message).is_err() {
+            self.queued.fetch_sub(event_count, Ordering::Relaxed);
+            eprintln!("Sink worker has exited; dropped {event_count} event(s)");
+        }
+    }
+
+    fn count_dropped(
+        &self,
+        event_count : usize
+    )
+    {
+        if event_count > 0 {
+            self.dropped.set(self.dropped.get() + (event_count as u64));
+            eprintln!("Sink queue full; dropped {event_count} event(s) ({} dropped total)", self.dropped.get());
+        }
+    }
+
+    pub fn dropped(&self) -> u64
+    {
+        self.dropped.get()
+    }
+}
+
+// Spawns the sink worker thread, which applies every event to every sink in order, retrying failed events
+// up to retry_count times with retry_delay_ms between attempts.
+pub fn spawn_worker(
+    queue_size : usize,
+    retry_count : u32,
+    retry_delay_ms : u64,
+    sinks : Vec<Box<dyn Sink>>
+) -> SinkSender
+{
+    let (sender, receiver) = channel(queue_size);
+
+    let latencies = sender.latencies.clone();
+
+    std::thread::spawn(move || worker(receiver, retry_count, retry_delay_ms, sinks, latencies));
+
+    sender
+}
+
+// The receiving side of the sink queue, from which the messages sent by a SinkSender are taken
+pub struct SinkReceiver
+{
+    receiver : Receiver<SinkMessage>,
+
+    // Number of events queued, shared with the SinkSender
+    queued : Arc<AtomicUsize>
+}
+
+impl SinkReceiver
+{
+    // Waits for the next message, returning None once the SinkSender is gone
+    pub fn recv(&self) -> Option<SinkMessage>
+    {
+        self.receiver.recv().ok().map(|message| self.taken(message))
+    }
+
+    // Returns the messages queued, without waiting for more
+    pub fn drain(&self) -> impl Iterator<Item = SinkMessage> + '_
+    {
+        self.receiver.try_iter().map(|message| self.taken(message))
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.receiver.is_empty()
+    }
+
+    fn taken(
+        &self,
+        message : SinkMessage
+    ) -> SinkMessage
+    {
+        self.queued.fetch_sub(message.event_count(), Ordering::Relaxed);
+        message
+    }
+}
+
+// Creates a sink queue holding at most queue_size events other than removals, returning the SinkSender that sends to
+// it and the SinkReceiver from which the messages are taken
+pub fn channel(queue_size : usize) -> (SinkSender, SinkReceiver)
+{
+    let (sender, receiver) = unbounded::<SinkMessage>();
+
+    let queued = Arc::new(AtomicUsize::new(0));
+
+    (
+        SinkSender {
+            sender,
+            queue_size,
+            queued : queued.clone(),
+            batch : RefCell::new(None),
+            equivalent_addresses : Default::default(),
+            grouped_addresses : Default::default(),
+            group_metadata : Default::default(),
+            group_throttles : Default::default(),
+            dropped : Cell::new(0),
+            context : Cell::new(None),
+            latencies : Arc::new(DecisionLatencies {
+                decision : Mutex::new(Histogram::new(DECISION_LATENCY_BOUNDS_MS)),
+                sink : Mutex::new(Histogram::new(DECISION_LATENCY_BOUNDS_MS))
+            }),
+            next_decision_id : Cell::new(1),
+            exemplars : Default::default(),
+            clock_offset_ms : Cell::new(0)
+        },
+        SinkReceiver { receiver, queued }
+    )
+}
+
+fn worker(
+    receiver : SinkReceiver,
+    retry_count : u32,
+    retry_delay_ms : u64,
+    mut sinks : Vec<Box<dyn Sink>>,
+    latencies : Arc<DecisionLatencies>
+)
+{
+    // The decision latencies and queueing times of the additions applied since the sinks were last flushed
+    let mut pending_latencies = vec![];
+
+    while let Some(message) = receiver.recv() {
+        let enqueued = message.enqueued();
+        for event in message.into_events() {
+            for sink in &mut sinks {
+                with_retries(retry_count, retry_delay_ms, "apply event", || sink.apply(&event));
+            }
+            if let GroupChange::Add { timestamp, trigger_timestamp: Some(trigger_timestamp), .. } = event {
+                if pending_latencies.len() < MAX_PENDING_SINK_LATENCIES {
+                    pending_latencies.push((timestamp.saturating_sub(trigger_timestamp), enqueued));
+                }
+            }
+        }
+
+        if receiver.is_empty() {
+            for sink in &mut sinks {
+                with_retries(retry_count, retry_delay_ms, "flush", || sink.flush());
+            }
+            // Event timestamps are on the internal clock, so the time since the decision is measured on the monotonic
+            // clock from when the addition was queued
+            let mut sink_latencies = latencies.sink.lock().unwrap();
+            for (decision_latency, enqueued) in pending_latencies.drain(..) {
+                sink_latencies.add(decision_latency + (enqueued.elapsed().as_millis() as u64));
+            }
+        }
+    }
+}
+
+// Calls f until it succeeds, retrying up to retry_count times with retry_delay_ms between attempts
+fn with_retries(
+    retry_count : u32,
+    retry_delay_ms : u64,
+    what : &str,
+    mut f : impl FnMut() -> Result<(), String>
+)
+{
+    let mut attempt = 0;
+    while let Err(e) = f() {
+        if attempt == retry_count {
+            eprintln!("Sink failed to {what} after {} attempts: {e}", attempt + 1);
+            break;
+        }
+        attempt += 1;
+        std::thread::sleep(std::time::Duration::from_millis(retry_delay_ms));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn removals_are_queued_when_the_queue_is_full()
+    {
+        let (sender, receiver) = channel(1);
+
+        let leader_status = |timestamp| GroupChange::LeaderStatus {
+            group_name : None,
+            leader : true,
+            slots_until_leader : None,
+            timestamp
+        };
+        let remove = GroupChange::Remove {
+            group_name : "spammers".to_string(),
+            ip_addr : IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            metadata : None,
+            expired : false
+        };
+
+        sender.send(leader_status(1));
+        sender.send(leader_status(2));
+        sender.send(remove);
+        assert_eq!(sender.dropped(), 1);
+
+        let events = receiver.drain().flat_map(SinkMessage::into_events).collect::<Vec<_>>();
+        assert!(matches!(events[..], [GroupChange::LeaderStatus { timestamp : 1, .. }, GroupChange::Remove { .. }]));
+
+        // Once taken, the events no longer count against the bound
+        sender.send(leader_status(3));
+        assert_eq!(sender.dropped(), 1);
+    }
+}
@@ -1,17 +1,53 @@
 use crate::{
-    config::Config,
-    group::{Group, DEFAULT_GROUP_EXPIRATION_SECONDS}
+    canary::CanaryResults,
+    churn::{ChurnHistory, DEFAULT_CHURN_BUCKET_COUNT, DEFAULT_CHURN_BUCKET_MS},
+    classification::{Classification, FeeCreditPolicy, SourcedValue, ZeroFeeAttribution},
+    clock::ClockJumpAction,
+    config::{
+        Config, GroupMetadata, GroupSizeAction, GroupSummaryFormat, LeaderSlotsWindow, PubkeyChangeAction,
+        StartupLeaderStatus, TpuService, TxKind, UnknownStake, DEFAULT_INGEST_SOURCE
+    },
+    group::{Group, Membership, Rule, DEFAULT_GROUP_EXPIRATION_SECONDS, PERMANENT_EXPIRATION},
+    histogram::{Histogram, CONNECTION_DURATION_BOUNDS_MS},
+    listener::IngestCounters,
+    log::{self, LogLevel},
+    plugin::{ClassifierPlugin, PluginDecision},
+    shrink::Shrink,
+    sink::{GroupChange, Metric, SinkSender},
+    storm::StormTracker,
+    threshold::StakeLookup
 };
+use serde::Serialize;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
-use std::collections::{HashMap, HashSet};
-use std::net::IpAddr;
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
-
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+pub const DEFAULT_PERIODIC_INTERVAL_MS : u64 = 1000; // 1 second
+const DEFAULT_REPORTING_INTERVAL_MS : u64 = 60 * 1000; // 1 minute
+const DEFAULT_GROUP_SUMMARY_TOP_RULE_COUNT : usize = 3;
+const DEFAULT_FEE_CREDIT_HALF_LIFE_MS : u64 = 100;
+const DEFAULT_PROBATION_EXPIRATION_SECONDS : u64 = 10 * 60; // 10 minutes
 const DEFAULT_USELESS_QUIC_CONNECTION_DURATION_MS : u64 = 2 * 1000; // 2 seconds
+
+// Maximum number of unfinished connections tracked per peer for connection durations
+const MAX_OPEN_CONNECTIONS : usize = 1000;
+const SLOT_DURATION_MS : u64 = 400;
 const TX_RETENTION_DURATION_MS : u64 = 2 * 60 * 1000; // 2 minutes
+const DEFAULT_LATE_FEE_RETENTION_MS : u64 = 30 * 1000; // 30 seconds
+const DEFAULT_ZERO_FEE_SHARE_WINDOW_MS : u64 = 10 * 60 * 1000; // 10 minutes
+const DEFAULT_BLOCK_CU_LIMIT : u64 = 48_000_000;
+const DEFAULT_STAKE_CHANGE_PERCENT : f64 = 10.0;
+const MAX_STAKE_HISTORY : usize = 16;
 const PEER_RETENTION_DURATION_MS : u64 = 3 * 24 * 60 * 60 * 1000; // 3 days
 
+// Address of the synthetic peer credited with fees of tx never seen submitted, if attribute_unseen_fees is configured
+pub const UNKNOWN_SUBMITTER : IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
 pub struct State
 {
     // Config is loaded from a file
@@ -23,6 +59,10 @@ pub struct State
     // Fee that represents a tx that paid no fee
     pub zero_fee : Fee,
 
+    // How long tx are retained after first submission before their fees are attributed to submitters; the longest
+    // attribution window of any fee classification
+    pub tx_retention_duration_ms : u64,
+
     // Timestamp of most recent event
     pub most_recent_timestamp : u64,
 
@@ -32,20 +72,182 @@ pub struct State
     // Leader status -- Some(true) if leader, Some(false) if not; None until known
     pub leader_status : Option<bool>,
 
+    // True if currently in our leader slots, as reported by BeginLeader and EndLeader events
+    pub in_leader_slots : bool,
+
+    // Number of slots until our next leader slots and the timestamp at which it was reported, from the most recent
+    // WillBeLeader event; None if not known
+    pub slots_until_leader : Option<(u8, u64)>,
+
+    // Fill of the blocks of the current leader rotation so far, while in our leader slots
+    pub current_slot_fill : Option<SlotFill>,
+
+    // Fill of the blocks of the most recently completed leader rotation
+    pub last_slot_fill : Option<SlotFill>,
+
+    // Timestamp of the first periodic pass, from which a startup leader status wait is measured
+    pub startup_timestamp : Option<u64>,
+
+    // The current epoch, if epoch rollups are configured, and the number of members added to each group as of its
+    // start
+    pub epoch : Option<u64>,
+
+    pub epoch_group_add_counts : HashMap<String, u64>,
+
     // Mapping from IP address to the Peer struct that records peer specific data
     pub peers : HashMap<IpAddr, Peer>,
 
     // Peer stake information
     pub stakes : HashMap<IpAddr, u64>,
 
+    // Addresses whose events are completely ignored, from config
+    pub ignored_addresses : HashSet<IpAddr>,
+
+    // Addresses whose every event is logged at the debug log level, initially from config
+    pub debug_addresses : HashSet<IpAddr>,
+
+    // Addresses whose every event, value accumulation, and threshold evaluation is logged regardless of log level,
+    // as set via the API
+    pub traced_addresses : HashSet<IpAddr>,
+
+    // Service reported by each ingest source that has one, from config
+    pub source_services : HashMap<String, TpuService>,
+
+    // Number of events ignored because they were for ignored addresses
+    pub ignored_event_count : u64,
+
+    // Number of Deprecated messages received, by ingest source.  A non-zero count means that a sender needs upgrading.
+    pub deprecated_counts : HashMap<Arc<str>, u64>,
+
+    // Number of Fee events for tx never seen submitted, and the total of their fees
+    pub unseen_fee_count : u64,
+
+    pub unseen_fee_lamports : u64,
+
+    // Counts of events dropped and connections closed by the ingest rate limit, maintained by the listener threads
+    pub ingest_counters : Arc<IngestCounters>,
+
+    // Map from each address in a set of equivalent addresses to the address that represents the set, from config
+    pub canonical_addresses : HashMap<IpAddr, IpAddr>,
+
     // Current tx.  Tracked for 5 minutes after first seen.
     pub current_tx : HashMap<Signature, Tx>,
 
-    // Pubkey groups.  Map from group name to map from ip_addr to expiration_ms.
-    pub pubkey_groups : HashMap<String, HashMap<IpAddr, u64>>,
+    // Timestamps of first submission and signatures of current tx, in order of first submission, for flushing them in
+    // that order
+    pub current_tx_order : VecDeque<(u64, Signature)>,
+
+    // Tx flushed without a known fee, retained for late_fee_retention_ms so that late Fee events can still be credited
+    // as they would have been before the flush, and the flush timestamps and signatures of those tx in flush order for
+    // expiring them
+    pub flushed_tx : HashMap<Signature, Tx>,
+
+    pub flushed_tx_order : VecDeque<(u64, Signature)>,
+
+    // Number of Fee events credited to the submitters of a tx after the tx was flushed
+    pub late_fee_count : u64,
+
+    // Milliseconds subtracted from every reported timestamp to translate it onto the internal clock, the sum of the
+    // clock jumps re-anchored so far
+    pub clock_offset_ms : i64,
+
+    // Number of jumps of the system clock detected
+    pub clock_jump_count : u64,
+
+    // Number of fee classification values left out because their submitter's stake was outside of the fee stake
+    // bounds
+    pub fee_stake_excluded_count : u64,
+
+    // Most recent readings of the internal clock and the system clock, for diagnosing their divergence
+    pub internal_clock_timestamp : u64,
+
+    pub system_clock_timestamp : u64,
+
+    // Number of current tx reported to have landed in a block other than our own, and how many of those we forwarded
+    pub landed_elsewhere_count : u64,
+
+    pub forwarded_landed_elsewhere_count : u64,
+
+    // All groups, whether their members were added by known pubkey, classification, or probation
+    pub groups : HashMap<String, Group>,
 
-    // Classification groups
-    pub classification_groups : HashMap<String, Group>
+    // Map from group name to the group's metadata, from config
+    pub group_metadata : HashMap<String, Arc<GroupMetadata>>,
+
+    // Number of peers seen for the first time since the most recent status report
+    pub new_peer_count : u64,
+
+    // Lower bounds of stake tiers in lamports, in ascending order and beginning with 0 for unstaked peers
+    pub stake_tiers : Vec<u64>,
+
+    // Histogram of connection durations for each stake tier
+    pub connection_durations : Vec<Histogram>,
+
+    // Aggregate activity of staked and unstaked peers over the most recent reporting interval
+    pub staked_aggregate : PeerAggregate,
+
+    pub unstaked_aggregate : PeerAggregate,
+
+    // Timestamp of the most recent status report
+    pub last_report_timestamp : Option<u64>,
+
+    // Index of the classification at which the next classification evaluation pass begins, so that classifications
+    // deferred by the classification budget are evaluated first in the next pass
+    pub next_classification_index : usize,
+
+    // Schedules of the independent phases of periodic work
+    pub tx_flush_phase : Phase,
+
+    pub classification_phase : Phase,
+
+    pub expiration_phase : Phase,
+
+    pub reporting_phase : Phase,
+
+    // Present if group summaries are configured
+    pub group_summary_phase : Option<Phase>,
+
+    // False if in monitor mode, in which case sinks log group changes but do not enforce them
+    pub enforcing : bool,
+
+    // Whether each of the group size rules from config is currently firing
+    pub group_size_rules_firing : Vec<bool>,
+
+    // Global rates watched by storm rules
+    pub storm : StormTracker,
+
+    // Whether each of the storm rules from config is currently firing
+    pub storm_rules_firing : Vec<bool>,
+
+    // Map from group name to the history of the group's membership changes
+    pub group_churn : HashMap<String, ChurnHistory>,
+
+    // True while a firing group size or storm rule calls for overload thresholds
+    pub overloaded : bool,
+
+    // True while a firing group size or storm rule calls for classifying IPv4 peers by /24 network
+    pub coarse_grouping : bool,
+
+    // Results of the tests of each canary, by canary name
+    pub canary_results : BTreeMap<String, CanaryResults>,
+
+    // Classifier plugins, which are fed every event and whose decisions are applied at each classification pass
+    pub plugins : Vec<Box<dyn ClassifierPlugin>>,
+
+    // The default ingest source, to which events fed by ingest are attributed
+    pub default_source : Arc<str>,
+
+    // Queue of events to be applied by the sink worker thread
+    pub sink : SinkSender
+}
+
+// A phase of periodic work, which is done at most once per interval
+pub struct Phase
+{
+    pub interval_ms : u64,
+
+    // Timestamp at which the phase was last done
+    pub last_timestamp : u64
 }
 
 #[derive(Default)]
@@ -57,8 +259,204 @@ pub struct Peer
     // Timestamp that an event was last seen from this peer
     pub most_recent_timestamp : u64,
 
-    // Total number of tx submitted (votes + user)
-    pub tx_submitted : u64
+    // Total number of vote tx submitted
+    pub vote_tx_submitted : u64,
+
+    // Total number of user (non-vote) tx submitted
+    pub user_tx_submitted : u64,
+
+    // Total number of connections started, including those refused for exceeding connection limits
+    pub connection_count : u64,
+
+    // Total lamports of fees credited to this peer for landed tx
+    pub fee_lamports : u64,
+
+    // Total number of failed or refused connections
+    pub failed_count : u64,
+
+    // Total number of submissions of tx that were first submitted by another peer
+    pub duplicate_count : u64,
+
+    // The submissions for which fees were attributed within the most recent zero_fee_share_window_ms, as (timestamp,
+    // zero_fee), oldest first.  zero_fee is true for submissions which were attributed zero fee, because either the
+    // tx never landed or it was a duplicate of a submission by another peer.
+    pub attributions : VecDeque<(u64, bool)>,
+
+    // Start timestamps of connections which have not yet finished, oldest first.  Connections are assumed to finish in
+    // the order that they started.
+    pub open_connections : VecDeque<u64>,
+
+    // Pubkey most recently reported for this peer, if any
+    pub pubkey : Option<Pubkey>,
+
+    // Number of times the pubkey reported for this peer changed
+    pub pubkey_change_count : u64,
+
+    // The stake first reported for this peer and each material change since, as (timestamp, stake), oldest first.
+    // Only the most recent MAX_STAKE_HISTORY are retained.
+    pub stake_history : VecDeque<(u64, u64)>,
+
+    // Number of material changes of the stake reported for this peer
+    pub stake_change_count : u64,
+
+    // Services that this peer has been seen connecting to, for ingest sources that report their service
+    pub services : Vec<TpuService>,
+
+    // Weighted score computed from this peer's activity over the most recent scoring interval
+    pub score : f64,
+
+    // Counter values as of the most recent scoring, from which the next score is computed
+    pub scored_counters : PeerCounters,
+
+    // Counter values as of the most recent status report, from which the next aggregates are computed
+    pub reported_counters : PeerCounters,
+
+    // Counter values as of the start of the current epoch, from which the epoch rollup is computed
+    pub epoch_counters : PeerCounters
+}
+
+// Snapshot of a peer's cumulative counters
+#[derive(Clone, Copy, Default)]
+pub struct PeerCounters
+{
+    pub vote_tx_submitted : u64,
+
+    pub user_tx_submitted : u64,
+
+    pub fee_lamports : u64,
+
+    pub connection_count : u64,
+
+    pub failed_count : u64,
+
+    pub duplicate_count : u64
+}
+
+// Returns true if a change of stake from old_stake to new_stake is material: to or from zero, or by at least
+// stake_change_percent of old_stake
+fn is_material_stake_change(
+    old_stake : u64,
+    new_stake : u64,
+    stake_change_percent : f64
+) -> bool
+{
+    if old_stake == new_stake {
+        false
+    }
+    else if (old_stake == 0) || (new_stake == 0) {
+        true
+    }
+    else {
+        ((new_stake as f64) - (old_stake as f64)).abs() * 100.0 >= (old_stake as f64) * stake_change_percent
+    }
+}
+
+// Compute units of the fees received during one of our leader rotations, divided by whether their tx was seen
+// submitted by a tracked peer, as a measure of how much of our blocks' capacity tracked peers filled
+#[derive(Clone, Default)]
+pub struct SlotFill
+{
+    pub start_timestamp : u64,
+
+    pub end_timestamp : u64,
+
+    // Number of slots of the rotation, estimated from its duration once it has ended
+    pub slot_count : u64,
+
+    // Compute units used by tx seen submitted by tracked peers
+    pub tracked_cu : u64,
+
+    // Compute units used by tx never seen submitted
+    pub unseen_cu : u64
+}
+
+impl SlotFill
+{
+    // Returns the fraction of the capacity of the rotation's blocks filled by cu
+    pub fn fill(
+        &self,
+        cu : u64,
+        block_cu_limit : u64
+    ) -> f64
+    {
+        (cu as f64) / ((self.slot_count.max(1) * block_cu_limit) as f64)
+    }
+}
+
+// Summary of a peer's activity, as reported by the API and exported when the peer is evicted
+#[derive(Serialize)]
+pub struct PeerSummary
+{
+    pub ip_addr : IpAddr,
+
+    pub stake : u64,
+
+    pub pubkey : Option<String>,
+
+    pub pubkey_change_count : u64,
+
+    pub stake_change_count : u64,
+
+    // The stake first reported for the peer and each material change since, oldest first
+    pub stake_history : Vec<StakeHistoryEntry>,
+
+    pub first_timestamp : u64,
+
+    pub most_recent_timestamp : u64,
+
+    // Votes + user tx
+    pub tx_submitted : u64,
+
+    pub vote_tx_submitted : u64,
+
+    pub user_tx_submitted : u64,
+
+    pub connection_count : u64,
+
+    pub fee_lamports : u64,
+
+    pub failed_count : u64,
+
+    pub duplicate_count : u64,
+
+    // Number of submissions for which fees were attributed within the zero fee share window
+    pub attributed_count : u64,
+
+    // Number of those submissions which were attributed zero fee
+    pub zero_fee_count : u64,
+
+    // Fraction of attributed submissions that were credited with zero fee; None if no submissions were attributed
+    pub zero_fee_share : Option<f64>,
+
+    pub score : f64,
+
+    // Services the peer has been seen connecting to, where known
+    pub services : Vec<&'static str>,
+
+    pub groups : Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct StakeHistoryEntry
+{
+    pub timestamp : u64,
+
+    pub stake : u64
+}
+
+// Aggregate activity of a set of peers over a reporting interval
+#[derive(Default)]
+pub struct PeerAggregate
+{
+    pub peer_count : u64,
+
+    // Number of peers with any counter change within the interval
+    pub active_peer_count : u64,
+
+    // Sums of the changes to the peers' counters within the interval
+    pub counters : PeerCounters,
+
+    pub duration_ms : u64
 }
 
 #[derive(Default)]
@@ -71,44 +469,87 @@ pub struct Tx
     pub submissions : Vec<SubmittedTx>,
 
     // Fee paid by the tx, if known
-    pub fee : Option<Fee>
+    pub fee : Option<Fee>,
+
+    // True if the tx was forwarded to another leader
+    pub forwarded : bool,
+
+    // Where the tx landed, if reported from outside of ingest (e.g. from RPC)
+    pub landed : Option<Landed>
+}
+
+#[derive(Clone, Copy)]
+pub struct Landed
+{
+    pub slot : u64,
+
+    // True if the block was one of our own; fees are only reported for tx landing in our own blocks
+    pub our_block : bool
 }
 
 pub struct SubmittedTx
 {
     pub timestamp : u64,
 
-    pub submitter : IpAddr
+    pub submitter : IpAddr,
+
+    // Pubkey most recently reported from the submitter at the time of submission, if any
+    pub submitter_pubkey : Option<Pubkey>,
+
+    // Ingest source from which the submission was reported
+    pub source : Arc<str>
 }
 
 impl Tx
 {
     pub fn new(
         timestamp : u64,
-        first_submitter : IpAddr
+        first_submitter : IpAddr,
+        first_submitter_pubkey : Option<Pubkey>,
+        source : Arc<str>
     ) -> Self
     {
         Self {
             submitters : vec![first_submitter].into_iter().collect(),
-            submissions : vec![SubmittedTx { timestamp, submitter : first_submitter.clone() }],
-            fee : None
+            submissions : vec![SubmittedTx {
+                timestamp,
+                submitter : first_submitter.clone(),
+                submitter_pubkey : first_submitter_pubkey,
+                source
+            }],
+            fee : None,
+            forwarded : false,
+            landed : None
         }
     }
 
+    // Records a submission of the tx by submitter.  Returns true if submitter had not previously submitted the tx, i.e.
+    // if this is a duplicate submission of a tx first submitted by someone else.
     pub fn submitted(
         &mut self,
         timestamp : u64,
-        submitter : IpAddr
-    )
+        submitter : IpAddr,
+        submitter_pubkey : Option<Pubkey>,
+        source : Arc<str>
+    ) -> bool
     {
         // If it's already been submitted by this submitter, then nothing more to do
         if self.submitters.contains(&submitter) {
-            return;
+            return false;
         }
 
         self.submitters.insert(submitter);
 
-        self.submissions.push(SubmittedTx { timestamp, submitter : submitter.clone() });
+        self.submissions.push(SubmittedTx { timestamp, submitter : submitter.clone(), submitter_pubkey, source });
+
+        true
+    }
+
+    // Returns true if the tx is known to have landed in another leader's block, in which case its fee is never
+    // reported, so is unknown rather than zero
+    pub fn landed_elsewhere(&self) -> bool
+    {
+        self.fee.is_none() && self.landed.map(|landed| !landed.our_block).unwrap_or(false)
     }
 }
 
@@ -122,9 +563,162 @@ pub struct Fee
     pub cu_used : u64
 }
 
+impl Peer
+{
+    pub fn counters(&self) -> PeerCounters
+    {
+        PeerCounters {
+            vote_tx_submitted : self.vote_tx_submitted,
+            user_tx_submitted : self.user_tx_submitted,
+            fee_lamports : self.fee_lamports,
+            connection_count : self.connection_count,
+            failed_count : self.failed_count,
+            duplicate_count : self.duplicate_count
+        }
+    }
+
+    // Total number of tx submitted of the given kind
+    pub fn tx_submitted(
+        &self,
+        tx_kind : TxKind
+    ) -> u64
+    {
+        match tx_kind {
+            TxKind::All => self.vote_tx_submitted + self.user_tx_submitted,
+            TxKind::User => self.user_tx_submitted,
+            TxKind::Vote => self.vote_tx_submitted
+        }
+    }
+
+    // Records the attribution of a fee to a submission at timestamp, discarding those older than window_ms before now
+    fn attribute(
+        &mut self,
+        timestamp : u64,
+        zero_fee : bool,
+        now : u64,
+        window_ms : u64
+    )
+    {
+        self.attributions.push_back((timestamp, zero_fee));
+        while self.attributions.front().map(|(timestamp, _)| (timestamp + window_ms) < now).unwrap_or(false) {
+            self.attributions.pop_front();
+        }
+    }
+}
+
+impl PeerCounters
+{
+    // Total number of tx submitted (votes + user)
+    pub fn tx_submitted(&self) -> u64
+    {
+        self.vote_tx_submitted + self.user_tx_submitted
+    }
+}
+
+// Returns the JSON representation of counters accumulated over an epoch
+fn epoch_counters_json(counters : &PeerCounters) -> serde_json::Value
+{
+    serde_json::json!({
+        "tx_submitted" : counters.tx_submitted(),
+        "vote_tx_submitted" : counters.vote_tx_submitted,
+        "user_tx_submitted" : counters.user_tx_submitted,
+        "fee_lamports" : counters.fee_lamports,
+        "connection_count" : counters.connection_count,
+        "failed_count" : counters.failed_count,
+        "duplicate_count" : counters.duplicate_count
+    })
+}
+
+impl PeerAggregate
+{
+    fn add(
+        &mut self,
+        current : &PeerCounters,
+        previous : &PeerCounters
+    )
+    {
+        self.peer_count += 1;
+
+        let vote_tx_submitted = current.vote_tx_submitted - previous.vote_tx_submitted;
+        let user_tx_submitted = current.user_tx_submitted - previous.user_tx_submitted;
+        let fee_lamports = current.fee_lamports - previous.fee_lamports;
+        let connection_count = current.connection_count - previous.connection_count;
+        let failed_count = current.failed_count - previous.failed_count;
+        let duplicate_count = current.duplicate_count - previous.duplicate_count;
+
+        if (vote_tx_submitted + user_tx_submitted + fee_lamports + connection_count + failed_count + duplicate_count) >
+            0
+        {
+            self.active_peer_count += 1;
+        }
+
+        self.counters.vote_tx_submitted += vote_tx_submitted;
+        self.counters.user_tx_submitted += user_tx_submitted;
+        self.counters.fee_lamports += fee_lamports;
+        self.counters.connection_count += connection_count;
+        self.counters.failed_count += failed_count;
+        self.counters.duplicate_count += duplicate_count;
+    }
+
+    // Returns the rate per second of count over the interval
+    pub fn rate(
+        &self,
+        count : u64
+    ) -> f64
+    {
+        if self.duration_ms == 0 {
+            0.0
+        }
+        else {
+            ((count as f64) * 1000.0) / (self.duration_ms as f64)
+        }
+    }
+
+    // Average fee lamports credited per tx submitted, or None if no tx were submitted
+    pub fn average_fee_lamports(&self) -> Option<f64>
+    {
+        (self.counters.tx_submitted() > 0)
+            .then(|| (self.counters.fee_lamports as f64) / (self.counters.tx_submitted() as f64))
+    }
+
+    // Fraction of connections that failed or were refused, or None if there were no connections
+    pub fn failure_rate(&self) -> Option<f64>
+    {
+        (self.counters.connection_count > 0)
+            .then(|| (self.counters.failed_count as f64) / (self.counters.connection_count as f64))
+    }
+}
+
+impl Phase
+{
+    pub fn new(interval_ms : u64) -> Self
+    {
+        Self { interval_ms, last_timestamp : 0 }
+    }
+
+    // Returns true if the phase is due to be done at time now, in which case it is considered to have been done
+    pub fn is_due(
+        &mut self,
+        now : u64
+    ) -> bool
+    {
+        if now < (self.last_timestamp + self.interval_ms) {
+            false
+        }
+        else {
+            self.last_timestamp = now;
+            true
+        }
+    }
+}
+
 impl State
 {
-    pub fn new(config : Config) -> Self
+    pub fn new(
+        config : Config,
+        mut sink : SinkSender,
+        ingest_counters : Arc<IngestCounters>
+    ) -> Self
     {
         // Create the pubkey_classifications
         let pubkey_classifications = if let Some(known_pubkeys) = &config.known_pubkeys {
@@ -148,138 +742,672 @@ impl State
             Default::default()
         };
 
+        let periodic_interval_ms = config.periodic_interval_ms.unwrap_or(DEFAULT_PERIODIC_INTERVAL_MS);
+
+        let tx_retention_duration_ms = Self::tx_retention_duration_ms(&config);
+
+        let ignored_addresses = config.ignored_addresses.iter().flatten().cloned().collect();
+
+        let debug_addresses = config.debug_addresses.iter().flatten().cloned().collect();
+
+        let source_services = config.source_services();
+
+        // Group changes for each set of equivalent addresses are made to the representative address, and must also be
+        // applied by sinks to the other addresses in the set
+        let canonical_addresses = config.canonical_addresses();
+
+        let mut equivalent_addresses = HashMap::<IpAddr, Vec<IpAddr>>::new();
+        for (address, canonical) in &canonical_addresses {
+            if address != canonical {
+                equivalent_addresses.entry(*canonical).or_default().push(*address);
+            }
+        }
+
+        sink.set_equivalent_addresses(equivalent_addresses);
+
+        let group_metadata = config
+            .group_metadata
+            .iter()
+            .flatten()
+            .map(|(group_name, metadata)| (group_name.clone(), Arc::new(metadata.clone())))
+            .collect::<HashMap<String, Arc<GroupMetadata>>>();
+
+        sink.set_group_metadata(group_metadata.clone());
+
+        sink.set_group_throttles(config.group_throttles.clone().unwrap_or_default());
+
+        // Groups with a configured ttl policy, expiration limits, or extension events are created up front with them;
+        // all others are created as needed with the defaults
+        let groups = config
+            .group_ttl_policies
+            .iter()
+            .flatten()
+            .map(|(group_name, _)| group_name)
+            .chain(config.group_expiration_limits.iter().flatten().map(|(group_name, _)| group_name))
+            .chain(config.group_extension_events.iter().flatten().map(|(group_name, _)| group_name))
+            .map(|group_name| {
+                let ttl_policy = config
+                    .group_ttl_policies
+                    .as_ref()
+                    .and_then(|policies| policies.get(group_name))
+                    .copied()
+                    .unwrap_or_default();
+                let expiration_limits = config
+                    .group_expiration_limits
+                    .as_ref()
+                    .and_then(|limits| limits.get(group_name))
+                    .cloned()
+                    .unwrap_or_default();
+                let extension_events = config
+                    .group_extension_events
+                    .as_ref()
+                    .and_then(|extension_events| extension_events.get(group_name))
+                    .copied()
+                    .unwrap_or_default();
+                (group_name.clone(), Group::with_policies(group_name, ttl_policy, &expiration_limits, extension_events))
+            })
+            .collect::<HashMap<String, Group>>();
+
+        let enforcing = !config.monitor_mode.unwrap_or(false);
+
+        let mut stake_tiers = config.stake_tiers.clone().unwrap_or(vec![1]);
+        stake_tiers.push(0);
+        stake_tiers.sort();
+        stake_tiers.dedup();
+
+        let connection_durations = vec![Histogram::new(CONNECTION_DURATION_BOUNDS_MS); stake_tiers.len()];
+
+        if !enforcing {
+            sink.send(GroupChange::Mode { enforcing, timestamp : 0 });
+        }
+
+        let group_size_rule_count = config.group_size_rules.as_ref().map(|rules| rules.len()).unwrap_or(0);
+        let storm_rule_count = config.storm_rules.as_ref().map(|rules| rules.len()).unwrap_or(0);
+
         Self {
+            tx_flush_phase : Phase::new(config.tx_flush_interval_ms.unwrap_or(periodic_interval_ms)),
+            classification_phase : Phase::new(config.classification_interval_ms.unwrap_or(periodic_interval_ms)),
+            expiration_phase : Phase::new(config.expiration_interval_ms.unwrap_or(periodic_interval_ms)),
+            reporting_phase : Phase::new(config.reporting_interval_ms.unwrap_or(DEFAULT_REPORTING_INTERVAL_MS)),
+            group_summary_phase : config
+                .group_summaries
+                .as_ref()
+                .map(|group_summaries| Phase::new(group_summaries.interval_ms.unwrap_or(periodic_interval_ms))),
             config,
             pubkey_classifications,
             zero_fee : Fee { total : 0, cu_limit : 1, cu_used : 1 },
+            tx_retention_duration_ms,
             most_recent_timestamp : 0,
             most_recent_timestamp_event_count : 0,
             leader_status : None,
+            in_leader_slots : false,
+            current_slot_fill : None,
+            last_slot_fill : None,
+            slots_until_leader : None,
+            startup_timestamp : None,
+            epoch : None,
+            epoch_group_add_counts : Default::default(),
+            new_peer_count : 0,
             peers : Default::default(),
             stakes : Default::default(),
+            ignored_addresses,
+            debug_addresses,
+            traced_addresses : Default::default(),
+            source_services,
+            ignored_event_count : 0,
+            deprecated_counts : Default::default(),
+            flushed_tx : Default::default(),
+            flushed_tx_order : Default::default(),
+            late_fee_count : 0,
+            clock_offset_ms : 0,
+            clock_jump_count : 0,
+            fee_stake_excluded_count : 0,
+            internal_clock_timestamp : 0,
+            system_clock_timestamp : 0,
+            landed_elsewhere_count : 0,
+            forwarded_landed_elsewhere_count : 0,
+            unseen_fee_count : 0,
+            unseen_fee_lamports : 0,
+            ingest_counters,
+            staked_aggregate : Default::default(),
+            unstaked_aggregate : Default::default(),
+            last_report_timestamp : None,
+            next_classification_index : 0,
+            stake_tiers,
+            connection_durations,
+            canonical_addresses,
             current_tx : Default::default(),
-            pubkey_groups : Default::default(),
-            classification_groups : Default::default()
+            current_tx_order : Default::default(),
+            groups,
+            group_metadata,
+            enforcing,
+            group_size_rules_firing : vec![false; group_size_rule_count],
+            storm : Default::default(),
+            storm_rules_firing : vec![false; storm_rule_count],
+            group_churn : Default::default(),
+            overloaded : false,
+            coarse_grouping : false,
+            canary_results : Default::default(),
+            plugins : vec![],
+            default_source : Arc::from(DEFAULT_INGEST_SOURCE),
+            sink
         }
     }
 
-    // Gets the timestamp to use given the reported timestamp of an event
-    fn get_timestamp(
+    // Starts or stops tracing ip_addr.  Returns false if it was already in that state.
+    pub fn set_traced(
         &mut self,
-        timestamp : u64
-    ) -> u64
+        ip_addr : IpAddr,
+        traced : bool
+    ) -> bool
     {
-        // If time stays the same or goes backwards, allow a maximum of 100 events before forcing time forward
-        // by 1 ms.  This is to make the case where time goes backwards sane -- every 100 events will be considered
-        // to be in the same millisecond, which is generally rational.
-        if timestamp <= self.most_recent_timestamp {
-            if self.most_recent_timestamp_event_count == 100 {
-                self.most_recent_timestamp += 1;
-                self.most_recent_timestamp_event_count = 0;
+        let changed =
+            if traced { self.traced_addresses.insert(ip_addr) } else { self.traced_addresses.remove(&ip_addr) };
+
+        if changed {
+            println!("Tracing of {ip_addr} {}", if traced { "enabled" } else { "disabled" });
+            for classification in self.config.classifications_mut() {
+                classification.traced_addresses = self.traced_addresses.clone();
             }
         }
-        else {
-            self.most_recent_timestamp = timestamp;
-            self.most_recent_timestamp_event_count = 0;
-        }
 
-        self.most_recent_timestamp
+        changed
     }
 
-    pub fn failed(
+    // Switches between enforce and monitor mode.  No state is lost in monitor mode, so on switching back to enforce
+    // mode the current members of every group are re-sent to sinks.
+    pub fn set_enforcing(
         &mut self,
-        timestamp : u64,
-        peer_addr : IpAddr
+        enforcing : bool,
+        now : u64
     )
     {
-        let timestamp = self.get_timestamp(timestamp);
+        if enforcing == self.enforcing {
+            return;
+        }
 
-        if let Some(failed_exceeded_quic_connections) = &mut self.config.failed_exceeded_quic_connections {
-            failed_exceeded_quic_connections.add_value(peer_addr, timestamp, 1);
+        self.enforcing = enforcing;
+
+        self.sink.send(GroupChange::Mode { enforcing, timestamp : now });
+
+        if enforcing {
+            self.sink.begin_batch();
+            for group in self.groups.values() {
+                group.resend(&self.sink);
+            }
+            self.sink.end_batch();
         }
     }
 
-    pub fn exceeded(
+    // Returns true if events for peer_addr are to be ignored, counting the ignored event
+    fn is_ignored(
         &mut self,
+        peer_addr : &IpAddr
+    ) -> bool
+    {
+        if self.ignored_addresses.contains(peer_addr) {
+            self.ignored_event_count += 1;
+            true
+        }
+        else {
+            false
+        }
+    }
+
+    // Returns the address that represents the peer at peer_addr, which differs from peer_addr only if peer_addr is one
+    // of a set of equivalent addresses, or coarse grouping is in effect
+    pub fn canonical_address(
+        &mut self,
+        peer_addr : IpAddr
+    ) -> IpAddr
+    {
+        let canonical = *self.canonical_addresses.get(&peer_addr).unwrap_or(&peer_addr);
+
+        match canonical {
+            IpAddr::V4(ipv4_addr) if self.coarse_grouping => {
+                let network = IpAddr::V4(Ipv4Addr::from(u32::from(ipv4_addr) & 0xFFFF_FF00));
+                // Group changes for the network address must reach the addresses it stands for
+                if network != canonical {
+                    self.sink.add_grouped_address(network, canonical);
+                }
+                network
+            },
+            _ => canonical
+        }
+    }
+
+    // Gets the timestamp to use given the reported timestamp of an event, which was read from the system clock
+    fn get_timestamp(
+        &mut self,
+        timestamp : u64
+    ) -> u64
+    {
+        // Translate out the clock jumps re-anchored so far
+        self.advance_timestamp(timestamp.saturating_add_signed(-self.clock_offset_ms))
+    }
+
+    // Gets the timestamp to use given a time on the internal clock, as when scheduled work runs
+    fn advance_timestamp(
+        &mut self,
+        timestamp : u64
+    ) -> u64
+    {
+        // If time stays the same or goes backwards, allow a maximum of 100 events before forcing time forward
+        // by 1 ms.  This is to make the case where time goes backwards sane -- every 100 events will be considered
+        // to be in the same millisecond, which is generally rational.
+        if timestamp <= self.most_recent_timestamp {
+            if self.most_recent_timestamp_event_count == 100 {
+                self.most_recent_timestamp += 1;
+                self.most_recent_timestamp_event_count = 0;
+            }
+        }
+        else {
+            self.most_recent_timestamp = timestamp;
+            self.most_recent_timestamp_event_count = 0;
+        }
+
+        self.most_recent_timestamp
+    }
+
+    // Tx are retained for the longest attribution window of the fee classifications
+    fn tx_retention_duration_ms(config : &Config) -> u64
+    {
+        [
+            &config.fee_lamports_submitted,
+            &config.fee_microlamports_per_cu_limit,
+            &config.fee_microlamports_per_cu_used,
+            &config.zero_fee_share
+        ]
+        .into_iter()
+        .flatten()
+        .map(|classification| classification.attribution_window_ms.unwrap_or(TX_RETENTION_DURATION_MS))
+        .max()
+        .unwrap_or(TX_RETENTION_DURATION_MS)
+    }
+
+    // Replaces the classification definitions, the fee stake bounds, and the peer score weights with those of config,
+    // which has been validated, keeping peers, group members, and the values accumulated by classifications that
+    // remain.  The rest of config is ignored.  Returns a description of each change, for logging.
+    pub fn reload_classifications(
+        &mut self,
+        mut config : Config
+    ) -> Vec<String>
+    {
+        let mut changes = vec![];
+
+        for (slot, new_slot) in
+            self.config.classification_slots_mut().into_iter().zip(config.classification_slots_mut())
+        {
+            match (slot.as_mut(), new_slot.take()) {
+                (Some(classification), Some(new)) => {
+                    if classification.reload(new) {
+                        changes.push(format!("{}: reloaded", classification.name));
+                    }
+                    else {
+                        changes.push(format!(
+                            "{}: reloaded; accumulated values discarded, since the new definition accumulates them \
+                             differently",
+                            classification.name
+                        ));
+                    }
+                },
+                (None, Some(mut new)) => {
+                    changes.push(format!("{}: added", new.name));
+                    // Applied to the new classification as to those already configured
+                    new.overloaded = self.overloaded;
+                    new.traced_addresses = self.traced_addresses.clone();
+                    *slot = Some(new);
+                },
+                (Some(classification), None) => {
+                    changes
+                        .push(format!("{}: removed; members it added remain until they expire", classification.name));
+                    *slot = None;
+                },
+                (None, None) => ()
+            }
+        }
+
+        self.config.fee_min_stake = config.fee_min_stake;
+        self.config.fee_max_stake = config.fee_max_stake;
+        self.config.peer_score_weights = config.peer_score_weights.take();
+
+        self.tx_retention_duration_ms = Self::tx_retention_duration_ms(&self.config);
+
+        changes
+    }
+
+    // Records readings of the internal clock and the system clock
+    pub fn clock_read(
+        &mut self,
+        internal_timestamp : u64,
+        system_timestamp : u64
+    )
+    {
+        self.internal_clock_timestamp = internal_timestamp;
+        self.system_clock_timestamp = system_timestamp;
+    }
+
+    // Handles a jump of the system clock by jump_ms (negative if backward), as detected by comparison against the
+    // monotonic clock, according to the configured clock jump action
+    pub fn clock_jumped(
+        &mut self,
+        jump_ms : i64
+    )
+    {
+        self.clock_jump_count += 1;
+
+        let direction = if jump_ms > 0 { "forward" } else { "backward" };
+
+        match self.config.clock_jump.as_ref().and_then(|clock_jump| clock_jump.action).unwrap_or_default() {
+            ClockJumpAction::Reanchor => {
+                self.clock_offset_ms += jump_ms;
+                self.sink.set_clock_offset(self.clock_offset_ms);
+                eprintln!(
+                    "WARNING: System clock jumped {direction} by {} ms; re-anchored, so that time continues from \
+                     before the jump (total offset {} ms)",
+                    jump_ms.unsigned_abs(),
+                    self.clock_offset_ms
+                );
+            },
+            ClockJumpAction::Follow => {
+                eprintln!("WARNING: System clock jumped {direction} by {} ms; following it", jump_ms.unsigned_abs())
+            }
+        }
+    }
+
+    // Handles an event from the default ingest source, for embedders feeding events directly rather than by listener
+    pub fn ingest(
+        &mut self,
+        msg : TxIngestMsg
+    )
+    {
+        let source = self.default_source.clone();
+
+        self.handle(&source, msg);
+    }
+
+    // Handles an event received from source
+    pub fn handle(
+        &mut self,
+        source : &Arc<str>,
+        msg : TxIngestMsg
+    )
+    {
+        if !self.traced_addresses.is_empty() || (log::enabled(LogLevel::Debug) && !self.debug_addresses.is_empty()) {
+            self.trace(source, &msg);
+        }
+
+        for plugin in &mut self.plugins {
+            plugin.handle(source, &msg);
+        }
+
+        match msg {
+            TxIngestMsg::Failed { timestamp, peer_addr } => self.failed(source, timestamp, peer_addr, None),
+            TxIngestMsg::Exceeded { timestamp, peer_addr, peer_pubkey, stake } => {
+                self.exceeded(source, timestamp, peer_addr, peer_pubkey, stake)
+            },
+            TxIngestMsg::Started { timestamp, peer_addr, peer_pubkey, stake } => {
+                self.started(source, timestamp, peer_addr, peer_pubkey, stake)
+            },
+            TxIngestMsg::Finished { timestamp, peer_addr } => self.finished(source, timestamp, peer_addr),
+            TxIngestMsg::VoteTx { timestamp, peer_addr } => self.votetx(timestamp, peer_addr),
+            TxIngestMsg::UserTx { timestamp, peer_addr, signature } => {
+                self.usertx(source, timestamp, peer_addr, signature)
+            },
+            TxIngestMsg::Forwarded { timestamp, signature } => self.forwarded(timestamp, signature),
+            TxIngestMsg::BadFee { timestamp, signature } => self.badfee(timestamp, signature),
+            TxIngestMsg::Fee { timestamp, signature, cu_limit, cu_used, fee } => {
+                self.fee(timestamp, signature, cu_limit, cu_used, fee)
+            },
+            TxIngestMsg::WillBeLeader { timestamp, slots } => self.will_be_leader(timestamp, slots),
+            TxIngestMsg::BeginLeader { timestamp } => self.begin_leader(timestamp),
+            TxIngestMsg::EndLeader { timestamp } => self.end_leader(timestamp),
+            TxIngestMsg::Deprecated => self.deprecated(source)
+        }
+    }
+
+    // Logs msg if it is from a traced address, or from a debug address at the debug log level
+    fn trace(
+        &self,
+        source : &str,
+        msg : &TxIngestMsg
+    )
+    {
+        let peer_addr = match msg {
+            TxIngestMsg::Failed { peer_addr, .. } |
+            TxIngestMsg::Exceeded { peer_addr, .. } |
+            TxIngestMsg::Started { peer_addr, .. } |
+            TxIngestMsg::Finished { peer_addr, .. } |
+            TxIngestMsg::VoteTx { peer_addr, .. } |
+            TxIngestMsg::UserTx { peer_addr, .. } => peer_addr,
+            _ => return
+        };
+
+        if self.traced_addresses.contains(peer_addr) {
+            println!("TRACE {peer_addr} from {source}: {msg:?}");
+        }
+        else if log::enabled(LogLevel::Debug) && self.debug_addresses.contains(peer_addr) {
+            println!("DEBUG {peer_addr} from {source}: {msg:?}");
+        }
+    }
+
+    // A failed connection from peer_addr, by the identity of peer_pubkey if the event carried it, else by the address
+    pub fn failed(
+        &mut self,
+        source : &str,
+        timestamp : u64,
+        peer_addr : IpAddr,
+        peer_pubkey : Option<Pubkey>
+    )
+    {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
+        // Storms are measured by address before coarse grouping, so that coarse grouping does not mask them
+        if self.config.storm_rules.is_some() {
+            self.storm.failed(peer_addr);
+        }
+
+        let peer_addr = self.canonical_address(peer_addr);
+
+        let timestamp = self.get_timestamp(timestamp);
+
+        if let Some(peer) = self.peers.get_mut(&peer_addr) {
+            peer.failed_count += 1;
+        }
+
+        if let Some(failed_exceeded_quic_connections) = &mut self.config.failed_exceeded_quic_connections {
+            failed_exceeded_quic_connections.add_value(peer_addr, peer_pubkey, timestamp, 1, source);
+        }
+    }
+
+    pub fn exceeded(
+        &mut self,
+        source : &str,
         timestamp : u64,
         peer_addr : IpAddr,
         peer_pubkey : Option<Pubkey>,
         stake : u64
     )
     {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
         // Treat it as a failure by that IP address
-        self.failed(timestamp, peer_addr.clone());
+        self.failed(source, timestamp, peer_addr, peer_pubkey);
 
         // Additionally, record the identity and stake level if not previously known
-        self.started(timestamp, peer_addr, peer_pubkey, stake);
+        self.started(source, timestamp, peer_addr, peer_pubkey, stake);
     }
 
     pub fn started(
         &mut self,
+        source : &str,
         timestamp : u64,
         peer_addr : IpAddr,
         peer_pubkey : Option<Pubkey>,
         stake : u64
     )
     {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
+        let peer_addr = self.canonical_address(peer_addr);
+
         let timestamp = self.get_timestamp(timestamp);
 
-        let peer = self.peers.entry(peer_addr.clone()).or_insert_with(|| Peer {
-            first_timestamp : timestamp,
-            most_recent_timestamp : timestamp,
-            ..Peer::default()
+        if self.config.storm_rules.is_some() {
+            self.storm.started();
+        }
+
+        let service = self.source_services.get(source).cloned();
+
+        let peer = self.peers.entry(peer_addr.clone()).or_insert_with(|| {
+            self.new_peer_count += 1;
+            if self.config.emit_new_peer_events.unwrap_or(false) {
+                self.sink.send(GroupChange::NewPeer {
+                    ip_addr : peer_addr,
+                    pubkey : peer_pubkey,
+                    stake,
+                    service,
+                    timestamp
+                });
+            }
+            Peer { first_timestamp : timestamp, most_recent_timestamp : timestamp, ..Peer::default() }
         });
 
+        // Identity churn at an address is itself a signal, so pubkey changes are reported and classified
+        if let (Some(old_pubkey), Some(new_pubkey)) = (peer.pubkey, peer_pubkey) {
+            if old_pubkey != new_pubkey {
+                self.sink.send(GroupChange::PubkeyChange { ip_addr : peer_addr, old_pubkey, new_pubkey, timestamp });
+
+                if self.config.pubkey_change_action == Some(PubkeyChangeAction::Reset) {
+                    *peer = Peer { first_timestamp : timestamp, ..Peer::default() };
+                    for classification in self.config.classifications_mut() {
+                        classification.forget(&peer_addr);
+                    }
+                }
+
+                peer.pubkey_change_count += 1;
+
+                if let Some(pubkey_changes) = &mut self.config.pubkey_changes {
+                    pubkey_changes.add_value(peer_addr, None, timestamp, 1, source);
+                }
+            }
+        }
+
+        if peer_pubkey.is_some() {
+            peer.pubkey = peer_pubkey;
+        }
+
+        let is_new_peer = peer.connection_count == 0;
+
+        if let Some(service) = service {
+            if !peer.services.contains(&service) {
+                peer.services.push(service);
+            }
+        }
+
         peer.most_recent_timestamp = timestamp;
 
+        peer.connection_count += 1;
+
+        peer.open_connections.push_back(timestamp);
+        if peer.open_connections.len() > MAX_OPEN_CONNECTIONS {
+            peer.open_connections.pop_front();
+        }
+
+        // Material stake changes move a peer between threshold stake tiers and are a signal in themselves, so they
+        // are recorded and reported.  The stake is compared against that last recorded rather than that last
+        // reported, so that a gradual drift is reported once it adds up to a material change.
+        let stake_change_percent = self.config.stake_change_percent.unwrap_or(DEFAULT_STAKE_CHANGE_PERCENT);
+        let old_stake =
+            peer.stake_history.back().map(|(_, stake)| *stake).or_else(|| self.stakes.get(&peer_addr).copied());
+        let material =
+            old_stake.map(|old_stake| is_material_stake_change(old_stake, stake, stake_change_percent)).unwrap_or(true);
+        if let (true, Some(old_stake)) = (material, old_stake) {
+            peer.stake_change_count += 1;
+            self.sink.send(GroupChange::StakeChange { ip_addr : peer_addr, old_stake, new_stake : stake, timestamp });
+        }
+        if material || peer.stake_history.is_empty() {
+            peer.stake_history.push_back((timestamp, stake));
+            if peer.stake_history.len() > MAX_STAKE_HISTORY {
+                peer.stake_history.pop_front();
+            }
+        }
+
+        // Place never-before-seen unstaked peers on probation
+        if is_new_peer && (stake == 0) {
+            if let Some(probation) = &self.config.probation {
+                let group_name = probation.group_name.as_ref().unwrap();
+                self.sink.with_context(Some(timestamp), Some(stake), || {
+                    self.groups.entry(group_name.clone()).or_insert_with(|| Group::new(group_name)).add(
+                        peer_addr,
+                        None,
+                        timestamp,
+                        probation.group_expiration_seconds.unwrap_or(DEFAULT_PROBATION_EXPIRATION_SECONDS) * 1000,
+                        &Rule::named("probation"),
+                        &self.sink
+                    )
+                });
+            }
+        }
+
         self.stakes.insert(peer_addr, stake);
 
         // If there is a classification for this pubkey, then put it in the corresponding group
         if let Some(peer_pubkey) = peer_pubkey {
             if let Some((group_name, group_expiration)) = self.pubkey_classifications.get(&peer_pubkey) {
-                let new_expiration = timestamp + (group_expiration * 1000);
-                self.pubkey_groups
-                    .entry(group_name.clone())
-                    .or_default()
-                    .entry(peer_addr)
-                    .and_modify(|expiration| {
-                        if *expiration < new_expiration {
-                            *expiration = new_expiration;
-                            println!(
-                                "Update {peer_pubkey} to {group_name} at address {peer_addr} with expiration \
-                                 {new_expiration}"
-                            );
-                        }
-                    })
-                    .or_insert_with(|| {
-                        println!(
-                            "Add {peer_pubkey} to {group_name} at address {peer_addr} with expiration {new_expiration}"
-                        );
-                        new_expiration
-                    });
+                self.sink.with_context(Some(timestamp), Some(stake), || {
+                    self.groups.entry(group_name.clone()).or_insert_with(|| Group::new(group_name)).add(
+                        peer_addr,
+                        Some(peer_pubkey),
+                        timestamp,
+                        group_expiration * 1000,
+                        &Rule::named("known_pubkeys"),
+                        &self.sink
+                    )
+                });
             }
         }
     }
 
     pub fn finished(
         &mut self,
+        source : &str,
         timestamp : u64,
         peer_addr : IpAddr
     )
     {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
+        let peer_addr = self.canonical_address(peer_addr);
+
         let timestamp = self.get_timestamp(timestamp);
 
         if let Some(peer) = self.peers.get_mut(&peer_addr) {
             peer.most_recent_timestamp = timestamp;
 
+            if let Some(started) = peer.open_connections.pop_front() {
+                let stake = self.stakes.get(&peer_addr).copied().unwrap_or(0);
+                let tier = self.stake_tiers.partition_point(|lower_bound| *lower_bound <= stake) - 1;
+                self.connection_durations[tier].add(timestamp.saturating_sub(started));
+            }
+
             if let Some(useless_quic_connections) = &mut self.config.useless_quic_connections {
-                if (peer.tx_submitted == 0) &&
+                if (peer.tx_submitted(self.config.useless_quic_connection_tx.unwrap_or_default()) == 0) &&
                     ((timestamp - peer.first_timestamp) >=
                         self.config
                             .useless_quic_connection_duration_ms
                             .unwrap_or(DEFAULT_USELESS_QUIC_CONNECTION_DURATION_MS))
                 {
-                    useless_quic_connections.add_value(peer_addr, timestamp, 1);
+                    // The event carries no pubkey, so the value is the address's rather than an identity's
+                    useless_quic_connections.add_value(peer_addr, None, timestamp, 1, source);
                 }
             }
         }
@@ -291,47 +1419,110 @@ impl State
         peer_addr : IpAddr
     )
     {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
+        let peer_addr = self.canonical_address(peer_addr);
+
         let timestamp = self.get_timestamp(timestamp);
 
         if let Some(peer) = self.peers.get_mut(&peer_addr) {
             peer.most_recent_timestamp = timestamp;
 
-            peer.tx_submitted += 1;
+            peer.vote_tx_submitted += 1;
         }
     }
 
     pub fn usertx(
         &mut self,
+        source : &Arc<str>,
         timestamp : u64,
         peer_addr : IpAddr,
         signature : Signature
     )
     {
+        if self.is_ignored(&peer_addr) {
+            return;
+        }
+
+        let peer_addr = self.canonical_address(peer_addr);
+
         let timestamp = self.get_timestamp(timestamp);
 
         if let Some(peer) = self.peers.get_mut(&peer_addr) {
             peer.most_recent_timestamp = timestamp;
 
-            peer.tx_submitted += 1;
+            peer.user_tx_submitted += 1;
         }
 
+        // The event carries no pubkey, so the submission is the address's rather than an identity's
+        let pubkey = None;
+
         // Only if this is the first time this peer has submitted this tx should the submitter be added to the
         // submissions list; all other submissions by the same peer are just re-submissions and are not accounted for,
         // so as not to count every one as a no-fee submitted tx which would lower the average tx fee rate for the
         // submitter
+        let mut is_duplicate = false;
         self.current_tx
             .entry(signature)
-            .and_modify(|tx| tx.submitted(timestamp, peer_addr))
-            .or_insert_with(|| Tx::new(timestamp, peer_addr));
+            .and_modify(|tx| is_duplicate = tx.submitted(timestamp, peer_addr, pubkey, source.clone()))
+            .or_insert_with(|| {
+                self.current_tx_order.push_back((timestamp, signature));
+                Tx::new(timestamp, peer_addr, pubkey, source.clone())
+            });
+
+        if is_duplicate {
+            if let Some(peer) = self.peers.get_mut(&peer_addr) {
+                peer.duplicate_count += 1;
+            }
+        }
     }
 
     pub fn forwarded(
         &mut self,
         _timestamp : u64,
-        _signature : Signature
+        signature : Signature
     )
     {
-        // Don't care
+        if let Some(tx) = self.current_tx.get_mut(&signature) {
+            tx.forwarded = true;
+        }
+    }
+
+    // Records that a tx landed in the block of slot, which is one of our own if our_block, as reported from outside of
+    // ingest (e.g. from RPC).  A tx that landed in another leader's block is not attributed as having paid zero fee,
+    // since its fee is never reported to us.  That report must arrive within tx_retention_duration_ms of the tx being
+    // first seen, while the tx is still current: the zero fee values of a tx that has already been flushed are not
+    // reversed.  Returns false if the tx is not current.
+    pub fn landed(
+        &mut self,
+        signature : &Signature,
+        slot : u64,
+        our_block : bool
+    ) -> bool
+    {
+        let Some(tx) = self.current_tx.get_mut(signature)
+        else {
+            // A flushed tx that landed elsewhere will never have its fee reported
+            if !our_block && self.flushed_tx.remove(signature).is_some() {
+                if let Some(index) = self.flushed_tx_order.iter().position(|(_, flushed)| flushed == signature) {
+                    self.flushed_tx_order.remove(index);
+                }
+            }
+            return false;
+        };
+
+        if !our_block && !tx.landed.map(|landed| !landed.our_block).unwrap_or(false) {
+            self.landed_elsewhere_count += 1;
+            if tx.forwarded {
+                self.forwarded_landed_elsewhere_count += 1;
+            }
+        }
+
+        tx.landed = Some(Landed { slot, our_block });
+
+        true
     }
 
     pub fn badfee(
@@ -353,10 +1544,65 @@ impl State
     )
     {
         // Advance timestamp if necessary
-        self.get_timestamp(timestamp);
+        let timestamp = self.get_timestamp(timestamp);
+
+        if let Some(slot_fill) = &mut self.current_slot_fill {
+            if self.current_tx.contains_key(&signature) || self.flushed_tx.contains_key(&signature) {
+                slot_fill.tracked_cu += cu_used;
+            }
+            else {
+                slot_fill.unseen_cu += cu_used;
+            }
+        }
 
         if let Some(tx) = self.current_tx.get_mut(&signature) {
             tx.fee = Some(Fee { total : fee, cu_limit, cu_used });
+            return;
+        }
+
+        // The tx was flushed before its fee arrived; correct the values already added to the fee classifications for
+        // its submissions to those that would have been added had the fee arrived before the flush
+        if let Some(mut tx) = self.flushed_tx.remove(&signature) {
+            self.late_fee_count += 1;
+            if let Some(peer) = self.peers.get_mut(&tx.submissions[0].submitter) {
+                peer.fee_lamports += fee;
+                let first_timestamp = tx.submissions[0].timestamp;
+                if let Some(attribution) =
+                    peer.attributions.iter_mut().find(|attribution| **attribution == (first_timestamp, true))
+                {
+                    attribution.1 = false;
+                }
+            }
+            let mut old_values = Default::default();
+            self.attribute_fees(&tx, &mut old_values);
+            tx.fee = Some(Fee { total : fee, cu_limit, cu_used });
+            let mut new_values = Default::default();
+            self.attribute_fees(&tx, &mut new_values);
+            self.retain_fee_stake_bounds(&mut old_values);
+            self.retain_fee_stake_bounds(&mut new_values);
+            for ((classification, old_values), new_values) in
+                self.config.fee_classifications_mut().into_iter().zip(old_values).zip(new_values)
+            {
+                if let Some(classification) = classification {
+                    Self::correct_fee_values(classification, old_values, new_values);
+                }
+            }
+            return;
+        }
+
+        // The tx was never seen submitted, so the fee can't be credited to its submitter
+        self.unseen_fee_count += 1;
+        self.unseen_fee_lamports += fee;
+
+        if self.config.attribute_unseen_fees.unwrap_or(false) {
+            let zero_fee_share_window_ms = self.zero_fee_share_window_ms();
+            let peer = self
+                .peers
+                .entry(UNKNOWN_SUBMITTER)
+                .or_insert_with(|| Peer { first_timestamp : timestamp, ..Peer::default() });
+            peer.most_recent_timestamp = timestamp;
+            peer.fee_lamports += fee;
+            peer.attribute(timestamp, false, timestamp, zero_fee_share_window_ms);
         }
     }
 
@@ -366,27 +1612,47 @@ impl State
         slots : u8
     )
     {
+        self.slots_until_leader = Some((slots, timestamp));
+
         if let Some(outside_leader_slots) = &mut self.config.outside_leader_slots {
+            for window in outside_leader_slots.windows.iter_mut().flatten() {
+                Self::window_classification(
+                    window,
+                    (slots as u64) < window.leader_slots,
+                    &self.sink,
+                    self.slots_until_leader,
+                    timestamp
+                );
+            }
+
             if (slots as u64) >= outside_leader_slots.leader_slots {
-                self.end_leader(timestamp);
+                self.not_leader_classification(timestamp);
                 return;
             }
         }
-        // If leader slots aren't being tracked, then use begin_leader to ensure that peers are treated as if we're
-        // leader and not blocked just because we're outside of leader slots
+        // If leader slots aren't being tracked, then use leader_classification to ensure that peers are treated as if
+        // we're leader and not blocked just because we're outside of leader slots
 
-        self.begin_leader(timestamp);
+        self.leader_classification(timestamp);
     }
 
     pub fn begin_leader(
         &mut self,
-        _timestamp : u64
+        timestamp : u64
     )
     {
-        if !self.config.outside_leader_slots.is_some() || !self.leader_status.unwrap_or(false) {
-            println!("LEADER CLASSIFICATION");
-            self.leader_status = Some(true);
+        self.in_leader_slots = true;
+        self.slots_until_leader = Some((0, timestamp));
+
+        self.current_slot_fill = Some(SlotFill { start_timestamp : timestamp, ..SlotFill::default() });
+
+        if let Some(outside_leader_slots) = &mut self.config.outside_leader_slots {
+            for window in outside_leader_slots.windows.iter_mut().flatten() {
+                Self::window_classification(window, true, &self.sink, self.slots_until_leader, timestamp);
+            }
         }
+
+        self.leader_classification(timestamp);
     }
 
     pub fn end_leader(
@@ -394,42 +1660,1609 @@ impl State
         timestamp : u64
     )
     {
-        if self.config.outside_leader_slots.is_some() {
-            if self.leader_status.unwrap_or(true) {
-                // If currently in leader state
-                println!("NOT LEADER CLASSIFICATION");
-                self.leader_status = Some(false);
+        // The number of slots until the next leader slots is not known until the next WillBeLeader event
+        self.in_leader_slots = false;
+        self.slots_until_leader = None;
+
+        if let Some(mut slot_fill) = self.current_slot_fill.take() {
+            slot_fill.end_timestamp = timestamp;
+            slot_fill.slot_count =
+                (timestamp.saturating_sub(slot_fill.start_timestamp) + (SLOT_DURATION_MS / 2)) / SLOT_DURATION_MS;
+            if log::enabled(LogLevel::Normal) {
+                let block_cu_limit = self.block_cu_limit();
+                println!(
+                    "Leader rotation of {} slots at {timestamp}: slot fill from tracked peers {:.1}%, from unseen tx \
+                     {:.1}%",
+                    slot_fill.slot_count,
+                    slot_fill.fill(slot_fill.tracked_cu, block_cu_limit) * 100.0,
+                    slot_fill.fill(slot_fill.unseen_cu, block_cu_limit) * 100.0
+                );
             }
+            self.last_slot_fill = Some(slot_fill);
         }
-        // If leader slots aren't being tracked, then use begin_leader to ensure that peers are treated as if we're
-        // leader and not blocked just because we're outside of leader slots
-        else {
-            self.begin_leader(timestamp);
+
+        if let Some(outside_leader_slots) = &mut self.config.outside_leader_slots {
+            for window in outside_leader_slots.windows.iter_mut().flatten() {
+                Self::window_classification(window, false, &self.sink, self.slots_until_leader, timestamp);
+            }
         }
+
+        self.not_leader_classification(timestamp);
     }
 
-    // Do periodic work: log stuff and clean.  Would be better to do it all based on timers instead of periodic
-    // polling but this code isn't that sophisticated yet.  Call once per second.
-    pub fn periodic(
+    pub fn deprecated(
         &mut self,
-        now : u64
+        source : &Arc<str>
     )
     {
-        // Convert now into a timestamp
-        let now = self.get_timestamp(now);
+        *self.deprecated_counts.entry(source.clone()).or_default() += 1;
+    }
 
-        // If the leader_status classification has not happened yet, then we've just started up and haven't been
-        // told anything about leader slots, so should assume we're outside of leader slots
-        if self.leader_status.is_none() {
-            self.end_leader(now);
+    pub fn zero_fee_share_window_ms(&self) -> u64
+    {
+        self.config.zero_fee_share_window_ms.unwrap_or(DEFAULT_ZERO_FEE_SHARE_WINDOW_MS)
+    }
+
+    pub fn block_cu_limit(&self) -> u64
+    {
+        self.config.block_cu_limit.unwrap_or(DEFAULT_BLOCK_CU_LIMIT)
+    }
+
+    // Returns the estimated number of slots until our next leader slots as of now, extrapolated from the most recent
+    // WillBeLeader event, or None if not known
+    pub fn estimated_slots_until_leader(
+        &self,
+        now : u64
+    ) -> Option<u64>
+    {
+        self.slots_until_leader
+            .map(|(slots, timestamp)| (slots as u64).saturating_sub(now.saturating_sub(timestamp) / SLOT_DURATION_MS))
+    }
+
+    // Updates the leader status of a leader slots window, sending transitions to sinks
+    fn window_classification(
+        window : &mut LeaderSlotsWindow,
+        within_leader_slots : bool,
+        sink : &SinkSender,
+        slots_until_leader : Option<(u8, u64)>,
+        timestamp : u64
+    )
+    {
+        if window.leader_status != Some(within_leader_slots) {
+            sink.send(GroupChange::LeaderStatus {
+                group_name : Some(window.group_name.clone()),
+                leader : within_leader_slots,
+                slots_until_leader : slots_until_leader.map(|(slots, _)| slots),
+                timestamp
+            });
+            window.leader_status = Some(within_leader_slots);
         }
+    }
 
-        //        // If it's time for a new period, then use recent_fees to produce a new avg_fees
-        //        if let Some(period_start) = self.period_start {
-        //            let next_period_start = period_start + PERIOD_DURATION_MS;
-        //            if now < next_period_start {
-        //                // If the current period has not completed, nothing more to do in this function
-        //                return;
+    fn leader_classification(
+        &mut self,
+        timestamp : u64
+    )
+    {
+        // Only transitions are sent to sinks
+        if self.leader_status != Some(true) {
+            self.sink.send(GroupChange::LeaderStatus {
+                group_name : None,
+                leader : true,
+                slots_until_leader : self.slots_until_leader.map(|(slots, _)| slots),
+                timestamp
+            });
+            self.leader_status = Some(true);
+        }
+        // If leader slots aren't being tracked, every check is still logged, as it always has been
+        else if self.config.outside_leader_slots.is_none() && log::enabled(LogLevel::Normal) {
+            println!("LEADER CLASSIFICATION");
+        }
+    }
+
+    fn not_leader_classification(
+        &mut self,
+        timestamp : u64
+    )
+    {
+        if self.config.outside_leader_slots.is_some() {
+            if self.leader_status.unwrap_or(true) {
+                // If currently in leader state
+                self.sink.send(GroupChange::LeaderStatus {
+                    group_name : None,
+                    leader : false,
+                    slots_until_leader : self.slots_until_leader.map(|(slots, _)| slots),
+                    timestamp
+                });
+                self.leader_status = Some(false);
+            }
+        }
+        // If leader slots aren't being tracked, then use leader_classification to ensure that peers are treated as if
+        // we're leader and not blocked just because we're outside of leader slots
+        else {
+            self.leader_classification(timestamp);
+        }
+    }
+
+    // Returns the summary of a peer
+    pub fn peer_summary(
+        &self,
+        ip_addr : &IpAddr,
+        peer : &Peer
+    ) -> PeerSummary
+    {
+        // Only the attributions within the zero fee share window are counted
+        let window_start = self.most_recent_timestamp.saturating_sub(self.zero_fee_share_window_ms());
+        let (attributed_count, zero_fee_count) = peer
+            .attributions
+            .iter()
+            .filter(|(timestamp, _)| *timestamp >= window_start)
+            .fold((0, 0), |(attributed_count, zero_fee_count), (_, zero_fee)| {
+                (attributed_count + 1, zero_fee_count + (*zero_fee as u64))
+            });
+
+        PeerSummary {
+            ip_addr : *ip_addr,
+            stake : *self.stakes.get(ip_addr).unwrap_or(&0),
+            pubkey : peer.pubkey.map(|pubkey| pubkey.to_string()),
+            pubkey_change_count : peer.pubkey_change_count,
+            stake_change_count : peer.stake_change_count,
+            stake_history : peer
+                .stake_history
+                .iter()
+                .map(|(timestamp, stake)| StakeHistoryEntry { timestamp : *timestamp, stake : *stake })
+                .collect(),
+            first_timestamp : peer.first_timestamp,
+            most_recent_timestamp : peer.most_recent_timestamp,
+            tx_submitted : peer.tx_submitted(TxKind::All),
+            vote_tx_submitted : peer.vote_tx_submitted,
+            user_tx_submitted : peer.user_tx_submitted,
+            connection_count : peer.connection_count,
+            fee_lamports : peer.fee_lamports,
+            failed_count : peer.failed_count,
+            duplicate_count : peer.duplicate_count,
+            attributed_count,
+            zero_fee_count,
+            zero_fee_share : (attributed_count > 0).then(|| (zero_fee_count as f64) / (attributed_count as f64)),
+            score : peer.score,
+            services : peer.services.iter().map(TpuService::name).collect(),
+            groups : self.groups_of(ip_addr)
+        }
+    }
+
+    // Returns the names of all groups that ip_addr is a member of, in sorted order
+    pub fn groups_of(
+        &self,
+        ip_addr : &IpAddr
+    ) -> Vec<String>
+    {
+        let mut group_names = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.members().contains_key(ip_addr))
+            .map(|(group_name, _)| group_name.clone())
+            .collect::<Vec<String>>();
+
+        group_names.sort();
+
+        group_names
+    }
+
+    // Adds ip_addr to the named group for ttl_ms from now on behalf of rule, as for addresses imported from outside of
+    // the classifier.  Returns true if it was not already a member.
+    pub fn add_group_member(
+        &mut self,
+        group_name : &str,
+        ip_addr : IpAddr,
+        ttl_ms : u64,
+        rule : &str,
+        now : u64
+    ) -> bool
+    {
+        // Convert now into a timestamp
+        let now = self.advance_timestamp(now);
+
+        self.sink.with_context(None, self.stakes.get(&ip_addr).copied(), || {
+            self.groups.entry(group_name.to_string()).or_insert_with(|| Group::new(group_name)).add(
+                ip_addr,
+                None,
+                now,
+                ttl_ms,
+                &Rule::named(rule),
+                &self.sink
+            )
+        })
+    }
+
+    // Adds ip_addr to the named group by rule until expiration, as when restoring a membership saved by a prior run.
+    // Memberships that have since expired, and those of addresses now ignored, are not restored.  Returns true if
+    // ip_addr was added.
+    pub fn restore_group_member(
+        &mut self,
+        group_name : &str,
+        ip_addr : IpAddr,
+        pubkey : Option<Pubkey>,
+        expiration : u64,
+        rule : &str,
+        now : u64
+    ) -> bool
+    {
+        let now = self.advance_timestamp(now);
+
+        if (expiration <= now) || self.ignored_addresses.contains(&ip_addr) {
+            return false;
+        }
+
+        self.sink.with_context(None, self.stakes.get(&ip_addr).copied(), || {
+            self.groups.entry(group_name.to_string()).or_insert_with(|| Group::new(group_name)).add(
+                ip_addr,
+                pubkey,
+                now,
+                expiration - now,
+                &Rule::named(rule),
+                &self.sink
+            )
+        })
+    }
+
+    // Removes ip_addr from the named group, as for members of permanent groups, which are never
+    // expired.  Returns true if it was a member.
+    pub fn remove_group_member(
+        &mut self,
+        group_name : &str,
+        ip_addr : &IpAddr
+    ) -> bool
+    {
+        self.groups.get_mut(group_name).map(|group| group.remove(ip_addr, &self.sink)).unwrap_or(false)
+    }
+
+    // Registers a classifier plugin, which is fed every event handled from now on
+    pub fn register_plugin(
+        &mut self,
+        plugin : Box<dyn ClassifierPlugin>
+    )
+    {
+        self.plugins.push(plugin);
+    }
+
+    // Applies the decisions of every plugin.  Decisions about ignored addresses are discarded, as their events would
+    // have been.
+    fn apply_plugin_decisions(
+        &mut self,
+        now : u64
+    )
+    {
+        for index in 0..self.plugins.len() {
+            let rule = Rule::named(&format!("plugin:{}", self.plugins[index].name()));
+            for decision in self.plugins[index].classify(now) {
+                match decision {
+                    PluginDecision::Add { group_name, ip_addr, pubkey, ttl_ms } => {
+                        if self.ignored_addresses.contains(&ip_addr) {
+                            continue;
+                        }
+                        let ip_addr = self.canonical_address(ip_addr);
+                        let ttl_ms = ttl_ms.unwrap_or(PERMANENT_EXPIRATION - now);
+                        self.sink.with_context(None, self.stakes.get(&ip_addr).copied(), || {
+                            self.groups
+                                .entry(group_name.clone())
+                                .or_insert_with(|| Group::new(&group_name))
+                                .add(ip_addr, pubkey, now, ttl_ms, &rule, &self.sink)
+                        });
+                    },
+                    PluginDecision::Remove { group_name, ip_addr } => {
+                        let ip_addr = self.canonical_address(ip_addr);
+                        self.remove_group_member(&group_name, &ip_addr);
+                    }
+                }
+            }
+        }
+    }
+
+    // Forgets everything known about the peer at ip_addr, removing it from every group, as if it had never been seen
+    pub fn forget_address(
+        &mut self,
+        ip_addr : &IpAddr
+    )
+    {
+        for group in self.groups.values_mut() {
+            group.remove(ip_addr, &self.sink);
+        }
+
+        for classification in self.config.classifications_mut() {
+            classification.forget(ip_addr);
+        }
+
+        self.peers.remove(ip_addr);
+        self.stakes.remove(ip_addr);
+    }
+
+    // Records the completion of a test of the named canary at ip_addr, which passed if the canary was seen by sinks
+    // as a member of group_name elapsed_ms after the start of the test.  The canary is then forgotten, so that the
+    // next test starts afresh.  Sinks are told when a canary starts failing and when it recovers.
+    pub fn canary_completed(
+        &mut self,
+        name : &str,
+        ip_addr : IpAddr,
+        group_name : &str,
+        passed : bool,
+        elapsed_ms : u64,
+        now : u64
+    )
+    {
+        let results = self.canary_results.entry(name.to_string()).or_default();
+
+        let changed = results.last_passed.unwrap_or(true) != passed;
+
+        results.last_passed = Some(passed);
+
+        if passed {
+            results.pass_count += 1;
+            results.last_latency_ms = Some(elapsed_ms);
+            if log::enabled(LogLevel::Normal) {
+                println!("Canary {name} passed: {ip_addr} added to {group_name} after {elapsed_ms} ms");
+            }
+        }
+        else {
+            results.fail_count += 1;
+            eprintln!("ERROR: Canary {name} failed: {ip_addr} not added to {group_name} within {elapsed_ms} ms");
+        }
+
+        if changed {
+            self.sink.send(GroupChange::Canary {
+                canary_name : name.to_string(),
+                ip_addr,
+                group_name : group_name.to_string(),
+                passed,
+                timestamp : now
+            });
+        }
+
+        self.forget_address(&ip_addr);
+    }
+
+    // Returns (group_name, member_count) for every group
+    pub fn group_member_counts(&self) -> Vec<(String, usize)>
+    {
+        self.groups.iter().map(|(group_name, group)| (group_name.clone(), group.members().len())).collect()
+    }
+
+    // Returns (ip_addr, membership) for every member of the named group, or None if there is no such group
+    pub fn group_members(
+        &self,
+        group_name : &str
+    ) -> Option<Vec<(IpAddr, Membership)>>
+    {
+        self.groups
+            .get(group_name)
+            .map(|group| group.members().iter().map(|(ip_addr, membership)| (*ip_addr, membership.clone())).collect())
+    }
+
+    // Do periodic work: log stuff and clean.  Would be better to do it all based on timers instead of periodic
+    // polling but this code isn't that sophisticated yet.  Call once per periodic interval; each phase of periodic
+    // work is only done when its own interval has elapsed.
+    pub fn periodic(
+        &mut self,
+        now : u64
+    )
+    {
+        // Convert now into a timestamp
+        let now = self.advance_timestamp(now);
+
+        let startup_timestamp = *self.startup_timestamp.get_or_insert(now);
+
+        // All group changes of the pass are delivered to sinks as one batch, so that sinks able to apply many changes
+        // at once do so, and no partially applied pass is ever visible
+        self.sink.begin_batch();
+
+        // If the leader_status classification has not happened yet, then we've just started up and haven't been
+        // told anything about leader slots, so assume the configured startup leader status (by default, outside of
+        // leader slots).  None means that the assumption is still being deferred.
+        let assumed_leader = match self
+            .config
+            .outside_leader_slots
+            .as_ref()
+            .and_then(|outside_leader_slots| outside_leader_slots.startup_leader_status)
+            .unwrap_or_default()
+        {
+            StartupLeaderStatus::AssumeNotLeader => Some(false),
+            StartupLeaderStatus::AssumeLeader => Some(true),
+            StartupLeaderStatus::Wait(grace_period_ms) => {
+                if now >= (startup_timestamp + grace_period_ms) {
+                    Some(false)
+                }
+                else {
+                    None
+                }
+            },
+        };
+
+        if let Some(assumed_leader) = assumed_leader {
+            if self.leader_status.is_none() {
+                if assumed_leader {
+                    self.leader_classification(now);
+                }
+                else {
+                    self.not_leader_classification(now);
+                }
+            }
+
+            // Likewise for any leader slots windows
+            if let Some(outside_leader_slots) = &mut self.config.outside_leader_slots {
+                for window in outside_leader_slots.windows.iter_mut().flatten() {
+                    if window.leader_status.is_none() {
+                        Self::window_classification(window, assumed_leader, &self.sink, self.slots_until_leader, now);
+                    }
+                }
+            }
+        }
+
+        if self.tx_flush_phase.is_due(now) {
+            self.flush_tx(now, self.config.tx_flush_budget.unwrap_or(usize::MAX));
+        }
+
+        if self.classification_phase.is_due(now) {
+            self.score_peers(now);
+            self.evaluate_classifications(now);
+            self.apply_plugin_decisions(now);
+            self.release_from_probation();
+            self.check_storms(now);
+            self.check_group_sizes(now);
+        }
+
+        if self.expiration_phase.is_due(now) {
+            self.expire(now);
+            self.check_group_sizes(now);
+        }
+
+        self.sample_churn(now);
+
+        if self.reporting_phase.is_due(now) {
+            self.report(now);
+        }
+
+        if self.group_summary_phase.as_mut().map(|phase| phase.is_due(now)).unwrap_or(false) {
+            self.summarize_groups(now);
+        }
+
+        self.roll_up_epoch(now);
+
+        // After expirations, so that networks whose last membership expired in this pass are forgotten by it
+        self.prune_grouped_addresses();
+
+        self.sink.end_batch();
+    }
+
+    // Forgets the addresses stood for by networks under coarse grouping that are no longer members of any group, and
+    // for which no classification holds values by which they could be added to one.  Should such a network be seen
+    // again, the addresses it is then seen for are recorded anew.
+    fn prune_grouped_addresses(&mut self)
+    {
+        let mut unused = self
+            .sink
+            .grouped_networks()
+            .filter(|network| !self.groups.values().any(|group| group.members().contains_key(network)))
+            .copied()
+            .collect::<HashSet<IpAddr>>();
+
+        if unused.is_empty() {
+            return;
+        }
+
+        for classification in self.config.classifications() {
+            for ip_addr in classification.value_addresses() {
+                unused.remove(ip_addr);
+            }
+        }
+
+        self.sink.remove_grouped_networks(&unused);
+    }
+
+    // Flushes at most tx_flush_budget due tx, if tx are flushed incrementally; to be called between events
+    pub fn flush_due_tx(
+        &mut self,
+        now : u64
+    )
+    {
+        if let Some(tx_flush_budget) = self.config.tx_flush_budget {
+            let now = self.advance_timestamp(now);
+            self.flush_tx(now, tx_flush_budget);
+        }
+    }
+
+    // Flushes at most budget tx, oldest first, of those that are old enough that they must have already landed if
+    // they're ever going to land, adding their fee details into the fee classifications
+    fn flush_tx(
+        &mut self,
+        now : u64,
+        budget : usize
+    )
+    {
+        // Values to be added to each fee classification, batched so that each classification is updated once per
+        // flush rather than once per submission
+        let mut values = Default::default();
+
+        // Forget flushed tx for which late fees are no longer expected
+        let late_fee_retention_ms = self.config.late_fee_retention_ms.unwrap_or(DEFAULT_LATE_FEE_RETENTION_MS);
+        let zero_fee_share_window_ms = self.zero_fee_share_window_ms();
+        while let Some((flush_timestamp, signature)) = self.flushed_tx_order.front() {
+            if (flush_timestamp + late_fee_retention_ms) >= now {
+                break;
+            }
+            self.flushed_tx.remove(signature);
+            self.flushed_tx_order.pop_front();
+        }
+
+        // Remove tx that are old enough that they must have already landed if they're ever going to land,
+        // and when removing them, add their fee details into groups.  current_tx_order is in timestamp order, since
+        // event timestamps never go backwards (see advance_timestamp), so once a tx is not yet due, none after it are.
+        let retain_timestamp = now - self.tx_retention_duration_ms;
+        let mut flushed_count = 0;
+        while flushed_count < budget {
+            let Some((first_timestamp, signature)) = self.current_tx_order.front().copied()
+            else {
+                break;
+            };
+            if first_timestamp >= retain_timestamp {
+                break;
+            }
+            self.current_tx_order.pop_front();
+            let Some(tx) = self.current_tx.remove(&signature)
+            else {
+                continue;
+            };
+            flushed_count += 1;
+            // The submissions of a tx that landed in another leader's block are not attributed at all, since its fee
+            // is unknown rather than zero
+            if tx.landed_elsewhere() {
+                continue;
+            }
+            // Only the first submitter is credited with the fee (or if the tx never landed, of course the first
+            // submitter is credited with nothing)
+            if let Some(peer) = self.peers.get_mut(&tx.submissions[0].submitter) {
+                peer.fee_lamports += tx.fee.as_ref().map(|fee| fee.total).unwrap_or(0);
+            }
+            // Record the submissions that were credited with zero_fee: all but the first, and the first too if the tx
+            // never landed; those older than the zero fee share window are discarded
+            for (i, submission) in tx.submissions.iter().enumerate() {
+                if let Some(peer) = self.peers.get_mut(&submission.submitter) {
+                    peer.attribute(submission.timestamp, (i > 0) || tx.fee.is_none(), now, zero_fee_share_window_ms);
+                }
+            }
+            self.attribute_fees(&tx, &mut values);
+            if tx.fee.is_none() && (late_fee_retention_ms > 0) {
+                self.flushed_tx.insert(signature, tx);
+                self.flushed_tx_order.push_back((now, signature));
+            }
+        }
+
+        self.fee_stake_excluded_count += self.retain_fee_stake_bounds(&mut values) as u64;
+
+        for (classification, values) in self.config.fee_classifications_mut().into_iter().zip(values) {
+            if let Some(classification) = classification {
+                classification.add_values(values);
+            }
+        }
+    }
+
+    // Appends to values the values of tx for each fee classification that is configured, in the order of
+    // Config::fee_classifications_mut
+    fn attribute_fees(
+        &self,
+        tx : &Tx,
+        values : &mut [Vec<SourcedValue>; 4]
+    )
+    {
+        let [zero_fee_share_values, lamports_values, per_cu_limit_values, per_cu_used_values] = values;
+        if let Some(zero_fee_share) = &self.config.zero_fee_share {
+            Self::attribute_zero_fee_share(zero_fee_share, tx, zero_fee_share_values);
+        }
+        if let Some(fee_lamports_submitted) = &self.config.fee_lamports_submitted {
+            Self::attribute_fee(fee_lamports_submitted, tx, &self.zero_fee, lamports_values, |fee| fee.total);
+        }
+        if let Some(fee_microlamports_per_cu_limit) = &self.config.fee_microlamports_per_cu_limit {
+            Self::attribute_fee(fee_microlamports_per_cu_limit, tx, &self.zero_fee, per_cu_limit_values, |fee| {
+                (fee.total * 1000) / fee.cu_limit
+            });
+        }
+        if let Some(fee_microlamports_per_cu_used) = &self.config.fee_microlamports_per_cu_used {
+            Self::attribute_fee(fee_microlamports_per_cu_used, tx, &self.zero_fee, per_cu_used_values, |fee| {
+                (fee.total * 1000) / fee.cu_used
+            });
+        }
+    }
+
+    // Removes from values those of peers outside of the fee stake bounds, which are left out of every fee
+    // classification, returning the number removed
+    fn retain_fee_stake_bounds(
+        &self,
+        values : &mut [Vec<SourcedValue>; 4]
+    ) -> usize
+    {
+        if self.config.fee_min_stake.is_none() && self.config.fee_max_stake.is_none() {
+            return 0;
+        }
+
+        let stakes = StakeLookup {
+            stakes : &self.stakes,
+            unknown_stake : self.config.unknown_stake.unwrap_or(UnknownStake::Zero)
+        };
+        let (min_stake, max_stake) = (self.config.fee_min_stake.unwrap_or(0), self.config.fee_max_stake);
+        let in_bounds = |value : &SourcedValue| {
+            stakes
+                .stake_of(&value.ip_addr)
+                .map(|stake| (stake >= min_stake) && max_stake.map(|max_stake| stake <= max_stake).unwrap_or(true))
+                .unwrap_or(false)
+        };
+
+        let mut excluded_count = 0;
+        for values in values {
+            let count = values.len();
+            values.retain(in_bounds);
+            excluded_count += count - values.len();
+        }
+
+        excluded_count
+    }
+
+    // Corrects the values added to a fee classification for the submissions of a tx flushed without a fee,
+    // old_values, to new_values, those of the tx with its fee.  Submissions credited with the fee but given no value
+    // without it (as when zero fee submissions are excluded) are added.
+    fn correct_fee_values(
+        classification : &mut Classification,
+        mut old_values : Vec<SourcedValue>,
+        new_values : Vec<SourcedValue>
+    )
+    {
+        for new_value in new_values {
+            match old_values.iter().position(|old_value| {
+                (old_value.ip_addr == new_value.ip_addr) && (old_value.timestamp == new_value.timestamp)
+            }) {
+                Some(index) => {
+                    let old_value = old_values.swap_remove(index);
+                    classification.correct_value(
+                        new_value.ip_addr,
+                        new_value.pubkey,
+                        new_value.timestamp,
+                        old_value.value,
+                        new_value.value,
+                        &new_value.source
+                    );
+                },
+                None => classification.add_value(
+                    new_value.ip_addr,
+                    new_value.pubkey,
+                    new_value.timestamp,
+                    new_value.value,
+                    &new_value.source
+                )
+            }
+        }
+    }
+
+    // Appends to values the value of the fee of tx as credited to each of its submissions, for a fee classification,
+    // according to the classification's fee credit policy.  Submissions after the classification's attribution window
+    // are not added at all, nor are submissions credited with zero fee if the classification's zero fee attribution
+    // excludes them.
+    fn attribute_fee(
+        classification : &Classification,
+        tx : &Tx,
+        zero_fee : &Fee,
+        values : &mut Vec<SourcedValue>,
+        value : impl Fn(&Fee) -> u64
+    )
+    {
+        let first_timestamp = tx.submissions[0].timestamp;
+        let attribution_end =
+            first_timestamp + classification.attribution_window_ms.unwrap_or(TX_RETENTION_DURATION_MS);
+        let grace_end = classification.first_submitter_grace_ms.map(|grace_ms| first_timestamp + grace_ms);
+        let is_tie =
+            |submission : &SubmittedTx| grace_end.map(|grace_end| submission.timestamp <= grace_end).unwrap_or(false);
+
+        let submissions = tx
+            .submissions
+            .iter()
+            .filter(|submission| submission.timestamp <= attribution_end)
+            .collect::<Vec<&SubmittedTx>>();
+
+        // The fee with which submissions credited with zero fee are valued, or None if they are not added
+        let declared_zero_fee =
+            tx.fee.as_ref().map(|fee| Fee { total : 0, cu_limit : fee.cu_limit, cu_used : fee.cu_used });
+        let uncredited_fee = match classification.zero_fee_attribution.unwrap_or(ZeroFeeAttribution::Zero) {
+            ZeroFeeAttribution::Exclude => None,
+            ZeroFeeAttribution::Zero => Some(zero_fee),
+            ZeroFeeAttribution::DeclaredCu => declared_zero_fee.as_ref()
+        };
+
+        match classification.fee_credit_policy.unwrap_or(FeeCreditPolicy::First) {
+            // Only the first submission, and submissions within the classification's first submitter grace period
+            // (which are considered to be ties), get the fee; everything else gets zero_fee (or if the tx never
+            // landed, of course every submission gets zero_fee)
+            FeeCreditPolicy::First => {
+                for (i, submission) in submissions.into_iter().enumerate() {
+                    let fee = if (i == 0) || is_tie(submission) {
+                        tx.fee.as_ref().or(uncredited_fee)
+                    }
+                    else {
+                        uncredited_fee
+                    };
+                    let Some(fee) = fee
+                    else {
+                        continue;
+                    };
+                    values.push(SourcedValue {
+                        ip_addr : submission.submitter,
+                        timestamp : submission.timestamp,
+                        value : value(fee) as i64,
+                        source : submission.source.clone(),
+                        pubkey : submission.submitter_pubkey
+                    });
+                }
+            },
+            // Each submission gets a share of the fee proportional to its weight, which halves with every half life
+            // of latency relative to the first submission.  Submissions within the grace period have zero latency.
+            FeeCreditPolicy::LatencyWeighted => {
+                let half_life_ms = classification.fee_credit_half_life_ms.unwrap_or(DEFAULT_FEE_CREDIT_HALF_LIFE_MS);
+                let weights = submissions
+                    .iter()
+                    .map(|submission| {
+                        let latency = if is_tie(submission) { 0 } else { submission.timestamp - first_timestamp };
+                        0.5_f64.powf((latency as f64) / (half_life_ms as f64))
+                    })
+                    .collect::<Vec<f64>>();
+                let total_weight = weights.iter().sum::<f64>();
+                let Some(fee) = tx.fee.as_ref().or(uncredited_fee)
+                else {
+                    return;
+                };
+
+                for (submission, weight) in submissions.into_iter().zip(weights) {
+                    let share = Fee {
+                        total : ((fee.total as f64) * (weight / total_weight)).round() as u64,
+                        cu_limit : fee.cu_limit,
+                        cu_used : fee.cu_used
+                    };
+                    values.push(SourcedValue {
+                        ip_addr : submission.submitter,
+                        timestamp : submission.timestamp,
+                        value : value(&share) as i64,
+                        source : submission.source.clone(),
+                        pubkey : submission.submitter_pubkey
+                    });
+                }
+            }
+        }
+    }
+
+    // Appends to values, for the zero fee share classification, a value of 100 for each submission of tx that is
+    // credited with zero fee, and 0 for each that is credited with the fee, so that averaging the values gives the
+    // percentage of a peer's submissions that were credited with zero fee.  The first submission, and submissions
+    // within the classification's first submitter grace period, are credited with the fee if the tx landed.
+    // Submissions after the classification's attribution window are not added at all.
+    fn attribute_zero_fee_share(
+        classification : &Classification,
+        tx : &Tx,
+        values : &mut Vec<SourcedValue>
+    )
+    {
+        let first_timestamp = tx.submissions[0].timestamp;
+        let attribution_end =
+            first_timestamp + classification.attribution_window_ms.unwrap_or(TX_RETENTION_DURATION_MS);
+        let grace_end = classification.first_submitter_grace_ms.map(|grace_ms| first_timestamp + grace_ms);
+
+        for (i, submission) in tx.submissions.iter().enumerate() {
+            if submission.timestamp > attribution_end {
+                continue;
+            }
+            let is_first = (i == 0) || grace_end.map(|grace_end| submission.timestamp <= grace_end).unwrap_or(false);
+            let value = if is_first && tx.fee.is_some() { 0 } else { 100 };
+            values.push(SourcedValue {
+                ip_addr : submission.submitter,
+                timestamp : submission.timestamp,
+                value,
+                source : submission.source.clone(),
+                pubkey : submission.submitter_pubkey
+            });
+        }
+    }
+
+    // Computes each active peer's score from the weighted change in its counters since it was last scored, and adds
+    // it as a value to the peer_score classification.  Peers with no activity since they were last scored keep their
+    // previous score and contribute no value.
+    fn score_peers(
+        &mut self,
+        now : u64
+    )
+    {
+        let Some(weights) = &self.config.peer_score_weights
+        else {
+            return;
+        };
+
+        for (ip_addr, peer) in &mut self.peers {
+            let counters = peer.counters();
+            let previous = peer.scored_counters;
+
+            if (counters.vote_tx_submitted == previous.vote_tx_submitted) &&
+                (counters.user_tx_submitted == previous.user_tx_submitted) &&
+                (counters.fee_lamports == previous.fee_lamports) &&
+                (counters.connection_count == previous.connection_count) &&
+                (counters.failed_count == previous.failed_count) &&
+                (counters.duplicate_count == previous.duplicate_count)
+            {
+                continue;
+            }
+
+            peer.score = weights.score(&counters, &previous);
+            peer.scored_counters = counters;
+
+            // The unknown submitter is not a real peer and so is never classified
+            if *ip_addr == UNKNOWN_SUBMITTER {
+                continue;
+            }
+
+            if let Some(peer_score) = &mut self.config.peer_score {
+                peer_score.add_value(*ip_addr, None, now, peer.score.round() as i64, DEFAULT_INGEST_SOURCE);
+            }
+        }
+    }
+
+    fn evaluate_classifications(
+        &mut self,
+        now : u64
+    )
+    {
+        let budget = self.config.classification_budget_ms.map(std::time::Duration::from_millis);
+
+        let stakes = StakeLookup {
+            stakes : &self.stakes,
+            unknown_stake : self.config.unknown_stake.unwrap_or(UnknownStake::Zero)
+        };
+
+        let start = std::time::Instant::now();
+
+        let mut classifications = self.config.classifications_mut();
+
+        let count = classifications.len();
+
+        if count == 0 {
+            return;
+        }
+
+        let first = self.next_classification_index % count;
+
+        self.next_classification_index = first;
+
+        // Do classification periodic work, starting with any classifications deferred by the previous pass
+        for index in (first..count).chain(0..first) {
+            // Classifications not yet due for evaluation are passed over without counting as deferred, and the next
+            // pass starts after them unless an earlier classification was deferred
+            if !classifications[index].is_evaluation_due(now) {
+                if (self.next_classification_index % count) == index {
+                    self.next_classification_index = index + 1;
+                }
+                continue;
+            }
+
+            if budget.map(|budget| start.elapsed() >= budget).unwrap_or(false) {
+                classifications[index].deferred_count += 1;
+                continue;
+            }
+
+            classifications[index].periodic(stakes, &mut self.groups, &self.sink, now);
+
+            self.next_classification_index = index + 1;
+        }
+    }
+
+    // Releases peers from probation once they have demonstrated the minimum tx volume or fee contribution
+    fn release_from_probation(&mut self)
+    {
+        let Some(probation) = &self.config.probation
+        else {
+            return;
+        };
+
+        let Some(group) = self.groups.get_mut(probation.group_name.as_ref().unwrap())
+        else {
+            return;
+        };
+
+        let released = group
+            .members()
+            .keys()
+            .filter(|ip_addr| {
+                self.peers
+                    .get(ip_addr)
+                    .map(|peer| {
+                        probation
+                            .min_tx_count
+                            .map(|min_tx_count| peer.tx_submitted(TxKind::All) >= min_tx_count)
+                            .unwrap_or(false) ||
+                            probation
+                                .min_fee_lamports
+                                .map(|min_fee_lamports| peer.fee_lamports >= min_fee_lamports)
+                                .unwrap_or(false)
+                    })
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect::<Vec<IpAddr>>();
+
+        for ip_addr in released {
+            group.remove(&ip_addr, &self.sink);
+        }
+    }
+
+    fn expire(
+        &mut self,
+        now : u64
+    )
+    {
+        for group in self.groups.values_mut() {
+            group.periodic(now, &self.sink);
+        }
+
+        // Remove peers whose most recent timestamp is older than 3 days old, first exporting them if configured to
+        let retain_timestamp = now - PEER_RETENTION_DURATION_MS;
+        if self.config.peer_export.is_some() {
+            for (ip_addr, peer) in &self.peers {
+                if peer.most_recent_timestamp < retain_timestamp {
+                    self.sink.send(GroupChange::PeerEvicted {
+                        ip_addr : *ip_addr,
+                        summary : serde_json::to_value(self.peer_summary(ip_addr, peer)).unwrap_or_default(),
+                        timestamp : now
+                    });
+                }
+            }
+        }
+        self.peers.retain(|ip_addr, peer| {
+            if peer.most_recent_timestamp < retain_timestamp {
+                self.stakes.remove(ip_addr);
+                false
+            }
+            else {
+                true
+            }
+        });
+
+        // Release memory grown during floods of events that have since passed
+        self.peers.shrink_idle();
+        self.stakes.shrink_idle();
+        self.current_tx.shrink_idle();
+        self.current_tx_order.shrink_idle();
+        self.flushed_tx.shrink_idle();
+        self.flushed_tx_order.shrink_idle();
+        for peer in self.peers.values_mut() {
+            peer.open_connections.shrink_idle();
+        }
+    }
+
+    // Fires or clears each group size rule according to its group's current member count, and applies the actions of
+    // the firing rules
+    fn check_group_sizes(
+        &mut self,
+        now : u64
+    )
+    {
+        let Some(group_size_rules) = &self.config.group_size_rules
+        else {
+            self.apply_rule_actions(now);
+            return;
+        };
+
+        for (rule, firing) in group_size_rules.iter().zip(&mut self.group_size_rules_firing) {
+            let member_count = self.groups.get(&rule.group_name).map(|group| group.members().len()).unwrap_or(0);
+
+            let exceeded = if *firing {
+                member_count > rule.clear_members.unwrap_or(rule.max_members)
+            }
+            else {
+                member_count > rule.max_members
+            };
+
+            if exceeded == *firing {
+                continue;
+            }
+
+            *firing = exceeded;
+
+            if rule.actions.contains(&GroupSizeAction::Notify) {
+                self.sink.send(GroupChange::GroupSize {
+                    group_name : rule.group_name.clone(),
+                    member_count,
+                    max_members : rule.max_members,
+                    exceeded,
+                    timestamp : now
+                });
+            }
+        }
+
+        self.apply_rule_actions(now);
+    }
+
+    // Fires or clears each storm rule according to its global rate over its window
+    fn check_storms(
+        &mut self,
+        now : u64
+    )
+    {
+        let Some(storm_rules) = &self.config.storm_rules
+        else {
+            return;
+        };
+
+        let retention_ms = storm_rules.iter().map(|rule| rule.window_ms()).max().unwrap_or(0);
+        self.storm.complete_bucket(now, retention_ms);
+
+        for (rule, firing) in storm_rules.iter().zip(&mut self.storm_rules_firing) {
+            let per_second = self.storm.rate(rule.measure, now, rule.window_ms());
+
+            let exceeded = if *firing {
+                per_second > rule.clear_per_second.unwrap_or(rule.max_per_second)
+            }
+            else {
+                per_second > rule.max_per_second
+            };
+
+            if exceeded == *firing {
+                continue;
+            }
+
+            *firing = exceeded;
+
+            println!(
+                "Storm rule {} {} at {per_second:.1}/s at {now}",
+                rule.name,
+                if exceeded { "fired" } else { "cleared" }
+            );
+
+            if rule.actions.contains(&GroupSizeAction::Notify) {
+                self.sink.send(GroupChange::Storm {
+                    rule_name : rule.name.clone(),
+                    per_second,
+                    max_per_second : rule.max_per_second,
+                    exceeded,
+                    timestamp : now
+                });
+            }
+        }
+    }
+
+    // Applies the actions of the firing group size and storm rules
+    fn apply_rule_actions(
+        &mut self,
+        now : u64
+    )
+    {
+        let is_active = |action| {
+            self.config
+                .group_size_rules
+                .iter()
+                .flatten()
+                .zip(&self.group_size_rules_firing)
+                .map(|(rule, firing)| (&rule.actions, firing))
+                .chain(
+                    self.config
+                        .storm_rules
+                        .iter()
+                        .flatten()
+                        .zip(&self.storm_rules_firing)
+                        .map(|(rule, firing)| (&rule.actions, firing))
+                )
+                .any(|(actions, firing)| *firing && actions.contains(&action))
+        };
+
+        let overloaded = is_active(GroupSizeAction::OverloadThresholds);
+        let coarse_grouping = is_active(GroupSizeAction::CoarseGrouping);
+
+        if overloaded != self.overloaded {
+            println!("Overload thresholds {} at {now}", if overloaded { "enabled" } else { "disabled" });
+            self.overloaded = overloaded;
+            for classification in self.config.classifications_mut() {
+                classification.overloaded = overloaded;
+            }
+        }
+
+        if coarse_grouping != self.coarse_grouping {
+            println!("Coarse grouping {} at {now}", if coarse_grouping { "enabled" } else { "disabled" });
+            self.coarse_grouping = coarse_grouping;
+        }
+    }
+
+    // Computes the staked and unstaked aggregates over the interval since the previous computation
+    fn aggregate(
+        &mut self,
+        now : u64
+    )
+    {
+        let duration_ms =
+            now - self.last_report_timestamp.unwrap_or(now.saturating_sub(self.reporting_phase.interval_ms));
+        self.last_report_timestamp = Some(now);
+
+        self.staked_aggregate = PeerAggregate { duration_ms, ..Default::default() };
+        self.unstaked_aggregate = PeerAggregate { duration_ms, ..Default::default() };
+
+        for (ip_addr, peer) in &mut self.peers {
+            if *ip_addr == UNKNOWN_SUBMITTER {
+                continue;
+            }
+            let counters = peer.counters();
+            if self.stakes.get(ip_addr).copied().unwrap_or(0) > 0 {
+                self.staked_aggregate.add(&counters, &peer.reported_counters);
+            }
+            else {
+                self.unstaked_aggregate.add(&counters, &peer.reported_counters);
+            }
+            peer.reported_counters = counters;
+        }
+    }
+
+    // If epoch rollups are configured and the epoch has changed, prints a report of the activity of peers and groups
+    // over the epoch that ended and begins rolling up the new epoch.  Peers and groups first seen during an epoch are
+    // rolled up from when they were first seen.
+    fn roll_up_epoch(
+        &mut self,
+        now : u64
+    )
+    {
+        let Some(epoch_rollup) = &self.config.epoch_rollup
+        else {
+            return;
+        };
+
+        let epoch = epoch_rollup.epoch_of(now);
+
+        let Some(ended_epoch) = self.epoch.replace(epoch)
+        else {
+            // Rollup begins part way through the epoch in which the classifier started
+            self.epoch_group_add_counts =
+                self.groups.iter().map(|(group_name, group)| (group_name.clone(), group.add_count())).collect();
+            return;
+        };
+
+        if epoch == ended_epoch {
+            return;
+        }
+
+        let mut staked = PeerAggregate::default();
+        let mut unstaked = PeerAggregate::default();
+
+        // Each active peer with its activity over the epoch
+        let mut peers = vec![];
+
+        for (ip_addr, peer) in &mut self.peers {
+            let counters = peer.counters();
+            if *ip_addr != UNKNOWN_SUBMITTER {
+                let stake = self.stakes.get(ip_addr).copied().unwrap_or(0);
+                let mut aggregate = PeerAggregate::default();
+                aggregate.add(&counters, &peer.epoch_counters);
+                if aggregate.active_peer_count > 0 {
+                    peers.push((*ip_addr, peer.pubkey, stake, aggregate.counters));
+                }
+                if stake > 0 {
+                    staked.add(&counters, &peer.epoch_counters);
+                }
+                else {
+                    unstaked.add(&counters, &peer.epoch_counters);
+                }
+            }
+            peer.epoch_counters = counters;
+        }
+
+        let top_peer_count = epoch_rollup.top_peer_count();
+        let mut top_peers = |key : fn(&PeerCounters) -> u64| {
+            peers.sort_by(|a, b| key(&b.3).cmp(&key(&a.3)).then_with(|| a.0.cmp(&b.0)));
+            peers
+                .iter()
+                .take(top_peer_count)
+                .map(|(ip_addr, pubkey, stake, counters)| {
+                    serde_json::json!({
+                        "ip_addr" : ip_addr,
+                        "pubkey" : pubkey.map(|pubkey| pubkey.to_string()),
+                        "stake" : stake,
+                        "counters" : epoch_counters_json(counters)
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let top_peers_by_fee = top_peers(|counters| counters.fee_lamports);
+        let top_peers_by_tx = top_peers(|counters| counters.tx_submitted());
+        let top_peers_by_failures = top_peers(|counters| counters.failed_count);
+
+        let mut groups = self
+            .groups
+            .iter()
+            .map(|(group_name, group)| {
+                let add_count = group.add_count() - self.epoch_group_add_counts.get(group_name).copied().unwrap_or(0);
+                (group_name, add_count, group.members().len())
+            })
+            .collect::<Vec<_>>();
+        groups.sort();
+
+        let aggregate_json = |aggregate : &PeerAggregate| {
+            serde_json::json!({
+                "peer_count" : aggregate.active_peer_count,
+                "counters" : epoch_counters_json(&aggregate.counters)
+            })
+        };
+
+        println!(
+            "Epoch {ended_epoch} report: {}",
+            serde_json::json!({
+                "epoch" : ended_epoch,
+                "start_timestamp" : epoch_rollup.start_of(ended_epoch),
+                "end_timestamp" : epoch_rollup.start_of(ended_epoch + 1),
+                "staked" : aggregate_json(&staked),
+                "unstaked" : aggregate_json(&unstaked),
+                "top_peers_by_fee" : top_peers_by_fee,
+                "top_peers_by_tx" : top_peers_by_tx,
+                "top_peers_by_failures" : top_peers_by_failures,
+                "groups" : groups
+                    .iter()
+                    .map(|(group_name, add_count, member_count)| {
+                        serde_json::json!({
+                            "group_name" : group_name,
+                            "add_count" : add_count,
+                            "member_count" : member_count
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+        );
+
+        self.epoch_group_add_counts =
+            self.groups.iter().map(|(group_name, group)| (group_name.clone(), group.add_count())).collect();
+    }
+
+    // Returns a machine readable summary of the policy in effect, with defaults resolved, so that fleet management can
+    // verify that every node is running the intended policy
+    pub fn capabilities(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "version" : env!("CARGO_PKG_VERSION"),
+            "mode" : if self.enforcing { "enforce" } else { "monitor" },
+            "intervals_ms" : {
+                "tx_flush" : self.tx_flush_phase.interval_ms,
+                "classification" : self.classification_phase.interval_ms,
+                "expiration" : self.expiration_phase.interval_ms,
+                "reporting" : self.reporting_phase.interval_ms
+            },
+            "tx_retention_duration_ms" : self.tx_retention_duration_ms,
+            "late_fee_retention_ms" : self.config.late_fee_retention_ms.unwrap_or(DEFAULT_LATE_FEE_RETENTION_MS),
+            "zero_fee_share_window_ms" : self.zero_fee_share_window_ms(),
+            "block_cu_limit" : self.block_cu_limit(),
+            "stake_tiers" : self.stake_tiers,
+            "unknown_stake" : self.config.unknown_stake.unwrap_or(UnknownStake::Zero),
+            "fee_stake_bounds" : [self.config.fee_min_stake, self.config.fee_max_stake],
+            "known_pubkey_count" : self.pubkey_classifications.len(),
+            "classifications" :
+                self.config.classifications().into_iter().map(Classification::describe).collect::<Vec<_>>(),
+            "outside_leader_slots" : self.config.outside_leader_slots.as_ref().map(|outside_leader_slots| {
+                serde_json::json!({
+                    "leader_slots" : outside_leader_slots.leader_slots,
+                    "startup_leader_status" : outside_leader_slots.startup_leader_status.unwrap_or_default()
+                })
+            }),
+            "probation" : self.config.probation.as_ref().map(|probation| probation.group_name.clone()),
+            "group_size_rule_count" : self.group_size_rules_firing.len(),
+            "storm_rule_count" : self.storm_rules_firing.len(),
+            "ignored_address_count" : self.ignored_addresses.len(),
+            "allowed_senders" : self.config.allowed_senders,
+            "log_level" : log::level(),
+            "debug_address_count" : self.debug_addresses.len(),
+            "equivalent_address_count" : self.canonical_addresses.len(),
+            "plugins" : self.plugins.iter().map(|plugin| plugin.name()).collect::<Vec<_>>()
+        })
+    }
+
+    // Returns the current values of metrics, for sinks that export them
+    fn metrics(&self) -> Vec<Metric>
+    {
+        let metric = |name, value, attributes| Metric { name, value, attributes, exemplar : None };
+
+        let mut exemplars = self.sink.take_exemplars();
+
+        let mut metrics = vec![
+            metric("txingest.peers", self.peers.len() as f64, vec![]),
+            metric("txingest.transactions", self.current_tx.len() as f64, vec![]),
+            metric("txingest.dropped_sink_events", self.sink.dropped() as f64, vec![]),
+            metric("txingest.ignored_events", self.ignored_event_count as f64, vec![]),
+            metric("txingest.unseen_fees", self.unseen_fee_count as f64, vec![]),
+            metric("txingest.late_fees", self.late_fee_count as f64, vec![]),
+            metric("txingest.clock_jumps", self.clock_jump_count as f64, vec![]),
+            metric("txingest.fee_stake_excluded", self.fee_stake_excluded_count as f64, vec![]),
+            metric("txingest.landed_elsewhere", self.landed_elsewhere_count as f64, vec![]),
+            metric("txingest.forwarded_landed_elsewhere", self.forwarded_landed_elsewhere_count as f64, vec![]),
+            metric("txingest.unseen_fee_lamports", self.unseen_fee_lamports as f64, vec![]),
+            metric(
+                "txingest.rate_limited_events",
+                self.ingest_counters.rate_limited_event_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.rate_limited_disconnects",
+                self.ingest_counters.rate_limited_disconnect_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.rejected_connections",
+                self.ingest_counters.rejected_connection_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.ingest_connections",
+                self.ingest_counters.open_connection_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.idle_disconnects",
+                self.ingest_counters.idle_disconnect_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.connection_panics",
+                self.ingest_counters.connection_panic_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric(
+                "txingest.listener_restarts",
+                self.ingest_counters.listener_restart_count.load(Ordering::Relaxed) as f64,
+                vec![]
+            ),
+            metric("txingest.enforcing", if self.enforcing { 1.0 } else { 0.0 }, vec![]),
+            metric("txingest.overloaded", if self.overloaded { 1.0 } else { 0.0 }, vec![]),
+            metric("txingest.coarse_grouping", if self.coarse_grouping { 1.0 } else { 0.0 }, vec![]),
+            metric("txingest.leader.in_leader_slots", if self.in_leader_slots { 1.0 } else { 0.0 }, vec![]),
+        ];
+
+        // Extrapolated to the current time, as reported by the API
+        if let Some(slots) = self.estimated_slots_until_leader(self.most_recent_timestamp) {
+            metrics.push(metric("txingest.leader.slots_until_leader", slots as f64, vec![]));
+        }
+
+        if let Some(slot_fill) = &self.last_slot_fill {
+            let block_cu_limit = self.block_cu_limit();
+            for (from, cu) in [("tracked", slot_fill.tracked_cu), ("unseen", slot_fill.unseen_cu)] {
+                metrics.push(metric("txingest.leader.slot_fill", slot_fill.fill(cu, block_cu_limit), vec![(
+                    "from",
+                    from.to_string()
+                )]));
+            }
+        }
+
+        for (rule, firing) in self.config.storm_rules.iter().flatten().zip(&self.storm_rules_firing) {
+            metrics.push(metric("txingest.storm", if *firing { 1.0 } else { 0.0 }, vec![("rule", rule.name.clone())]));
+        }
+
+        for (name, results) in &self.canary_results {
+            let attributes = vec![("canary", name.clone())];
+            metrics.push(metric("txingest.canary.failures", results.fail_count as f64, attributes.clone()));
+            if let Some(last_latency_ms) = results.last_latency_ms {
+                metrics.push(metric("txingest.canary.latency_ms", last_latency_ms as f64, attributes));
+            }
+        }
+
+        for (stage, histogram) in self.sink.decision_latencies() {
+            metrics.push(metric("txingest.add_latency.count", histogram.count() as f64, vec![(
+                "stage",
+                stage.to_string()
+            )]));
+            for percentile in [50.0, 90.0, 99.0] {
+                if let Some(ms) = histogram.percentile(percentile) {
+                    let mut latency = metric("txingest.add_latency_ms", ms as f64, vec![
+                        ("stage", stage.to_string()),
+                        ("percentile", percentile.to_string()),
+                    ]);
+                    // The tail of the decision latency is exemplified by the slowest recent decision
+                    if (stage == "decision") && (percentile == 99.0) {
+                        latency.exemplar = exemplars.slowest_decision.take();
+                    }
+                    metrics.push(latency);
+                }
+            }
+        }
+
+        for (source, count) in &self.deprecated_counts {
+            metrics.push(metric("txingest.deprecated_messages", *count as f64, vec![("source", source.to_string())]));
+        }
+
+        for classification in self.config.classifications() {
+            let attributes = vec![("classification", classification.name.clone())];
+            metrics.push(metric(
+                "txingest.classification.tracked_ips",
+                classification.tracked_ip_count() as f64,
+                attributes.clone()
+            ));
+            metrics.push(Metric {
+                exemplar : exemplars.by_classification.remove(&classification.name),
+                ..metric("txingest.classification.adds", classification.add_count() as f64, attributes.clone())
+            });
+            metrics.push(metric("txingest.classification.alerts", classification.alert_count as f64, attributes));
+        }
+
+        for (group_name, member_count) in self.group_member_counts() {
+            metrics.push(metric("txingest.group.members", member_count as f64, vec![("group", group_name)]));
+        }
+
+        for (group_name, group) in &self.groups {
+            let totals = group.churn_totals();
+            let attributes = vec![("group", group_name.clone())];
+            metrics.push(Metric {
+                exemplar : exemplars.by_group.remove(group_name),
+                ..metric("txingest.group.adds", totals.add_count as f64, attributes.clone())
+            });
+            metrics.push(metric("txingest.group.removes", totals.remove_count as f64, attributes.clone()));
+            metrics.push(metric("txingest.group.expirations", totals.expire_count as f64, attributes.clone()));
+            if let Some(churn_per_minute) = self.group_churn.get(group_name).and_then(ChurnHistory::churn_per_minute) {
+                metrics.push(metric("txingest.group.churn_per_minute", churn_per_minute, attributes));
+            }
+        }
+
+        metrics
+    }
+
+    // Samples the membership changes of every group into its churn history
+    fn sample_churn(
+        &mut self,
+        now : u64
+    )
+    {
+        let bucket_ms = self.config.churn_bucket_ms.unwrap_or(DEFAULT_CHURN_BUCKET_MS);
+        let bucket_count = self.config.churn_bucket_count.unwrap_or(DEFAULT_CHURN_BUCKET_COUNT);
+
+        for (group_name, group) in &self.groups {
+            // Entries are only created for new groups, so that steady state sampling does not allocate
+            let churn = match self.group_churn.get_mut(group_name) {
+                Some(churn) => churn,
+                None => self.group_churn.entry(group_name.clone()).or_default()
+            };
+            churn.sample(now, bucket_ms, bucket_count, group.churn_totals(), group.members().len());
+        }
+    }
+
+    // Prints a summary of the activity of each group since the previous summary, in sorted order of group name
+    fn summarize_groups(
+        &mut self,
+        now : u64
+    )
+    {
+        let Some(group_summaries) = &self.config.group_summaries
+        else {
+            return;
+        };
+
+        let format = group_summaries.format.unwrap_or_default();
+        let top_rule_count = group_summaries.top_rule_count.unwrap_or(DEFAULT_GROUP_SUMMARY_TOP_RULE_COUNT);
+
+        let mut groups = self.groups.iter_mut().collect::<Vec<_>>();
+        groups.sort_by_key(|(group_name, _)| group_name.as_str());
+
+        for (group_name, group) in groups {
+            let (add_counts, remove_count) = group.take_activity();
+            let add_count = add_counts.values().sum::<u64>();
+
+            // Most adds first, ties in rule order, so that summaries are stable from pass to pass
+            let mut top_rules = add_counts.into_iter().collect::<Vec<_>>();
+            top_rules.sort_by(|(a_rule, a_count), (b_rule, b_count)| b_count.cmp(a_count).then(a_rule.cmp(b_rule)));
+            top_rules.truncate(top_rule_count);
+
+            match format {
+                GroupSummaryFormat::Line => println!(
+                    "Group {group_name}: {} members, {add_count} added, {remove_count} removed, top rules: {}",
+                    group.members().len(),
+                    if top_rules.is_empty() {
+                        "-".to_string()
+                    }
+                    else {
+                        top_rules.iter().map(|(rule, count)| format!("{rule} ({count})")).collect::<Vec<_>>().join(", ")
+                    }
+                ),
+                GroupSummaryFormat::Json => println!(
+                    "{}",
+                    serde_json::json!({
+                        "type" : "group_summary",
+                        "group_name" : group_name,
+                        "member_count" : group.members().len(),
+                        "add_count" : add_count,
+                        "remove_count" : remove_count,
+                        "top_rules" : top_rules
+                            .iter()
+                            .map(|(rule, count)| serde_json::json!({ "rule" : rule.as_ref(), "add_count" : count }))
+                            .collect::<Vec<_>>(),
+                        "timestamp" : now
+                    })
+                )
+            }
+        }
+    }
+
+    fn report(
+        &mut self,
+        now : u64
+    )
+    {
+        let new_peer_count = std::mem::take(&mut self.new_peer_count);
+
+        self.aggregate(now);
+
+        self.sink.send(GroupChange::Metrics { metrics : self.metrics(), timestamp : now });
+
+        // Status reports are not printed when quiet, but metrics are still exported
+        if !log::enabled(LogLevel::Normal) {
+            return;
+        }
+
+        println!(
+            "Status: {} peers ({} new), {} tx, {} groups ({} with known pubkey members), {} dropped sink events, {} \
+             ignored events, {} deprecated messages, {} rate limited events, {} rate limited disconnects, {} fees ({} \
+             lamports) for unseen tx, {} late fees, {} tx landed elsewhere ({} forwarded)",
+            self.peers.len(),
+            new_peer_count,
+            self.current_tx.len(),
+            self.groups.len(),
+            self.groups.values().filter(|group| group.members().values().any(|m| m.pubkey.is_some())).count(),
+            self.sink.dropped(),
+            self.ignored_event_count,
+            self.deprecated_counts.values().sum::<u64>(),
+            self.ingest_counters.rate_limited_event_count.load(Ordering::Relaxed),
+            self.ingest_counters.rate_limited_disconnect_count.load(Ordering::Relaxed),
+            self.unseen_fee_count,
+            self.unseen_fee_lamports,
+            self.late_fee_count,
+            self.landed_elsewhere_count,
+            self.forwarded_landed_elsewhere_count
+        );
+
+        for (lower_bound, histogram) in self.stake_tiers.iter().zip(&self.connection_durations) {
+            println!(
+                "Connection durations for stake >= {lower_bound}: {} connections, p50 {}, p90 {}, p99 {}",
+                histogram.count(),
+                histogram.describe_percentile(50.0),
+                histogram.describe_percentile(90.0),
+                histogram.describe_percentile(99.0)
+            );
+        }
+
+        for (stage, histogram) in self.sink.decision_latencies() {
+            println!(
+                "Add latency to {stage}: {} additions, p50 {}, p90 {}, p99 {}",
+                histogram.count(),
+                histogram.describe_percentile(50.0),
+                histogram.describe_percentile(90.0),
+                histogram.describe_percentile(99.0)
+            );
+        }
+
+        for (name, aggregate) in [("Staked", &self.staked_aggregate), ("Unstaked", &self.unstaked_aggregate)] {
+            println!(
+                "{name} peers: {} ({} active), {:.1} tx/s ({:.1} user), {:.1} connections/s, average fee {} lamports, \
+                 {} failed",
+                aggregate.peer_count,
+                aggregate.active_peer_count,
+                aggregate.rate(aggregate.counters.tx_submitted()),
+                aggregate.rate(aggregate.counters.user_tx_submitted),
+                aggregate.rate(aggregate.counters.connection_count),
+                aggregate.average_fee_lamports().map(|fee| format!("{fee:.0}")).unwrap_or("-".to_string()),
+                aggregate.failure_rate().map(|rate| format!("{:.1}%", rate * 100.0)).unwrap_or("-".to_string())
+            );
+        }
+
+        if let Some(percentile) = self.config.threshold_suggestion_percentile {
+            let stakes = StakeLookup {
+                stakes : &self.stakes,
+                unknown_stake : self.config.unknown_stake.unwrap_or(UnknownStake::Zero)
+            };
+            for classification in self.config.classifications() {
+                println!("Suggested thresholds: {}", classification.suggested_thresholds(stakes, now, percentile));
+            }
+        }
+
+        //        // If it's time for a new period, then use recent_fees to produce a new avg_fees
+        //        if let Some(period_start) = self.period_start {
+        //            let next_period_start = period_start + PERIOD_DURATION_MS;
+        //            if now < next_period_start {
+        //                // If the current period has not completed, nothing more to do in this function
+        //                return;
         //            }
         //            let duration = (now - period_start) / 1000;
         //            self.avg_fees.push_back(TimestampedFee {
@@ -477,89 +3310,5 @@ impl State
         //        println!("Avg CU Used: {avg_cu_used}");
         //        println!("Avg Fee/CU Limit: {:0.9}", (avg_fee as f64) / (avg_cu_limit as f64));
         //        println!("Avg Fee/CU Used: {:0.9}", (avg_fee as f64) / (avg_cu_used as f64));
-
-        // Remove tx that are old enough that they must have already landed if they're ever going to land,
-        // and when removing them, add their fee details into groups.
-        let retain_timestamp = now - TX_RETENTION_DURATION_MS;
-        self.current_tx.retain(|_, tx| {
-            if tx.submissions[0].timestamp < retain_timestamp {
-                for i in 0..tx.submissions.len() {
-                    let submission = &tx.submissions[i];
-                    // Only the first submission gets the fee; everything else gets zero_fee (or if the tx never
-                    // landed, of course the submission gets zero_fee)
-                    let fee = if i == 0 { tx.fee.as_ref().unwrap_or(&self.zero_fee) } else { &self.zero_fee };
-                    if let Some(fee_lamports_submitted) = &mut self.config.fee_lamports_submitted {
-                        fee_lamports_submitted.add_value(submission.submitter, submission.timestamp, fee.total);
-                    }
-                    if let Some(fee_microlamports_per_cu_limit) = &mut self.config.fee_microlamports_per_cu_limit {
-                        fee_microlamports_per_cu_limit.add_value(
-                            submission.submitter,
-                            submission.timestamp,
-                            (fee.total * 1000) / fee.cu_limit
-                        );
-                    }
-                    if let Some(fee_microlamports_per_cu_used) = &mut self.config.fee_microlamports_per_cu_used {
-                        fee_microlamports_per_cu_used.add_value(
-                            submission.submitter,
-                            submission.timestamp,
-                            (fee.total * 1000) / fee.cu_used
-                        );
-                    }
-                }
-                false
-            }
-            else {
-                true
-            }
-        });
-
-        // Do group periodic work
-        if let Some(failed_exceeded_quic_connections) = &mut self.config.failed_exceeded_quic_connections {
-            failed_exceeded_quic_connections.periodic(&self.stakes, &mut self.classification_groups, now);
-        }
-
-        if let Some(useless_quic_connections) = &mut self.config.useless_quic_connections {
-            useless_quic_connections.periodic(&self.stakes, &mut self.classification_groups, now);
-        }
-
-        if let Some(fee_lamports_submitted) = &mut self.config.fee_lamports_submitted {
-            fee_lamports_submitted.periodic(&self.stakes, &mut self.classification_groups, now);
-        }
-
-        if let Some(fee_microlamports_per_cu_limit) = &mut self.config.fee_microlamports_per_cu_limit {
-            fee_microlamports_per_cu_limit.periodic(&self.stakes, &mut self.classification_groups, now);
-        }
-
-        if let Some(fee_microlamports_per_cu_used) = &mut self.config.fee_microlamports_per_cu_used {
-            fee_microlamports_per_cu_used.periodic(&self.stakes, &mut self.classification_groups, now);
-        }
-
-        for (group_name, group) in &mut self.pubkey_groups {
-            group.retain(|ip_addr, expiration| {
-                if *expiration >= now {
-                    true
-                }
-                else {
-                    println!("Remove {ip_addr} from group {group_name}");
-                    false
-                }
-            });
-        }
-
-        for group in self.classification_groups.values_mut() {
-            group.periodic(now);
-        }
-
-        // Remove peers whose most recent timestamp is older than 3 days old
-        let retain_timestamp = now - PEER_RETENTION_DURATION_MS;
-        self.peers.retain(|ip_addr, peer| {
-            if peer.most_recent_timestamp < retain_timestamp {
-                self.stakes.remove(ip_addr);
-                false
-            }
-            else {
-                true
-            }
-        });
     }
 }
@@ -0,0 +1,171 @@
+use crate::config::GroupSizeAction;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::net::IpAddr;
+
+const DEFAULT_STORM_WINDOW_MS : u64 = 10 * 1000; // 10 seconds
+
+// A global rate across all peers watched by a storm rule
+#[derive(Clone, Copy, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StormMeasure
+{
+    // Connections started, including those that exceeded limits
+    Connections,
+
+    // Failed connections, including those that exceeded limits
+    FailedConnections,
+
+    // Distinct addresses having at least one failed connection during the window.  Addresses are counted before any
+    // coarse grouping, so that coarse grouping enabled by the rule does not itself clear the rule.
+    FailingAddresses
+}
+
+// A rule that fires when a global rate across all peers exceeds a limit, e.g. during a connection attempt storm in
+// which thousands of distinct addresses each stay under per-address thresholds
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StormRule
+{
+    // Name of the rule, for logs and events
+    pub name : String,
+
+    // The global rate watched
+    pub measure : StormMeasure,
+
+    // The rule fires when the measure, averaged over the window, exceeds this many per second
+    pub max_per_second : f64,
+
+    // Once fired, the rule clears when the measure is at or below this many per second, so that a rate hovering
+    // around max_per_second does not toggle the actions.  If not present, max_per_second is used.
+    pub clear_per_second : Option<f64>,
+
+    // Milliseconds over which the measure is averaged.  If not present, a default of 10 seconds is used.
+    pub window_ms : Option<u64>,
+
+    // Actions taken while the rule is firing
+    pub actions : Vec<GroupSizeAction>
+}
+
+impl StormRule
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.max_per_second <= 0.0 {
+            return Err(format!("Invalid storm_rules entry {}: max_per_second must be positive", self.name));
+        }
+
+        if self.clear_per_second.map(|clear_per_second| clear_per_second > self.max_per_second).unwrap_or(false) {
+            return Err(format!(
+                "Invalid storm_rules entry {}: clear_per_second greater than max_per_second",
+                self.name
+            ));
+        }
+
+        if self.window_ms == Some(0) {
+            return Err(format!("Invalid storm_rules entry {}: zero window_ms", self.name));
+        }
+
+        if self.actions.is_empty() {
+            return Err(format!("Invalid storm_rules entry {}: no actions", self.name));
+        }
+
+        Ok(())
+    }
+
+    pub fn window_ms(&self) -> u64
+    {
+        self.window_ms.unwrap_or(DEFAULT_STORM_WINDOW_MS)
+    }
+}
+
+// Global counts of the connections of one interval
+#[derive(Default)]
+struct StormBucket
+{
+    connection_count : u64,
+
+    failed_count : u64,
+
+    failing_addresses : HashSet<IpAddr>
+}
+
+// Tracks the global rates watched by storm rules, in buckets of the intervals between evaluations
+#[derive(Default)]
+pub struct StormTracker
+{
+    // Counts since the most recent evaluation
+    current : StormBucket,
+
+    // Completed buckets with the timestamps at which they ended, oldest first
+    buckets : VecDeque<(u64, StormBucket)>,
+
+    // Timestamp at which the oldest retained bucket began
+    start_timestamp : Option<u64>
+}
+
+impl StormTracker
+{
+    pub fn started(&mut self)
+    {
+        self.current.connection_count += 1;
+    }
+
+    pub fn failed(
+        &mut self,
+        ip_addr : IpAddr
+    )
+    {
+        self.current.failed_count += 1;
+        self.current.failing_addresses.insert(ip_addr);
+    }
+
+    // Completes the current bucket at now and drops buckets ending more than retention_ms ago
+    pub fn complete_bucket(
+        &mut self,
+        now : u64,
+        retention_ms : u64
+    )
+    {
+        self.start_timestamp.get_or_insert(now);
+
+        self.buckets.push_back((now, std::mem::take(&mut self.current)));
+
+        while let Some((timestamp, _)) = self.buckets.front() {
+            if (*timestamp + retention_ms) > now {
+                break;
+            }
+            self.start_timestamp = Some(*timestamp);
+            self.buckets.pop_front();
+        }
+    }
+
+    // Returns the per second rate of measure over the window_ms before now, or over the time tracked so far if less
+    pub fn rate(
+        &self,
+        measure : StormMeasure,
+        now : u64,
+        window_ms : u64
+    ) -> f64
+    {
+        let window_start = now.saturating_sub(window_ms).max(self.start_timestamp.unwrap_or(now));
+
+        let duration_ms = now - window_start;
+        if duration_ms == 0 {
+            return 0.0;
+        }
+
+        let buckets = self.buckets.iter().filter(|(timestamp, _)| *timestamp > window_start).map(|(_, bucket)| bucket);
+
+        let count = match measure {
+            StormMeasure::Connections => buckets.map(|bucket| bucket.connection_count).sum::<u64>(),
+            StormMeasure::FailedConnections => buckets.map(|bucket| bucket.failed_count).sum::<u64>(),
+            StormMeasure::FailingAddresses => {
+                buckets.flat_map(|bucket| bucket.failing_addresses.iter()).collect::<HashSet<_>>().len() as u64
+            },
+        };
+
+        (count as f64) * 1000.0 / (duration_ms as f64)
+    }
+}
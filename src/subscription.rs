@@ -0,0 +1,164 @@
+// In-process subscriptions to classification decisions, for embedders (e.g. a relayer) that react to group changes
+// directly rather than through the classifier's stdout, files, or webhooks.  A subscription is a sink, given to the
+// sink worker in place of (or alongside) the stdout sink, which passes a simplified event to a callback or channel.
+
+use crate::sink::{GroupChange, Sink};
+use crossbeam::channel::{bounded, Receiver, Sender, TrySendError};
+use solana_sdk::pubkey::Pubkey;
+use std::net::IpAddr;
+
+// A classification decision, as delivered to subscribers
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClassificationEvent
+{
+    MemberAdded
+    {
+        group_name : String, ip_addr : IpAddr, pubkey : Option<Pubkey>, expiration : u64
+    },
+
+    // The expiration of an existing member changed (as by an update or extend event), or the member was re-sent on
+    // returning to enforce mode
+    MemberUpdated
+    {
+        group_name : String, ip_addr : IpAddr, pubkey : Option<Pubkey>, expiration : u64
+    },
+
+    MemberRemoved
+    {
+        group_name : String, ip_addr : IpAddr
+    },
+
+    // The leader classification changed, globally (group_name None) or for the outside leader slots window of a group
+    LeaderChange
+    {
+        group_name : Option<String>, leader : bool, timestamp : u64
+    },
+
+    // The classifier switched between enforce and monitor mode; subscribers that enforce decisions must undo them on
+    // switching to monitor mode
+    ModeChange
+    {
+        enforcing : bool, timestamp : u64
+    }
+}
+
+impl ClassificationEvent
+{
+    // Returns the classification event of a sink event, or None for events that are not classification decisions
+    pub fn from_sink_event(event : &GroupChange) -> Option<Self>
+    {
+        match event {
+            GroupChange::Add { group_name, ip_addr, pubkey, expiration, .. } => {
+                Some(ClassificationEvent::MemberAdded {
+                    group_name : group_name.clone(),
+                    ip_addr : *ip_addr,
+                    pubkey : *pubkey,
+                    expiration : *expiration
+                })
+            },
+            GroupChange::Update { group_name, ip_addr, pubkey, expiration, .. } |
+            GroupChange::Extend { group_name, ip_addr, pubkey, expiration, .. } => {
+                Some(ClassificationEvent::MemberUpdated {
+                    group_name : group_name.clone(),
+                    ip_addr : *ip_addr,
+                    pubkey : *pubkey,
+                    expiration : *expiration
+                })
+            },
+            GroupChange::Remove { group_name, ip_addr, .. } => {
+                Some(ClassificationEvent::MemberRemoved { group_name : group_name.clone(), ip_addr : *ip_addr })
+            },
+            GroupChange::LeaderStatus { group_name, leader, timestamp, .. } => {
+                Some(ClassificationEvent::LeaderChange {
+                    group_name : group_name.clone(),
+                    leader : *leader,
+                    timestamp : *timestamp
+                })
+            },
+            GroupChange::Mode { enforcing, timestamp } => {
+                Some(ClassificationEvent::ModeChange { enforcing : *enforcing, timestamp : *timestamp })
+            },
+            _ => None
+        }
+    }
+}
+
+// Sink that calls a callback with each classification event.  The callback is called on the sink worker thread, so it
+// may block without stalling event processing, but blocks all other sinks while it does.
+pub struct CallbackSink
+{
+    callback : Box<dyn FnMut(ClassificationEvent) + Send>
+}
+
+impl CallbackSink
+{
+    pub fn new(callback : impl FnMut(ClassificationEvent) + Send + 'static) -> Self
+    {
+        Self { callback : Box::new(callback) }
+    }
+}
+
+impl Sink for CallbackSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "callback" })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if let Some(event) = ClassificationEvent::from_sink_event(event) {
+            (self.callback)(event);
+        }
+
+        Ok(())
+    }
+}
+
+// Sink that sends each classification event to a channel
+pub struct ChannelSink
+{
+    sender : Sender<ClassificationEvent>
+}
+
+impl ChannelSink
+{
+    // Creates a sink sending to a channel holding at most queue_size events, returning also the receiver of the
+    // channel.  While the channel is full, the sink fails to apply events, so they are retried according to the sink
+    // retry policy and then dropped.
+    pub fn subscribe(queue_size : usize) -> (Self, Receiver<ClassificationEvent>)
+    {
+        let (sender, receiver) = bounded::<ClassificationEvent>(queue_size);
+
+        (Self { sender }, receiver)
+    }
+}
+
+impl Sink for ChannelSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "channel", "capacity" : self.sender.capacity() })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        let Some(event) = ClassificationEvent::from_sink_event(event)
+        else {
+            return Ok(());
+        };
+
+        match self.sender.try_send(event) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err("Subscription channel full".to_string()),
+            // Nobody is listening any more, which is not worth retrying
+            Err(TrySendError::Disconnected(_)) => Ok(())
+        }
+    }
+}
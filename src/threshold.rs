@@ -1,10 +1,47 @@
-use crate::classification::TimestampedValue;
-use crate::group::Group;
-use serde::Deserialize;
-use std::collections::{HashMap, VecDeque};
+use crate::classification::PeerValues;
+use crate::config::UnknownStake;
+use crate::group::{Group, Rule, ThresholdRule};
+use crate::schedule::{is_scheduled, TimeWindow};
+use crate::sink::SinkSender;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Arc;
 
-#[derive(Deserialize)]
+const DEFAULT_AUTO_TUNE_STEP_FRACTION : f64 = 0.05;
+const DEFAULT_AUTO_TUNE_TOLERANCE : f64 = 0.2;
+const DEFAULT_AUTO_TUNE_INTERVAL_MS : u64 = 10 * 60 * 1000; // 10 minutes
+
+// The stakes of peers, along with how peers of unknown stake are to be treated
+#[derive(Clone, Copy)]
+pub struct StakeLookup<'a>
+{
+    pub stakes : &'a HashMap<IpAddr, u64>,
+
+    pub unknown_stake : UnknownStake
+}
+
+impl StakeLookup<'_>
+{
+    // Returns the stake of ip_addr, or None if its stake is unknown and is to be treated as such
+    pub fn stake_of(
+        &self,
+        ip_addr : &IpAddr
+    ) -> Option<u64>
+    {
+        match (self.stakes.get(ip_addr), self.unknown_stake) {
+            (Some(stake), _) => Some(*stake),
+            (None, UnknownStake::Zero) => Some(0),
+            (None, UnknownStake::Assumed(stake)) => Some(stake),
+            (None, UnknownStake::SkipStakeBanded) => None
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Threshold
 {
     // Group name to add peers who exceed this threshold to; if not provided, defaults to the group name specified
@@ -33,7 +70,7 @@ pub struct Threshold
     pub threshold_type : ThresholdType,
 
     // The value to compare accumulated values to
-    pub value : u64,
+    pub value : i64,
 
     // The time span in milliseconds over which to sum or average accumulated values to get the value to compare
     // against
@@ -41,10 +78,65 @@ pub struct Threshold
 
     // If present and false, then continue evaluating thresholds for ip addresses that matched this threshold for this
     // classification
-    pub continue_after_match : Option<bool>
+    pub continue_after_match : Option<bool>,
+
+    // If present, this threshold only applies during these windows of UTC time, e.g. to apply stricter thresholds
+    // during known high congestion hours or scheduled mints.  If not present, the threshold always applies.
+    pub schedule : Option<Vec<TimeWindow>>,
+
+    // If present, value is slowly adjusted to keep the fraction of ip addresses meeting this threshold near a target
+    pub auto_tune : Option<AutoTune>,
+
+    // Number of ip addresses newly added to the group by this threshold
+    #[serde(skip)]
+    pub add_count : u64,
+
+    // Identifies this threshold as the rule responsible for group memberships, named e.g. "zero_fee_share[1]"
+    #[serde(skip)]
+    pub rule : Rule,
+
+    // Half-life of the decayed values of the containing classification, if it accumulates decayed values
+    #[serde(skip)]
+    pub decay_half_life_ms : Option<u64>,
+
+    // Timestamp of the most recent auto tuning check
+    #[serde(skip)]
+    last_auto_tune_timestamp : Option<u64>
 }
 
-#[derive(Deserialize)]
+// Controls the automatic adjustment of a threshold's value.  At each adjustment interval, the fraction of ip
+// addresses to which the threshold applies (those within its stake range having at least min_value_count values)
+// that meet the threshold is computed.  If it is above the target by more than the tolerance, the value is moved one
+// step in the direction that makes the threshold harder to meet; if below, one step in the other direction.  Every
+// adjustment is logged.
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AutoTune
+{
+    // The fraction of ip addresses that should meet the threshold, between 0 and 1 (e.g. 0.005 to block at most 0.5%
+    // of unstaked peers with a threshold having a high_stake of 0).  No adjustment is made until there are enough ip
+    // addresses that the target is at least one of them.
+    pub target_fraction : f64,
+
+    // The value is never adjusted below this
+    pub min_value : i64,
+
+    // The value is never adjusted above this
+    pub max_value : i64,
+
+    // Fraction of the current value by which the value is adjusted in each step (at least 1).  If not present, a
+    // default of 0.05 is used.
+    pub step_fraction : Option<f64>,
+
+    // Fraction of target_fraction by which the observed fraction may differ from it without adjustment, so that the
+    // value does not oscillate around the target.  If not present, a default of 0.2 is used.
+    pub tolerance : Option<f64>,
+
+    // Milliseconds between adjustments.  If not present, a default of 10 minutes is used.
+    pub interval_ms : Option<u64>
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
 pub enum ThresholdType
 {
     // If the value is greater than the threshold, then it meets the classification criteria
@@ -64,7 +156,7 @@ pub enum ThresholdType
     LessThanOrEqual
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
 pub enum ValueOperation
 {
     #[serde(rename = "sum")]
@@ -76,14 +168,48 @@ pub enum ValueOperation
 
 impl Threshold
 {
+    // Creates a threshold with the required fields given and every optional field absent, as if parsed from config
+    pub fn new(
+        value_operation : ValueOperation,
+        threshold_type : ThresholdType,
+        value : i64,
+        duration_ms : u64
+    ) -> Self
+    {
+        Self {
+            group_name : None,
+            group_expiration_seconds : None,
+            low_stake : None,
+            high_stake : None,
+            min_value_count : None,
+            value_operation,
+            threshold_type,
+            value,
+            duration_ms,
+            continue_after_match : None,
+            schedule : None,
+            auto_tune : None,
+            add_count : 0,
+            rule : Rule::default(),
+            decay_half_life_ms : None,
+            last_auto_tune_timestamp : None
+        }
+    }
+
+    // Validates the threshold at threshold_index in the thresholds of the classification, or in its overload_thresholds
+    // if overload
     pub fn validate(
         &mut self,
-        classification_name : &str,
+        classification : &str,
+        overload : bool,
         threshold_index : usize,
         classification_group_name : &str,
         classification_group_expiration_seconds : u64
     ) -> Result<(), String>
     {
+        let classification_name =
+            if overload { format!("{classification}.overload_thresholds") } else { classification.to_string() };
+
         if let Some(low_stake) = self.low_stake {
             if let Some(high_stake) = self.high_stake {
                 if high_stake < low_stake {
@@ -115,35 +241,321 @@ impl Threshold
             self.group_expiration_seconds = Some(classification_group_expiration_seconds);
         }
 
+        self.rule = Rule {
+            name : Arc::from(format!("{classification_name}[{threshold_index}]")),
+            threshold : Some(ThresholdRule {
+                classification : Arc::from(classification),
+                index : threshold_index,
+                overload
+            })
+        };
+
+        for window in self.schedule.iter_mut().flatten() {
+            window.validate().map_err(|e| {
+                format!("Classification {classification_name} has threshold at index {threshold_index} with {e}")
+            })?;
+        }
+
+        if let Some(auto_tune) = &self.auto_tune {
+            let invalid = |reason : &str| {
+                Err(format!(
+                    "Classification {classification_name} has threshold at index {threshold_index} with invalid \
+                     auto_tune: {reason}"
+                ))
+            };
+            if (auto_tune.target_fraction <= 0.0) || (auto_tune.target_fraction >= 1.0) {
+                return invalid("target_fraction must be between 0 and 1");
+            }
+            if auto_tune.min_value > auto_tune.max_value {
+                return invalid("min_value is greater than max_value");
+            }
+            if (self.value < auto_tune.min_value) || (self.value > auto_tune.max_value) {
+                return invalid("value is not between min_value and max_value");
+            }
+            if auto_tune.step_fraction.map(|step_fraction| step_fraction <= 0.0).unwrap_or(false) {
+                return invalid("step_fraction must be greater than 0");
+            }
+            if auto_tune.tolerance.map(|tolerance| tolerance < 0.0).unwrap_or(false) {
+                return invalid("tolerance must not be negative");
+            }
+            if auto_tune.interval_ms == Some(0) {
+                return invalid("interval_ms must not be zero");
+            }
+        }
+
         Ok(())
     }
 
-    pub fn stop_after_exceeded(
-        &mut self,
-        stakes : &HashMap<IpAddr, u64>,
+    // Returns a machine readable description of the threshold with defaults resolved, for the capability report
+    pub fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({
+            "rule" : self.rule.name.as_ref(),
+            "group_name" : self.group_name,
+            // Held in milliseconds once validated
+            "group_expiration_seconds" : self.group_expiration_seconds.map(|ms| ms / 1000),
+            "low_stake" : self.low_stake,
+            "high_stake" : self.high_stake,
+            "min_value_count" : self.min_value_count,
+            "value_operation" : self.value_operation,
+            "threshold_type" : self.threshold_type,
+            "value" : self.value,
+            "duration_ms" : self.duration_ms,
+            "continue_after_match" : self.continue_after_match.unwrap_or(false),
+            "schedule" : self.schedule,
+            "auto_tune" : self.auto_tune
+        })
+    }
+
+    // Returns true if the values of ip_addr meet this threshold
+    pub fn is_exceeded(
+        &self,
+        stakes : StakeLookup,
         now : u64,
         ip_addr : &IpAddr,
-        recent_values : &VecDeque<TimestampedValue>,
-        groups : &mut HashMap<String, Group>
+        recent_values : &PeerValues
     ) -> bool
     {
-        // Skip this threshold check if the stake level of the ip_addr doesn't match
-        if let Some(low_stake) = self.low_stake {
-            let stake = *(stakes.get(ip_addr).unwrap_or(&0));
-            if stake < low_stake {
-                return false;
-            }
-            if let Some(high_stake) = self.high_stake {
-                if stake > high_stake {
-                    return false;
-                }
+        // Skip this threshold check if it is outside of its schedule or the stake level of the ip_addr doesn't match
+        if !is_scheduled(&self.schedule, now) || !self.applies_to(stakes, ip_addr) {
+            return false;
+        }
+
+        self.accumulated_value(now, recent_values).map(|value_sum| self.meets(value_sum)).unwrap_or(false)
+    }
+
+    // Returns a description of the evaluation of this threshold for the values of ip_addr, for tracing
+    pub fn trace(
+        &self,
+        stakes : StakeLookup,
+        now : u64,
+        ip_addr : &IpAddr,
+        recent_values : &PeerValues
+    ) -> String
+    {
+        let comparison = format!("{} {}", serde_json::json!(self.threshold_type).as_str().unwrap_or("?"), self.value);
+
+        if !is_scheduled(&self.schedule, now) {
+            format!("{}: {comparison}: outside of schedule", self.rule.name)
+        }
+        else if !self.applies_to(stakes, ip_addr) {
+            format!(
+                "{}: {comparison}: stake {} outside of stake range",
+                self.rule.name,
+                stakes.stake_of(ip_addr).map(|stake| stake.to_string()).unwrap_or("unknown".to_string())
+            )
+        }
+        else {
+            match self.accumulated_value(now, recent_values) {
+                Some(value_sum) => format!(
+                    "{}: {value_sum} {comparison}: {}",
+                    self.rule.name,
+                    if self.meets(value_sum) { "met" } else { "not met" }
+                ),
+                None => format!("{}: {comparison}: fewer than min_value_count values", self.rule.name)
             }
         }
-        else if let Some(high_stake) = self.high_stake {
-            if *(stakes.get(ip_addr).unwrap_or(&0)) > high_stake {
-                return false;
+    }
+
+    // Adds ip_addr, which exceeded this threshold, to group_name if present or else to this threshold's group, with
+    // pubkey if it was the identity of pubkey at ip_addr that exceeded it.  Returns true if no further thresholds are
+    // to be evaluated for ip_addr (or that identity).
+    pub fn stop_after_adding(
+        &mut self,
+        now : u64,
+        ip_addr : &IpAddr,
+        pubkey : Option<Pubkey>,
+        group_name : Option<&str>,
+        groups : &mut HashMap<String, Group>,
+        sink : &SinkSender
+    ) -> bool
+    {
+        let group_name = group_name.unwrap_or_else(|| self.group_name.as_ref().unwrap());
+        if groups.entry(group_name.to_string()).or_insert_with(|| Group::new(group_name)).add(
+            *ip_addr,
+            pubkey,
+            now,
+            self.group_expiration_seconds.unwrap(),
+            &self.rule,
+            sink
+        ) {
+            self.add_count += 1;
+        }
+
+        !self.continue_after_match.unwrap_or(false)
+    }
+
+    // Returns true if an accumulated value meets this threshold
+    fn meets(
+        &self,
+        value_sum : i64
+    ) -> bool
+    {
+        match self.threshold_type {
+            ThresholdType::GreaterThan => value_sum > self.value,
+            ThresholdType::GreaterThanOrEqual => value_sum >= self.value,
+            ThresholdType::LessThan => value_sum < self.value,
+            ThresholdType::LessThanOrEqual => value_sum <= self.value
+        }
+    }
+
+    // Adjusts the value of this threshold if it has auto_tune and an adjustment interval has passed since the last
+    // check
+    pub fn auto_tune(
+        &mut self,
+        stakes : StakeLookup,
+        now : u64,
+        recent_values : &HashMap<IpAddr, PeerValues>
+    )
+    {
+        let Some(auto_tune) = &self.auto_tune
+        else {
+            return;
+        };
+
+        // Thresholds are only tuned against the traffic seen while they apply
+        if !is_scheduled(&self.schedule, now) {
+            return;
+        }
+
+        let interval_ms = auto_tune.interval_ms.unwrap_or(DEFAULT_AUTO_TUNE_INTERVAL_MS);
+        match self.last_auto_tune_timestamp {
+            Some(last) if now < (last + interval_ms) => return,
+            Some(_) => (),
+            // The first check is one interval after startup, so that values have accumulated
+            None => {
+                self.last_auto_tune_timestamp = Some(now);
+                return;
             }
         }
+        self.last_auto_tune_timestamp = Some(now);
+
+        let (applicable_count, meeting_count) = recent_values
+            .iter()
+            .filter(|(ip_addr, _)| self.applies_to(stakes, ip_addr))
+            .filter_map(|(_, recent_values)| self.accumulated_value(now, recent_values))
+            .fold((0_u64, 0_u64), |(applicable_count, meeting_count), value_sum| {
+                (applicable_count + 1, meeting_count + (self.meets(value_sum) as u64))
+            });
+
+        if ((applicable_count as f64) * auto_tune.target_fraction) < 1.0 {
+            return;
+        }
+
+        let fraction = (meeting_count as f64) / (applicable_count as f64);
+        let tolerance = auto_tune.target_fraction * auto_tune.tolerance.unwrap_or(DEFAULT_AUTO_TUNE_TOLERANCE);
+
+        // Positive to make the threshold harder to meet, negative to make it easier
+        let direction = if fraction > (auto_tune.target_fraction + tolerance) {
+            1
+        }
+        else if fraction < (auto_tune.target_fraction - tolerance) {
+            -1
+        }
+        else {
+            return;
+        };
+
+        let step = (((self.value.unsigned_abs() as f64) *
+            auto_tune.step_fraction.unwrap_or(DEFAULT_AUTO_TUNE_STEP_FRACTION)) as i64)
+            .max(1);
+
+        // Greater than thresholds are harder to meet with higher values; less than thresholds with lower values
+        let raise = match self.threshold_type {
+            ThresholdType::GreaterThan | ThresholdType::GreaterThanOrEqual => direction > 0,
+            ThresholdType::LessThan | ThresholdType::LessThanOrEqual => direction < 0
+        };
+
+        let value = if raise { self.value.saturating_add(step) } else { self.value.saturating_sub(step) }
+            .clamp(auto_tune.min_value, auto_tune.max_value);
+
+        if value != self.value {
+            println!(
+                "Auto-tuned threshold {} value from {} to {value}: {meeting_count} of {applicable_count} ip addresses \
+                 ({:.3}%) met it, target {:.3}%",
+                self.rule.name,
+                self.value,
+                fraction * 100.0,
+                auto_tune.target_fraction * 100.0
+            );
+            self.value = value;
+        }
+    }
+
+    // Returns a suggested value for this threshold: the value at the given percentile of the accumulated values of
+    // all ip addresses to which the threshold applies, where for less_than thresholds the percentile is taken from
+    // the low end so that in either case roughly (100 - percentile)% of ip addresses would meet the threshold.
+    // Returns None if no ip address has enough values.
+    pub fn suggested_value(
+        &self,
+        stakes : StakeLookup,
+        now : u64,
+        recent_values : &HashMap<IpAddr, PeerValues>,
+        percentile : f64
+    ) -> Option<i64>
+    {
+        let mut values = recent_values
+            .iter()
+            .filter(|(ip_addr, _)| self.applies_to(stakes, ip_addr))
+            .filter_map(|(_, recent_values)| self.accumulated_value(now, recent_values))
+            .collect::<Vec<i64>>();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        values.sort_unstable();
+
+        let percentile = match self.threshold_type {
+            ThresholdType::GreaterThan | ThresholdType::GreaterThanOrEqual => percentile,
+            ThresholdType::LessThan | ThresholdType::LessThanOrEqual => 100.0 - percentile
+        };
+
+        let index = ((percentile / 100.0) * ((values.len() - 1) as f64)).round() as usize;
+
+        Some(values[index])
+    }
+
+    // Returns true if the stake level of ip_addr is within the stake range of this threshold
+    pub fn applies_to(
+        &self,
+        stakes : StakeLookup,
+        ip_addr : &IpAddr
+    ) -> bool
+    {
+        let stake = match stakes.stake_of(ip_addr) {
+            Some(stake) => stake,
+            // Only thresholds without a stake range apply to peers whose stake is to be considered unknown
+            None => return self.low_stake.is_none() && self.high_stake.is_none()
+        };
+
+        self.low_stake.map(|low_stake| stake >= low_stake).unwrap_or(true) &&
+            self.high_stake.map(|high_stake| stake <= high_stake).unwrap_or(true)
+    }
+
+    // Returns the sum or average of the values within the duration of this threshold (or of the decayed values), or
+    // None if there are fewer than min_value_count of them
+    pub fn accumulated_value(
+        &self,
+        now : u64,
+        values : &PeerValues
+    ) -> Option<i64>
+    {
+        let recent_values = match values {
+            PeerValues::Window(recent_values) => recent_values,
+            PeerValues::Decayed(decayed_value) => {
+                // Decayed values only exist for classifications with a half-life, which is given to their thresholds
+                let (sum, count) = decayed_value.decayed(now, self.decay_half_life_ms.unwrap());
+                if self.min_value_count.map(|min_value_count| count < (min_value_count as f64)).unwrap_or(false) {
+                    return None;
+                }
+                return Some(match self.value_operation {
+                    ValueOperation::Sum => sum.round() as i64,
+                    ValueOperation::Average if count > 0.0 => (sum / count).round() as i64,
+                    ValueOperation::Average => sum.round() as i64
+                });
+            }
+        };
 
         let use_timestamp = now - self.duration_ms;
 
@@ -156,15 +568,15 @@ impl Threshold
                     None
                 }
                 else {
-                    value_count += 1;
+                    value_count += timestamped_value.count;
                     Some(timestamped_value.value)
                 }
             })
-            .sum::<u64>();
+            .sum::<i64>();
 
         if let Some(min_value_count) = self.min_value_count {
             if value_count < min_value_count {
-                return false;
+                return None;
             }
         }
 
@@ -172,28 +584,11 @@ impl Threshold
             ValueOperation::Sum => (),
             ValueOperation::Average => {
                 if value_count > 0 {
-                    value_sum /= value_count
+                    value_sum /= value_count as i64
                 }
             },
         }
 
-        let is_in_group = match self.threshold_type {
-            ThresholdType::GreaterThan => value_sum > self.value,
-            ThresholdType::GreaterThanOrEqual => value_sum >= self.value,
-            ThresholdType::LessThan => value_sum < self.value,
-            ThresholdType::LessThanOrEqual => value_sum <= self.value
-        };
-
-        if is_in_group {
-            let group_name = self.group_name.as_ref().unwrap();
-            groups
-                .entry(group_name.clone())
-                .or_insert_with(|| Group::new(group_name))
-                .add(ip_addr.clone(), now + self.group_expiration_seconds.unwrap());
-            !self.continue_after_match.unwrap_or(false)
-        }
-        else {
-            false
-        }
+        Some(value_sum)
     }
 }
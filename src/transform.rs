@@ -0,0 +1,76 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const DEFAULT_LOG_SCALE : u64 = 1;
+
+// A transform applied to values as they are added to a classification, so that e.g. heavy-tailed values can be made
+// suitable for thresholds
+#[derive(Deserialize, JsonSchema)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum ValueTransform
+{
+    // Replaces each value with log2(value + 1), multiplied by scale (default 1) and rounded.  A scale of e.g. 10
+    // retains a tenth of a doubling of precision.  Negative values are transformed by magnitude, keeping their sign.
+    #[serde(rename = "log2")]
+    Log2
+    {
+        scale : Option<u64>
+    },
+
+    // Limits each value to the range min..=max; either bound may be omitted
+    #[serde(rename = "clamp")]
+    Clamp
+    {
+        min : Option<i64>, max : Option<i64>
+    },
+
+    // Replaces each value with the index of the first of bounds (which must be ascending) that it is less than or
+    // equal to, or the number of bounds if it is greater than all of them
+    #[serde(rename = "bucketize")]
+    Bucketize
+    {
+        bounds : Vec<i64>
+    }
+}
+
+impl ValueTransform
+{
+    pub fn validate(
+        &self,
+        classification_name : &str
+    ) -> Result<(), String>
+    {
+        match self {
+            ValueTransform::Log2 { scale: Some(0) } => {
+                Err(format!("Classification {classification_name} has log2 transform with zero scale"))
+            },
+            ValueTransform::Clamp { min: Some(min), max: Some(max) } if min > max => Err(format!(
+                "Classification {classification_name} has clamp transform with min {min} greater than max {max}"
+            )),
+            ValueTransform::Bucketize { bounds } if bounds.is_empty() => {
+                Err(format!("Classification {classification_name} has bucketize transform with no bounds"))
+            },
+            ValueTransform::Bucketize { bounds } if bounds.windows(2).any(|pair| pair[0] >= pair[1]) => {
+                Err(format!("Classification {classification_name} has bucketize transform with bounds not ascending"))
+            },
+            _ => Ok(())
+        }
+    }
+
+    pub fn apply(
+        &self,
+        value : i64
+    ) -> i64
+    {
+        match self {
+            ValueTransform::Log2 { scale } => {
+                let magnitude = (((value.unsigned_abs() as f64) + 1.0).log2() *
+                    (scale.unwrap_or(DEFAULT_LOG_SCALE) as f64))
+                    .round() as i64;
+                magnitude * value.signum()
+            },
+            ValueTransform::Clamp { min, max } => value.max(min.unwrap_or(i64::MIN)).min(max.unwrap_or(i64::MAX)),
+            ValueTransform::Bucketize { bounds } => bounds.partition_point(|bound| *bound < value) as i64
+        }
+    }
+}
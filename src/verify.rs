@@ -0,0 +1,153 @@
+use crate::config::Config;
+use crate::listener::IngestCounters;
+use crate::sink::{self, SinkReceiver, DEFAULT_SINK_QUEUE_SIZE};
+use crate::state::State;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use solana_sdk::txingest::TxIngestMsg;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+const DEFAULT_MAX_LOGGED_DIVERGENCES : usize = 10;
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VerificationConfig
+{
+    // Path of the config file of the shadow instance, typically the same config with performance options (e.g.
+    // classification_budget_ms, max_values_per_ip, phase intervals) changed.  Only classification settings of the
+    // shadow config are used; its sinks, listeners, and API are ignored.  If not present, the shadow uses the same
+    // config file as the primary.
+    pub shadow_config : Option<String>,
+
+    // Maximum number of divergences logged per comparison; all divergences are counted.  If not present, a default of
+    // 10 is used.
+    pub max_logged_divergences : Option<usize>
+}
+
+// Differential verification: a shadow State is fed exactly the events fed to the primary State, and after every
+// periodic pass the group memberships of the two are compared, with any divergences logged and counted.  The shadow's
+// decisions are never acted upon.  This allows an optimized implementation or configuration to be run alongside the
+// current one in production, flagging any change in group decisions, at the cost of doing all work twice.
+pub struct Verifier
+{
+    shadow : State,
+
+    // Receives the shadow's sink events, which are discarded
+    shadow_sink_receiver : SinkReceiver,
+
+    max_logged_divergences : usize,
+
+    // Number of comparisons made
+    comparison_count : u64,
+
+    // Number of comparisons which found at least one divergence
+    divergent_comparison_count : u64,
+
+    // Total number of divergences found, each being an address which is a member of a group in one of the primary or
+    // shadow but not the other
+    divergence_count : u64
+}
+
+impl Verifier
+{
+    pub fn new(
+        verification : &VerificationConfig,
+        mut shadow_config : Config
+    ) -> Self
+    {
+        // The shadow does not report status; divergences are reported instead
+        shadow_config.reporting_interval_ms = Some(u64::MAX);
+
+        let (shadow_sink, shadow_sink_receiver) =
+            sink::channel(shadow_config.sink_queue_size.unwrap_or(DEFAULT_SINK_QUEUE_SIZE));
+
+        Self {
+            shadow : State::new(shadow_config, shadow_sink, Arc::new(IngestCounters::default())),
+            shadow_sink_receiver,
+            max_logged_divergences : verification.max_logged_divergences.unwrap_or(DEFAULT_MAX_LOGGED_DIVERGENCES),
+            comparison_count : 0,
+            divergent_comparison_count : 0,
+            divergence_count : 0
+        }
+    }
+
+    // Feeds an event to the shadow
+    pub fn handle(
+        &mut self,
+        source : &Arc<str>,
+        msg : TxIngestMsg
+    )
+    {
+        self.shadow.handle(source, msg);
+    }
+
+    // To be called immediately after the periodic pass of the primary, at the same time now.  Does the shadow's
+    // periodic pass, then compares the group memberships of the primary and shadow.
+    pub fn periodic(
+        &mut self,
+        primary : &State,
+        now : u64
+    )
+    {
+        self.shadow.periodic(now);
+
+        // Shadow sink events are not acted upon
+        self.shadow_sink_receiver.drain().for_each(drop);
+
+        let primary_groups = group_decisions(primary);
+        let shadow_groups = group_decisions(&self.shadow);
+
+        let group_names = primary_groups.keys().chain(shadow_groups.keys()).collect::<BTreeSet<&String>>();
+
+        let mut divergences = vec![];
+
+        for group_name in group_names {
+            let empty = BTreeSet::new();
+            let primary_members = primary_groups.get(group_name).unwrap_or(&empty);
+            let shadow_members = shadow_groups.get(group_name).unwrap_or(&empty);
+
+            for ip_addr in primary_members.difference(shadow_members) {
+                divergences.push(format!("{ip_addr} is a member of {group_name} in the primary only"));
+            }
+            for ip_addr in shadow_members.difference(primary_members) {
+                divergences.push(format!("{ip_addr} is a member of {group_name} in the shadow only"));
+            }
+        }
+
+        self.comparison_count += 1;
+
+        if divergences.is_empty() {
+            return;
+        }
+
+        self.divergent_comparison_count += 1;
+        self.divergence_count += divergences.len() as u64;
+
+        println!(
+            "Verification: {} divergences in group decisions at {now} ({} total, in {} of {} comparisons)",
+            divergences.len(),
+            self.divergence_count,
+            self.divergent_comparison_count,
+            self.comparison_count
+        );
+
+        for divergence in divergences.iter().take(self.max_logged_divergences) {
+            println!("  Divergence: {divergence}");
+        }
+    }
+}
+
+// Returns the members of every group, by group name
+fn group_decisions(state : &State) -> BTreeMap<String, BTreeSet<IpAddr>>
+{
+    state
+        .group_member_counts()
+        .into_iter()
+        .filter_map(|(group_name, _)| {
+            let members = state.group_members(&group_name)?.into_iter().map(|(ip_addr, _)| ip_addr).collect();
+            Some((group_name, members))
+        })
+        .collect()
+}
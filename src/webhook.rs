@@ -0,0 +1,118 @@
+use crate::http::HttpEndpoint;
+use crate::sink::{GroupChange, Sink};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const DEFAULT_WEBHOOK_TIMEOUT_MS : u64 = 5 * 1000; // 5 seconds
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig
+{
+    // URL to POST events to, as JSON objects.  Only plain http URLs are supported (e.g.
+    // "http://127.0.0.1:8080/events"); use a local relay for https endpoints.
+    pub url : String,
+
+    // Groups whose events are posted to this webhook.  If not present, events for all groups are posted, as well as
+    // events not specific to any group (such as new peers and global leader status).
+    pub groups : Option<Vec<String>>,
+
+    // Timeout in milliseconds for connecting to and exchanging data with the webhook.  If not present, a default of 5
+    // seconds is used.
+    pub timeout_ms : Option<u64>
+}
+
+impl WebhookConfig
+{
+    pub fn validate(&self) -> Result<(), String>
+    {
+        self.endpoint().map(|_| ()).map_err(|e| format!("Invalid webhook: {e}"))
+    }
+
+    fn endpoint(&self) -> Result<HttpEndpoint, String>
+    {
+        HttpEndpoint::new(&self.url, self.timeout_ms.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT_MS))
+    }
+}
+
+// Sink that posts events to a webhook, filtered by group
+pub struct WebhookSink
+{
+    config : WebhookConfig,
+
+    endpoint : HttpEndpoint,
+
+    // False when monitoring, in which case only Mode events are posted
+    enforcing : bool
+}
+
+impl WebhookSink
+{
+    pub fn new(config : WebhookConfig) -> Self
+    {
+        // The url was validated when the config was loaded
+        let endpoint = config.endpoint().unwrap();
+
+        Self { config, endpoint, enforcing : true }
+    }
+
+    // Returns true if the event is routed to this webhook
+    fn is_routed(
+        &self,
+        event : &GroupChange
+    ) -> bool
+    {
+        let group_name = match event {
+            GroupChange::Add { group_name, .. } |
+            GroupChange::Update { group_name, .. } |
+            GroupChange::Extend { group_name, .. } |
+            GroupChange::Remove { group_name, .. } => Some(group_name),
+            GroupChange::LeaderStatus { group_name, .. } => group_name.as_ref(),
+            GroupChange::GroupSize { group_name, .. } => Some(group_name),
+            GroupChange::NewPeer { .. } |
+            GroupChange::PeerEvicted { .. } |
+            GroupChange::PubkeyChange { .. } |
+            GroupChange::StakeChange { .. } |
+            GroupChange::Storm { .. } => None,
+            // Metrics are only of interest to metrics exporters
+            GroupChange::Metrics { .. } => return false,
+            // Every webhook is told of mode changes and canary alerts, which concern the classifier as a whole
+            GroupChange::Mode { .. } | GroupChange::Canary { .. } => return true
+        };
+
+        if !self.enforcing {
+            return false;
+        }
+
+        match (&self.config.groups, group_name) {
+            (None, _) => true,
+            (Some(groups), Some(group_name)) => groups.iter().any(|g| g == group_name),
+            (Some(_), None) => false
+        }
+    }
+}
+
+impl Sink for WebhookSink
+{
+    fn describe(&self) -> serde_json::Value
+    {
+        serde_json::json!({ "type" : "webhook", "url" : self.endpoint.url(), "groups" : self.config.groups })
+    }
+
+    fn apply(
+        &mut self,
+        event : &GroupChange
+    ) -> Result<(), String>
+    {
+        if let GroupChange::Mode { enforcing, .. } = event {
+            self.enforcing = *enforcing;
+        }
+
+        if self.is_routed(event) {
+            self.endpoint.post_json(&event.to_json().to_string())
+        }
+        else {
+            Ok(())
+        }
+    }
+}